@@ -0,0 +1,225 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tanoshi_lib::http::{Http, HttpClient, Request, Response};
+
+/// Name of the index of a fixture directory
+pub const INDEX: &str = "fixtures.json";
+
+/// Set to record responses from the network instead of replaying them
+pub const RECORD_ENV: &str = "TANOSHI_RECORD";
+
+/// A recorded response, its body is stored in its own file next to the index
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Entry {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// File name of the body, relative to the fixture directory
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Replay,
+    Record,
+}
+
+/// Answers the requests of extensions with the responses recorded in a directory.
+///
+/// The directory has a `fixtures.json` listing the responses and a file for each body.
+/// Responses are matched by method and url, a request without a recorded response fails.
+/// In record mode requests go to the network and their responses are saved,
+/// replacing the ones recorded before for the same request.
+#[derive(Clone)]
+pub struct Fixtures {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    dir: PathBuf,
+    mode: Mode,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl Fixtures {
+    /// Replay the responses recorded in `dir`
+    pub fn replay(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let index = dir.join(INDEX);
+        let entries = fs::read(&index)
+            .map_err(|e| anyhow!("failed to read {:?}: {}, record it first", index, e))?;
+        let entries = serde_json::from_slice(&entries)
+            .map_err(|e| anyhow!("failed to parse {:?}: {}", index, e))?;
+        Ok(Self::new(dir, Mode::Replay, entries))
+    }
+
+    /// Record responses from the network into `dir`, keeping the ones already recorded there
+    pub fn record(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let entries = match fs::read(dir.join(INDEX)) {
+            Ok(entries) => serde_json::from_slice(&entries)?,
+            Err(_) => vec![],
+        };
+        Ok(Self::new(dir, Mode::Record, entries))
+    }
+
+    /// Record when `TANOSHI_RECORD` is set, replay otherwise
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        match std::env::var_os(RECORD_ENV) {
+            Some(_) => Self::record(dir),
+            None => Self::replay(dir),
+        }
+    }
+
+    fn new(dir: &Path, mode: Mode, entries: Vec<Entry>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                dir: dir.to_path_buf(),
+                mode,
+                entries: Mutex::new(entries),
+            }),
+        }
+    }
+
+    /// Returns a client answering from the fixtures, to give to the extension
+    pub fn http(&self) -> Http {
+        Http::new(self.inner.clone())
+    }
+
+    /// Makes the fixtures the client of `Http::default()`, used by the default `get_page`
+    /// of extensions tested in process. Built plugins get their client from `Plugin::load`.
+    pub fn set_default(&self) {
+        tanoshi_lib::http::set_default(self.http());
+    }
+}
+
+impl Inner {
+    fn replay(&self, request: &Request) -> Result<Response> {
+        let entry = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|entry| {
+                entry.method.eq_ignore_ascii_case(&request.method) && entry.url == request.url
+            })
+            .cloned()
+            .ok_or_else(|| {
+                anyhow!(
+                    "no fixture for {} {}, record it with {}=1",
+                    request.method,
+                    request.url,
+                    RECORD_ENV
+                )
+            })?;
+        let body = self.dir.join(&entry.body);
+        Ok(Response {
+            status: entry.status,
+            url: entry.url,
+            headers: entry.headers,
+            body: fs::read(&body).map_err(|e| anyhow!("failed to read {:?}: {}", body, e))?,
+        })
+    }
+
+    fn record(&self, request: &Request) -> Result<Response> {
+        let mut req = ureq::request(&request.method, &request.url);
+        if let Some(redirects) = request.redirects {
+            req.redirects(redirects);
+        }
+        for (name, value) in request.headers.iter() {
+            req.set(name, value);
+        }
+        let resp = if request.body.is_empty() {
+            req.call()
+        } else {
+            req.send_bytes(&request.body)
+        };
+        if let Some(e) = resp.synthetic_error() {
+            return Err(anyhow!("{} {}: {}", request.method, request.url, e));
+        }
+
+        let mut response = Response {
+            status: resp.status(),
+            url: resp.get_url().to_string(),
+            headers: resp
+                .headers_names()
+                .into_iter()
+                .filter_map(|name| {
+                    let value = resp.header(&name)?.to_string();
+                    Some((name, value))
+                })
+                .collect(),
+            body: vec![],
+        };
+        resp.into_reader().read_to_end(&mut response.body)?;
+
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries
+            .iter()
+            .position(|entry| {
+                entry.method.eq_ignore_ascii_case(&request.method) && entry.url == request.url
+            })
+            .unwrap_or_else(|| {
+                entries.push(Entry {
+                    method: request.method.clone(),
+                    url: request.url.clone(),
+                    status: 0,
+                    headers: vec![],
+                    body: String::new(),
+                });
+                entries.len() - 1
+            });
+        let entry = &mut entries[index];
+        entry.status = response.status;
+        entry.headers = response.headers.clone();
+        entry.body = format!(
+            "{:04}.{}",
+            index + 1,
+            extension(response.header("Content-Type").unwrap_or_default())
+        );
+        fs::write(self.dir.join(&entry.body), &response.body)?;
+        fs::write(
+            self.dir.join(INDEX),
+            serde_json::to_string_pretty(&*entries)?,
+        )?;
+
+        Ok(response)
+    }
+}
+
+impl HttpClient for Inner {
+    fn send(&self, request: Request) -> Result<Response> {
+        match self.mode {
+            Mode::Replay => self.replay(&request),
+            Mode::Record => self.record(&request),
+        }
+    }
+}
+
+/// File extension of a body, so recorded bodies can be opened with the right program
+fn extension(content_type: &str) -> &'static str {
+    let content_type = content_type.to_lowercase();
+    [
+        ("html", "html"),
+        ("json", "json"),
+        ("xml", "xml"),
+        ("javascript", "js"),
+        ("text/", "txt"),
+        ("png", "png"),
+        ("jpeg", "jpg"),
+        ("gif", "gif"),
+        ("webp", "webp"),
+    ]
+    .iter()
+    .find(|(name, _)| content_type.contains(name))
+    .map(|(_, extension)| *extension)
+    .unwrap_or("bin")
+}
@@ -0,0 +1,46 @@
+//! Conformance tests for tanoshi extensions.
+//!
+//! The suite calls `get_mangas`, `get_manga_info`, `get_chapters` and `get_pages` of an extension
+//! the way tanoshi does, and checks the results can be used, e.g paths are not empty,
+//! chapter numbers can be parsed, page urls are absolute and upload dates are sane.
+//! Requests are answered from responses recorded in a directory, so the suite runs offline
+//! and doesn't break when the source changes its pages.
+//!
+//! An extension can be tested in process:
+//! ```ignore
+//! #[test]
+//! fn conformance() {
+//!     let fixtures = Fixtures::open("tests/fixtures").unwrap();
+//!     fixtures.set_default();
+//!     let extension = Mangadex::new(fixtures.http(), None);
+//!     Suite::new(&extension).keyword("one piece").run().assert();
+//! }
+//! ```
+//!
+//! or built, the same way tanoshi loads it:
+//! ```ignore
+//! #[test]
+//! fn conformance() {
+//!     let fixtures = Fixtures::open("tests/fixtures").unwrap();
+//!     let plugin = unsafe { Plugin::load("target/debug/libmangadex.so", None, fixtures.http()) }.unwrap();
+//!     for (_, extension) in plugin.extensions() {
+//!         Suite::new(extension).run().assert();
+//!     }
+//! }
+//! ```
+//!
+//! Run the tests once with `TANOSHI_RECORD=1` to record the responses from the network,
+//! and commit the fixture directory with the tests.
+
+/// This module contains the recorded HTTP responses served to extensions
+pub mod fixture;
+
+/// This module contains loading of built plugins
+pub mod plugin;
+
+/// This module contains the conformance checks
+pub mod suite;
+
+pub use fixture::Fixtures;
+pub use plugin::Plugin;
+pub use suite::{Check, Report, Suite};
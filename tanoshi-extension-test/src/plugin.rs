@@ -0,0 +1,69 @@
+use anyhow::{anyhow, Result};
+use libloading::Library;
+use std::path::Path;
+
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::ffi::{self, PluginDeclaration};
+use tanoshi_lib::http::Http;
+
+/// A built plugin, loaded the same way tanoshi loads it
+pub struct Plugin {
+    extensions: Vec<(String, Box<dyn Extension>)>,
+    /// Declared after `extensions` so the library is closed only after the extensions are dropped
+    #[allow(dead_code)]
+    library: Library,
+}
+
+impl Plugin {
+    /// Load the plugin at `path` and register its extensions with `config`,
+    /// every source of the plugin gets `http` as its client
+    ///
+    /// # Safety
+    /// Loading a library runs its code, `path` must be a tanoshi plugin
+    pub unsafe fn load(
+        path: impl AsRef<Path>,
+        config: Option<&serde_yaml::Value>,
+        http: Http,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let library =
+            Library::new(path).map_err(|e| anyhow!("failed to load {:?}: {}", path, e))?;
+        let decl = match library.get::<*const PluginDeclaration>(ffi::DECLARATION_SYMBOL) {
+            Ok(decl) => *decl,
+            Err(_) if library.get::<*const u8>(b"plugin_declaration\0").is_ok() => {
+                return Err(anyhow!(
+                    "{:?} is built for the legacy plugin interface, rebuild it with tanoshi-lib {}",
+                    path,
+                    tanoshi_lib::CORE_VERSION
+                ));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let extensions = ffi::register_extensions(decl, config, &|_| http.clone())?
+            .into_iter()
+            .map(|(name, extension)| (name, Box::new(extension) as Box<dyn Extension>))
+            .collect::<Vec<_>>();
+        if extensions.is_empty() {
+            return Err(anyhow!("{:?} registered no extension", path));
+        }
+
+        Ok(Self {
+            extensions,
+            library,
+        })
+    }
+
+    /// Returns the extensions of the plugin with their names
+    pub fn extensions(&self) -> impl Iterator<Item = (&str, &dyn Extension)> {
+        self.extensions
+            .iter()
+            .map(|(name, extension)| (name.as_str(), extension.as_ref()))
+    }
+
+    /// Returns the extension registered as `name`
+    pub fn get(&self, name: &str) -> Option<&dyn Extension> {
+        self.extensions()
+            .find(|(n, _)| *n == name)
+            .map(|(_, extension)| extension)
+    }
+}
@@ -0,0 +1,291 @@
+use chrono::{Duration, NaiveDate, Utc};
+use std::collections::HashSet;
+use std::fmt;
+
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::manga::{Chapter, Manga, Params};
+
+/// Runs the conformance checks against an extension.
+///
+/// Mangas are listed with `params`, then the first `samples` mangas are checked with
+/// `get_manga_info` and `get_chapters`, and the first `samples` chapters of each with `get_pages`.
+/// A step is skipped when the one it depends on failed.
+pub struct Suite<'a> {
+    extension: &'a dyn Extension,
+    params: Params,
+    auth: String,
+    samples: usize,
+}
+
+impl<'a> Suite<'a> {
+    pub fn new(extension: &'a dyn Extension) -> Self {
+        Self {
+            extension,
+            params: Params {
+                keyword: None,
+                genres: None,
+                page: None,
+                sort_by: None,
+                sort_order: None,
+                refresh: None,
+                sort_key: None,
+                filters: None,
+            },
+            auth: String::new(),
+            samples: 1,
+        }
+    }
+
+    /// Search mangas with `keyword` instead of listing the first page
+    pub fn keyword(mut self, keyword: &str) -> Self {
+        self.params.keyword = Some(keyword.to_string());
+        self
+    }
+
+    /// Parameters to list mangas with
+    pub fn params(mut self, params: Params) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// `auth` passed to every call, for a source that requires login
+    pub fn auth(mut self, auth: &str) -> Self {
+        self.auth = auth.to_string();
+        self
+    }
+
+    /// Mangas and chapters to check, default to 1
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn run(&self) -> Report {
+        let info = self.extension.info();
+        let mut report = Report {
+            source: info.name.clone(),
+            checks: vec![],
+        };
+
+        let mut problems = vec![];
+        if info.name.is_empty() {
+            problems.push("name is empty".to_string());
+        }
+        if let Err(e) = tanoshi_lib::version::parse(&info.version) {
+            problems.push(e.to_string());
+        }
+        check_url(&mut problems, "url", &info.url);
+        report.push("info", problems);
+
+        let mangas = match self
+            .extension
+            .get_mangas(self.params.clone(), self.auth.clone())
+        {
+            Ok(mangas) => mangas,
+            Err(e) => {
+                report.push("get_mangas", vec![e.to_string()]);
+                return report;
+            }
+        };
+        report.push("get_mangas", check_mangas(&mangas));
+
+        for manga in mangas
+            .iter()
+            .filter(|m| !m.path.is_empty())
+            .take(self.samples)
+        {
+            let name = format!("get_manga_info {}", manga.path);
+            match self
+                .extension
+                .get_manga_info(&manga.path, self.auth.clone())
+            {
+                Ok(info) => report.push(&name, check_manga(&info)),
+                Err(e) => report.push(&name, vec![e.to_string()]),
+            }
+
+            let name = format!("get_chapters {}", manga.path);
+            let chapters = match self.extension.get_chapters(&manga.path, self.auth.clone()) {
+                Ok(chapters) => chapters,
+                Err(e) => {
+                    report.push(&name, vec![e.to_string()]);
+                    continue;
+                }
+            };
+            report.push(&name, check_chapters(&chapters));
+
+            for chapter in chapters
+                .iter()
+                .filter(|c| !c.path.is_empty())
+                .take(self.samples)
+            {
+                let name = format!("get_pages {}", chapter.path);
+                let pages = match self.extension.get_pages(&chapter.path, self.auth.clone()) {
+                    Ok(pages) => pages,
+                    Err(e) => {
+                        report.push(&name, vec![e.to_string()]);
+                        continue;
+                    }
+                };
+                report.push(&name, check_pages(&pages));
+
+                if let Some(page) = pages.first() {
+                    let name = format!("get_page {}", page);
+                    match self.extension.get_page(page, self.auth.clone()) {
+                        Ok(image) if image.is_empty() => {
+                            report.push(&name, vec!["image is empty".to_string()])
+                        }
+                        Ok(_) => report.push(&name, vec![]),
+                        Err(e) => report.push(&name, vec![e.to_string()]),
+                    }
+                }
+            }
+        }
+
+        report
+    }
+}
+
+fn check_url(problems: &mut Vec<String>, what: &str, value: &str) {
+    match url::Url::parse(value) {
+        Ok(url) if (url.scheme() == "http" || url.scheme() == "https") && url.has_host() => {}
+        _ => problems.push(format!(
+            "{} {:?} is not an absolute http(s) url",
+            what, value
+        )),
+    }
+}
+
+fn check_manga(manga: &Manga) -> Vec<String> {
+    let mut problems = vec![];
+    if manga.path.is_empty() {
+        problems.push(format!("path of {:?} is empty", manga.title));
+    }
+    if manga.title.is_empty() {
+        problems.push(format!("title of {} is empty", manga.path));
+    }
+    if !manga.thumbnail_url.is_empty() {
+        check_url(
+            &mut problems,
+            &format!("thumbnail of {}", manga.path),
+            &manga.thumbnail_url,
+        );
+    }
+    problems
+}
+
+fn check_mangas(mangas: &[Manga]) -> Vec<String> {
+    if mangas.is_empty() {
+        return vec!["no manga".to_string()];
+    }
+    let mut problems: Vec<String> = mangas.iter().flat_map(check_manga).collect();
+    let mut paths = HashSet::new();
+    for manga in mangas {
+        if !manga.path.is_empty() && !paths.insert(&manga.path) {
+            problems.push(format!("path {} is listed more than once", manga.path));
+        }
+    }
+    problems
+}
+
+fn check_chapters(chapters: &[Chapter]) -> Vec<String> {
+    if chapters.is_empty() {
+        return vec!["no chapter".to_string()];
+    }
+    let oldest = NaiveDate::from_ymd(1990, 1, 1).and_hms(0, 0, 0);
+    let newest = Utc::now().naive_utc() + Duration::days(1);
+    let mut problems = vec![];
+    let mut paths = HashSet::new();
+    for chapter in chapters {
+        if chapter.path.is_empty() {
+            problems.push(format!("path of chapter {:?} is empty", chapter.no));
+        } else if !paths.insert(&chapter.path) {
+            problems.push(format!("path {} is listed more than once", chapter.path));
+        }
+        if let Some(no) = &chapter.no {
+            if no.trim().parse::<f64>().is_err() {
+                problems.push(format!(
+                    "number {:?} of {} is not a number",
+                    no, chapter.path
+                ));
+            }
+        }
+        if chapter.uploaded < oldest || chapter.uploaded > newest {
+            problems.push(format!(
+                "{} is uploaded at {}, expected between {} and now",
+                chapter.path, chapter.uploaded, oldest
+            ));
+        }
+    }
+    problems
+}
+
+fn check_pages(pages: &[String]) -> Vec<String> {
+    if pages.is_empty() {
+        return vec!["no page".to_string()];
+    }
+    let mut problems = vec![];
+    for page in pages {
+        check_url(&mut problems, "page", page);
+    }
+    problems
+}
+
+/// Result of a step of the suite, it passed when there is no problem
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub problems: Vec<String>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Results of the suite for a source, prints one line for each check
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub source: String,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, name: &str, problems: Vec<String>) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            problems,
+        });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(Check::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.passed())
+    }
+
+    /// Panics with the report when a check failed, to use in `#[test]`
+    pub fn assert(&self) {
+        if !self.passed() {
+            panic!("{} failed conformance checks\n{}", self.source, self);
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in self.checks.iter() {
+            if check.passed() {
+                writeln!(f, "ok   {}", check.name)?;
+            } else {
+                writeln!(f, "FAIL {}", check.name)?;
+                for problem in check.problems.iter() {
+                    writeln!(f, "     {}", problem)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use tanoshi_extension_test::{Fixtures, Suite};
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::http::Http;
+use tanoshi_lib::manga::{Chapter, Manga, Params, Source};
+
+static FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/example");
+static API: &str = "https://example.com/api";
+
+#[derive(Deserialize)]
+struct ExampleManga {
+    slug: String,
+    title: String,
+    cover: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ExampleChapter {
+    id: String,
+    number: String,
+    title: String,
+    date: chrono::NaiveDateTime,
+}
+
+/// A source with a JSON api, its responses are recorded in `tests/fixtures/example`
+struct Example {
+    http: Http,
+}
+
+impl Example {
+    fn manga(manga: ExampleManga) -> Manga {
+        Manga {
+            source: "example".to_string(),
+            title: manga.title,
+            author: manga.authors,
+            description: manga.description,
+            path: manga.slug,
+            thumbnail_url: manga.cover,
+            ..Default::default()
+        }
+    }
+}
+
+impl Extension for Example {
+    fn info(&self) -> Source {
+        Source {
+            name: "example".to_string(),
+            url: "https://example.com".to_string(),
+            version: "0.1.0".to_string(),
+            rate_limit: None,
+        }
+    }
+
+    fn get_mangas(&self, param: Params, _auth: String) -> Result<Vec<Manga>> {
+        let url = match param.keyword {
+            Some(keyword) => format!("{}/search?q={}", API, keyword),
+            None => format!(
+                "{}/mangas?page={}",
+                API,
+                param.page.as_deref().unwrap_or("1")
+            ),
+        };
+        let mangas: Vec<ExampleManga> = self.http.get(&url)?.error_for_status()?.json()?;
+        Ok(mangas.into_iter().map(Example::manga).collect())
+    }
+
+    fn get_manga_info(&self, path: &str, _auth: String) -> Result<Manga> {
+        let url = format!("{}/manga/{}", API, path);
+        Ok(Example::manga(
+            self.http.get(&url)?.error_for_status()?.json()?,
+        ))
+    }
+
+    fn get_chapters(&self, path: &str, _auth: String) -> Result<Vec<Chapter>> {
+        let url = format!("{}/manga/{}/chapters", API, path);
+        let chapters: Vec<ExampleChapter> = self.http.get(&url)?.error_for_status()?.json()?;
+        Ok(chapters
+            .into_iter()
+            .map(|chapter| Chapter {
+                source: "example".to_string(),
+                no: Some(chapter.number),
+                title: Some(chapter.title),
+                path: chapter.id,
+                uploaded: chapter.date,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn get_pages(&self, path: &str, _auth: String) -> Result<Vec<String>> {
+        let url = format!("{}/chapter/{}", API, path);
+        self.http.get(&url)?.error_for_status()?.json()
+    }
+}
+
+fn example() -> Example {
+    let fixtures = Fixtures::replay(FIXTURES).unwrap();
+    fixtures.set_default();
+    Example {
+        http: fixtures.http(),
+    }
+}
+
+#[test]
+fn suite_passes_with_recorded_responses() {
+    let extension = example();
+    let report = Suite::new(&extension).run();
+    report.assert();
+
+    let checks: Vec<&str> = report
+        .checks
+        .iter()
+        .map(|check| check.name.as_str())
+        .collect();
+    assert_eq!(
+        checks,
+        vec![
+            "info",
+            "get_mangas",
+            "get_manga_info one",
+            "get_chapters one",
+            "get_pages 1",
+            "get_page https://example.com/img/1-1.png",
+        ]
+    );
+}
+
+#[test]
+fn request_without_fixture_fails() {
+    let extension = example();
+    let report = Suite::new(&extension).keyword("three").run();
+    assert!(!report.passed());
+
+    let failures: Vec<_> = report.failures().collect();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].name, "get_mangas");
+    assert!(failures[0].problems[0]
+        .contains("no fixture for GET https://example.com/api/search?q=three"));
+}
@@ -0,0 +1,782 @@
+//! FFI-safe plugin interface.
+//!
+//! Rust has no stable ABI, so extensions and tanoshi never exchange Rust types directly.
+//! Every call goes through `#[repr(C)]` function tables, arguments and results are passed as
+//! JSON encoded bytes, and memory is always released by the side that allocated it.
+//! This allows extensions built with a different compiler to be loaded, as long as
+//! both sides agree on [`INTERFACE_VERSION`](crate::INTERFACE_VERSION).
+//!
+//! Extensions don't need to use this module directly, [`export_plugin!`](crate::export_plugin)
+//! generates everything needed from a `PluginRegistrar` based register function.
+use crate::extensions::{Extension, Panicked, PluginRegistrar};
+use crate::http::{Http, HttpClient, Request, Response};
+use crate::manga::{
+    Chapter, Filters, Manga, Params, Preferences, Source, SourceLogin, SourceLoginResult,
+};
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Bytes borrowed from the caller, only valid for the duration of a call
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Slice {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl Slice {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+
+    /// # Safety
+    /// `ptr` must point to `len` bytes that are valid for the lifetime of the returned slice
+    pub unsafe fn as_slice<'a>(&self) -> &'a [u8] {
+        if self.ptr.is_null() || self.len == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(self.ptr, self.len)
+    }
+}
+
+/// A `&'static str` that can be stored in a `#[repr(C)]` static
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct StaticStr {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl StaticStr {
+    pub const fn new(s: &'static str) -> Self {
+        Self {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        }
+    }
+
+    /// # Safety
+    /// must be created by [`StaticStr::new`] in a library that is still loaded
+    pub unsafe fn to_string(&self) -> String {
+        String::from_utf8_lossy(
+            Slice {
+                ptr: self.ptr,
+                len: self.len,
+            }
+            .as_slice(),
+        )
+        .to_string()
+    }
+}
+
+unsafe impl Sync for StaticStr {}
+
+/// Bytes owned by the side that allocated them, must be released with that side's `free_buffer`
+#[repr(C)]
+pub struct Buffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub cap: usize,
+}
+
+impl Buffer {
+    pub fn from_vec(bytes: Vec<u8>) -> Self {
+        let mut bytes = std::mem::ManuallyDrop::new(bytes);
+        Self {
+            ptr: bytes.as_mut_ptr(),
+            len: bytes.len(),
+            cap: bytes.capacity(),
+        }
+    }
+
+    /// # Safety
+    /// must only be called by the side that created the buffer with [`Buffer::from_vec`]
+    pub unsafe fn into_vec(self) -> Vec<u8> {
+        Vec::from_raw_parts(self.ptr, self.len, self.cap)
+    }
+}
+
+/// Status of an extension call, passed across the ABI as the `u32` of [`CallResult`]
+/// so a value unknown to the receiving side is an error instead of an invalid enum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// `data` contains the result
+    Ok = 0,
+    /// `data` contains an utf-8 error message
+    Err = 1,
+    /// The call panicked, `data` contains the utf-8 panic message
+    Panic = 2,
+}
+
+impl TryFrom<u32> for Status {
+    type Error = anyhow::Error;
+
+    fn try_from(status: u32) -> Result<Self> {
+        match status {
+            0 => Ok(Status::Ok),
+            1 => Ok(Status::Err),
+            2 => Ok(Status::Panic),
+            status => Err(anyhow!("unknown call status {}", status)),
+        }
+    }
+}
+
+/// Result of an extension call
+#[repr(C)]
+pub struct CallResult {
+    /// A [`Status`]
+    pub status: u32,
+    pub data: Buffer,
+}
+
+impl CallResult {
+    fn new(status: Status, bytes: Vec<u8>) -> Self {
+        Self {
+            status: status as u32,
+            data: Buffer::from_vec(bytes),
+        }
+    }
+
+    fn ok(bytes: Vec<u8>) -> Self {
+        Self::new(Status::Ok, bytes)
+    }
+
+    fn err(message: String) -> Self {
+        Self::new(Status::Err, message.into_bytes())
+    }
+
+    fn panic(message: String) -> Self {
+        Self::new(Status::Panic, message.into_bytes())
+    }
+}
+
+/// Function table of a single extension
+#[repr(C)]
+pub struct ExtensionVTable {
+    pub instance: *mut c_void,
+    pub info: unsafe extern "C" fn(*const c_void) -> CallResult,
+    pub filters: unsafe extern "C" fn(*const c_void) -> CallResult,
+    pub preferences: unsafe extern "C" fn(*const c_void) -> CallResult,
+    /// Takes a JSON encoded [`GetMangasArgs`]
+    pub get_mangas: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+    /// Takes a JSON encoded [`PathArgs`]
+    pub get_manga_info: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+    /// Takes a JSON encoded [`PathArgs`]
+    pub get_chapters: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+    /// Takes a JSON encoded [`PathArgs`]
+    pub get_pages: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+    /// Takes a JSON encoded [`PathArgs`] with the url as path, returns raw image bytes
+    pub get_page: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+    /// Takes a JSON encoded [`SourceLogin`]
+    pub login: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+    pub free_buffer: unsafe extern "C" fn(Buffer),
+    pub drop: unsafe extern "C" fn(*mut c_void),
+}
+
+/// Function table of an HTTP client tanoshi provides to an extension
+#[repr(C)]
+pub struct HttpVTable {
+    pub instance: *mut c_void,
+    /// Takes a [`Request`] encoded with `Request::to_bytes`, returns an encoded [`Response`]
+    pub send: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+    pub free_buffer: unsafe extern "C" fn(Buffer),
+    pub drop: unsafe extern "C" fn(*mut c_void),
+}
+
+/// Callbacks used by extensions to hand their function tables to tanoshi
+#[repr(C)]
+pub struct Registrar {
+    pub context: *mut c_void,
+    pub register: unsafe extern "C" fn(*mut c_void, Slice, ExtensionVTable),
+    /// Takes the name of a source, returns its HTTP client
+    pub http: unsafe extern "C" fn(*mut c_void, Slice) -> HttpVTable,
+    /// Takes an utf-8 message, registering failed and the plugin is not loaded
+    pub error: unsafe extern "C" fn(*mut c_void, Slice),
+}
+
+/// Exported by every extension as `tanoshi_plugin_declaration`
+#[repr(C)]
+pub struct PluginDeclaration {
+    /// Must stay the first field, it is read before anything else
+    pub interface_version: u32,
+    pub core_version: StaticStr,
+    pub name: StaticStr,
+    /// Takes YAML encoded plugin config, an empty slice means no config
+    pub register: unsafe extern "C" fn(*mut Registrar, Slice),
+}
+
+unsafe impl Sync for PluginDeclaration {}
+
+/// Symbol name of [`PluginDeclaration`]
+pub static DECLARATION_SYMBOL: &[u8] = b"tanoshi_plugin_declaration\0";
+
+/// Arguments of `get_mangas`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetMangasArgs {
+    pub param: Params,
+    pub auth: String,
+}
+
+/// Arguments of `get_manga_info`, `get_chapters`, `get_pages` and `get_page`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PathArgs {
+    pub path: String,
+    pub auth: String,
+}
+
+fn call<F>(instance: *const c_void, f: F) -> CallResult
+where
+    F: FnOnce(&dyn Extension) -> Result<Vec<u8>>,
+{
+    let extension = unsafe { &*(instance as *const Box<dyn Extension>) };
+    catch_call(|| f(extension.as_ref()))
+}
+
+fn catch_call<F>(f: F) -> CallResult
+where
+    F: FnOnce() -> Result<Vec<u8>>,
+{
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(bytes)) => CallResult::ok(bytes),
+        Ok(Err(e)) => CallResult::err(e.to_string()),
+        Err(panic) => CallResult::panic(panic_message(panic)),
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+fn decode<T: DeserializeOwned>(args: Slice) -> Result<T> {
+    Ok(serde_json::from_slice(unsafe { args.as_slice() })?)
+}
+
+unsafe extern "C" fn ext_info(instance: *const c_void) -> CallResult {
+    call(instance, |ext| Ok(serde_json::to_vec(&ext.info())?))
+}
+
+unsafe extern "C" fn ext_filters(instance: *const c_void) -> CallResult {
+    call(instance, |ext| Ok(serde_json::to_vec(&ext.filters())?))
+}
+
+unsafe extern "C" fn ext_preferences(instance: *const c_void) -> CallResult {
+    call(instance, |ext| Ok(serde_json::to_vec(&ext.preferences())?))
+}
+
+unsafe extern "C" fn ext_get_mangas(instance: *const c_void, args: Slice) -> CallResult {
+    call(instance, |ext| {
+        let args: GetMangasArgs = decode(args)?;
+        Ok(serde_json::to_vec(&ext.get_mangas(args.param, args.auth)?)?)
+    })
+}
+
+unsafe extern "C" fn ext_get_manga_info(instance: *const c_void, args: Slice) -> CallResult {
+    call(instance, |ext| {
+        let args: PathArgs = decode(args)?;
+        Ok(serde_json::to_vec(
+            &ext.get_manga_info(&args.path, args.auth)?,
+        )?)
+    })
+}
+
+unsafe extern "C" fn ext_get_chapters(instance: *const c_void, args: Slice) -> CallResult {
+    call(instance, |ext| {
+        let args: PathArgs = decode(args)?;
+        Ok(serde_json::to_vec(
+            &ext.get_chapters(&args.path, args.auth)?,
+        )?)
+    })
+}
+
+unsafe extern "C" fn ext_get_pages(instance: *const c_void, args: Slice) -> CallResult {
+    call(instance, |ext| {
+        let args: PathArgs = decode(args)?;
+        Ok(serde_json::to_vec(&ext.get_pages(&args.path, args.auth)?)?)
+    })
+}
+
+unsafe extern "C" fn ext_get_page(instance: *const c_void, args: Slice) -> CallResult {
+    call(instance, |ext| {
+        let args: PathArgs = decode(args)?;
+        ext.get_page(&args.path, args.auth)
+    })
+}
+
+unsafe extern "C" fn ext_login(instance: *const c_void, args: Slice) -> CallResult {
+    call(instance, |ext| {
+        let login: SourceLogin = decode(args)?;
+        Ok(serde_json::to_vec(&ext.login(login)?)?)
+    })
+}
+
+unsafe extern "C" fn ext_free_buffer(buffer: Buffer) {
+    drop(buffer.into_vec());
+}
+
+unsafe extern "C" fn ext_drop(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut Box<dyn Extension>));
+}
+
+impl ExtensionVTable {
+    /// Wraps an extension into a function table, the extension is dropped with `drop`
+    pub fn new(extension: Box<dyn Extension>) -> Self {
+        Self {
+            instance: Box::into_raw(Box::new(extension)) as *mut c_void,
+            info: ext_info,
+            filters: ext_filters,
+            preferences: ext_preferences,
+            get_mangas: ext_get_mangas,
+            get_manga_info: ext_get_manga_info,
+            get_chapters: ext_get_chapters,
+            get_pages: ext_get_pages,
+            get_page: ext_get_page,
+            login: ext_login,
+            free_buffer: ext_free_buffer,
+            drop: ext_drop,
+        }
+    }
+}
+
+unsafe extern "C" fn http_send(instance: *const c_void, args: Slice) -> CallResult {
+    let http = &*(instance as *const Http);
+    catch_call(|| {
+        let request = Request::from_bytes(args.as_slice())?;
+        http.send(request)?.to_bytes()
+    })
+}
+
+unsafe extern "C" fn http_free_buffer(buffer: Buffer) {
+    drop(buffer.into_vec());
+}
+
+unsafe extern "C" fn http_drop(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut Http));
+}
+
+impl HttpVTable {
+    /// Wraps an HTTP client into a function table, the client is dropped with `drop`
+    pub fn new(http: Http) -> Self {
+        Self {
+            instance: Box::into_raw(Box::new(http)) as *mut c_void,
+            send: http_send,
+            free_buffer: http_free_buffer,
+            drop: http_drop,
+        }
+    }
+}
+
+/// An HTTP client behind a function table, provided by tanoshi
+struct FfiHttpClient {
+    vtable: HttpVTable,
+}
+
+unsafe impl Send for FfiHttpClient {}
+unsafe impl Sync for FfiHttpClient {}
+
+impl HttpClient for FfiHttpClient {
+    fn send(&self, request: Request) -> Result<Response> {
+        let args = request.to_bytes()?;
+        let result = unsafe { (self.vtable.send)(self.vtable.instance, Slice::new(&args)) };
+        let bytes = unsafe {
+            let bytes = Slice {
+                ptr: result.data.ptr,
+                len: result.data.len,
+            }
+            .as_slice()
+            .to_vec();
+            (self.vtable.free_buffer)(result.data);
+            bytes
+        };
+        match Status::try_from(result.status)? {
+            Status::Ok => Response::from_bytes(&bytes),
+            Status::Err => Err(anyhow!("{}", String::from_utf8_lossy(&bytes))),
+            Status::Panic => Err(anyhow!(
+                "http client panicked: {}",
+                String::from_utf8_lossy(&bytes)
+            )),
+        }
+    }
+}
+
+impl Drop for FfiHttpClient {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.drop)(self.vtable.instance) }
+    }
+}
+
+struct ExportRegistrar {
+    registrar: *mut Registrar,
+}
+
+impl PluginRegistrar for ExportRegistrar {
+    fn register_function(&mut self, name: &str, extension: Box<dyn Extension>) {
+        unsafe {
+            let registrar = &*self.registrar;
+            (registrar.register)(
+                registrar.context,
+                Slice::new(name.as_bytes()),
+                ExtensionVTable::new(extension),
+            );
+        }
+    }
+
+    fn http(&self, source: &str) -> Http {
+        let vtable = unsafe {
+            let registrar = &*self.registrar;
+            (registrar.http)(registrar.context, Slice::new(source.as_bytes()))
+        };
+        Http::new(std::sync::Arc::new(FfiHttpClient { vtable }))
+    }
+}
+
+/// Called by the code generated by [`export_plugin!`](crate::export_plugin)
+///
+/// # Safety
+/// `registrar` must be a valid pointer for the duration of the call
+#[doc(hidden)]
+pub unsafe fn register_plugin(
+    registrar: *mut Registrar,
+    config: Slice,
+    name: &str,
+    register: unsafe fn(&mut dyn PluginRegistrar, Option<&serde_yaml::Value>),
+) {
+    let config = match config.as_slice() {
+        [] => None,
+        bytes => serde_yaml::from_slice::<serde_yaml::Value>(bytes).ok(),
+    };
+    let mut export = ExportRegistrar { registrar };
+    crate::http::set_default(export.http(name));
+    if let Err(panic) = catch_unwind(AssertUnwindSafe(|| {
+        register(&mut export, config.as_ref());
+    })) {
+        let message = format!(
+            "{} panicked while registering: {}",
+            name,
+            panic_message(panic)
+        );
+        let registrar = &*registrar;
+        (registrar.error)(registrar.context, Slice::new(message.as_bytes()));
+    }
+}
+
+/// An extension behind a function table, usually loaded from a dynamic library
+///
+/// The library the function table comes from must outlive this value.
+/// `info` is asked once when the extension is registered.
+pub struct FfiExtension {
+    vtable: ExtensionVTable,
+    info: Source,
+}
+
+unsafe impl Send for FfiExtension {}
+unsafe impl Sync for FfiExtension {}
+
+impl FfiExtension {
+    fn result(&self, result: CallResult) -> Result<Vec<u8>> {
+        let status = Status::try_from(result.status);
+        let bytes = unsafe {
+            let bytes = Slice {
+                ptr: result.data.ptr,
+                len: result.data.len,
+            }
+            .as_slice()
+            .to_vec();
+            (self.vtable.free_buffer)(result.data);
+            bytes
+        };
+        match status? {
+            Status::Ok => Ok(bytes),
+            Status::Err => Err(anyhow!("{}", String::from_utf8_lossy(&bytes))),
+            Status::Panic => Err(Panicked(String::from_utf8_lossy(&bytes).to_string()).into()),
+        }
+    }
+
+    fn call<A, T>(
+        &self,
+        f: unsafe extern "C" fn(*const c_void, Slice) -> CallResult,
+        args: &A,
+    ) -> Result<T>
+    where
+        A: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let args = serde_json::to_vec(args)?;
+        let result = unsafe { f(self.vtable.instance, Slice::new(&args)) };
+        Ok(serde_json::from_slice(&self.result(result)?)?)
+    }
+}
+
+impl Drop for FfiExtension {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.drop)(self.vtable.instance) }
+    }
+}
+
+impl Extension for FfiExtension {
+    fn info(&self) -> Source {
+        self.info.clone()
+    }
+
+    fn filters(&self) -> Filters {
+        let result = unsafe { (self.vtable.filters)(self.vtable.instance) };
+        self.result(result)
+            .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            .unwrap_or_default()
+    }
+
+    fn preferences(&self) -> Preferences {
+        let result = unsafe { (self.vtable.preferences)(self.vtable.instance) };
+        self.result(result)
+            .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            .unwrap_or_default()
+    }
+
+    fn get_mangas(&self, param: Params, auth: String) -> Result<Vec<Manga>> {
+        self.call(self.vtable.get_mangas, &GetMangasArgs { param, auth })
+    }
+
+    fn get_manga_info(&self, path: &str, auth: String) -> Result<Manga> {
+        let path = path.to_string();
+        self.call(self.vtable.get_manga_info, &PathArgs { path, auth })
+    }
+
+    fn get_chapters(&self, path: &str, auth: String) -> Result<Vec<Chapter>> {
+        let path = path.to_string();
+        self.call(self.vtable.get_chapters, &PathArgs { path, auth })
+    }
+
+    fn get_pages(&self, path: &str, auth: String) -> Result<Vec<String>> {
+        let path = path.to_string();
+        self.call(self.vtable.get_pages, &PathArgs { path, auth })
+    }
+
+    fn get_page(&self, url: &str, auth: String) -> Result<Vec<u8>> {
+        let path = url.to_string();
+        let args = serde_json::to_vec(&PathArgs { path, auth })?;
+        let result = unsafe { (self.vtable.get_page)(self.vtable.instance, Slice::new(&args)) };
+        self.result(result)
+    }
+
+    fn login(&self, login: SourceLogin) -> Result<SourceLoginResult> {
+        self.call(self.vtable.login, &login)
+    }
+}
+
+/// Context of [`Registrar`] on tanoshi side
+struct Collector<'a> {
+    extensions: Vec<(String, FfiExtension)>,
+    http: &'a dyn Fn(&str) -> Http,
+    error: Option<String>,
+}
+
+unsafe extern "C" fn collect_extension(context: *mut c_void, name: Slice, vtable: ExtensionVTable) {
+    let collector = &mut *(context as *mut Collector);
+    let name = String::from_utf8_lossy(name.as_slice()).to_string();
+    let info = Source::default();
+    collector
+        .extensions
+        .push((name, FfiExtension { vtable, info }));
+}
+
+unsafe extern "C" fn collect_error(context: *mut c_void, message: Slice) {
+    let collector = &mut *(context as *mut Collector);
+    collector.error = Some(String::from_utf8_lossy(message.as_slice()).to_string());
+}
+
+unsafe extern "C" fn provide_http(context: *mut c_void, source: Slice) -> HttpVTable {
+    let collector = &*(context as *const Collector);
+    let source = String::from_utf8_lossy(source.as_slice());
+    HttpVTable::new((collector.http)(&source))
+}
+
+/// Registers every extension of a plugin declaration, refusing declarations
+/// built against a different interface version. `http` returns the client of a source.
+///
+/// # Safety
+/// `declaration` must point to a `tanoshi_plugin_declaration` of a library that outlives
+/// the returned extensions
+pub unsafe fn register_extensions(
+    declaration: *const PluginDeclaration,
+    config: Option<&serde_yaml::Value>,
+    http: &dyn Fn(&str) -> Http,
+) -> Result<Vec<(String, FfiExtension)>> {
+    let interface_version = std::ptr::read(declaration as *const u32);
+    if interface_version != crate::INTERFACE_VERSION {
+        return Err(anyhow!(
+            "interface version mismatch: extension={}, tanoshi={}",
+            interface_version,
+            crate::INTERFACE_VERSION
+        ));
+    }
+
+    let declaration = &*declaration;
+    let config = match config {
+        Some(config) => serde_yaml::to_string(config)?.into_bytes(),
+        None => vec![],
+    };
+
+    let mut collector = Collector {
+        extensions: vec![],
+        http,
+        error: None,
+    };
+    let mut registrar = Registrar {
+        context: &mut collector as *mut Collector as *mut c_void,
+        register: collect_extension,
+        http: provide_http,
+        error: collect_error,
+    };
+    (declaration.register)(&mut registrar, Slice::new(&config));
+
+    if let Some(error) = collector.error {
+        return Err(anyhow!("{}", error));
+    }
+    for (name, extension) in collector.extensions.iter_mut() {
+        let result = (extension.vtable.info)(extension.vtable.instance);
+        extension.info = extension
+            .result(result)
+            .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            .map_err(|e| anyhow!("failed to get info of {}: {}", name, e))?;
+    }
+
+    Ok(collector.extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Example {
+        broken_info: bool,
+    }
+
+    impl Extension for Example {
+        fn info(&self) -> Source {
+            if self.broken_info {
+                panic!("no info");
+            }
+            Source {
+                name: "example".to_string(),
+                url: "https://example.com".to_string(),
+                version: "0.1.0".to_string(),
+                rate_limit: None,
+            }
+        }
+
+        fn get_mangas(&self, _param: Params, _auth: String) -> Result<Vec<Manga>> {
+            Ok(vec![Manga {
+                title: "One".to_string(),
+                ..Default::default()
+            }])
+        }
+
+        fn get_manga_info(&self, path: &str, _auth: String) -> Result<Manga> {
+            Err(anyhow!("{} not found", path))
+        }
+
+        fn get_chapters(&self, _path: &str, _auth: String) -> Result<Vec<Chapter>> {
+            panic!("broken")
+        }
+
+        fn get_pages(&self, _path: &str, _auth: String) -> Result<Vec<String>> {
+            Ok(vec![])
+        }
+    }
+
+    unsafe fn register(registrar: &mut dyn PluginRegistrar, config: Option<&serde_yaml::Value>) {
+        match config.and_then(|config| config.as_str()) {
+            Some("panic") => panic!("invalid config"),
+            Some("broken info") => {
+                registrar.register_function("example", Box::new(Example { broken_info: true }))
+            }
+            _ => registrar.register_function("example", Box::new(Example { broken_info: false })),
+        }
+    }
+
+    unsafe extern "C" fn register_example(registrar: *mut Registrar, config: Slice) {
+        register_plugin(registrar, config, "example", register)
+    }
+
+    static DECLARATION: PluginDeclaration = PluginDeclaration {
+        interface_version: crate::INTERFACE_VERSION,
+        core_version: StaticStr::new(crate::CORE_VERSION),
+        name: StaticStr::new("example"),
+        register: register_example,
+    };
+
+    fn load(config: Option<&str>) -> Result<Vec<(String, FfiExtension)>> {
+        let config = config.map(|config| serde_yaml::Value::String(config.to_string()));
+        unsafe { register_extensions(&DECLARATION, config.as_ref(), &|_| Http::default()) }
+    }
+
+    #[test]
+    fn status_from_u32() {
+        assert_eq!(Status::try_from(0).unwrap(), Status::Ok);
+        assert_eq!(Status::try_from(1).unwrap(), Status::Err);
+        assert_eq!(Status::try_from(2).unwrap(), Status::Panic);
+        assert!(Status::try_from(3).is_err());
+        assert!(Status::try_from(u32::MAX).is_err());
+    }
+
+    #[test]
+    fn call_through_vtable() {
+        let extensions = load(None).unwrap();
+        assert_eq!(extensions.len(), 1);
+        let (name, extension) = &extensions[0];
+        assert_eq!(name, "example");
+        assert_eq!(extension.info().name, "example");
+
+        let mangas = extension
+            .get_mangas(Params::default(), String::new())
+            .unwrap();
+        assert_eq!(mangas[0].title, "One");
+
+        let e = extension.get_manga_info("two", String::new()).unwrap_err();
+        assert!(!e.is::<Panicked>());
+        assert_eq!(e.to_string(), "two not found");
+
+        let e = extension.get_chapters("one", String::new()).unwrap_err();
+        assert!(e.is::<Panicked>());
+        assert_eq!(e.to_string(), "extension panicked: broken");
+    }
+
+    #[test]
+    fn unknown_status_is_an_error() {
+        let extensions = load(None).unwrap();
+        let extension = &extensions[0].1;
+        let mut result = CallResult::ok(b"[]".to_vec());
+        result.status = 7;
+        assert_eq!(
+            extension.result(result).unwrap_err().to_string(),
+            "unknown call status 7"
+        );
+    }
+
+    #[test]
+    fn register_panic_is_an_error() {
+        let e = load(Some("panic")).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "example panicked while registering: invalid config"
+        );
+    }
+
+    #[test]
+    fn info_panic_is_an_error() {
+        let e = load(Some("broken info")).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "failed to get info of example: extension panicked: no info"
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! HTTP client for extensions.
+//!
+//! Tanoshi gives every source a client while registering it, configured with the timeouts,
+//! user agent, proxy and cookie jar of that source, so extensions don't need networking of their own.
+//!
+//! ```ignore
+//! unsafe fn register(registrar: &mut dyn PluginRegistrar, config: Option<&serde_yaml::Value>) {
+//!     let http = registrar.http("mangadex");
+//!     registrar.register_function("mangadex", Box::new(Mangadex::new(http, config)));
+//! }
+//!
+//! let resp = self.http.get("https://mangadex.org/api/manga/1")?.error_for_status()?;
+//! let manga: MangaResponse = resp.json()?;
+//! ```
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// Redirects to follow, `None` follows as many as tanoshi allows
+    #[serde(default)]
+    pub redirects: Option<u32>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Self {
+        Self {
+            method: method.to_uppercase(),
+            url: url.to_string(),
+            headers: vec![],
+            redirects: None,
+            body: vec![],
+        }
+    }
+
+    pub fn get(url: &str) -> Self {
+        Self::new("GET", url)
+    }
+
+    pub fn post(url: &str) -> Self {
+        Self::new("POST", url)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn redirects(mut self, redirects: u32) -> Self {
+        self.redirects = Some(redirects);
+        self
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        frame(self, &self.body)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (mut request, body): (Self, Vec<u8>) = unframe(bytes)?;
+        request.body = body;
+        Ok(request)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Response {
+    pub status: u16,
+    /// Url of the response, after redirects
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns the first header named `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+
+    /// Returns an error for 4xx and 5xx status
+    pub fn error_for_status(self) -> Result<Self> {
+        if self.status >= 400 {
+            return Err(anyhow!("{} returned status {}", self.url, self.status));
+        }
+        Ok(self)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        frame(self, &self.body)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (mut response, body): (Self, Vec<u8>) = unframe(bytes)?;
+        response.body = body;
+        Ok(response)
+    }
+}
+
+/// The head is JSON prefixed with its length as little endian `u32`, the body follows as is,
+/// so images don't have to be encoded
+fn frame<H: Serialize>(head: &H, body: &[u8]) -> Result<Vec<u8>> {
+    let head = serde_json::to_vec(head)?;
+    let mut bytes = Vec::with_capacity(4 + head.len() + body.len());
+    bytes.extend_from_slice(&(head.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&head);
+    bytes.extend_from_slice(body);
+    Ok(bytes)
+}
+
+fn unframe<H: DeserializeOwned>(bytes: &[u8]) -> Result<(H, Vec<u8>)> {
+    if bytes.len() < 4 {
+        return Err(anyhow!("malformed http message"));
+    }
+    let (len, rest) = bytes.split_at(4);
+    let len = u32::from_le_bytes(len.try_into()?) as usize;
+    if rest.len() < len {
+        return Err(anyhow!("malformed http message"));
+    }
+    let (head, body) = rest.split_at(len);
+    Ok((serde_json::from_slice(head)?, body.to_vec()))
+}
+
+/// Sends requests of an extension, implemented by tanoshi
+pub trait HttpClient: Send + Sync {
+    fn send(&self, request: Request) -> Result<Response>;
+}
+
+/// Handle to the HTTP client of a source, cheap to clone
+#[derive(Clone)]
+pub struct Http(Arc<dyn HttpClient>);
+
+impl Http {
+    pub fn new(client: Arc<dyn HttpClient>) -> Self {
+        Self(client)
+    }
+
+    pub fn send(&self, request: Request) -> Result<Response> {
+        self.0.send(request)
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response> {
+        self.send(Request::get(url))
+    }
+}
+
+/// Client of the plugin when no client is asked for a specific source
+static DEFAULT: RwLock<Option<Http>> = RwLock::new(None);
+
+#[doc(hidden)]
+pub fn set_default(http: Http) {
+    if let Ok(mut default) = DEFAULT.write() {
+        *default = Some(http);
+    }
+}
+
+/// The client tanoshi gave the plugin, a client without any configuration
+/// when the extension runs outside of tanoshi, e.g in tests
+impl Default for Http {
+    fn default() -> Self {
+        match DEFAULT.read().ok().and_then(|default| default.clone()) {
+            Some(http) => http,
+            None => Http::new(Arc::new(Plain)),
+        }
+    }
+}
+
+struct Plain;
+
+impl HttpClient for Plain {
+    fn send(&self, request: Request) -> Result<Response> {
+        let mut req = ureq::request(&request.method, &request.url);
+        if let Some(redirects) = request.redirects {
+            req.redirects(redirects);
+        }
+        for (name, value) in request.headers.iter() {
+            req.set(name, value);
+        }
+        let resp = if request.body.is_empty() {
+            req.call()
+        } else {
+            req.send_bytes(&request.body)
+        };
+        if let Some(e) = resp.synthetic_error() {
+            return Err(anyhow!("{}", e));
+        }
+
+        let mut response = Response {
+            status: resp.status(),
+            url: resp.get_url().to_string(),
+            headers: resp
+                .headers_names()
+                .into_iter()
+                .filter_map(|name| {
+                    let value = resp.header(&name)?.to_string();
+                    Some((name, value))
+                })
+                .collect(),
+            body: vec![],
+        };
+        resp.into_reader().read_to_end(&mut response.body)?;
+        Ok(response)
+    }
+}
@@ -1,20 +1,35 @@
 #![crate_name = "tanoshi_lib"]
 
-/// This is used to ensure both application and extension use the same version
-pub static CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
-/// Rust doesn't have stable ABI, this is used to ensure `rustc` version is match
+/// Version of tanoshi-lib the application or extension is built with
+pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Version of `rustc` tanoshi-lib is built with, informational only since extensions use a stable ABI
 pub static RUSTC_VERSION: &str = env!("RUSTC_VERSION");
+/// Version of the FFI plugin interface, only extensions with the same interface version can be loaded
+pub const INTERFACE_VERSION: u32 = 6;
+
+/// This module contains semantic version parsing and comparison for extensions
+pub mod version;
+
+/// This module contains FFI-safe plugin interface used to load extensions
+#[cfg(feature = "extensions")]
+pub mod ffi;
+
+/// This module contains HTTP client tanoshi provides to extensions
+#[cfg(feature = "extensions")]
+pub mod http;
 
 /// This module contains model used in extensions and rest api
 #[cfg(feature = "model")]
 pub mod manga {
     use chrono::Local;
-    use serde::{Deserialize, Serialize};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
 
     #[derive(Debug, Deserialize, Serialize, Clone)]
     pub struct SourceIndex {
         pub name: String,
         pub path: String,
+        #[serde(default)]
         pub rustc_version: String,
         pub core_version: String,
         #[serde(default)]
@@ -24,6 +39,15 @@ pub mod manga {
         pub installed: bool,
         #[serde(default)]
         pub update: bool,
+        /// Name of the repository this source is listed in, empty for sources that are not in any repository
+        #[serde(default)]
+        pub repository: String,
+        /// Hex encoded SHA-256 of the plugin file
+        #[serde(default)]
+        pub hash: String,
+        /// Base64 encoded ed25519 signature of the plugin file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub signature: Option<String>,
     }
 
     /// Model to login to source that require login, like mangadex to search
@@ -35,12 +59,15 @@ pub mod manga {
         pub two_factor: Option<String>,
     }
 
-    /// Result of source login
+    /// Result of source login, `value` is passed as `auth` to the extension
     #[derive(Debug, Deserialize, Serialize, Clone, Default)]
     pub struct SourceLoginResult {
         pub source_name: String,
         pub auth_type: String,
         pub value: String,
+        /// When the session expires, tanoshi logs in again with the saved credentials afterward
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expires: Option<chrono::NaiveDateTime>,
     }
 
     /// A type represent source
@@ -49,6 +76,21 @@ pub mod manga {
         pub name: String,
         pub url: String,
         pub version: String,
+        /// Calls the source allows, `rate_limit` in `plugin_config` takes precedence
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub rate_limit: Option<RateLimit>,
+    }
+
+    /// A token bucket of `requests` calls, refilled every `period` seconds
+    #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+    pub struct RateLimit {
+        pub requests: u32,
+        #[serde(default = "default_rate_limit_period")]
+        pub period: u64,
+    }
+
+    fn default_rate_limit_period() -> u64 {
+        1
     }
 
     /// A type represent manga details, normalized across source
@@ -147,7 +189,7 @@ pub mod manga {
     }
 
     /// A type represent parameter for query manga from source, normalized across source
-    #[derive(Debug, Deserialize, Serialize, Clone)]
+    #[derive(Debug, Deserialize, Serialize, Clone, Default)]
     pub struct Params {
         pub keyword: Option<String>,
         pub genres: Option<Vec<String>>,
@@ -155,6 +197,224 @@ pub mod manga {
         pub sort_by: Option<SortByParam>,
         pub sort_order: Option<SortOrderParam>,
         pub refresh: Option<bool>,
+        /// Key of one of the sort options in `Filters::sort_by`
+        pub sort_key: Option<String>,
+        /// Values of the fields in `Filters::fields`
+        pub filters: Option<FilterValues>,
+    }
+
+    /// A type represent filters and sort options supported by a source
+    #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+    pub struct Filters {
+        #[serde(default)]
+        pub fields: Vec<FilterField>,
+        #[serde(default)]
+        pub sort_by: Vec<FilterOption>,
+    }
+
+    /// A type represent a filter input, `key` is the key of its value in `FilterValues`
+    #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum FilterField {
+        /// One of `options`, the value is `FilterValue::Text`
+        Select {
+            key: String,
+            name: String,
+            options: Vec<FilterOption>,
+            #[serde(default)]
+            default: Option<String>,
+        },
+        /// Any of `options`, the value is `FilterValue::List`
+        MultiSelect {
+            key: String,
+            name: String,
+            options: Vec<FilterOption>,
+        },
+        /// Each of `options` is either ignored, included or excluded, like genres,
+        /// the value is `FilterValue::TriState`
+        TriState {
+            key: String,
+            name: String,
+            options: Vec<FilterOption>,
+        },
+        /// Free text, the value is `FilterValue::Text`
+        Text { key: String, name: String },
+    }
+
+    impl FilterField {
+        pub fn key(&self) -> &str {
+            match self {
+                FilterField::Select { key, .. }
+                | FilterField::MultiSelect { key, .. }
+                | FilterField::TriState { key, .. }
+                | FilterField::Text { key, .. } => key,
+            }
+        }
+
+        pub fn name(&self) -> &str {
+            match self {
+                FilterField::Select { name, .. }
+                | FilterField::MultiSelect { name, .. }
+                | FilterField::TriState { name, .. }
+                | FilterField::Text { name, .. } => name,
+            }
+        }
+    }
+
+    /// A type represent an option of a filter or sort, `value` is passed to the source
+    #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+    pub struct FilterOption {
+        pub value: String,
+        pub name: String,
+    }
+
+    /// A type represent configurable preferences of a source,
+    /// values are passed to the extension as its config when it is registered
+    #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+    pub struct Preferences {
+        #[serde(default)]
+        pub fields: Vec<PreferenceField>,
+    }
+
+    /// A type represent a preference input, `key` is the key of its value in the extension config
+    #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum PreferenceField {
+        /// Free text, the value is a string
+        Text {
+            key: String,
+            name: String,
+            #[serde(default)]
+            default: Option<String>,
+        },
+        /// One of `options`, the value is the value of the option
+        Select {
+            key: String,
+            name: String,
+            options: Vec<FilterOption>,
+            #[serde(default)]
+            default: Option<String>,
+        },
+        /// On or off, the value is a boolean
+        Toggle {
+            key: String,
+            name: String,
+            #[serde(default)]
+            default: bool,
+        },
+    }
+
+    impl PreferenceField {
+        pub fn key(&self) -> &str {
+            match self {
+                PreferenceField::Text { key, .. }
+                | PreferenceField::Select { key, .. }
+                | PreferenceField::Toggle { key, .. } => key,
+            }
+        }
+
+        pub fn name(&self) -> &str {
+            match self {
+                PreferenceField::Text { name, .. }
+                | PreferenceField::Select { name, .. }
+                | PreferenceField::Toggle { name, .. } => name,
+            }
+        }
+
+        /// Returns an error if `value` can't be used as value of this preference
+        pub fn validate(&self, value: &serde_json::Value) -> anyhow::Result<()> {
+            let valid = match (self, value) {
+                (PreferenceField::Text { .. }, serde_json::Value::String(_)) => true,
+                (PreferenceField::Select { options, .. }, serde_json::Value::String(value)) => {
+                    options.iter().any(|option| &option.value == value)
+                }
+                (PreferenceField::Toggle { .. }, serde_json::Value::Bool(_)) => true,
+                _ => false,
+            };
+            if valid {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(
+                    "{} is not a valid value of {}",
+                    value,
+                    self.key()
+                ))
+            }
+        }
+    }
+
+    /// A type represent value of a filter filled in by user
+    #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+    #[serde(untagged)]
+    pub enum FilterValue {
+        Text(String),
+        List(Vec<String>),
+        TriState {
+            include: Vec<String>,
+            exclude: Vec<String>,
+        },
+    }
+
+    /// A type represent values of filters keyed by `FilterField` key
+    ///
+    /// It is serialized as a JSON string so it can be passed in a query string,
+    /// and can be deserialized from either a JSON string or a map.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct FilterValues(pub BTreeMap<String, FilterValue>);
+
+    impl FilterValues {
+        pub fn get(&self, key: &str) -> Option<&FilterValue> {
+            self.0.get(key)
+        }
+
+        /// Returns value of a `Select` or `Text` field
+        pub fn text(&self, key: &str) -> Option<&str> {
+            match self.0.get(key) {
+                Some(FilterValue::Text(value)) => Some(value),
+                _ => None,
+            }
+        }
+
+        /// Returns values of a `MultiSelect` field
+        pub fn list(&self, key: &str) -> &[String] {
+            match self.0.get(key) {
+                Some(FilterValue::List(values)) => values,
+                _ => &[],
+            }
+        }
+
+        /// Returns included and excluded values of a `TriState` field
+        pub fn tri_state(&self, key: &str) -> (&[String], &[String]) {
+            match self.0.get(key) {
+                Some(FilterValue::TriState { include, exclude }) => (include, exclude),
+                _ => (&[], &[]),
+            }
+        }
+    }
+
+    impl Serialize for FilterValues {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let json = serde_json::to_string(&self.0).map_err(serde::ser::Error::custom)?;
+            serializer.serialize_str(&json)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for FilterValues {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Repr {
+                Json(String),
+                Map(BTreeMap<String, FilterValue>),
+            }
+
+            match Repr::deserialize(deserializer)? {
+                Repr::Json(json) => serde_json::from_str(&json)
+                    .map(FilterValues)
+                    .map_err(serde::de::Error::custom),
+                Repr::Map(map) => Ok(FilterValues(map)),
+            }
+        }
     }
 
     /// A type represent parameter for query manga from source, normalized across source
@@ -163,6 +423,12 @@ pub mod manga {
         pub refresh: Option<bool>,
     }
 
+    /// A type represent parameter for searching every installed source
+    #[derive(Debug, Deserialize, Serialize, Clone)]
+    pub struct SearchParams {
+        pub keyword: String,
+    }
+
     /// A type represent history
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct History {
@@ -212,7 +478,9 @@ pub mod manga {
 /// This module contains request and response payload for rest api
 #[cfg(feature = "rest")]
 pub mod rest {
-    use crate::manga::{Chapter, History, Manga, Source, SourceIndex, Update};
+    use crate::manga::{
+        Chapter, Filters, History, Manga, Preferences, Source, SourceIndex, Update,
+    };
     use serde::{Deserialize, Serialize};
 
     #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -228,6 +496,21 @@ pub mod rest {
         pub status: String,
     }
 
+    /// Response for get filters of a source request
+    #[derive(Debug, Deserialize, Serialize, Clone)]
+    pub struct GetFiltersResponse {
+        pub filters: Filters,
+        pub status: String,
+    }
+
+    /// Response for get source config request, `config` is the current config of the source
+    #[derive(Debug, Deserialize, Serialize, Clone)]
+    pub struct GetSourceConfigResponse {
+        pub preferences: Preferences,
+        pub config: serde_json::Value,
+        pub status: String,
+    }
+
     /// Response for get list of manga request
     #[derive(Debug, Deserialize, Serialize, Clone)]
     pub struct GetMangasResponse {
@@ -235,6 +518,22 @@ pub mod rest {
         pub status: String,
     }
 
+    /// Mangas found in a source by global search, `error` is set when the source failed or timed out
+    #[derive(Debug, Deserialize, Serialize, Clone)]
+    pub struct SourceSearchResult {
+        pub source: String,
+        pub mangas: Vec<Manga>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    /// Response for global search request, results are grouped by source
+    #[derive(Debug, Deserialize, Serialize, Clone)]
+    pub struct GlobalSearchResponse {
+        pub sources: Vec<SourceSearchResult>,
+        pub status: String,
+    }
+
     /// Response for get manga detail request
     #[derive(Debug, Deserialize, Serialize, Clone)]
     pub struct GetMangaResponse {
@@ -287,56 +586,244 @@ pub mod rest {
         pub status: String,
     }
 
+    /// A favorite whose chapters failed to refresh in a library update
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct LibraryUpdateError {
+        pub manga_id: i32,
+        pub title: String,
+        pub source: String,
+        pub message: String,
+    }
+
+    /// Last run of the library update refreshing the chapters of every favorite
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    pub struct LibraryUpdate {
+        pub running: bool,
+        pub started_at: Option<chrono::NaiveDateTime>,
+        pub finished_at: Option<chrono::NaiveDateTime>,
+        /// When the next scheduled run starts, not set when scheduling is disabled
+        pub next_at: Option<chrono::NaiveDateTime>,
+        /// Favorites refreshed so far, counted once for every user
+        pub mangas: usize,
+        pub new_chapters: usize,
+        pub errors: Vec<LibraryUpdateError>,
+    }
+
+    /// Response for library update status request
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct LibraryUpdateResponse {
+        pub update: LibraryUpdate,
+        pub status: String,
+    }
+
+    /// Event a webhook is called for
+    #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum WebhookEvent {
+        /// A library update found new chapters of a favorite
+        NewChapters,
+        /// An installed source has a newer version in a repository, admins only
+        ExtensionUpdate,
+        /// A library update failed to refresh some favorites, admins only
+        LibraryUpdateFailed,
+        /// Sent on request to try a webhook
+        Test,
+    }
+
+    /// Url called with a JSON body on events, `template` is the body with `{{variable}}`
+    /// placeholders, the variables themselves are sent without it
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct Webhook {
+        pub id: i32,
+        pub url: String,
+        pub events: Vec<WebhookEvent>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub template: Option<serde_json::Value>,
+        /// Key of the HMAC-SHA256 signature of every delivery
+        pub secret: String,
+    }
+
+    /// Request to add or change a webhook
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct WebhookRequest {
+        pub url: String,
+        pub events: Vec<WebhookEvent>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub template: Option<serde_json::Value>,
+    }
+
+    /// An attempt to call a webhook, `status` is not set when there was no response
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct WebhookDelivery {
+        pub id: i64,
+        pub webhook_id: i32,
+        pub event: WebhookEvent,
+        pub body: String,
+        pub attempt: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub status: Option<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+        pub at: chrono::NaiveDateTime,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct WebhooksResponse {
+        pub webhooks: Vec<Webhook>,
+        pub status: String,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct WebhookResponse {
+        pub webhook: Webhook,
+        pub status: String,
+    }
+
+    /// Recent deliveries of a webhook, newest first
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct WebhookDeliveriesResponse {
+        pub deliveries: Vec<WebhookDelivery>,
+        pub status: String,
+    }
+
+    /// State of a chapter in the download queue
+    #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum DownloadStatus {
+        Queued,
+        Downloading,
+        Done,
+        /// Gave up on the chapter, enqueuing it again retries the pages not downloaded yet
+        Failed,
+    }
+
+    /// A chapter in the download queue, `pages` is 0 until the pages are known
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct Download {
+        pub chapter_id: i32,
+        pub manga_id: i32,
+        pub title: String,
+        pub source: String,
+        pub volume: Option<String>,
+        pub number: Option<String>,
+        pub status: DownloadStatus,
+        pub pages: u32,
+        pub downloaded: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+        pub created: chrono::NaiveDateTime,
+    }
+
+    /// Download queue of a user, oldest first
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct DownloadsResponse {
+        pub downloads: Vec<Download>,
+        pub status: String,
+    }
+
+    /// Chapters a download request added to the queue, downloaded and queued ones are left out
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct EnqueueResponse {
+        pub queued: usize,
+        pub status: String,
+    }
+
     /// Response for add new favorite request
     #[derive(Debug, Serialize, Deserialize, Clone)]
     pub struct AddFavoritesResponse {
         pub status: String,
     }
+
+    /// Machine readable reason of an error response, each maps to one HTTP status
+    #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ErrorCode {
+        /// 400, the request is malformed or a value is invalid
+        BadRequest,
+        /// 401, the token is missing, expired or invalid, or the password is wrong
+        Unauthorized,
+        /// 403, the user is not allowed to do this
+        Forbidden,
+        /// 404, the manga, chapter, page or user doesn't exist
+        NotFound,
+        /// 405, the path exists with another method
+        MethodNotAllowed,
+        /// 409, e.g the username is taken
+        Conflict,
+        /// 404, no extension is loaded for the source
+        SourceNotFound,
+        /// 503, the source panicked or timed out too many times in a row and is disabled for a while
+        SourceDisabled,
+        /// 504, the source did not answer in time
+        SourceTimeout,
+        /// 502, the source returned an error or the extension panicked
+        SourceFailed,
+        /// 500, the database failed
+        Database,
+        /// 500, anything else
+        Internal,
+    }
+
+    /// Body of every error response of the api
+    #[derive(Debug, Deserialize, Serialize, Clone)]
+    pub struct ErrorResponse {
+        pub code: ErrorCode,
+        pub message: String,
+        /// Source that failed, for `source_*` codes
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub source: Option<String>,
+        /// Always `error`
+        pub status: String,
+    }
 }
 
 /// This module contains `Extension` trait, and function for interacting with `Extension`
 #[cfg(feature = "extensions")]
 pub mod extensions {
-    use crate::manga::{Chapter, Manga, Params, Source, SourceLogin, SourceLoginResult};
+    use crate::http::Http;
+    use crate::manga::{
+        Chapter, Filters, Manga, Params, Preferences, Source, SourceLogin, SourceLoginResult,
+    };
     use anyhow::{anyhow, Result};
-    use serde_yaml;
-    use std::io::Read;
 
     /// `Extension` trait is an implementation for building extensions
     pub trait Extension: Send + Sync {
         /// Returns the information of the source
         fn info(&self) -> Source;
 
+        /// Returns filters and sort options the source supports,
+        /// values filled in by user are passed to `get_mangas` in `Params`
+        fn filters(&self) -> Filters {
+            Filters::default()
+        }
+
+        /// Returns preferences of the source that can be changed from web,
+        /// the extension is registered again with the new config when they are changed
+        fn preferences(&self) -> Preferences {
+            Preferences::default()
+        }
+
         /// Returns list of manga from the source
         ///
         /// # Arguments
         ///
-        /// * `url` - An url to specified page in source that can be parsed into a list of mangas
         /// * `param` - Parameter to filter manga from source
+        /// * `auth` - `value` of the `SourceLoginResult` of the user, empty when the user is not logged in
         fn get_mangas(&self, param: Params, auth: String) -> Result<Vec<Manga>>;
 
         /// Returns detail of manga
-        fn get_manga_info(&self, path: &String) -> Result<Manga>;
+        fn get_manga_info(&self, path: &str, auth: String) -> Result<Manga>;
 
         /// Returns list of chapters of a manga
-        fn get_chapters(&self, path: &String) -> Result<Vec<Chapter>>;
+        fn get_chapters(&self, path: &str, auth: String) -> Result<Vec<Chapter>>;
 
         /// Returns list of pages from a chapter of a manga
-        fn get_pages(&self, path: &String) -> Result<Vec<String>>;
-
-        /// Returns an image by download to disk first then serve to web
-        fn get_page(&self, url: &String) -> Result<Vec<u8>> {
-            let bytes = {
-                let resp = ureq::get(&url).call();
-                let mut reader = resp.into_reader();
-                let mut bytes = vec![];
-                if reader.read_to_end(&mut bytes).is_err() {
-                    return Err(anyhow!("error read image"));
-                }
-                bytes
-            };
+        fn get_pages(&self, path: &str, auth: String) -> Result<Vec<String>>;
 
-            Ok(bytes)
+        /// Returns an image by download to disk first then serve to web,
+        /// by default it is downloaded with the HTTP client tanoshi gave the plugin
+        fn get_page(&self, url: &str, _auth: String) -> Result<Vec<u8>> {
+            Ok(Http::default().get(url)?.error_for_status()?.body)
         }
 
         /// Login to source
@@ -345,31 +832,72 @@ pub mod extensions {
         }
     }
 
-    /// A type represents an extension
-    pub struct PluginDeclaration {
-        pub rustc_version: &'static str,
-        pub core_version: &'static str,
-        pub name: &'static str,
-        pub register: unsafe fn(&mut dyn PluginRegistrar, Option<&serde_yaml::Value>),
+    /// Message of the error an extension returns when `auth` is no longer accepted by the source,
+    /// tanoshi logs in again with the credentials the user saved and retries the call once
+    pub const SESSION_EXPIRED: &str = "session expired";
+
+    /// Returns the error telling tanoshi the session passed as `auth` expired
+    pub fn session_expired() -> anyhow::Error {
+        anyhow!(SESSION_EXPIRED)
+    }
+
+    /// Error of a call the extension didn't return from, e.g a panic in a native plugin
+    /// or a trap in a WebAssembly module. tanoshi disables a source that keeps panicking.
+    #[derive(Debug)]
+    pub struct Panicked(pub String);
+
+    impl std::fmt::Display for Panicked {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "extension panicked: {}", self.0)
+        }
     }
 
+    impl std::error::Error for Panicked {}
+
     /// A trait for register an extension
     pub trait PluginRegistrar {
         fn register_function(&mut self, name: &str, extension: Box<dyn Extension>);
+
+        /// Returns the HTTP client tanoshi provides for `source`,
+        /// with the timeouts, user agent, proxy and cookie jar of that source
+        fn http(&self, _source: &str) -> Http {
+            Http::default()
+        }
     }
 
     /// macro for export an extension
+    ///
+    /// `$register` is called with a `PluginRegistrar` and the extension config from `plugin_config`,
+    /// every extension registered is exposed to tanoshi through a stable C ABI.
+    /// `$name` is also the source whose HTTP client is used by default.
+    ///
+    /// ```ignore
+    /// tanoshi_lib::export_plugin!("mangadex", register);
+    ///
+    /// unsafe fn register(registrar: &mut dyn PluginRegistrar, config: Option<&serde_yaml::Value>) {
+    ///     let http = registrar.http("mangadex");
+    ///     registrar.register_function("mangadex", Box::new(Mangadex::new(http, config)));
+    /// }
+    /// ```
     #[macro_export]
     macro_rules! export_plugin {
         ($name:expr, $register:expr) => {
             #[doc(hidden)]
             #[no_mangle]
-            pub static plugin_declaration: $crate::extensions::PluginDeclaration =
-                $crate::extensions::PluginDeclaration {
-                    rustc_version: $crate::RUSTC_VERSION,
-                    core_version: $crate::CORE_VERSION,
-                    name: $name,
-                    register: $register,
+            pub static tanoshi_plugin_declaration: $crate::ffi::PluginDeclaration =
+                $crate::ffi::PluginDeclaration {
+                    interface_version: $crate::INTERFACE_VERSION,
+                    core_version: $crate::ffi::StaticStr::new($crate::CORE_VERSION),
+                    name: $crate::ffi::StaticStr::new($name),
+                    register: {
+                        unsafe extern "C" fn __tanoshi_plugin_register(
+                            registrar: *mut $crate::ffi::Registrar,
+                            config: $crate::ffi::Slice,
+                        ) {
+                            $crate::ffi::register_plugin(registrar, config, $name, $register)
+                        }
+                        __tanoshi_plugin_register
+                    },
                 };
         };
     }
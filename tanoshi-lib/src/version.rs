@@ -0,0 +1,132 @@
+//! Semantic version handling for extensions
+//!
+//! Versions that are not strictly semver but common in extension indexes, like `1.2` or `v1.2.0`,
+//! are accepted and padded to `major.minor.patch`.
+use anyhow::{anyhow, Result};
+use semver::{Version, VersionReq};
+
+/// Parse an extension version
+pub fn parse(version: &str) -> Result<Version> {
+    let version = version.trim().trim_start_matches('v');
+    let end = version.find(['-', '+']).unwrap_or(version.len());
+    let (core, rest) = version.split_at(end);
+    let padding = match core.split('.').count() {
+        1 => ".0.0",
+        2 => ".0",
+        _ => "",
+    };
+    Version::parse(&format!("{}{}{}", core, padding, rest))
+        .map_err(|e| anyhow!("invalid version {}: {}", version, e))
+}
+
+/// Parse a version requirement, a plain version like `0.14.0` means compatible with it, same as `^0.14.0`
+pub fn parse_req(req: &str) -> Result<VersionReq> {
+    VersionReq::parse(req.trim()).map_err(|e| anyhow!("invalid version requirement {}: {}", req, e))
+}
+
+/// Returns true if `available` is newer than `installed`.
+/// A pre-release is only offered as an update to an installed pre-release.
+pub fn is_update(installed: &str, available: &str) -> bool {
+    let (installed, available) = match (parse(installed), parse(available)) {
+        (Ok(installed), Ok(available)) => (installed, available),
+        _ => return false,
+    };
+    if available.is_prerelease() && !installed.is_prerelease() {
+        return false;
+    }
+    available > installed
+}
+
+/// Returns true if an extension requiring `core_version` can be used with this version of tanoshi-lib
+pub fn is_compatible(core_version: &str) -> bool {
+    satisfies(crate::CORE_VERSION, core_version)
+}
+
+fn satisfies(version: &str, req: &str) -> bool {
+    match (parse_req(req), parse(version)) {
+        (Ok(req), Ok(version)) => req.matches(&version),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_loose_versions() {
+        assert_eq!(parse("1.2.3").unwrap(), Version::parse("1.2.3").unwrap());
+        assert_eq!(parse("v1.2").unwrap(), Version::parse("1.2.0").unwrap());
+        assert_eq!(parse(" 1 ").unwrap(), Version::parse("1.0.0").unwrap());
+        assert_eq!(
+            parse("1.2-beta.1").unwrap(),
+            Version::parse("1.2.0-beta.1").unwrap()
+        );
+        assert_eq!(
+            parse("1.2+build.5").unwrap(),
+            Version::parse("1.2.0+build.5").unwrap()
+        );
+        assert!(parse("").is_err());
+        assert!(parse("one").is_err());
+        assert!(parse("1.2.3.4").is_err());
+    }
+
+    #[test]
+    fn updates() {
+        for (installed, available, update) in &[
+            ("1.0.0", "1.0.1", true),
+            ("1.0.0", "1.1", true),
+            ("1.9.0", "1.10.0", true),
+            ("1.0.0", "1.0.0", false),
+            ("1.1.0", "1.0.9", false),
+            ("1.0", "v1.0.0", false),
+            // a pre-release is only offered to a pre-release
+            ("1.0.0", "1.1.0-beta", false),
+            ("1.1.0-alpha", "1.1.0-beta", true),
+            ("1.1.0-beta", "1.1.0", true),
+            ("1.1.0", "1.1.0-beta", false),
+            // build metadata is not newer
+            ("1.0.0", "1.0.0+2", false),
+            ("invalid", "1.0.0", false),
+            ("1.0.0", "invalid", false),
+        ] {
+            assert_eq!(
+                is_update(installed, available),
+                *update,
+                "{} -> {}",
+                installed,
+                available
+            );
+        }
+    }
+
+    #[test]
+    fn compatibility() {
+        for (version, req, compatible) in &[
+            ("0.14.0", "0.14.0", true),
+            ("0.14.3", "0.14.0", true),
+            ("0.15.0", "0.14.0", false),
+            ("0.13.9", "0.14.0", false),
+            ("0.14.3", "^0.14", true),
+            ("0.14.3", "~0.14.1", true),
+            ("0.15.0", ">=0.14, <0.16", true),
+            ("0.16.0", ">=0.14, <0.16", false),
+            ("1.2.0", "1.0.0", true),
+            ("2.0.0", "1.0.0", false),
+            ("0.14.0", "=0.14.0", true),
+            ("0.14.1", "=0.14.0", false),
+            ("0.14.0", "*", true),
+            ("0.14.0-beta", "0.14.0", false),
+            ("0.14.0", "not a requirement", false),
+        ] {
+            assert_eq!(
+                satisfies(version, req),
+                *compatible,
+                "{} satisfies {}",
+                version,
+                req
+            );
+        }
+        assert!(is_compatible(crate::CORE_VERSION));
+    }
+}
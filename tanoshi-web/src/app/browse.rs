@@ -9,6 +9,7 @@ use super::component::NavigationBar;
 use super::detail::Detail;
 use super::history::History;
 use super::home::Home;
+use super::search::Search;
 use super::settings::Settings;
 
 #[derive(Switch, Debug, Clone)]
@@ -23,6 +24,8 @@ pub enum BrowseRoute {
     History,
     #[to = "/settings"]
     Settings,
+    #[to = "/search"]
+    Search,
     #[to = "/"]
     Home,
 }
@@ -100,6 +103,7 @@ impl Component for Browse {
                         BrowseRoute::History => html!{<History/>},
                         BrowseRoute::Home => html!{<Home/>},
                         BrowseRoute::Settings => html!{<Settings />},
+                        BrowseRoute::Search => html!{<Search />},
                     }}) / >
             < / >
         }
@@ -453,6 +453,7 @@ impl Chapter {
 
         if i >= self.current_page.checked_sub(before).unwrap_or(0) && i < self.current_page + after
         {
+            // page urls come with an image token, images can't send the authorization header
             page.to_string()
         } else {
             "".to_string()
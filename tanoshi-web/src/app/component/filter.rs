@@ -1,17 +1,22 @@
-use tanoshi_lib::manga::{SortByParam, SortOrderParam};
+use tanoshi_lib::manga::{FilterField, FilterValue, FilterValues, Filters, SortOrderParam};
 use yew::prelude::*;
 
 #[derive(Clone, PartialEq, Properties)]
 pub struct Props {
     pub show: bool,
-    pub onsortbychange: Callback<SortByParam>,
+    #[prop_or_default]
+    pub filters: Filters,
+    pub onsortbychange: Callback<String>,
     pub onsortorderchange: Callback<SortOrderParam>,
+    pub onfilterchange: Callback<FilterValues>,
     pub onclose: Callback<()>,
     pub oncancel: Callback<()>,
     #[prop_or_default]
-    pub sort_by: SortByParam,
+    pub sort_key: Option<String>,
     #[prop_or_default]
     pub sort_order: SortOrderParam,
+    #[prop_or_default]
+    pub values: FilterValues,
 }
 
 pub struct Filter {
@@ -21,7 +26,11 @@ pub struct Filter {
 }
 
 pub enum Msg {
-    SortClick(SortByParam),
+    SortClick(String),
+    SelectChange(String, ChangeData),
+    TextChange(String, InputData),
+    MultiSelectToggle(String, String),
+    TriStateToggle(String, String),
     Done,
     Cancel,
 }
@@ -49,17 +58,63 @@ impl Component for Filter {
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
-            Msg::SortClick(sort_by) => {
-                if self.props.sort_by == sort_by {
+            Msg::SortClick(sort_key) => {
+                if self.props.sort_key.as_ref() == Some(&sort_key) {
                     match self.props.sort_order {
                         SortOrderParam::Asc => self.props.sort_order = SortOrderParam::Desc,
                         SortOrderParam::Desc => self.props.sort_order = SortOrderParam::Asc,
                     }
-                    self.props.onsortorderchange.emit(self.props.sort_order.clone());
+                    self.props
+                        .onsortorderchange
+                        .emit(self.props.sort_order.clone());
+                } else {
+                    self.props.sort_key = Some(sort_key.clone());
+                    self.props.onsortbychange.emit(sort_key);
+                }
+            }
+            Msg::SelectChange(key, e) => {
+                if let ChangeData::Select(el) = e {
+                    self.set_text(key, el.value());
+                }
+            }
+            Msg::TextChange(key, e) => {
+                self.set_text(key, e.value);
+            }
+            Msg::MultiSelectToggle(key, value) => {
+                let mut values = self.props.values.list(&key).to_vec();
+                if values.contains(&value) {
+                    values.retain(|v| v != &value);
+                } else {
+                    values.push(value);
+                }
+                if values.is_empty() {
+                    self.props.values.0.remove(&key);
                 } else {
-                    self.props.sort_by = sort_by;
-                    self.props.onsortbychange.emit(self.props.sort_by.clone());
+                    self.props.values.0.insert(key, FilterValue::List(values));
                 }
+                self.props.onfilterchange.emit(self.props.values.clone());
+            }
+            Msg::TriStateToggle(key, value) => {
+                let (include, exclude) = self.props.values.tri_state(&key);
+                let (mut include, mut exclude) = (include.to_vec(), exclude.to_vec());
+                // ignored -> included -> excluded -> ignored
+                if include.contains(&value) {
+                    include.retain(|v| v != &value);
+                    exclude.push(value);
+                } else if exclude.contains(&value) {
+                    exclude.retain(|v| v != &value);
+                } else {
+                    include.push(value);
+                }
+                if include.is_empty() && exclude.is_empty() {
+                    self.props.values.0.remove(&key);
+                } else {
+                    self.props
+                        .values
+                        .0
+                        .insert(key, FilterValue::TriState { include, exclude });
+                }
+                self.props.onfilterchange.emit(self.props.values.clone());
             }
             Msg::Done => {
                 self.props.onclose.emit(());
@@ -78,51 +133,152 @@ impl Component for Filter {
                     <button class="flex rounded text-tachiyomi-blue py-1 px-2 justify-center" onclick=self.link.callback(|_| Msg::Cancel)>{"Cancel"}</button>
                     <button class="flex rounded bg-tachiyomi-blue text-white py-1 px-2 shadow justify-center" onclick=self.link.callback(|_| Msg::Done)>{"Search"}</button>
                 </div>
-                <div class="w-full max-w-full flex flex-col mx-auto mt-12">
-                    <div class="w-full shadow p-2">{"Sort By"}</div>
-                    <button class="inline-flex justify-center p-2" onclick=self.link.callback(|_| Msg::SortClick(SortByParam::LastUpdated))>
-                        {
-                            match self.props.sort_by {
-                                SortByParam::LastUpdated  => self.asc_or_desc(),
-                                _ => self.empty_svg(),
-                            }
+                <div class="w-full max-w-full flex flex-col mx-auto mt-12 overflow-y-auto" style="max-height: 60vh">
+                    {self.sort_view()}
+                    {for self.props.filters.fields.iter().map(|field| self.field_view(field))}
+                </div>
+            </div>
+        }
+    }
+}
+
+impl Filter {
+    fn set_text(&mut self, key: String, value: String) {
+        if value.is_empty() {
+            self.props.values.0.remove(&key);
+        } else {
+            self.props.values.0.insert(key, FilterValue::Text(value));
+        }
+        self.props.onfilterchange.emit(self.props.values.clone());
+    }
+
+    fn sort_view(&self) -> Html {
+        if self.props.filters.sort_by.is_empty() {
+            return html! {};
+        }
+        html! {
+            <>
+                <div class="w-full shadow p-2">{"Sort By"}</div>
+                {
+                    for self.props.filters.sort_by.iter().map(|option| {
+                        let value = option.value.clone();
+                        html! {
+                            <button class="inline-flex justify-center p-2" onclick=self.link.callback(move |_| Msg::SortClick(value.clone()))>
+                                {
+                                    if self.props.sort_key.as_ref() == Some(&option.value) {
+                                        self.asc_or_desc()
+                                    } else {
+                                        self.empty_svg()
+                                    }
+                                }
+                                <span>{option.name.clone()}</span>
+                            </button>
                         }
-                        <span>{"Last Updated"}</span>
-                    </button>
-                    <button class="inline-flex justify-center p-2" onclick=self.link.callback(|_| Msg::SortClick(SortByParam::Title))>
+                    })
+                }
+            </>
+        }
+    }
+
+    fn field_view(&self, field: &FilterField) -> Html {
+        let input = match field {
+            FilterField::Select {
+                key,
+                options,
+                default,
+                ..
+            } => {
+                let selected = self
+                    .props
+                    .values
+                    .text(key)
+                    .map(|value| value.to_string())
+                    .or_else(|| default.clone())
+                    .unwrap_or_default();
+                let key = key.clone();
+                html! {
+                    <select class="m-2 p-1 border rounded" onchange=self.link.callback(move |e: ChangeData| Msg::SelectChange(key.clone(), e))>
                         {
-                            match self.props.sort_by {
-                                SortByParam::Title  => self.asc_or_desc(),
-                                _ => self.empty_svg(),
+                            if default.is_none() {
+                                html! {<option value="" selected={selected.is_empty()}>{"Any"}</option>}
+                            } else {
+                                html! {}
                             }
                         }
-                        <span>{"Title"}</span>
-                    </button>
-                    <button class="inline-flex justify-center p-2" onclick=self.link.callback(|_| Msg::SortClick(SortByParam::Comment))>
                         {
-                            match self.props.sort_by {
-                                SortByParam::Comment  => self.asc_or_desc(),
-                                _ => self.empty_svg(),
-                            }
+                            for options.iter().map(|option| html! {
+                                <option value={option.value.clone()} selected={option.value == selected}>{option.name.clone()}</option>
+                            })
                         }
-                        <span>{"Comment"}</span>
-                    </button>
-                    <button class="inline-flex justify-center p-2" onclick=self.link.callback(|_| Msg::SortClick(SortByParam::Views))>
-                        {
-                            match self.props.sort_by {
-                                SortByParam::Views  => self.asc_or_desc(),
-                                _ => self.empty_svg(),
+                    </select>
+                }
+            }
+            FilterField::MultiSelect { key, options, .. } => {
+                let values = self.props.values.list(key);
+                html! {
+                    <div class="flex flex-wrap p-2">
+                    {
+                        for options.iter().map(|option| {
+                            let key = key.clone();
+                            let value = option.value.clone();
+                            html! {
+                                <label class="inline-flex items-center p-1 mr-2">
+                                    <input type="checkbox"
+                                        checked={values.contains(&option.value)}
+                                        onclick=self.link.callback(move |_| Msg::MultiSelectToggle(key.clone(), value.clone()))/>
+                                    <span class="ml-1">{option.name.clone()}</span>
+                                </label>
                             }
-                        }
-                        <span>{"Views"}</span>
-                    </button>
-                </div>
-            </div>
+                        })
+                    }
+                    </div>
+                }
+            }
+            FilterField::TriState { key, options, .. } => {
+                let (include, exclude) = self.props.values.tri_state(key);
+                html! {
+                    <div class="flex flex-wrap p-2">
+                    {
+                        for options.iter().map(|option| {
+                            let (class, mark) = if include.contains(&option.value) {
+                                ("rounded border px-2 py-1 m-1 text-green-600 border-green-600", "+ ")
+                            } else if exclude.contains(&option.value) {
+                                ("rounded border px-2 py-1 m-1 text-red-600 border-red-600 line-through", "- ")
+                            } else {
+                                ("rounded border px-2 py-1 m-1 text-gray-700", "")
+                            };
+                            let key = key.clone();
+                            let value = option.value.clone();
+                            html! {
+                                <button class={class} onclick=self.link.callback(move |_| Msg::TriStateToggle(key.clone(), value.clone()))>
+                                    {format!("{}{}", mark, &option.name)}
+                                </button>
+                            }
+                        })
+                    }
+                    </div>
+                }
+            }
+            FilterField::Text { key, .. } => {
+                let value = self.props.values.text(key).unwrap_or_default().to_string();
+                let key = key.clone();
+                html! {
+                    <input type="text"
+                        class="m-2 px-3 py-2 text-sm leading-tight text-gray-700 border rounded shadow appearance-none focus:outline-none focus:shadow-outline"
+                        value={value}
+                        oninput=self.link.callback(move |e: InputData| Msg::TextChange(key.clone(), e))/>
+                }
+            }
+        };
+
+        html! {
+            <>
+                <div class="w-full shadow p-2">{field.name()}</div>
+                {input}
+            </>
         }
     }
-}
 
-impl Filter {
     fn classes(&self) -> Vec<&str> {
         let mut classes = vec![
             "animated",
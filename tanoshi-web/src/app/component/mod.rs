@@ -21,6 +21,9 @@ pub mod model;
 mod filter;
 pub use filter::Filter;
 
+mod source_settings;
+pub use source_settings::SourceSettings;
+
 use std::cell::RefCell;
 use std::ops::Deref;
 use std::rc::Rc;
@@ -0,0 +1,332 @@
+use http::{Request, Response};
+use yew::format::{Json, Nothing, Text};
+use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask};
+
+use tanoshi_lib::manga::{PreferenceField, Preferences};
+use tanoshi_lib::rest::{GetSourceConfigResponse, GetSourceIndexResponse};
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub token: String,
+}
+
+/// Preferences of installed sources, only available to admin
+pub struct SourceSettings {
+    fetch_task: Option<FetchTask>,
+    link: ComponentLink<Self>,
+    token: String,
+    sources: Vec<String>,
+    selected: Option<String>,
+    preferences: Preferences,
+    values: serde_json::Map<String, serde_json::Value>,
+    is_saved: bool,
+}
+
+pub enum Msg {
+    SourcesReady(GetSourceIndexResponse),
+    Configure(String),
+    ConfigReady(GetSourceConfigResponse),
+    TextChange(String, InputData),
+    SelectChange(String, ChangeData),
+    ToggleChange(String, bool),
+    Save,
+    Saved,
+    Noop,
+}
+
+impl Component for SourceSettings {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        SourceSettings {
+            fetch_task: None,
+            link,
+            token: props.token,
+            sources: vec![],
+            selected: None,
+            preferences: Preferences::default(),
+            values: serde_json::Map::new(),
+            is_saved: false,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.token != props.token {
+            self.token = props.token;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn rendered(&mut self, first_render: bool) {
+        if first_render {
+            self.fetch_sources();
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::SourcesReady(data) => {
+                // built-in sources have no path and can't be configured
+                self.sources = data
+                    .sources
+                    .into_iter()
+                    .filter(|s| s.installed && !s.path.is_empty())
+                    .map(|s| s.name)
+                    .collect();
+            }
+            Msg::Configure(name) => {
+                if self.selected.as_ref() == Some(&name) {
+                    self.selected = None;
+                } else {
+                    self.fetch_config(name.clone());
+                    self.selected = Some(name);
+                    self.preferences = Preferences::default();
+                    self.values.clear();
+                }
+                self.is_saved = false;
+            }
+            Msg::ConfigReady(data) => {
+                let preferences = data.preferences;
+                self.values = match data.config {
+                    serde_json::Value::Object(config) => config
+                        .into_iter()
+                        .filter(|(key, _)| preferences.fields.iter().any(|f| f.key() == key))
+                        .collect(),
+                    _ => serde_json::Map::new(),
+                };
+                self.preferences = preferences;
+            }
+            Msg::TextChange(key, e) => {
+                self.values.insert(key, serde_json::Value::String(e.value));
+                self.is_saved = false;
+            }
+            Msg::SelectChange(key, e) => {
+                if let ChangeData::Select(el) = e {
+                    self.values
+                        .insert(key, serde_json::Value::String(el.value()));
+                    self.is_saved = false;
+                }
+            }
+            Msg::ToggleChange(key, value) => {
+                self.values.insert(key, serde_json::Value::Bool(value));
+                self.is_saved = false;
+            }
+            Msg::Save => {
+                self.save_config();
+            }
+            Msg::Saved => {
+                self.is_saved = true;
+            }
+            Msg::Noop => {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div class="flex flex-col rounded-lg border border-grey-light m-2" id="source-setting">
+                <div class={"shadow p-2 bg-tachiyomi-blue rounded-t"}>
+                    <span class="text-semibold text-white">{"Sources"}</span>
+                </div>
+                {
+                    for self.sources.iter().map(|name| {
+                        let source = name.clone();
+                        html! {
+                            <>
+                                <div class="flex justify-between border-b border-gray-light p-2 content-center">
+                                    <span class="font-semibold my-auto">{name}</span>
+                                    <button class="bg-gray-300 hover:bg-gray-400 text-gray-800 font-bold py-1 px-2 rounded"
+                                        onclick=self.link.callback(move |_| Msg::Configure(source.clone()))>
+                                        {if self.selected.as_ref() == Some(name) {"Close"} else {"Configure"}}
+                                    </button>
+                                </div>
+                                {
+                                    if self.selected.as_ref() == Some(name) {
+                                        self.preferences_view()
+                                    } else {
+                                        html! {}
+                                    }
+                                }
+                            </>
+                        }
+                    })
+                }
+            </div>
+        }
+    }
+}
+
+impl SourceSettings {
+    fn preferences_view(&self) -> Html {
+        if self.preferences.fields.is_empty() {
+            return html! {
+                <div class="border-b border-gray-light p-2 text-gray-600">{"This source has no preferences"}</div>
+            };
+        }
+        html! {
+            <>
+                {for self.preferences.fields.iter().map(|field| self.field_view(field))}
+                <button class={"bg-grey-300 hover:bg-gray-400 text-gray-800 font-bold py-2 px-4 rounded-r border-b"}
+                    onclick=self.link.callback(|_| Msg::Save)>
+                    {if self.is_saved {"Saved"} else {"Save"}}
+                </button>
+            </>
+        }
+    }
+
+    fn field_view(&self, field: &PreferenceField) -> Html {
+        let key = field.key().to_string();
+        let input = match field {
+            PreferenceField::Text { default, .. } => {
+                let value = self
+                    .values
+                    .get(&key)
+                    .and_then(|value| value.as_str())
+                    .map(|value| value.to_string())
+                    .or_else(|| default.clone())
+                    .unwrap_or_default();
+                html! {
+                    <input
+                        class="w-full border-b border-grey-light"
+                        value=value
+                        oninput=self.link.callback(move |e: InputData| Msg::TextChange(key.clone(), e))/>
+                }
+            }
+            PreferenceField::Select {
+                options, default, ..
+            } => {
+                let selected = self
+                    .values
+                    .get(&key)
+                    .and_then(|value| value.as_str())
+                    .map(|value| value.to_string())
+                    .or_else(|| default.clone())
+                    .unwrap_or_default();
+                html! {
+                    <select onchange=self.link.callback(move |e: ChangeData| Msg::SelectChange(key.clone(), e))>
+                        {
+                            if selected.is_empty() {
+                                html! {<option value="" selected=true disabled=true>{"-"}</option>}
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            for options.iter().map(|option| html! {
+                                <option value={option.value.clone()} selected={option.value == selected}>{option.name.clone()}</option>
+                            })
+                        }
+                    </select>
+                }
+            }
+            PreferenceField::Toggle { default, .. } => {
+                let value = self
+                    .values
+                    .get(&key)
+                    .and_then(|value| value.as_bool())
+                    .unwrap_or(*default);
+                let on = key.clone();
+                html! {
+                    <>
+                        <button class={
+                            format!("{} hover:bg-gray-400 text-gray-800 font-bold py-1 px-2 rounded-l",
+                            if value { "bg-gray-400" } else {"bg-gray-300"})}
+                            onclick=self.link.callback(move |_| Msg::ToggleChange(on.clone(), true))>
+                            {"On"}
+                        </button>
+                        <button class={
+                            format!("{} hover:bg-gray-400 text-gray-800 font-bold py-1 px-2 rounded-r",
+                            if !value { "bg-gray-400" } else {"bg-gray-300"})}
+                            onclick=self.link.callback(move |_| Msg::ToggleChange(key.clone(), false))>
+                            {"Off"}
+                        </button>
+                    </>
+                }
+            }
+        };
+
+        html! {
+            <div class="flex justify-between border-b border-gray-light p-2 content-center">
+                <span class="font-semibold my-auto">{field.name()}</span>
+                <div class="inline-flex">{input}</div>
+            </div>
+        }
+    }
+
+    fn fetch_sources(&mut self) {
+        let req = Request::get("/api/source")
+            .body(Nothing)
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(
+                |response: Response<Json<Result<GetSourceIndexResponse, anyhow::Error>>>| {
+                    if let (meta, Json(Ok(data))) = response.into_parts() {
+                        if meta.status.is_success() {
+                            return Msg::SourcesReady(data);
+                        }
+                    }
+                    Msg::Noop
+                },
+            ),
+        ) {
+            self.fetch_task = Some(task);
+        }
+    }
+
+    fn fetch_config(&mut self, name: String) {
+        let req = Request::get(format!("/api/source/{}/config", &name))
+            .header("Authorization", self.token.to_string())
+            .body(Nothing)
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(
+                |response: Response<Json<Result<GetSourceConfigResponse, anyhow::Error>>>| {
+                    if let (meta, Json(Ok(data))) = response.into_parts() {
+                        if meta.status.is_success() {
+                            return Msg::ConfigReady(data);
+                        }
+                    }
+                    Msg::Noop
+                },
+            ),
+        ) {
+            self.fetch_task = Some(task);
+        }
+    }
+
+    fn save_config(&mut self) {
+        let name = match &self.selected {
+            Some(name) => name.clone(),
+            None => return,
+        };
+        let req = Request::put(format!("/api/source/{}/config", &name))
+            .header("Authorization", self.token.to_string())
+            .header("Content-Type", "application/json")
+            .body(Json(&self.values))
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(|response: Response<Text>| {
+                if let (meta, Ok(_)) = response.into_parts() {
+                    if meta.status.is_success() {
+                        return Msg::Saved;
+                    }
+                }
+                Msg::Noop
+            }),
+        ) {
+            self.fetch_task = Some(task);
+        }
+    }
+}
@@ -6,7 +6,9 @@ use yew::services::{FetchService, StorageService};
 use yew::{html, Component, ComponentLink, Html, Properties, ShouldRender};
 
 use super::component::{Manga, MangaList, Spinner, WeakComponentLink, Filter};
-use tanoshi_lib::manga::{Manga as MangaModel, SortByParam, SortOrderParam, Params};
+use tanoshi_lib::manga::{
+    FilterOption, FilterValues, Filters, Manga as MangaModel, Params, SortByParam, SortOrderParam,
+};
 use tanoshi_lib::rest::GetMangasResponse;
 
 #[derive(Clone, Properties)]
@@ -21,6 +23,7 @@ pub struct Home {
     should_fetch: bool,
     update_queue: Vec<i32>,
     show_filter: bool,
+    filters: Filters,
     sort_by: SortByParam,
     sort_order: SortOrderParam,
 }
@@ -32,7 +35,7 @@ pub enum Msg {
     Filter,
     FilterClosed,
     FilterCancel,
-    SortByChange(SortByParam),
+    SortByChange(String),
     SortOrderChange(SortOrderParam),
     Noop,
 }
@@ -60,6 +63,19 @@ impl Component for Home {
             should_fetch: true,
             update_queue: vec![],
             show_filter: false,
+            filters: Filters {
+                fields: vec![],
+                sort_by: vec![
+                    FilterOption {
+                        value: "title".to_string(),
+                        name: "Title".to_string(),
+                    },
+                    FilterOption {
+                        value: "last_updated".to_string(),
+                        name: "Last Updated".to_string(),
+                    },
+                ],
+            },
             sort_by: SortByParam::Title,
             sort_order: SortOrderParam::Asc,
         }
@@ -100,8 +116,11 @@ impl Component for Home {
             Msg::FilterCancel => {
                 self.show_filter = false;
             }
-            Msg::SortByChange(sort_by) => {
-                self.sort_by = sort_by;
+            Msg::SortByChange(sort_key) => {
+                self.sort_by = match sort_key.as_str() {
+                    "last_updated" => SortByParam::LastUpdated,
+                    _ => SortByParam::Title,
+                };
             }
             Msg::SortOrderChange(sort_order) => {
                 self.sort_order = sort_order;
@@ -149,11 +168,16 @@ impl Component for Home {
                 </MangaList>
                 <Filter
                     show={self.show_filter}
+                    filters={&self.filters}
                     onsortbychange={self.link.callback(|data| Msg::SortByChange(data))}
                     onsortorderchange={self.link.callback(|data| Msg::SortOrderChange(data))}
+                    onfilterchange={self.link.callback(|_: FilterValues| Msg::Noop)}
                     onclose={self.link.callback(|_| Msg::FilterClosed)}
                     oncancel={self.link.callback(|_| Msg::FilterCancel)}
-                    sort_by={&self.sort_by}
+                    sort_key={match self.sort_by {
+                        SortByParam::LastUpdated => "last_updated".to_string(),
+                        _ => "title".to_string(),
+                    }}
                     sort_order={&self.sort_order}
                 />
             </div>
@@ -198,6 +222,8 @@ impl Home {
             page: None,
             genres: None,
             refresh: None,
+            sort_key: None,
+            filters: None,
         }).unwrap();
         let req = Request::get(format!("/api/favorites?{}", params))
             .header("Authorization", self.token.to_string())
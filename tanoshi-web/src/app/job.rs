@@ -112,10 +112,9 @@ impl Agent for Worker {
             }
             Msg::LoginReady(id, data) => {
                 self.fetch_task.remove(&id.clone());
-                self.storage.store(
-                    format!("source-token-{}", &data.clone().source_name).as_str(),
-                    Ok(data.clone().value),
-                );
+                // the session is kept by the server, tokens stored by older versions are removed
+                self.storage
+                    .remove(format!("source-token-{}", &data.source_name).as_str());
                 self.link.respond(id, Response::LoginPosted(data));
             }
             Msg::ReadReady(id, data) => {
@@ -156,14 +155,8 @@ impl Agent for Worker {
             Request::FetchMangas(source_name, params) => {
                 let params = serde_urlencoded::to_string(params).unwrap();
 
-                let source_auth = self
-                    .storage
-                    .restore::<Result<String>>(format!("source-token-{}", source_name).as_str())
-                    .unwrap_or("".to_string());
-
                 let req = HttpRequest::get(format!("/api/source/{}?{}", source_name, params))
                     .header("Authorization", self.token.clone())
-                    .header("SourceAuthorization", source_auth.as_str())
                     .body(Nothing)
                     .expect("failed to build request");
 
@@ -233,6 +226,7 @@ impl Agent for Worker {
             Request::FetchPages(chapter_id, refresh) => {
                 let req =
                     HttpRequest::get(format!("/api/chapter/{}?refresh={}", chapter_id, refresh))
+                        .header("Authorization", self.token.to_string())
                         .body(Nothing)
                         .expect("failed to build request");
 
@@ -11,6 +11,7 @@ pub mod home;
 pub mod job;
 pub mod login;
 pub mod logout;
+pub mod search;
 pub mod select;
 pub mod settings;
 pub mod source;
@@ -0,0 +1,173 @@
+use super::component::{Manga, MangaList, Spinner, WeakComponentLink};
+use http::{Request, Response};
+use yew::format::{Json, Nothing};
+use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask};
+use yew::services::{storage::Area, StorageService};
+use yew::{html, Component, ComponentLink, Html, Properties, ShouldRender};
+use yew_router::components::RouterAnchor;
+
+use tanoshi_lib::rest::{GlobalSearchResponse, SourceSearchResult};
+
+use super::browse::BrowseRoute;
+use super::catalogue::CatalogueRoute;
+
+#[derive(Clone, Properties)]
+pub struct Props {}
+
+pub struct Search {
+    fetch_task: Option<FetchTask>,
+    link: ComponentLink<Self>,
+    token: String,
+    keyword: String,
+    sources: Vec<SourceSearchResult>,
+    is_fetching: bool,
+}
+
+pub enum Msg {
+    KeywordChanged(InputData),
+    Search(FocusEvent),
+    SearchReady(GlobalSearchResponse),
+    Noop,
+}
+
+impl Component for Search {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(_props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let storage = StorageService::new(Area::Local).unwrap();
+        let token = {
+            if let Ok(token) = storage.restore("token") {
+                token
+            } else {
+                "".to_string()
+            }
+        };
+
+        Search {
+            fetch_task: None,
+            link,
+            token,
+            keyword: "".to_string(),
+            sources: vec![],
+            is_fetching: false,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::KeywordChanged(e) => {
+                self.keyword = e.value;
+            }
+            Msg::Search(e) => {
+                e.prevent_default();
+                self.sources.clear();
+                self.search();
+            }
+            Msg::SearchReady(data) => {
+                self.is_fetching = false;
+                self.sources = data.sources;
+            }
+            Msg::Noop => {
+                self.is_fetching = false;
+            }
+        }
+        true
+    }
+
+    fn change(&mut self, _props: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div class="container mx-auto pb-20">
+                <div class="w-full px-2 pb-2 flex justify-between block fixed inset-x-0 top-0 z-50 bg-tachiyomi-blue shadow" style="padding-top: calc(env(safe-area-inset-top) + .5rem)">
+                    <form class="mx-2 flex-grow" onsubmit=self.link.callback(|e| Msg::Search(e))>
+                        <input type="search"
+                            class="w-full px-3 py-2 focus:outline-none text-sm leading-tight text-white bg-tachiyomi-blue-darker shadow-inner rounded appearance-none"
+                            placeholder={"Search all sources"}
+                            value={self.keyword.clone()}
+                            oninput=self.link.callback(|e| Msg::KeywordChanged(e))/>
+                    </form>
+                </div>
+                <div class="pt-12" style="margin-top: calc(env(safe-area-inset-top) + .5rem)">
+                {
+                    match self.is_fetching {
+                        true => html!{<Spinner is_active=true is_fullscreen=false />},
+                        false => html!{for self.sources.iter().map(|source| self.view_source(source))},
+                    }
+                }
+                </div>
+            </div>
+        }
+    }
+}
+
+impl Search {
+    fn view_source(&self, source: &SourceSearchResult) -> Html {
+        let list_link = &WeakComponentLink::<MangaList>::default();
+        html! {
+            <div class="mb-2">
+                <RouterAnchor<BrowseRoute>
+                    classes="flex justify-between mx-2 p-2 border-b border-gray-light hover:bg-gray-200"
+                    route=BrowseRoute::Catalogue(CatalogueRoute::Source(source.source.clone()))>
+                    <span class="text-lg font-semibold">{source.source.to_owned()}</span>
+                    <span class="text-md">{source.mangas.len()}</span>
+                </RouterAnchor<BrowseRoute>>
+                {
+                    match &source.error {
+                        Some(error) => html!{<p class="mx-2 p-2 text-sm text-red-600">{error}</p>},
+                        None if source.mangas.is_empty() => html!{<p class="mx-2 p-2 text-sm text-gray-600">{"No result"}</p>},
+                        None => html!{
+                            <MangaList weak_link=list_link>
+                                { for source.mangas.iter().map(|manga| {
+                                    html_nested!{
+                                    <Manga
+                                        key=manga.id
+                                        id=manga.id
+                                        title=&manga.title
+                                        thumbnail=&manga.thumbnail_url
+                                        is_favorite=&manga.is_favorite />
+                                }})
+                                }
+                            </MangaList>
+                        },
+                    }
+                }
+            </div>
+        }
+    }
+
+    fn search(&mut self) {
+        if self.keyword.trim().is_empty() {
+            return;
+        }
+
+        let req = Request::get(format!(
+            "/api/search?keyword={}",
+            String::from(js_sys::encode_uri_component(&self.keyword))
+        ))
+        .header("Authorization", self.token.to_string())
+        .body(Nothing)
+        .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(
+                |response: Response<Json<Result<GlobalSearchResponse, anyhow::Error>>>| {
+                    if let (meta, Json(Ok(data))) = response.into_parts() {
+                        if meta.status.is_success() {
+                            return Msg::SearchReady(data);
+                        }
+                    }
+                    Msg::Noop
+                },
+            ),
+        ) {
+            self.fetch_task = Some(task);
+            self.is_fetching = true;
+        }
+    }
+}
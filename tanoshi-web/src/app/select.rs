@@ -3,6 +3,7 @@ use web_sys::HtmlElement;
 use yew::format::{Json, Nothing, Text};
 use yew::prelude::*;
 use yew::services::fetch::{FetchService, FetchTask};
+use yew::services::{storage::Area, StorageService};
 use yew::utils::window;
 use yew::{html, Component, ComponentLink, Html, Properties, ShouldRender};
 use yew_router::components::RouterAnchor;
@@ -29,6 +30,7 @@ pub struct Select {
     is_fetching: bool,
     active_tab: Tab,
     button_refs: Vec<NodeRef>,
+    token: String,
 }
 
 pub enum Msg {
@@ -37,6 +39,10 @@ pub enum Msg {
     ChangeToInstalledTab,
     InstallExtension(usize),
     ExtensionInstalled,
+    UninstallExtension(String),
+    ExtensionUninstalled,
+    UpdateAllExtensions,
+    ExtensionsUpdated,
     Noop,
 }
 
@@ -45,6 +51,14 @@ impl Component for Select {
     type Properties = Props;
 
     fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let storage = StorageService::new(Area::Local).unwrap();
+        let token = {
+            if let Ok(token) = storage.restore("token") {
+                token
+            } else {
+                "".to_string()
+            }
+        };
         Select {
             fetch_task: None,
             link,
@@ -52,6 +66,7 @@ impl Component for Select {
             is_fetching: false,
             active_tab: Tab::Installed,
             button_refs: vec![NodeRef::default(), NodeRef::default()],
+            token,
         }
     }
 
@@ -109,6 +124,18 @@ impl Component for Select {
             Msg::ExtensionInstalled => {
                 self.fetch_sources();
             }
+            Msg::UninstallExtension(name) => {
+                self.uninstall_source(name);
+            }
+            Msg::ExtensionUninstalled => {
+                self.fetch_sources();
+            }
+            Msg::UpdateAllExtensions => {
+                self.update_sources();
+            }
+            Msg::ExtensionsUpdated => {
+                self.fetch_sources();
+            }
             Msg::Noop => {}
         }
         true
@@ -128,6 +155,11 @@ impl Component for Select {
                         class="hover:bg-tachiyomi-blue-darker rounded flex-grow">
                         <span class="text-white m-1">{"Available"}</span>
                     </button>
+                    <RouterAnchor<BrowseRoute>
+                        classes="hover:bg-tachiyomi-blue-darker rounded flex-none"
+                        route=BrowseRoute::Search>
+                        <svg fill="none" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" viewBox="0 0 24 24" width="24" height="24" stroke="currentColor" class="mx-2 my-1 text-white"><path d="M21 21l-6-6m2-5a7 7 0 11-14 0 7 7 0 0114 0z"></path></svg>
+                    </RouterAnchor<BrowseRoute>>
                 </div>
                 <Spinner is_active=self.is_fetching is_fullscreen=true />
                 {
@@ -155,14 +187,30 @@ impl Select {
         html! {
             <div class="flex flex-col rounded-lg border border-grey-light mx-2 shadow" style="margin-top: calc(env(safe-area-inset-top) + .5rem)">
             {
-                for sources.iter().map(|source| html!{
-                    <RouterAnchor<BrowseRoute>
-                        classes="flex inline-flex justify-between border-b border-gray-light p-2 content-center hover:bg-gray-200"
-                        route=BrowseRoute::Catalogue(CatalogueRoute::Source(source.name.clone()))>
-                        <span class="text-lg font-semibold">{source.name.to_owned()}</span>
-                        <span class="text-md mx-2">{source.installed_version.to_owned()}</span>
-                    </RouterAnchor<BrowseRoute>>
-                })
+                for sources.iter().map(|source| {
+                    let name = source.name.clone();
+                    html!{
+                    <div class="flex inline-flex justify-between border-b border-gray-light content-center hover:bg-gray-200">
+                        <RouterAnchor<BrowseRoute>
+                            classes="flex flex-grow inline-flex justify-between p-2"
+                            route=BrowseRoute::Catalogue(CatalogueRoute::Source(source.name.clone()))>
+                            <span class="text-lg font-semibold">{source.name.to_owned()}</span>
+                            <span class="text-md mx-2">{source.installed_version.to_owned()}</span>
+                        </RouterAnchor<BrowseRoute>>
+                        {
+                            if !source.path.is_empty() {
+                                html!{
+                                    <button class="bg-gray-300 hover:bg-gray-400 text-gray-800 font-bold px-4 m-2 rounded"
+                                        onclick={self.link.callback(move |_| Msg::UninstallExtension(name.clone()))}>
+                                        {"Uninstall"}
+                                    </button>
+                                }
+                            } else {
+                                html!{}
+                            }
+                        }
+                    </div>
+                }})
             }
             </div>
         }
@@ -171,11 +219,28 @@ impl Select {
     fn available_view(&self) -> Html {
         html! {
             <div class="flex flex-col rounded-lg border border-grey-light mx-2 shadow" style="margin-top: calc(env(safe-area-inset-top) + .5rem)">
+            {
+                if self.sources.iter().any(|s| s.update) {
+                    html!{
+                        <div class="flex inline-flex justify-end border-b border-gray-light p-2">
+                            <button class="bg-gray-300 hover:bg-gray-400 text-gray-800 font-bold px-4 rounded"
+                                onclick={self.link.callback(|_| Msg::UpdateAllExtensions)}>
+                                {"Update all"}
+                            </button>
+                        </div>
+                    }
+                } else {
+                    html!{}
+                }
+            }
             {
                 for (0..self.sources.len()).map(|i| html!{
                     <div
                         class="flex inline-flex justify-between border-b border-gray-light p-2 content-center hover:bg-gray-200">
-                        <span class="text-lg font-semibold">{self.sources[i].name.clone()}</span>
+                        <div>
+                            <span class="text-lg font-semibold">{self.sources[i].name.clone()}</span>
+                            <span class="text-sm text-gray-600 mx-2">{self.sources[i].repository.clone()}</span>
+                        </div>
                         <div>
                         <span class="text-md mx-2">{self.sources[i].version.clone()}</span>
                         <button class="bg-gray-300 hover:bg-gray-400 text-gray-800 font-bold px-4 rounded"
@@ -226,6 +291,7 @@ impl Select {
 
     fn install_source(&mut self, name: String) {
         let req = Request::post(format!("/api/source/install/{}", &name))
+            .header("Authorization", self.token.to_string())
             .body(Nothing)
             .expect("failed to build request");
 
@@ -244,4 +310,48 @@ impl Select {
             self.is_fetching = true;
         }
     }
+
+    fn uninstall_source(&mut self, name: String) {
+        let req = Request::delete(format!("/api/source/{}", &name))
+            .header("Authorization", self.token.to_string())
+            .body(Nothing)
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(|response: Response<Text>| {
+                if let (meta, Ok(_)) = response.into_parts() {
+                    if meta.status.is_success() {
+                        return Msg::ExtensionUninstalled;
+                    }
+                }
+                Msg::Noop
+            }),
+        ) {
+            self.fetch_task = Some(FetchTask::from(task));
+            self.is_fetching = true;
+        }
+    }
+
+    fn update_sources(&mut self) {
+        let req = Request::post("/api/source/update")
+            .header("Authorization", self.token.to_string())
+            .body(Nothing)
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(|response: Response<Text>| {
+                if let (meta, Ok(_)) = response.into_parts() {
+                    if meta.status.is_success() {
+                        return Msg::ExtensionsUpdated;
+                    }
+                }
+                Msg::Noop
+            }),
+        ) {
+            self.fetch_task = Some(task);
+            self.is_fetching = true;
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use super::component::model::{
     BackgroundColor, Claims, PageRendering, ReadingDirection, SettingParams, User,
 };
+use super::component::SourceSettings;
 use serde::Deserialize;
 use yew::format::{Json, Nothing, Text};
 use yew::services::fetch::{FetchService, FetchTask, Request, Response};
@@ -25,6 +26,11 @@ pub struct UserListResponse {
     users: Vec<User>,
 }
 
+#[derive(Deserialize)]
+pub struct TelegramLinkResponse {
+    command: String,
+}
+
 #[derive(Clone, Properties)]
 pub struct Props {}
 
@@ -41,6 +47,7 @@ pub struct Settings {
     me_password: Option<String>,
     me_confirm_password: Option<String>,
     me_telegram_chat_id: Option<i64>,
+    telegram_command: Option<String>,
     change_password: bool,
     closure: Closure<dyn FnMut(JsValue)>,
 }
@@ -62,6 +69,10 @@ pub enum Msg {
     PasswordChangedReady,
     SaveUser(usize),
     SaveUserSuccess(usize),
+    LinkTelegram,
+    TelegramLinkReady(String),
+    UnlinkTelegram,
+    TelegramUnlinked,
     ClearCache,
     Noop,
 }
@@ -101,6 +112,7 @@ impl Component for Settings {
             me_confirm_password: None,
             me_password: None,
             me_telegram_chat_id: None,
+            telegram_command: None,
             change_password: false,
             closure,
         }
@@ -189,6 +201,19 @@ impl Component for Settings {
                 self.users[i].is_edit = false;
                 self.users[i].is_new = false;
             }
+            Msg::LinkTelegram => {
+                self.link_telegram();
+            }
+            Msg::TelegramLinkReady(command) => {
+                self.telegram_command = Some(command);
+            }
+            Msg::UnlinkTelegram => {
+                self.unlink_telegram();
+            }
+            Msg::TelegramUnlinked => {
+                self.me_telegram_chat_id = None;
+                self.telegram_command = None;
+            }
             Msg::ClearCache => {
                 let _ = window()
                     .expect("should get window")
@@ -227,7 +252,12 @@ impl Component for Settings {
                 {self.account_setting()}
                 {
                     if self.is_admin {
-                        self.admin_settings()
+                        html!{
+                            <>
+                                {self.admin_settings()}
+                                <SourceSettings token=self.token.clone() />
+                            </>
+                        }
                     } else {
                         html!{}
                     }
@@ -333,6 +363,50 @@ impl Settings {
         }
     }
 
+    fn link_telegram(&mut self) {
+        let req = Request::post("/api/user/telegram")
+            .header("Authorization", self.token.clone())
+            .body(Nothing)
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(
+                |response: Response<Json<Result<TelegramLinkResponse, anyhow::Error>>>| {
+                    if let (meta, Json(Ok(res))) = response.into_parts() {
+                        if meta.status.is_success() {
+                            return Msg::TelegramLinkReady(res.command);
+                        }
+                    }
+                    Msg::Noop
+                },
+            ),
+        ) {
+            self.fetch_task = Some(task);
+        }
+    }
+
+    fn unlink_telegram(&mut self) {
+        let req = Request::delete("/api/user/telegram")
+            .header("Authorization", self.token.clone())
+            .body(Nothing)
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(move |response: Response<Text>| {
+                if let (meta, Ok(_res)) = response.into_parts() {
+                    if meta.status.is_success() {
+                        return Msg::TelegramUnlinked;
+                    }
+                }
+                Msg::Noop
+            }),
+        ) {
+            self.fetch_task = Some(task);
+        }
+    }
+
     fn register_user(&mut self, i: usize) {
         let req = Request::post("/api/register")
             .header("Authorization", self.token.clone())
@@ -445,6 +519,29 @@ impl Settings {
         }
     }
 
+    fn telegram_setting(&self) -> Html {
+        match (self.me_telegram_chat_id, &self.telegram_command) {
+            (Some(_), _) => html! {
+                <>
+                    <span class="mr-2">{"Linked"}</span>
+                    <button class="bg-gray-300 hover:bg-gray-400 text-gray-800 font-bold py-1 px-2 rounded"
+                        onclick=self.link.callback(|_| Msg::UnlinkTelegram)>
+                        {"Unlink"}
+                    </button>
+                </>
+            },
+            (None, Some(command)) => html! {
+                <span>{"Send "}<code class="select-all">{command}</code>{" to the bot within 10 minutes, then reload"}</span>
+            },
+            (None, None) => html! {
+                <button class="bg-gray-300 hover:bg-gray-400 text-gray-800 font-bold py-1 px-2 rounded"
+                    onclick=self.link.callback(|_| Msg::LinkTelegram)>
+                    {"Link"}
+                </button>
+            },
+        }
+    }
+
     fn account_setting(&self) -> Html {
         html! {
             <div class="flex flex-col rounded-lg border border-grey-light m-2" id="account-setting">
@@ -455,6 +552,7 @@ impl Settings {
                 {self.setting_card("Role", html! {
                     <span>{self.me_role.clone()}</span>
                 })}
+                {self.setting_card("Telegram", self.telegram_setting())}
                 {
                     if self.change_password {
                         html!{
@@ -1,12 +1,18 @@
 use super::component::{Filter, Manga, MangaList, Spinner, WeakComponentLink};
+use http::{Request, Response};
 use web_sys::HtmlElement;
+use yew::format::{Json, Nothing};
 use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask};
+use yew::services::{storage::Area, StorageService};
 use yew::{html, Component, ComponentLink, Html, Properties, ShouldRender};
 
 use yew::utils::{document, window};
 
-use tanoshi_lib::manga::{Manga as MangaModel, Params, SortByParam, SortOrderParam, SourceLogin};
-use tanoshi_lib::rest::GetMangasResponse;
+use tanoshi_lib::manga::{
+    FilterValues, Filters, Manga as MangaModel, Params, SortOrderParam, SourceLogin,
+};
+use tanoshi_lib::rest::{GetFiltersResponse, GetMangasResponse};
 
 use crate::app::job;
 
@@ -19,7 +25,9 @@ pub struct Props {
 }
 
 pub struct Source {
+    fetch_task: Option<FetchTask>,
     link: ComponentLink<Self>,
+    token: String,
     source_name: String,
     page: i32,
     mangas: Vec<MangaModel>,
@@ -30,11 +38,14 @@ pub struct Source {
     is_login_page: bool,
     login: SourceLogin,
     show_filter: bool,
-    sort_by: SortByParam,
+    filters: Filters,
+    filter_values: FilterValues,
+    sort_key: Option<String>,
     sort_order: SortOrderParam,
 }
 
 pub enum Msg {
+    FiltersReady(GetFiltersResponse),
     MangasReady(GetMangasResponse),
     ScrolledDown,
     KeywordChanged(InputData),
@@ -49,8 +60,9 @@ pub enum Msg {
     Filter,
     FilterClosed,
     FilterCancel,
-    SortByChange(SortByParam),
+    SortByChange(String),
     SortOrderChange(SortOrderParam),
+    FilterChange(FilterValues),
     Noop,
 }
 
@@ -82,8 +94,19 @@ impl Component for Source {
         });
         let worker = job::Worker::bridge(worker_callback);
 
+        let storage = StorageService::new(Area::Local).unwrap();
+        let token = {
+            if let Ok(token) = storage.restore("token") {
+                token
+            } else {
+                "".to_string()
+            }
+        };
+
         Source {
+            fetch_task: None,
             link,
+            token,
             source_name: props.source_name,
             page: 1,
             mangas: vec![],
@@ -94,7 +117,9 @@ impl Component for Source {
             is_login_page: false,
             login: SourceLogin::default(),
             show_filter: false,
-            sort_by: SortByParam::Views,
+            filters: Filters::default(),
+            filter_values: FilterValues::default(),
+            sort_key: None,
             sort_order: SortOrderParam::Desc,
         }
     }
@@ -102,6 +127,10 @@ impl Component for Source {
     fn change(&mut self, props: Self::Properties) -> ShouldRender {
         if self.source_name != props.clone().source_name {
             self.source_name = props.source_name;
+            self.filters = Filters::default();
+            self.filter_values = FilterValues::default();
+            self.sort_key = None;
+            self.fetch_filters();
             return true;
         }
         return false;
@@ -109,12 +138,16 @@ impl Component for Source {
 
     fn rendered(&mut self, first_render: bool) {
         if first_render {
+            self.fetch_filters();
             self.fetch_mangas();
         }
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
+            Msg::FiltersReady(data) => {
+                self.filters = data.filters;
+            }
             Msg::MangasReady(data) => {
                 self.is_fetching = false;
 
@@ -184,12 +217,15 @@ impl Component for Source {
             Msg::FilterCancel => {
                 self.show_filter = false;
             }
-            Msg::SortByChange(sort_by) => {
-                self.sort_by = sort_by;
+            Msg::SortByChange(sort_key) => {
+                self.sort_key = Some(sort_key);
             }
             Msg::SortOrderChange(sort_order) => {
                 self.sort_order = sort_order;
             }
+            Msg::FilterChange(filter_values) => {
+                self.filter_values = filter_values;
+            }
             Msg::Noop => {
                 return false;
             }
@@ -226,12 +262,15 @@ impl Component for Source {
                 {if !self.is_login_page{self.view_mangas()} else {self.view_login_page()}}
                 <Filter
                     show={self.show_filter}
+                    filters={&self.filters}
                     onsortbychange={self.link.callback(|data| Msg::SortByChange(data))}
                     onsortorderchange={self.link.callback(|data| Msg::SortOrderChange(data))}
+                    onfilterchange={self.link.callback(|data| Msg::FilterChange(data))}
                     onclose={self.link.callback(|_| Msg::FilterClosed)}
                     oncancel={self.link.callback(|_| Msg::FilterCancel)}
-                    sort_by={&self.sort_by}
+                    sort_key={&self.sort_key}
                     sort_order={&self.sort_order}
+                    values={&self.filter_values}
                 />
             </div>
         };
@@ -356,7 +395,7 @@ impl Source {
             self.source_name.clone(),
             Params {
                 keyword: Some(self.keyword.to_owned()),
-                sort_by: Some(self.sort_by.clone()),
+                sort_by: None,
                 sort_order: Some(self.sort_order.clone()),
                 page: Some(self.page.to_string()),
                 genres: None,
@@ -364,8 +403,37 @@ impl Source {
                     1 => Some(true),
                     _ => None,
                 },
+                sort_key: self.sort_key.clone(),
+                filters: if self.filter_values.0.is_empty() {
+                    None
+                } else {
+                    Some(self.filter_values.clone())
+                },
             },
         ));
         self.is_fetching = true;
     }
+
+    fn fetch_filters(&mut self) {
+        let req = Request::get(format!("/api/source/{}/filters", &self.source_name))
+            .header("Authorization", self.token.to_string())
+            .body(Nothing)
+            .expect("failed to build request");
+
+        if let Ok(task) = FetchService::fetch(
+            req,
+            self.link.callback(
+                |response: Response<Json<Result<GetFiltersResponse, anyhow::Error>>>| {
+                    if let (meta, Json(Ok(data))) = response.into_parts() {
+                        if meta.status.is_success() {
+                            return Msg::FiltersReady(data);
+                        }
+                    }
+                    Msg::Noop
+                },
+            ),
+        ) {
+            self.fetch_task = Some(task);
+        }
+    }
 }
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS source_config
+(
+    name    TEXT PRIMARY KEY,
+    config  TEXT NOT NULL,
+    updated TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS source_session
+(
+    user_id     INTEGER NOT NULL,
+    source      TEXT    NOT NULL,
+    session     BLOB    NOT NULL,
+    credentials BLOB,
+    expires     TIMESTAMP,
+    updated     TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, source),
+    FOREIGN KEY (user_id)
+        REFERENCES "user" (id)
+        ON DELETE CASCADE
+        On UPDATE NO ACTION
+);
@@ -0,0 +1,3 @@
+ALTER TABLE "user" ADD COLUMN feed_token TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_feed_token_idx ON "user" (feed_token);
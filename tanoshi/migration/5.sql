@@ -0,0 +1,3 @@
+ALTER TABLE "user" ADD COLUMN telegram_chat_id INTEGER;
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_telegram_chat_id_idx ON "user" (telegram_chat_id);
@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS webhook
+(
+    id       INTEGER PRIMARY KEY,
+    user_id  INTEGER NOT NULL,
+    url      TEXT NOT NULL,
+    events   TEXT NOT NULL,
+    template TEXT,
+    secret   TEXT NOT NULL,
+    created  TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated  TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES "user" (id)
+        ON DELETE CASCADE
+        On UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS webhook_user_idx ON webhook (user_id);
+
+CREATE TABLE IF NOT EXISTS webhook_delivery
+(
+    id         INTEGER PRIMARY KEY,
+    webhook_id INTEGER NOT NULL,
+    event      TEXT NOT NULL,
+    body       TEXT NOT NULL,
+    attempt    INTEGER NOT NULL,
+    status     INTEGER,
+    error      TEXT,
+    at         TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (webhook_id)
+        REFERENCES webhook (id)
+        ON DELETE CASCADE
+        On UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS webhook_delivery_idx ON webhook_delivery (webhook_id, id);
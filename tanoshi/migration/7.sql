@@ -0,0 +1,18 @@
+ALTER TABLE page ADD COLUMN local_path TEXT;
+
+CREATE TABLE IF NOT EXISTS download
+(
+    id         INTEGER PRIMARY KEY,
+    chapter_id INTEGER NOT NULL UNIQUE,
+    status     TEXT NOT NULL DEFAULT 'queued',
+    pages      INTEGER NOT NULL DEFAULT 0,
+    error      TEXT,
+    created    TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated    TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (chapter_id)
+        REFERENCES chapter (id)
+        ON DELETE CASCADE
+        On UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS download_status_idx ON download (status, id);
@@ -0,0 +1,2 @@
+ALTER TABLE "user" ADD COLUMN telegram_link_token TEXT;
+ALTER TABLE "user" ADD COLUMN telegram_link_expires TIMESTAMP;
@@ -1,11 +1,15 @@
-use anyhow::Result;
 use argon2::{self, Config};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use rand;
 use rand::Rng;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 
-use crate::auth::{Claims, User, UserResponse};
+use crate::auth::{Claims, ImageClaims, User, UserResponse};
+use crate::error::Error;
+
+/// Seconds an image token is valid, opening a chapter gives its pages a new one
+static IMAGE_TOKEN_TTL: i64 = 6 * 60 * 60;
+static IMAGE_SCOPE: &str = "image";
 
 #[derive(Clone)]
 pub struct Auth {
@@ -17,91 +21,67 @@ impl Auth {
         Self { database_path }
     }
 
-    fn connect_db(&self) -> Connection {
-        Connection::open(self.database_path.clone()).unwrap()
+    fn connect_db(&self) -> Result<Connection, Error> {
+        Ok(Connection::open(self.database_path.clone())?)
     }
 
-    pub async fn register(&self, user: User) -> UserResponse {
-        let db = self.connect_db();
-        let hashed = Auth::hash(user.password.unwrap_or("tanoshi123".to_string()).as_bytes());
-        match db.execute(
+    pub async fn register(&self, user: User) -> Result<UserResponse, Error> {
+        let db = self.connect_db()?;
+        let hashed = Auth::hash(user.password.as_deref().unwrap_or("tanoshi123").as_bytes())?;
+        db.execute(
             r#"INSERT INTO "user"(username, password, role) VALUES (?1, ?2, ?3)"#,
             params![user.username, hashed, user.role],
-        ) {
-            Ok(_) => UserResponse {
-                claim: None,
-                token: None,
-                status: "success".to_string(),
-            },
-            Err(e) => UserResponse {
-                claim: None,
-                token: None,
-                status: format!("failed create account, reason: {}", e.to_string()),
-            },
-        }
-    }
-
-    pub async fn login(&self, secret: String, user: User) -> UserResponse {
-        let db = self.connect_db();
-        let account = db
-            .query_row(
-                r#"SELECT username, password, role FROM "user" WHERE username = ?1"#,
-                params![user.username],
-                |row| {
-                    Ok(User {
-                        username: row.get(0)?,
-                        password: row.get(1)?,
-                        role: row.get(2)?,
-                    })
-                },
-            )
-            .unwrap();
-
-        if Auth::verify(account.password.unwrap(), user.password.unwrap().as_bytes()) {
-            let user_claims = Claims {
-                sub: account.username,
-                role: account.role,
-                exp: 10000000000,
-            };
-            let token = match encode(
-                &Header::default(),
-                &user_claims,
-                &EncodingKey::from_secret(secret.as_bytes()),
-            ) {
-                Ok(t) => t,
-                Err(_) => panic!(), // in practice you would return the error
-            };
-            return UserResponse {
-                claim: Some(user_claims),
-                token: Some(token),
-                status: "success".to_string(),
-            };
-        }
-        return UserResponse {
+        )
+        .map_err(|e| match Error::from(e) {
+            Error::Conflict(_) => Error::Conflict(format!("{} already exists", user.username)),
+            e => e,
+        })?;
+        Ok(UserResponse {
             claim: None,
             token: None,
-            status: "failed".to_string(),
-        };
+            status: "success".to_string(),
+        })
     }
 
-    pub async fn user_list(&self) -> Vec<User> {
-        let db = self.connect_db();
-        let mut stmt = db.prepare(r#"SELECT username, role FROM "user""#).unwrap();
-        stmt.query_map(params![], |row| {
-            Ok(User {
-                username: row.get(0)?,
-                role: row.get(1)?,
-                password: None,
-            })
+    pub async fn login(&self, secret: String, user: User) -> Result<UserResponse, Error> {
+        let password = user
+            .password
+            .ok_or_else(|| Error::BadRequest("password is required".to_string()))?;
+        let user_claims = self
+            .check(&user.username, &password)
+            .ok_or_else(|| Error::Unauthorized("invalid username or password".to_string()))?;
+        let token = encode(
+            &Header::default(),
+            &user_claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .map_err(|e| Error::Internal(format!("failed to create token: {}", e)))?;
+        Ok(UserResponse {
+            claim: Some(user_claims),
+            token: Some(token),
+            status: "success".to_string(),
         })
-        .unwrap()
-        .filter_map(|u| u.ok())
-        .collect()
     }
 
-    pub async fn change_password(&self, username: String, password: String) -> Result<()> {
-        let db = self.connect_db();
-        let hashed = Auth::hash(password.as_bytes());
+    pub async fn user_list(&self) -> Result<Vec<User>, Error> {
+        let db = self.connect_db()?;
+        let mut stmt = db.prepare(r#"SELECT username, role, telegram_chat_id FROM "user""#)?;
+        let users = stmt
+            .query_map(params![], |row| {
+                Ok(User {
+                    username: row.get(0)?,
+                    role: row.get(1)?,
+                    password: None,
+                    telegram_chat_id: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<User>, _>>()?;
+        Ok(users)
+    }
+
+    pub async fn change_password(&self, username: String, password: String) -> Result<(), Error> {
+        let db = self.connect_db()?;
+        let hashed = Auth::hash(password.as_bytes())?;
         db.execute(
             r#"UPDATE "user" SET password = ?1 WHERE username = ?2"#,
             params![hashed, username],
@@ -109,15 +89,177 @@ impl Auth {
         Ok(())
     }
 
-    pub async fn modify_user_role(&self, user: User) -> Result<()> {
-        let db = self.connect_db();
-        db.execute(
+    pub async fn modify_user_role(&self, user: User) -> Result<(), Error> {
+        let db = self.connect_db()?;
+        let updated = db.execute(
             r#"UPDATE "user" SET role = ?1 WHERE username = ?2"#,
             params![user.role, user.username],
         )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("user {} not found", user.username)));
+        }
         Ok(())
     }
 
+    /// Returns the claims of a user if `password` is correct, for clients that can only send basic auth
+    pub fn check(&self, username: &str, password: &str) -> Option<Claims> {
+        let db = self.connect_db().ok()?;
+        let (password_hash, role, telegram_chat_id): (String, String, Option<i64>) = db
+            .query_row(
+                r#"SELECT password, role, telegram_chat_id FROM "user" WHERE username = ?1"#,
+                params![username],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .ok()??;
+        if !Auth::verify(password_hash, password.as_bytes()) {
+            return None;
+        }
+        Some(Claims {
+            sub: username.to_string(),
+            role,
+            exp: 10000000000,
+            telegram_chat_id,
+        })
+    }
+
+    /// Returns the feed token of a user, creating one if the user doesn't have any
+    pub async fn feed_token(&self, username: String) -> Result<String, Error> {
+        let db = self.connect_db()?;
+        let token: Option<String> = db.query_row(
+            r#"SELECT feed_token FROM "user" WHERE username = ?1"#,
+            params![username],
+            |row| row.get(0),
+        )?;
+        match token {
+            Some(token) => Ok(token),
+            None => self.reset_feed_token(username).await,
+        }
+    }
+
+    /// Replaces the feed token of a user, links with the old token stop working
+    pub async fn reset_feed_token(&self, username: String) -> Result<String, Error> {
+        let db = self.connect_db()?;
+        let token: [u8; 32] = rand::thread_rng().gen();
+        let token = base64::encode_config(token, base64::URL_SAFE_NO_PAD);
+        db.execute(
+            r#"UPDATE "user" SET feed_token = ?1 WHERE username = ?2"#,
+            params![token, username],
+        )?;
+        Ok(token)
+    }
+
+    /// Returns the claims of the user a feed token belongs to
+    pub fn validate_feed_token(&self, token: &str) -> Option<Claims> {
+        let db = self.connect_db().ok()?;
+        db.query_row(
+            r#"SELECT username, role, telegram_chat_id FROM "user" WHERE feed_token = ?1"#,
+            params![token],
+            |row| {
+                Ok(Claims {
+                    sub: row.get(0)?,
+                    role: row.get(1)?,
+                    exp: 10000000000,
+                    telegram_chat_id: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+        .ok()?
+    }
+
+    /// Returns `claims` with the telegram chat saved now, the one in a token may be outdated
+    pub async fn refresh_claims(&self, claims: Claims) -> Result<Claims, Error> {
+        let telegram_chat_id = self.telegram_chat_id(&claims.sub)?;
+        Ok(Claims {
+            telegram_chat_id,
+            ..claims
+        })
+    }
+
+    pub fn telegram_chat_id(&self, username: &str) -> Result<Option<i64>, Error> {
+        let db = self.connect_db()?;
+        Ok(db.query_row(
+            r#"SELECT telegram_chat_id FROM "user" WHERE username = ?1"#,
+            params![username],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Returns a token that links the chat it is sent from to the user with `/start <token>`,
+    /// it can be used once within 10 minutes
+    pub async fn telegram_link_token(&self, username: String) -> Result<String, Error> {
+        let db = self.connect_db()?;
+        let token: [u8; 24] = rand::thread_rng().gen();
+        let token = base64::encode_config(token, base64::URL_SAFE_NO_PAD);
+        db.execute(
+            r#"UPDATE "user" SET
+                telegram_link_token = ?1,
+                telegram_link_expires = datetime('now', '+10 minutes')
+            WHERE username = ?2"#,
+            params![token, username],
+        )?;
+        Ok(token)
+    }
+
+    /// Links a telegram chat to the user of a link token, returns the user or `None`
+    /// when the token is unknown or expired. A chat can only be linked to one user.
+    pub fn link_telegram_chat(
+        &self,
+        token: &str,
+        telegram_chat_id: i64,
+    ) -> Result<Option<String>, Error> {
+        let db = self.connect_db()?;
+        let username: Option<String> = db
+            .query_row(
+                r#"SELECT username FROM "user"
+                WHERE telegram_link_token = ?1 AND telegram_link_expires > datetime('now')"#,
+                params![token],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let username = match username {
+            Some(username) => username,
+            None => return Ok(None),
+        };
+        db.execute(
+            r#"UPDATE "user" SET
+                telegram_chat_id = ?1,
+                telegram_link_token = NULL,
+                telegram_link_expires = NULL
+            WHERE username = ?2"#,
+            params![telegram_chat_id, username],
+        )
+        .map_err(|e| match Error::from(e) {
+            Error::Conflict(_) => {
+                Error::Conflict("telegram chat is linked to another user".to_string())
+            }
+            e => e,
+        })?;
+        Ok(Some(username))
+    }
+
+    pub async fn unlink_telegram_chat(&self, username: String) -> Result<(), Error> {
+        let db = self.connect_db()?;
+        db.execute(
+            r#"UPDATE "user" SET telegram_chat_id = NULL WHERE username = ?1"#,
+            params![username],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the user a telegram chat is linked to
+    pub fn telegram_user(&self, telegram_chat_id: i64) -> Result<Option<String>, Error> {
+        let db = self.connect_db()?;
+        Ok(db
+            .query_row(
+                r#"SELECT username FROM "user" WHERE telegram_chat_id = ?1"#,
+                params![telegram_chat_id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
     pub fn validate(secret: String, token: String) -> Option<Claims> {
         match decode::<Claims>(
             &token,
@@ -129,13 +271,51 @@ impl Auth {
         }
     }
 
-    fn hash(password: &[u8]) -> String {
+    /// Returns a token that only loads images of `username`, for urls the browser loads by itself.
+    /// It is signed with its own key, so it is never accepted as a login token, and the other way around.
+    pub fn image_token(secret: &str, username: &str) -> Result<String, Error> {
+        let claims = ImageClaims {
+            sub: username.to_string(),
+            scope: IMAGE_SCOPE.to_string(),
+            exp: (chrono::Utc::now().timestamp() + IMAGE_TOKEN_TTL) as usize,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(&Auth::image_key(secret)),
+        )
+        .map_err(|e| Error::Internal(format!("failed to create image token: {}", e)))
+    }
+
+    /// Returns the username of a valid image token
+    pub fn validate_image_token(secret: &str, token: &str) -> Option<String> {
+        let claims = decode::<ImageClaims>(
+            token,
+            &DecodingKey::from_secret(&Auth::image_key(secret)),
+            &Validation::default(),
+        )
+        .ok()?
+        .claims;
+        if claims.scope == IMAGE_SCOPE {
+            Some(claims.sub)
+        } else {
+            None
+        }
+    }
+
+    fn image_key(secret: &str) -> Vec<u8> {
+        format!("{}:{}", IMAGE_SCOPE, secret).into_bytes()
+    }
+
+    fn hash(password: &[u8]) -> Result<String, Error> {
         let salt: [u8; 32] = rand::thread_rng().gen();
         let config = Config::default();
-        return argon2::hash_encoded(password, &salt, &config).unwrap();
+        argon2::hash_encoded(password, &salt, &config)
+            .map_err(|e| Error::Internal(format!("failed to hash password: {}", e)))
     }
 
+    /// A malformed hash never matches
     fn verify(hash: String, password: &[u8]) -> bool {
-        return argon2::verify_encoded(&hash, password).unwrap();
+        argon2::verify_encoded(&hash, password).unwrap_or(false)
     }
 }
@@ -8,6 +8,8 @@ pub struct User {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password: Option<String>,
     pub role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telegram_chat_id: Option<i64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -19,9 +21,19 @@ pub struct UserResponse {
     pub status: String,
 }
 
+/// Claims of a token that only loads images of `sub`, see `Auth::image_token`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageClaims {
+    pub sub: String,
+    pub scope: String,
+    pub exp: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Claims {
     pub sub: String,
     pub role: String,
     pub exp: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telegram_chat_id: Option<i64>,
 }
@@ -3,6 +3,17 @@ use rand::{thread_rng, Rng};
 use std::collections::BTreeMap;
 use std::iter;
 
+/// An extension repository, a directory with `index.json` and the plugins it lists
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct Repository {
+    pub name: String,
+    /// `http(s)://` or `file://` url of the directory, `{os}` is replaced by the current operating system
+    pub url: String,
+    /// When a source is listed in more than one repository, the one with higher priority is used
+    #[serde(default)]
+    pub priority: i32,
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Config {
     pub base_url: Option<String>,
@@ -17,10 +28,136 @@ pub struct Config {
     #[serde(default = "default_update_interval")]
     pub update_interval: u64,
     pub telegram_token: Option<String>,
+    /// Telegram Bot API server the bot talks to
+    #[serde(default = "default_telegram_api")]
+    pub telegram_api: String,
     #[serde(default = "default_plugin_path")]
     pub plugin_path: String,
+    /// Directory downloaded chapters are saved in
+    #[serde(default = "default_download_path")]
+    pub download_path: String,
     #[serde(default = "BTreeMap::new")]
     pub plugin_config: BTreeMap<String, serde_yaml::Value>,
+    #[serde(default = "default_plugin_watch_interval")]
+    pub plugin_watch_interval: u64,
+    #[serde(default)]
+    pub local_path: Vec<String>,
+    #[serde(default = "default_repositories")]
+    pub repositories: Vec<Repository>,
+    /// Base64 encoded ed25519 public keys, when set only plugins signed by one of them are loaded
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// Load plugins without a manifest, e.g copied manually, when `trusted_keys` is not set
+    #[serde(default)]
+    pub allow_unsigned_plugins: bool,
+    #[serde(flatten)]
+    pub extension_limits: ExtensionLimits,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+}
+
+/// Deliveries of webhooks
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct WebhookConfig {
+    /// Attempts of a delivery before giving up on it
+    #[serde(default = "default_webhook_attempts")]
+    pub attempts: u32,
+    /// Seconds before the first retry, doubled for every retry after it
+    #[serde(default = "default_webhook_backoff")]
+    pub backoff: u64,
+    /// Seconds to wait for a response
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+    /// Deliveries kept for each webhook
+    #[serde(default = "default_webhook_history")]
+    pub history: u32,
+    /// Allows webhooks to this machine and private networks, e.g a notification server on the LAN
+    #[serde(default)]
+    pub allow_internal: bool,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            attempts: default_webhook_attempts(),
+            backoff: default_webhook_backoff(),
+            timeout: default_webhook_timeout(),
+            history: default_webhook_history(),
+            allow_internal: false,
+        }
+    }
+}
+
+/// HTTP client extensions use to reach their sources
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct HttpConfig {
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// Seconds to wait for a connection
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Seconds to wait for a response
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64,
+    /// `http://` or `socks5://` proxy every request of extensions goes through
+    pub proxy: Option<String>,
+    /// Largest response body in bytes
+    #[serde(default = "default_max_response_size")]
+    pub max_response_size: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: default_user_agent(),
+            connect_timeout: default_connect_timeout(),
+            read_timeout: default_read_timeout(),
+            proxy: None,
+            max_response_size: default_max_response_size(),
+        }
+    }
+}
+
+/// Limits on calls into extensions
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct ExtensionLimits {
+    /// Seconds to wait for an extension call before giving up on it
+    #[serde(default = "default_extension_timeout")]
+    pub extension_timeout: u64,
+    /// Panics or timeouts in a row before a source is disabled
+    #[serde(default = "default_extension_max_failures")]
+    pub extension_max_failures: u32,
+    /// Seconds a failing source stays disabled
+    #[serde(default = "default_extension_cooldown")]
+    pub extension_cooldown: u64,
+    /// Extension calls running at once across every source
+    #[serde(default = "default_extension_pool_size")]
+    pub extension_pool_size: usize,
+    /// Extension calls running at once for a single source
+    #[serde(default = "default_extension_concurrency")]
+    pub extension_concurrency: usize,
+    /// `extension_concurrency` of specific sources
+    #[serde(default)]
+    pub source_concurrency: BTreeMap<String, usize>,
+    /// Seconds to wait for each source in global search, sources answering later are left out
+    #[serde(default = "default_search_timeout")]
+    pub search_timeout: u64,
+}
+
+impl Default for ExtensionLimits {
+    fn default() -> Self {
+        Self {
+            extension_timeout: default_extension_timeout(),
+            extension_max_failures: default_extension_max_failures(),
+            extension_cooldown: default_extension_cooldown(),
+            extension_pool_size: default_extension_pool_size(),
+            extension_concurrency: default_extension_concurrency(),
+            source_concurrency: BTreeMap::new(),
+            search_timeout: default_search_timeout(),
+        }
+    }
 }
 
 impl Default for Config {
@@ -33,8 +170,18 @@ impl Default for Config {
             cache_ttl: default_cache_ttl(),
             update_interval: default_update_interval(),
             telegram_token: None,
+            telegram_api: default_telegram_api(),
             plugin_path: default_plugin_path(),
+            download_path: default_download_path(),
             plugin_config: Default::default(),
+            plugin_watch_interval: default_plugin_watch_interval(),
+            local_path: vec![],
+            repositories: default_repositories(),
+            trusted_keys: vec![],
+            allow_unsigned_plugins: false,
+            extension_limits: Default::default(),
+            http: Default::default(),
+            webhook: Default::default(),
         }
     }
 }
@@ -51,6 +198,83 @@ fn default_update_interval() -> u64 {
     1
 }
 
+fn default_telegram_api() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_plugin_watch_interval() -> u64 {
+    5
+}
+
+fn default_extension_timeout() -> u64 {
+    60
+}
+
+fn default_extension_max_failures() -> u32 {
+    5
+}
+
+fn default_extension_cooldown() -> u64 {
+    60
+}
+
+fn default_extension_pool_size() -> usize {
+    16
+}
+
+fn default_extension_concurrency() -> usize {
+    4
+}
+
+fn default_search_timeout() -> u64 {
+    15
+}
+
+fn default_user_agent() -> String {
+    format!("tanoshi/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    30
+}
+
+fn default_max_response_size() -> u64 {
+    32 * 1024 * 1024
+}
+
+fn default_webhook_attempts() -> u32 {
+    5
+}
+
+fn default_webhook_backoff() -> u64 {
+    30
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+fn default_webhook_history() -> u32 {
+    50
+}
+
+fn default_download_path() -> String {
+    let path = dirs::home_dir().unwrap().join(".tanoshi").join("downloads");
+    path.to_str().unwrap().to_string()
+}
+
+fn default_repositories() -> Vec<Repository> {
+    vec![Repository {
+        name: "faldez".to_string(),
+        url: "https://raw.githubusercontent.com/faldez/tanoshi-extensions/repo-{os}".to_string(),
+        priority: 0,
+    }]
+}
+
 fn default_database_path() -> String {
     let path = dirs::home_dir().unwrap().join(".tanoshi");
     if !path.exists() {
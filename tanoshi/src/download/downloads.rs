@@ -0,0 +1,498 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use warp::Rejection;
+
+use tanoshi_lib::rest::{Download, DownloadStatus, DownloadsResponse, EnqueueResponse};
+
+use crate::auth::Claims;
+use crate::error::Error;
+use crate::extension::guard::Priority;
+use crate::extension::manga::Manga;
+use crate::handlers::reject;
+
+/// How often the queue is read when nothing was queued
+static POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadParam {
+    /// Only chapters the user never opened
+    pub unread: Option<bool>,
+}
+
+/// Chapters of users downloaded to `download_path`, so they can be read when their source
+/// is down or removed them.
+///
+/// The queue is the `download` table, a chapter at a time is downloaded with `Priority::Background`,
+/// and pages already saved are skipped, so a restart or a retry continues where it stopped.
+/// Files are saved as `<source>/<manga_id>/<chapter_id>/<rank>.<ext>`, `Manga::get_image`
+/// reads them before asking the source.
+#[derive(Clone)]
+pub struct Downloads {
+    database_path: String,
+    download_path: PathBuf,
+    manga: Manga,
+    /// Wakes the worker up when chapters are queued
+    queued: Arc<Notify>,
+}
+
+impl Downloads {
+    pub fn new(database_path: String, download_path: String, manga: Manga) -> Self {
+        Self {
+            database_path,
+            download_path: PathBuf::from(download_path),
+            manga,
+            queued: Arc::new(Notify::new()),
+        }
+    }
+
+    fn connect_db(&self) -> Result<Connection, Error> {
+        Ok(Connection::open(self.database_path.clone())?)
+    }
+
+    pub async fn get_downloads(&self, claim: Claims) -> Result<impl warp::Reply, Rejection> {
+        let downloads = self.downloads(&claim.sub).map_err(reject)?;
+        Ok(warp::reply::json(&DownloadsResponse {
+            downloads,
+            status: "success".to_string(),
+        }))
+    }
+
+    pub async fn download_chapter(
+        &self,
+        claim: Claims,
+        chapter_id: i32,
+    ) -> Result<impl warp::Reply, Rejection> {
+        let db = self.connect_db().map_err(reject)?;
+        let chapter = db
+            .query_row(
+                r#"SELECT id FROM chapter
+                WHERE id = ?1 AND user_id = (SELECT id FROM "user" WHERE username = ?2)"#,
+                params![chapter_id, claim.sub],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(reject)?;
+        match chapter {
+            Some(chapter_id) => self.enqueue(vec![chapter_id]),
+            None => Err(reject(Error::NotFound(format!(
+                "chapter {} not found",
+                chapter_id
+            )))),
+        }
+    }
+
+    /// Queues every chapter of a manga, or only the unread ones.
+    /// Chapters are fetched from the source when the user has none saved yet.
+    pub async fn download_manga(
+        &self,
+        claim: Claims,
+        manga_id: i32,
+        param: DownloadParam,
+    ) -> Result<impl warp::Reply, Rejection> {
+        let unread = param.unread.unwrap_or(false);
+        if !self.has_chapters(&claim.sub, manga_id).map_err(reject)? {
+            self.manga
+                .refresh_chapters(manga_id, &claim.sub, Priority::Interactive)
+                .await
+                .map_err(|e| reject(Error::from(e)))?;
+        }
+
+        let db = self.connect_db().map_err(reject)?;
+        let mut stmt = db
+            .prepare(
+                r#"SELECT chapter.id FROM chapter
+                LEFT JOIN history ON
+                    history.chapter_id = chapter.id AND
+                    history.user_id = chapter.user_id
+                WHERE
+                    chapter.manga_id = ?1 AND
+                    chapter.user_id = (SELECT id FROM "user" WHERE username = ?2) AND
+                    (?3 = 0 OR history.id IS NULL)
+                ORDER BY chapter.id"#,
+            )
+            .map_err(reject)?;
+        let chapters = stmt
+            .query_map(params![manga_id, claim.sub, unread], |row| row.get(0))
+            .map_err(reject)?
+            .filter_map(|c| c.ok())
+            .collect();
+        self.enqueue(chapters)
+    }
+
+    /// Removes a chapter from the queue and deletes its files
+    pub async fn delete_download(
+        &self,
+        claim: Claims,
+        chapter_id: i32,
+    ) -> Result<impl warp::Reply, Rejection> {
+        let db = self.connect_db().map_err(reject)?;
+        let path = db
+            .query_row(
+                r#"SELECT manga.source, manga.id FROM download
+                JOIN chapter ON chapter.id = download.chapter_id
+                JOIN manga ON manga.id = chapter.manga_id
+                WHERE
+                    download.chapter_id = ?1 AND
+                    chapter.user_id = (SELECT id FROM "user" WHERE username = ?2)"#,
+                params![chapter_id, claim.sub],
+                |row| Ok(self.chapter_path(&row.get::<_, String>(0)?, row.get(1)?, chapter_id)),
+            )
+            .optional()
+            .map_err(reject)?
+            .ok_or_else(|| {
+                reject(Error::NotFound(format!(
+                    "chapter {} is not downloaded",
+                    chapter_id
+                )))
+            })?;
+
+        db.execute(
+            "DELETE FROM download WHERE chapter_id = ?1",
+            params![chapter_id],
+        )
+        .map_err(reject)?;
+        db.execute(
+            "UPDATE page SET local_path = NULL WHERE chapter_id = ?1",
+            params![chapter_id],
+        )
+        .map_err(reject)?;
+        let removed = blocking(move || {
+            if path.exists() {
+                std::fs::remove_dir_all(&path).map_err(|e| {
+                    Error::Internal(format!("failed to remove {}: {}", path.display(), e))
+                })?;
+            }
+            Ok(())
+        })
+        .await;
+        if let Err(e) = removed {
+            warn!("{}", e);
+        }
+        Ok(warp::reply())
+    }
+
+    /// Adds chapters to the queue, failed ones are queued again
+    fn enqueue(&self, chapters: Vec<i32>) -> Result<warp::reply::Json, Rejection> {
+        let mut db = self.connect_db().map_err(reject)?;
+        let tx = db.transaction().map_err(reject)?;
+        let mut queued = 0;
+        for chapter_id in chapters {
+            queued += tx
+                .execute(
+                    r#"INSERT INTO download(chapter_id) VALUES(?1)
+                    ON CONFLICT(chapter_id) DO UPDATE
+                    SET status = 'queued', error = NULL, updated = CURRENT_TIMESTAMP
+                    WHERE status = 'failed'"#,
+                    params![chapter_id],
+                )
+                .map_err(reject)?;
+        }
+        tx.commit().map_err(reject)?;
+
+        if queued > 0 {
+            self.queued.notify();
+        }
+        Ok(warp::reply::json(&EnqueueResponse {
+            queued,
+            status: "success".to_string(),
+        }))
+    }
+
+    /// Downloads queued chapters until the process exits, a chapter interrupted by a
+    /// restart is downloaded again
+    pub fn start(&self) {
+        let downloads = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = downloads.resume() {
+                error!("failed to resume downloads: {}", e);
+            }
+            loop {
+                match downloads.next() {
+                    Ok(Some((chapter_id, username, source, manga_id))) => {
+                        let path = downloads.chapter_path(&source, manga_id, chapter_id);
+                        if let Err(e) = downloads.download(chapter_id, &username, path).await {
+                            warn!("failed to download chapter {}: {}", chapter_id, e);
+                            if let Err(e) = downloads.failed(chapter_id, &e.to_string()) {
+                                error!("failed to save download of {}: {}", chapter_id, e);
+                            }
+                        }
+                    }
+                    // a chapter that got new pages is queued again without a notification
+                    Ok(None) => {
+                        let _ =
+                            tokio::time::timeout(POLL_INTERVAL, downloads.queued.notified()).await;
+                    }
+                    Err(e) => {
+                        error!("failed to read download queue: {}", e);
+                        tokio::time::delay_for(Duration::from_secs(10)).await;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Saves the pages of a chapter not downloaded yet, stops when the chapter is
+    /// removed from the queue
+    async fn download(&self, chapter_id: i32, username: &str, path: PathBuf) -> Result<(), Error> {
+        self.set_status(chapter_id, DownloadStatus::Downloading)?;
+        self.manga
+            .save_pages(chapter_id, username, Priority::Background)
+            .await
+            .map_err(Error::from)?;
+
+        let pages = self.pages(chapter_id)?;
+        {
+            let db = self.connect_db()?;
+            db.execute(
+                "UPDATE download SET pages = ?1, updated = CURRENT_TIMESTAMP WHERE chapter_id = ?2",
+                params![pages.len() as u32, chapter_id],
+            )?;
+        }
+        {
+            let path = path.clone();
+            blocking(move || {
+                std::fs::create_dir_all(&path).map_err(|e| {
+                    Error::Internal(format!("failed to create {}: {}", path.display(), e))
+                })
+            })
+            .await?;
+        }
+
+        for (page_id, rank, local_path) in pages {
+            if matches!(&local_path, Some(p) if std::path::Path::new(p).exists()) {
+                continue;
+            }
+            if !self.is_queued(chapter_id)? {
+                info!("download of chapter {} was removed", chapter_id);
+                return Ok(());
+            }
+
+            let (mime, bytes) = self
+                .manga
+                .fetch_image(page_id, username, Priority::Background)
+                .await
+                .map_err(Error::from)?
+                .ok_or_else(|| Error::NotFound(format!("page {} not found", page_id)))?;
+            let file = path.join(format!("{:04}.{}", rank, extension(&mime)));
+            {
+                let file = file.clone();
+                blocking(move || {
+                    std::fs::write(&file, bytes).map_err(|e| {
+                        Error::Internal(format!("failed to write {}: {}", file.display(), e))
+                    })
+                })
+                .await?;
+            }
+
+            let db = self.connect_db()?;
+            db.execute(
+                "UPDATE page SET local_path = ?1 WHERE id = ?2",
+                params![file.to_string_lossy(), page_id],
+            )?;
+        }
+
+        self.set_status(chapter_id, DownloadStatus::Done)?;
+        info!("downloaded chapter {}", chapter_id);
+        Ok(())
+    }
+
+    fn chapter_path(&self, source: &str, manga_id: i32, chapter_id: i32) -> PathBuf {
+        self.download_path
+            .join(dir_name(source))
+            .join(manga_id.to_string())
+            .join(chapter_id.to_string())
+    }
+
+    /// Oldest queued chapter with the user it belongs to, its source and manga
+    fn next(&self) -> Result<Option<(i32, String, String, i32)>, Error> {
+        let db = self.connect_db()?;
+        Ok(db
+            .query_row(
+                r#"SELECT download.chapter_id, "user".username, manga.source, manga.id FROM download
+                JOIN chapter ON chapter.id = download.chapter_id
+                JOIN "user" ON "user".id = chapter.user_id
+                JOIN manga ON manga.id = chapter.manga_id
+                WHERE download.status = 'queued'
+                ORDER BY download.id
+                LIMIT 1"#,
+                params![],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?)
+    }
+
+    /// Id, rank and downloaded file of every page of a chapter
+    fn pages(&self, chapter_id: i32) -> Result<Vec<(i32, i32, Option<String>)>, Error> {
+        let db = self.connect_db()?;
+        let mut stmt = db
+            .prepare("SELECT id, rank, local_path FROM page WHERE chapter_id = ?1 ORDER BY rank")?;
+        let pages = stmt
+            .query_map(params![chapter_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .filter_map(|p| p.ok())
+            .collect();
+        Ok(pages)
+    }
+
+    fn is_queued(&self, chapter_id: i32) -> Result<bool, Error> {
+        let db = self.connect_db()?;
+        Ok(db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM download WHERE chapter_id = ?1)",
+            params![chapter_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn has_chapters(&self, username: &str, manga_id: i32) -> Result<bool, Error> {
+        let db = self.connect_db()?;
+        Ok(db.query_row(
+            r#"SELECT EXISTS(
+                SELECT 1 FROM chapter
+                WHERE manga_id = ?1 AND user_id = (SELECT id FROM "user" WHERE username = ?2))"#,
+            params![manga_id, username],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn set_status(&self, chapter_id: i32, status: DownloadStatus) -> Result<(), Error> {
+        let db = self.connect_db()?;
+        db.execute(
+            r#"UPDATE download SET status = ?1, error = NULL, updated = CURRENT_TIMESTAMP
+            WHERE chapter_id = ?2"#,
+            params![status_name(status), chapter_id],
+        )?;
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), Error> {
+        let db = self.connect_db()?;
+        db.execute(
+            "UPDATE download SET status = 'queued' WHERE status = 'downloading'",
+            params![],
+        )?;
+        Ok(())
+    }
+
+    fn failed(&self, chapter_id: i32, message: &str) -> Result<(), Error> {
+        let db = self.connect_db()?;
+        db.execute(
+            r#"UPDATE download SET status = 'failed', error = ?1, updated = CURRENT_TIMESTAMP
+            WHERE chapter_id = ?2"#,
+            params![message, chapter_id],
+        )?;
+        Ok(())
+    }
+
+    fn downloads(&self, username: &str) -> Result<Vec<Download>, Error> {
+        let db = self.connect_db()?;
+        let mut stmt = db.prepare(
+            r#"SELECT
+                download.chapter_id,
+                manga.id,
+                manga.title,
+                manga.source,
+                chapter.volume,
+                chapter.number,
+                download.status,
+                download.pages,
+                (SELECT COUNT(1) FROM page
+                    WHERE page.chapter_id = download.chapter_id AND page.local_path IS NOT NULL),
+                download.error,
+                download.created
+            FROM download
+            JOIN chapter ON chapter.id = download.chapter_id
+            JOIN manga ON manga.id = chapter.manga_id
+            WHERE chapter.user_id = (SELECT id FROM "user" WHERE username = ?1)
+            ORDER BY download.id"#,
+        )?;
+        let downloads = stmt
+            .query_map(params![username], |row| {
+                let status: String = row.get(6)?;
+                Ok(Download {
+                    chapter_id: row.get(0)?,
+                    manga_id: row.get(1)?,
+                    title: row.get(2)?,
+                    source: row.get(3)?,
+                    volume: row.get(4)?,
+                    number: row.get(5)?,
+                    status: serde_json::from_value(json!(status)).unwrap_or(DownloadStatus::Queued),
+                    pages: row.get(7)?,
+                    downloaded: row.get(8)?,
+                    error: row.get(9)?,
+                    created: row.get(10)?,
+                })
+            })?
+            .filter_map(|d| d.ok())
+            .collect();
+        Ok(downloads)
+    }
+}
+
+/// Source names come from extensions, anything but letters, digits, `-` and `_`
+/// is replaced so a name can't point outside of `download_path`
+fn dir_name(source: &str) -> String {
+    let name: String = source
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name
+    }
+}
+
+/// Runs file operations on a blocking thread
+async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Internal(e.to_string()))?
+}
+
+fn status_name(status: DownloadStatus) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|s| s.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// File extension of an image, `bin` for anything unknown
+fn extension(mime: &str) -> &'static str {
+    match mime {
+        "image/jpeg" => "jpg",
+        _ => mime_guess::get_mime_extensions_str(mime)
+            .and_then(|extensions| extensions.first().copied())
+            .unwrap_or("bin"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_name_stays_in_download_path() {
+        assert_eq!(dir_name("mangadex"), "mangadex");
+        assert_eq!(dir_name("my-source_2"), "my-source_2");
+        assert_eq!(dir_name("../../etc"), "______etc");
+        assert_eq!(dir_name("/abs"), "_abs");
+        assert_eq!(dir_name(".."), "__");
+        assert_eq!(dir_name(""), "_");
+    }
+}
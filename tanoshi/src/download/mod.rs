@@ -0,0 +1,2 @@
+pub mod downloads;
+pub use downloads::*;
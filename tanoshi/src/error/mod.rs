@@ -0,0 +1,126 @@
+use std::fmt;
+use warp::http::StatusCode;
+
+use tanoshi_lib::rest::{ErrorCode, ErrorResponse};
+
+use crate::extension::guard::{ErrorKind, ExtensionError};
+
+/// Error of a request, every handler rejects with it and
+/// `filters::handle_rejection` answers it as an `ErrorResponse`
+#[derive(Debug)]
+pub enum Error {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    Extension(ExtensionError),
+    Database(rusqlite::Error),
+    Internal(String),
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::BadRequest(_) => ErrorCode::BadRequest,
+            Error::Unauthorized(_) => ErrorCode::Unauthorized,
+            Error::Forbidden(_) => ErrorCode::Forbidden,
+            Error::NotFound(_) => ErrorCode::NotFound,
+            Error::Conflict(_) => ErrorCode::Conflict,
+            Error::Extension(e) => match e.kind {
+                ErrorKind::NotFound => ErrorCode::SourceNotFound,
+                ErrorKind::Disabled => ErrorCode::SourceDisabled,
+                ErrorKind::Timeout | ErrorKind::Busy => ErrorCode::SourceTimeout,
+                ErrorKind::Panic | ErrorKind::Failed => ErrorCode::SourceFailed,
+            },
+            Error::Database(_) => ErrorCode::Database,
+            Error::Internal(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn response(&self) -> ErrorResponse {
+        ErrorResponse {
+            code: self.code(),
+            message: self.to_string(),
+            source: match self {
+                Error::Extension(e) => Some(e.source.clone()),
+                _ => None,
+            },
+            status: "error".to_string(),
+        }
+    }
+}
+
+/// HTTP status of an error code
+pub fn status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        ErrorCode::NotFound | ErrorCode::SourceNotFound => StatusCode::NOT_FOUND,
+        ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+        ErrorCode::Conflict => StatusCode::CONFLICT,
+        ErrorCode::SourceDisabled => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::SourceTimeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorCode::SourceFailed => StatusCode::BAD_GATEWAY,
+        ErrorCode::Database | ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadRequest(message)
+            | Error::Unauthorized(message)
+            | Error::Forbidden(message)
+            | Error::NotFound(message)
+            | Error::Conflict(message)
+            | Error::Internal(message) => write!(f, "{}", message),
+            Error::Extension(e) => write!(f, "{}", e),
+            Error::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl warp::reject::Reject for Error {}
+
+impl From<ExtensionError> for Error {
+    fn from(e: ExtensionError) -> Self {
+        Error::Extension(e)
+    }
+}
+
+/// A query expecting a row is answered as not found, a violated constraint as a conflict
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => Error::NotFound("not found".to_string()),
+            rusqlite::Error::SqliteFailure(err, message)
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Error::Conflict(message.unwrap_or_else(|| err.to_string()))
+            }
+            e => Error::Database(e),
+        }
+    }
+}
+
+/// Keeps the error an `anyhow::Error` was made from, anything else is internal
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<Error>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<ExtensionError>() {
+            Ok(e) => return e.into(),
+            Err(e) => e,
+        };
+        match e.downcast::<rusqlite::Error>() {
+            Ok(e) => e.into(),
+            Err(e) => Error::Internal(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use tanoshi_lib::extensions::Panicked;
+use tanoshi_lib::manga::RateLimit;
+use tokio::sync::Semaphore;
+
+use crate::config::ExtensionLimits;
+use crate::extension::{ExtensionProxy, Extensions};
+
+/// Why a call to an extension failed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// No extension is loaded for the source
+    NotFound,
+    /// The source panicked or timed out too many times in a row and is disabled for a while
+    Disabled,
+    /// The extension did not return in time
+    Timeout,
+    /// No slot was free in time, the extension was not called
+    Busy,
+    /// The extension panicked, or a WebAssembly extension trapped
+    Panic,
+    /// The extension returned an error
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtensionError {
+    pub source: String,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl ExtensionError {
+    fn new(source: &str, kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            source: source.to_string(),
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.source, self.message)
+    }
+}
+
+impl std::error::Error for ExtensionError {}
+
+impl warp::reject::Reject for ExtensionError {}
+
+/// Who waits for a call, decides which call goes first when a source is rate limited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    /// A user waits for the result, e.g reading pages
+    Interactive,
+    /// Nobody waits for the result, e.g refreshing chapters
+    Background,
+}
+
+/// Consecutive failures of a source
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    disabled_until: Option<Instant>,
+}
+
+/// Token bucket of a rate limited source, starts full so a source can burst up to `requests`
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+    /// Interactive calls waiting for a token, background calls wait until there are none
+    interactive: usize,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            tokens: limit.requests as f64,
+            limit,
+            updated: Instant::now(),
+            interactive: 0,
+        }
+    }
+
+    /// Time to earn a token
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.limit.period.max(1)) / self.limit.requests
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = (now - self.updated).as_secs_f64() / self.interval().as_secs_f64();
+        self.tokens = (self.tokens + earned).min(self.limit.requests as f64);
+        self.updated = now;
+    }
+}
+
+/// Counts an interactive call as waiting for a token until it is dropped,
+/// so a call abandoned while waiting doesn't keep background calls waiting
+struct Waiting {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+    source: String,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if let Some(bucket) = self
+            .buckets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(&self.source)
+        {
+            bucket.interactive = bucket.interactive.saturating_sub(1);
+        }
+    }
+}
+
+/// Runs calls into extensions so a misbehaving source can't take the server down with it
+///
+/// Extensions are blocking, so every call runs on the blocking thread pool with panics caught,
+/// and is abandoned after `timeout`. At most `extension_pool_size` calls run at once, and at most
+/// `extension_concurrency` of them for the same source, so one slow source can't use up the pool.
+/// A source that panics or times out `max_failures` times in a row is disabled for `cooldown`,
+/// afterwards it is called again but a single failure disables it again until it succeeds.
+/// Errors the extension returns, e.g a manga not found, don't count as failures.
+///
+/// A source with a rate limit gets a token bucket, each call takes a token before it runs.
+/// Background calls only take a token when no interactive call waits for one.
+#[derive(Clone)]
+pub struct Guard {
+    exts: Arc<RwLock<Extensions>>,
+    timeout: Duration,
+    max_failures: u32,
+    cooldown: Duration,
+    concurrency: usize,
+    source_concurrency: BTreeMap<String, usize>,
+    search_timeout: Duration,
+    pool: Arc<Semaphore>,
+    slots: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+    breakers: Arc<Mutex<HashMap<String, Breaker>>>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl Guard {
+    pub fn new(exts: Arc<RwLock<Extensions>>, limits: ExtensionLimits) -> Self {
+        Self {
+            exts,
+            timeout: Duration::from_secs(limits.extension_timeout),
+            max_failures: limits.extension_max_failures,
+            cooldown: Duration::from_secs(limits.extension_cooldown),
+            concurrency: limits.extension_concurrency,
+            source_concurrency: limits.source_concurrency,
+            search_timeout: Duration::from_secs(limits.search_timeout),
+            pool: Arc::new(Semaphore::new(limits.extension_pool_size.max(1))),
+            slots: Arc::new(Mutex::new(HashMap::new())),
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Call `f` with the extension of `source` for a user waiting for the result
+    pub async fn call<T, F>(&self, source: &str, f: F) -> Result<T, ExtensionError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ExtensionProxy) -> anyhow::Result<T> + Send + 'static,
+    {
+        self.call_with(source, Priority::Interactive, f).await
+    }
+
+    /// Call `f` with the extension of `source`, waiting for a token of the rate limit
+    /// or a free slot doesn't count toward the timeout. A call that waits longer than the timeout
+    /// for a slot fails as busy without counting as a failure of the source, its slots may
+    /// be held by other sources. A call that times out keeps running in the background
+    /// and holds its slot until it returns, its result is dropped.
+    pub async fn call_with<T, F>(
+        &self,
+        source: &str,
+        priority: Priority,
+        f: F,
+    ) -> Result<T, ExtensionError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ExtensionProxy) -> anyhow::Result<T> + Send + 'static,
+    {
+        self.check(source)?;
+        // the lock is released before calling, a call never holds it
+        let ext = self
+            .exts
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&source.to_string())
+            .ok_or_else(|| ExtensionError::new(source, ErrorKind::NotFound, "source not found"))?;
+
+        if let Some(limit) = ext.rate_limit() {
+            self.throttle(source, limit.clone(), priority).await;
+        }
+
+        let slot = self.slot(source);
+        let pool = self.pool.clone();
+        let permits = async move {
+            let slot = slot.acquire_owned().await;
+            let permit = pool.acquire_owned().await;
+            (slot, permit)
+        };
+        let permits = tokio::time::timeout(self.timeout, permits)
+            .await
+            .map_err(|_| {
+                ExtensionError::new(
+                    source,
+                    ErrorKind::Busy,
+                    format!("no free slot after {} seconds", self.timeout.as_secs()),
+                )
+            })?;
+        let call = tokio::task::spawn_blocking(move || {
+            let _permits = permits;
+            catch_unwind(AssertUnwindSafe(|| f(&ext)))
+        });
+
+        let res = match tokio::time::timeout(self.timeout, call).await {
+            Ok(Ok(Ok(Ok(value)))) => Ok(value),
+            // native plugins catch their own panics and return them as errors
+            Ok(Ok(Ok(Err(e)))) if e.is::<Panicked>() => {
+                Err(ExtensionError::new(source, ErrorKind::Panic, e.to_string()))
+            }
+            Ok(Ok(Ok(Err(e)))) => Err(ExtensionError::new(
+                source,
+                ErrorKind::Failed,
+                e.to_string(),
+            )),
+            Ok(Ok(Err(panic))) => {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                Err(ExtensionError::new(
+                    source,
+                    ErrorKind::Panic,
+                    format!("extension panicked: {}", message),
+                ))
+            }
+            Ok(Err(e)) => Err(ExtensionError::new(
+                source,
+                ErrorKind::Panic,
+                format!("extension call ended without a result: {}", e),
+            )),
+            Err(_) => Err(ExtensionError::new(
+                source,
+                ErrorKind::Timeout,
+                format!("no response after {} seconds", self.timeout.as_secs()),
+            )),
+        };
+
+        match &res {
+            Ok(_) => self.succeed(source),
+            Err(e) if matches!(e.kind, ErrorKind::Panic | ErrorKind::Timeout) => self.fail(e),
+            Err(_) => {}
+        }
+        res
+    }
+
+    /// Wait until the bucket of `source` has a token and take it
+    async fn throttle(&self, source: &str, limit: RateLimit, priority: Priority) {
+        if limit.requests == 0 {
+            return;
+        }
+        let _waiting = match priority {
+            Priority::Interactive => {
+                let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+                Self::entry(&mut buckets, source, &limit).interactive += 1;
+                Some(Waiting {
+                    buckets: self.buckets.clone(),
+                    source: source.to_string(),
+                })
+            }
+            Priority::Background => None,
+        };
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+                let bucket = Self::entry(&mut buckets, source, &limit);
+                bucket.refill();
+                if priority == Priority::Background && bucket.interactive > 0 {
+                    bucket.interval()
+                } else if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                } else {
+                    bucket.interval().mul_f64(1.0 - bucket.tokens)
+                }
+            };
+            tokio::time::delay_for(wait).await;
+        }
+    }
+
+    /// Returns the bucket of `source`, a new one when its limit changed after a reload
+    fn entry<'a>(
+        buckets: &'a mut HashMap<String, Bucket>,
+        source: &str,
+        limit: &RateLimit,
+    ) -> &'a mut Bucket {
+        let bucket = buckets
+            .entry(source.to_string())
+            .or_insert_with(|| Bucket::new(limit.clone()));
+        if &bucket.limit != limit {
+            let interactive = bucket.interactive;
+            *bucket = Bucket::new(limit.clone());
+            bucket.interactive = interactive;
+        }
+        bucket
+    }
+
+    /// Time each source gets in global search
+    pub fn search_timeout(&self) -> Duration {
+        self.search_timeout
+    }
+
+    fn slot(&self, source: &str) -> Arc<Semaphore> {
+        let concurrency = self
+            .source_concurrency
+            .get(source)
+            .copied()
+            .unwrap_or(self.concurrency)
+            .max(1);
+        self.slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(source.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(concurrency)))
+            .clone()
+    }
+
+    /// Forget the failures of a source, e.g after it is reloaded
+    pub fn reset(&self, source: &str) {
+        self.breakers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(source);
+    }
+
+    fn check(&self, source: &str) -> Result<(), ExtensionError> {
+        let breakers = self.breakers.lock().unwrap_or_else(PoisonError::into_inner);
+        match breakers.get(source).and_then(|b| b.disabled_until) {
+            Some(until) if until > Instant::now() => Err(ExtensionError::new(
+                source,
+                ErrorKind::Disabled,
+                format!(
+                    "disabled for {} seconds after failing {} times in a row",
+                    (until - Instant::now()).as_secs() + 1,
+                    breakers[source].failures
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn succeed(&self, source: &str) {
+        self.reset(source);
+    }
+
+    fn fail(&self, e: &ExtensionError) {
+        let mut breakers = self.breakers.lock().unwrap_or_else(PoisonError::into_inner);
+        let breaker = breakers.entry(e.source.clone()).or_default();
+        breaker.failures += 1;
+        if breaker.failures >= self.max_failures {
+            warn!(
+                "disable {} for {} seconds after failing {} times in a row, last error: {}",
+                &e.source,
+                self.cooldown.as_secs(),
+                breaker.failures,
+                &e.message
+            );
+            breaker.disabled_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use tanoshi_lib::extensions::Extension;
+    use tanoshi_lib::manga::{Chapter, Manga, Params, Source};
+
+    use crate::config::HttpConfig;
+    use crate::extension::http::HttpClients;
+
+    /// Answers `get_manga_info` depending on the path
+    struct Flaky;
+
+    impl Extension for Flaky {
+        fn info(&self) -> Source {
+            Source {
+                name: "flaky".to_string(),
+                url: "https://example.com".to_string(),
+                version: "0.1.0".to_string(),
+                rate_limit: None,
+            }
+        }
+
+        fn get_mangas(&self, _param: Params, _auth: String) -> anyhow::Result<Vec<Manga>> {
+            Ok(vec![])
+        }
+
+        fn get_manga_info(&self, path: &str, _auth: String) -> anyhow::Result<Manga> {
+            match path {
+                "panic" => panic!("broken"),
+                "panicked" => Err(Panicked("broken".to_string()).into()),
+                "slow" => {
+                    std::thread::sleep(Duration::from_millis(1500));
+                    Ok(Manga::default())
+                }
+                "missing" => Err(anyhow!("manga not found")),
+                _ => Ok(Manga::default()),
+            }
+        }
+
+        fn get_chapters(&self, _path: &str, _auth: String) -> anyhow::Result<Vec<Chapter>> {
+            Ok(vec![])
+        }
+
+        fn get_pages(&self, _path: &str, _auth: String) -> anyhow::Result<Vec<String>> {
+            Ok(vec![])
+        }
+    }
+
+    fn guard(limits: ExtensionLimits) -> Guard {
+        let mut exts = Extensions::new(HttpClients::new(HttpConfig::default()).unwrap());
+        exts.insert("flaky".to_string(), Box::new(Flaky));
+        exts.insert("healthy".to_string(), Box::new(Flaky));
+        Guard::new(Arc::new(RwLock::new(exts)), limits)
+    }
+
+    async fn manga_info(guard: &Guard, path: &str) -> Result<Manga, ExtensionError> {
+        manga_info_of(guard, "flaky", path).await
+    }
+
+    async fn manga_info_of(
+        guard: &Guard,
+        source: &str,
+        path: &str,
+    ) -> Result<Manga, ExtensionError> {
+        let path = path.to_string();
+        guard
+            .call(source, move |ext| ext.get_manga_info(&path, String::new()))
+            .await
+    }
+
+    fn kind(res: Result<Manga, ExtensionError>) -> ErrorKind {
+        res.map(|_| ()).unwrap_err().kind
+    }
+
+    #[test]
+    fn bucket_refills_up_to_requests() {
+        let mut bucket = Bucket::new(RateLimit {
+            requests: 4,
+            period: 2,
+        });
+        assert_eq!(bucket.tokens, 4.0);
+        assert_eq!(bucket.interval(), Duration::from_millis(500));
+
+        bucket.tokens = 0.0;
+        bucket.updated = Instant::now() - Duration::from_millis(1000);
+        bucket.refill();
+        assert!(
+            bucket.tokens >= 2.0 && bucket.tokens < 2.5,
+            "{}",
+            bucket.tokens
+        );
+
+        bucket.updated = Instant::now() - Duration::from_secs(60);
+        bucket.refill();
+        assert_eq!(bucket.tokens, 4.0);
+    }
+
+    #[tokio::test]
+    async fn breaker_trips_on_panics() {
+        let guard = guard(ExtensionLimits {
+            extension_max_failures: 2,
+            ..Default::default()
+        });
+
+        assert_eq!(kind(manga_info(&guard, "panic").await), ErrorKind::Panic);
+        assert_eq!(kind(manga_info(&guard, "panicked").await), ErrorKind::Panic);
+        let disabled = manga_info(&guard, "ok").await.map(|_| ()).unwrap_err();
+        assert_eq!(disabled.kind, ErrorKind::Disabled);
+        assert!(disabled.message.contains("failing 2 times"));
+
+        // once the cooldown is over a single panic disables it again
+        guard
+            .breakers
+            .lock()
+            .unwrap()
+            .get_mut("flaky")
+            .unwrap()
+            .disabled_until = Some(Instant::now() - Duration::from_secs(1));
+        assert_eq!(kind(manga_info(&guard, "panic").await), ErrorKind::Panic);
+        assert_eq!(kind(manga_info(&guard, "ok").await), ErrorKind::Disabled);
+
+        guard.reset("flaky");
+        assert!(manga_info(&guard, "ok").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn breaker_ignores_errors_and_resets_on_success() {
+        let guard = guard(ExtensionLimits {
+            extension_max_failures: 2,
+            ..Default::default()
+        });
+
+        for _ in 0..3 {
+            assert_eq!(kind(manga_info(&guard, "missing").await), ErrorKind::Failed);
+        }
+        assert_eq!(kind(manga_info(&guard, "panic").await), ErrorKind::Panic);
+        assert!(manga_info(&guard, "ok").await.is_ok());
+        assert_eq!(kind(manga_info(&guard, "panic").await), ErrorKind::Panic);
+        assert_eq!(kind(manga_info(&guard, "missing").await), ErrorKind::Failed);
+    }
+
+    #[tokio::test]
+    async fn breaker_trips_on_timeouts() {
+        let guard = guard(ExtensionLimits {
+            extension_timeout: 1,
+            extension_max_failures: 1,
+            ..Default::default()
+        });
+
+        assert_eq!(kind(manga_info(&guard, "slow").await), ErrorKind::Timeout);
+        assert_eq!(kind(manga_info(&guard, "ok").await), ErrorKind::Disabled);
+        assert_eq!(
+            kind(manga_info(&guard, "missing").await),
+            ErrorKind::Disabled
+        );
+    }
+
+    #[tokio::test]
+    async fn stalled_source_does_not_trip_others() {
+        let guard = guard(ExtensionLimits {
+            extension_timeout: 1,
+            extension_max_failures: 1,
+            extension_pool_size: 1,
+            ..Default::default()
+        });
+
+        // the stalled call holds the only permit of the pool for 1.5 seconds
+        let stalled = {
+            let guard = guard.clone();
+            tokio::spawn(async move { manga_info(&guard, "slow").await.map(|_| ()) })
+        };
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        let busy = manga_info_of(&guard, "healthy", "ok")
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(busy.kind, ErrorKind::Busy);
+        assert_eq!(stalled.await.unwrap().unwrap_err().kind, ErrorKind::Timeout);
+
+        tokio::time::delay_for(Duration::from_millis(600)).await;
+        assert!(manga_info_of(&guard, "healthy", "ok").await.is_ok());
+        assert_eq!(kind(manga_info(&guard, "ok").await), ErrorKind::Disabled);
+    }
+}
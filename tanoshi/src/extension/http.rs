@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use url::{Host, Url};
+
+use tanoshi_lib::http::{Http, HttpClient, Request, Response};
+
+use crate::config::HttpConfig;
+
+/// Every request of extensions goes through here, with the timeouts, user agent and proxy
+/// from `config.yml`. Each source has its own cookie jar, kept as long as tanoshi runs,
+/// so a source keeps its cookies when it is reloaded.
+#[derive(Clone)]
+pub struct HttpClients {
+    config: Arc<HttpConfig>,
+    proxy: Option<ureq::Proxy>,
+    agents: Arc<Mutex<HashMap<String, ureq::Agent>>>,
+}
+
+impl HttpClients {
+    pub fn new(config: HttpConfig) -> Result<Self> {
+        let proxy = match &config.proxy {
+            Some(proxy) => Some(
+                ureq::Proxy::new(proxy).map_err(|e| anyhow!("invalid proxy {}: {}", proxy, e))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            proxy,
+            agents: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Returns the client of `source`
+    pub fn client(&self, source: &str) -> Http {
+        let agent = self
+            .agents
+            .lock()
+            .unwrap()
+            .entry(source.to_string())
+            .or_insert_with(|| ureq::Agent::new().build())
+            .clone();
+        Http::new(Arc::new(SourceClient {
+            source: source.to_string(),
+            agent,
+            config: self.config.clone(),
+            proxy: self.proxy.clone(),
+        }))
+    }
+}
+
+struct SourceClient {
+    source: String,
+    agent: ureq::Agent,
+    config: Arc<HttpConfig>,
+    proxy: Option<ureq::Proxy>,
+}
+
+impl HttpClient for SourceClient {
+    fn send(&self, request: Request) -> Result<Response> {
+        let start = Instant::now();
+        let mut req = self.agent.request(&request.method, &request.url);
+        req.timeout_connect(self.config.connect_timeout * 1000)
+            .timeout_read(self.config.read_timeout * 1000)
+            .timeout_write(self.config.read_timeout * 1000)
+            .set("User-Agent", &self.config.user_agent);
+        if let Some(redirects) = request.redirects {
+            req.redirects(redirects);
+        }
+        if let Some(proxy) = &self.proxy {
+            req.set_proxy(proxy.clone());
+        }
+        for (name, value) in request.headers.iter() {
+            req.set(name, value);
+        }
+
+        let resp = if request.body.is_empty() {
+            req.call()
+        } else {
+            req.send_bytes(&request.body)
+        };
+        if let Some(e) = resp.synthetic_error() {
+            warn!(
+                "{} {} {} failed: {}",
+                self.source, request.method, request.url, e
+            );
+            return Err(anyhow!("{} {}: {}", request.method, request.url, e));
+        }
+
+        let mut response = Response {
+            status: resp.status(),
+            url: resp.get_url().to_string(),
+            headers: resp
+                .headers_names()
+                .into_iter()
+                .filter_map(|name| {
+                    let value = resp.header(&name)?.to_string();
+                    Some((name, value))
+                })
+                .collect(),
+            body: vec![],
+        };
+        resp.into_reader()
+            .take(self.config.max_response_size + 1)
+            .read_to_end(&mut response.body)?;
+        if response.body.len() as u64 > self.config.max_response_size {
+            return Err(anyhow!("response of {} is too large", request.url));
+        }
+
+        debug!(
+            "{} {} {} {} {}ms",
+            self.source,
+            request.method,
+            request.url,
+            response.status,
+            start.elapsed().as_millis()
+        );
+        Ok(response)
+    }
+}
+
+/// Whether `ip` is on this machine or a private network, e.g `127.0.0.1`, `10.0.0.1`
+/// or `169.254.169.254`, requests tanoshi makes on behalf of users must not reach them
+pub fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // 0.0.0.0/8 and the carrier-grade NAT range 100.64.0.0/10
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                // IPv4-mapped and IPv4-compatible addresses
+                || matches!(ip.to_ipv4(), Some(ip) if is_internal(IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// Resolves the host of `url` and fails when any of its addresses is internal
+pub fn check_public(url: &Url) -> Result<()> {
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<IpAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => (domain, port)
+            .to_socket_addrs()
+            .map_err(|e| anyhow!("failed to resolve {}: {}", domain, e))?
+            .map(|addr| addr.ip())
+            .collect(),
+        None => return Err(anyhow!("{} has no host", url)),
+    };
+    if addrs.is_empty() {
+        return Err(anyhow!("{} has no address", url));
+    }
+    if let Some(ip) = addrs.into_iter().find(|ip| is_internal(*ip)) {
+        return Err(anyhow!("{} resolves to internal address {}", url, ip));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses() {
+        for ip in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{} is internal", ip);
+        }
+        for ip in &["1.1.1.1", "93.184.216.34", "100.128.0.1", "2606:4700::1111"] {
+            assert!(!is_internal(ip.parse().unwrap()), "{} is public", ip);
+        }
+    }
+
+    #[test]
+    fn check_public_literals() {
+        assert!(check_public(&Url::parse("http://127.0.0.1:8080/").unwrap()).is_err());
+        assert!(check_public(&Url::parse("http://[::1]/").unwrap()).is_err());
+        assert!(check_public(&Url::parse("http://169.254.169.254/latest").unwrap()).is_err());
+        assert!(check_public(&Url::parse("http://localhost/").unwrap()).is_err());
+        assert!(check_public(&Url::parse("https://1.1.1.1/").unwrap()).is_ok());
+    }
+}
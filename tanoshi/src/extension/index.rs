@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Result};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tanoshi_lib::manga::SourceIndex;
+
+use crate::config::Repository;
+
+static CONNECT_TIMEOUT: u64 = 10_000;
+/// Limit of a whole request, downloading a plugin library may take a while
+static TIMEOUT: u64 = 120_000;
+
+/// Extension index merged from every configured repository
+#[derive(Clone)]
+pub struct Index {
+    repositories: Vec<Repository>,
+}
+
+enum Location {
+    Remote(String),
+    Local(PathBuf),
+}
+
+impl Index {
+    pub fn new(repositories: Vec<Repository>) -> Self {
+        let mut repositories = repositories;
+        // stable sort, repositories with the same priority keep their configured order
+        repositories.sort_by_key(|r| std::cmp::Reverse(r.priority));
+        Self { repositories }
+    }
+
+    /// Returns sources of every repository, each tagged with its repository.
+    /// A source listed in more than one repository is taken from the one with the highest priority,
+    /// a repository that can't be read is skipped.
+    pub fn sources(&self) -> Vec<SourceIndex> {
+        let mut sources: Vec<SourceIndex> = vec![];
+        for repository in self.repositories.iter() {
+            let index = match read(repository, "index.json")
+                .and_then(|bytes| Ok(serde_json::from_slice::<Vec<SourceIndex>>(&bytes)?))
+            {
+                Ok(index) => index,
+                Err(e) => {
+                    error!("failed to read repository {}: {}", repository.name, e);
+                    continue;
+                }
+            };
+
+            for mut source in index {
+                if sources.iter().any(|s| s.name == source.name) {
+                    continue;
+                }
+                source.repository = repository.name.clone();
+                sources.push(source);
+            }
+        }
+        sources
+    }
+
+    /// Same as `sources`, but reads the repositories on a blocking thread
+    pub async fn fetch_sources(&self) -> Vec<SourceIndex> {
+        let index = self.clone();
+        match tokio::task::spawn_blocking(move || index.sources()).await {
+            Ok(sources) => sources,
+            Err(e) => {
+                error!("failed to read repositories: {}", e);
+                vec![]
+            }
+        }
+    }
+
+    pub async fn find(&self, name: &str) -> Option<SourceIndex> {
+        self.fetch_sources()
+            .await
+            .into_iter()
+            .find(|s| s.name == name)
+    }
+
+    /// Download plugin file of a source from the repository it is tagged with
+    pub fn download(&self, source: &SourceIndex) -> Result<Vec<u8>> {
+        let repository = self
+            .repositories
+            .iter()
+            .find(|r| r.name == source.repository)
+            .ok_or_else(|| anyhow!("repository {} not found", &source.repository))?;
+        read(repository, &source.path)
+    }
+}
+
+fn location(repository: &Repository) -> Result<Location> {
+    let url = repository.url.replace("{os}", std::env::consts::OS);
+    let parsed = url::Url::parse(&url)?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(Location::Remote(url.trim_end_matches('/').to_string())),
+        "file" => parsed
+            .to_file_path()
+            .map(Location::Local)
+            .map_err(|_| anyhow!("{} is not a valid directory", url)),
+        scheme => Err(anyhow!("unsupported repository scheme {}", scheme)),
+    }
+}
+
+/// Read a file from a repository, `path` is relative to the repository url
+fn read(repository: &Repository, path: &str) -> Result<Vec<u8>> {
+    match location(repository)? {
+        Location::Remote(url) => {
+            let resp = ureq::get(&format!("{}/{}", url, path))
+                .timeout_connect(CONNECT_TIMEOUT)
+                .timeout(Duration::from_millis(TIMEOUT))
+                .call();
+            if let Some(e) = resp.synthetic_error() {
+                return Err(anyhow!("{}", e));
+            }
+            if !resp.ok() {
+                return Err(anyhow!("{}/{} returns {}", url, path, resp.status()));
+            }
+            let mut bytes = vec![];
+            resp.into_reader().read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+        Location::Local(dir) => {
+            let file = dir.join(path);
+            if !file.starts_with(&dir) || path.split('/').any(|p| p == "..") {
+                return Err(anyhow!("{} is outside of repository", path));
+            }
+            Ok(std::fs::read(file)?)
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac, NewMac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::manga::{
+    Chapter, FilterOption, Filters, Manga, Params, SortByParam, SortOrderParam, Source,
+};
+
+pub static NAME: &str = "local";
+
+static PAGE_SIZE: usize = 20;
+static ARCHIVE_EXTENSIONS: &[&str] = &["cbz", "zip"];
+pub static IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+
+/// Built-in source that reads manga from library directories on disk.
+///
+/// Every directory inside a library directory is a manga, and every
+/// subdirectory or `.cbz`/`.zip` archive inside a manga is a chapter.
+/// A manga directory that only contains images is treated as a single chapter.
+#[derive(Clone)]
+pub struct Local {
+    paths: Vec<PathBuf>,
+    secret: String,
+}
+
+/// Query of a cover url, a manga is addressed by its library and directory name,
+/// `sig` proves the url was given by the server
+#[derive(Debug, Deserialize)]
+pub struct CoverQuery {
+    pub library: usize,
+    pub manga: String,
+    pub sig: String,
+}
+
+impl Local {
+    pub fn new(paths: Vec<String>, secret: &str) -> Self {
+        Self {
+            paths: paths.into_iter().map(PathBuf::from).collect(),
+            secret: secret.to_string(),
+        }
+    }
+
+    /// Both sides are canonicalized, so `..` or a symlink can't lead out of a library
+    fn is_in_library(&self, path: &Path) -> bool {
+        let path = match canonicalize(path) {
+            Some(path) => path,
+            None => return false,
+        };
+        self.paths
+            .iter()
+            .filter_map(|library| library.canonicalize().ok())
+            .any(|library| path.starts_with(library))
+    }
+
+    fn manga_from_dir(&self, path: &Path) -> Manga {
+        Manga {
+            source: NAME.to_string(),
+            title: file_name(path),
+            path: path.to_str().unwrap_or_default().to_string(),
+            thumbnail_url: self.cover_url(path).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the url `/api/source/local/cover` serves the cover of a manga directory with,
+    /// clients never see where the library is on disk
+    fn cover_url(&self, path: &Path) -> Option<String> {
+        find_cover(path)?;
+        let parent = path.parent()?.canonicalize().ok()?;
+        let library = self
+            .paths
+            .iter()
+            .position(|library| library.canonicalize().ok().as_ref() == Some(&parent))?;
+        let manga = path.file_name()?.to_str()?;
+
+        Some(format!(
+            "/api/source/local/cover?{}",
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("library", &library.to_string())
+                .append_pair("manga", manga)
+                .append_pair("sig", &self.cover_signature(library, manga))
+                .finish()
+        ))
+    }
+
+    fn cover_mac(&self, library: usize, manga: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_varkey(self.secret.as_bytes())
+            .expect("HMAC accepts any key length");
+        mac.update(format!("cover:{}/{}", library, manga).as_bytes());
+        mac
+    }
+
+    fn cover_signature(&self, library: usize, manga: &str) -> String {
+        base64::encode_config(
+            self.cover_mac(library, manga).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    /// Returns the content type and bytes of the cover of a manga,
+    /// `None` if the url was not given by `cover_url` or the manga has no cover
+    pub fn cover(&self, query: &CoverQuery) -> Option<(String, Vec<u8>)> {
+        let sig = base64::decode_config(&query.sig, base64::URL_SAFE_NO_PAD).ok()?;
+        self.cover_mac(query.library, &query.manga)
+            .verify(&sig)
+            .ok()?;
+
+        let mut components = Path::new(&query.manga).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => {}
+            _ => return None,
+        }
+        let path = self.paths.get(query.library)?.join(&query.manga);
+        if !self.is_in_library(&path) || !path.is_dir() {
+            return None;
+        }
+
+        let cover = find_cover(&path)?;
+        let bytes = std::fs::read(&cover).ok()?;
+        let mime = mime_guess::from_path(&cover).first_or_octet_stream();
+        Some((mime.to_string(), bytes))
+    }
+}
+
+impl Extension for Local {
+    fn info(&self) -> Source {
+        Source {
+            name: NAME.to_string(),
+            url: "".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            rate_limit: None,
+        }
+    }
+
+    fn filters(&self) -> Filters {
+        Filters {
+            fields: vec![],
+            sort_by: vec![
+                FilterOption {
+                    value: "title".to_string(),
+                    name: "Title".to_string(),
+                },
+                FilterOption {
+                    value: "last_updated".to_string(),
+                    name: "Last Updated".to_string(),
+                },
+            ],
+        }
+    }
+
+    fn get_mangas(&self, param: Params, _auth: String) -> Result<Vec<Manga>> {
+        let keyword = param.keyword.unwrap_or_default().to_lowercase();
+
+        let mut mangas = vec![];
+        for library in self.paths.iter() {
+            let entries = match std::fs::read_dir(library) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!("failed to read library {:?}: {}", library, e);
+                    continue;
+                }
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if !path.is_dir() {
+                    continue;
+                }
+                if !keyword.is_empty() && !file_name(&path).to_lowercase().contains(&keyword) {
+                    continue;
+                }
+                mangas.push(path);
+            }
+        }
+
+        let last_updated = match param.sort_key.as_deref() {
+            Some(key) => key == "last_updated",
+            None => param.sort_by.unwrap_or_default() == SortByParam::LastUpdated,
+        };
+        if last_updated {
+            mangas.sort_by_key(|path| modified(path));
+        } else {
+            mangas.sort_by_key(|path| file_name(path).to_lowercase());
+        }
+        if param.sort_order.unwrap_or_default() == SortOrderParam::Desc {
+            mangas.reverse();
+        }
+
+        let page = param
+            .page
+            .and_then(|page| page.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+
+        Ok(mangas
+            .iter()
+            .skip((page - 1) * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .map(|path| self.manga_from_dir(path))
+            .collect())
+    }
+
+    fn get_manga_info(&self, path: &str, _auth: String) -> Result<Manga> {
+        let path = PathBuf::from(path);
+        if !self.is_in_library(&path) || !path.is_dir() {
+            return Err(anyhow!("{:?} is not a manga in library", path));
+        }
+
+        Ok(self.manga_from_dir(&path))
+    }
+
+    fn get_chapters(&self, path: &str, _auth: String) -> Result<Vec<Chapter>> {
+        let path = PathBuf::from(path);
+        if !self.is_in_library(&path) || !path.is_dir() {
+            return Err(anyhow!("{:?} is not a manga in library", path));
+        }
+
+        let mut chapters = vec![];
+        let mut has_images = false;
+        for entry in std::fs::read_dir(&path)?.filter_map(|e| e.ok()) {
+            let entry_path = entry.path();
+            if entry_path.is_dir() || has_extension(&entry_path, ARCHIVE_EXTENSIONS) {
+                chapters.push(chapter_from_path(&entry_path));
+            } else if has_extension(&entry_path, IMAGE_EXTENSIONS) {
+                has_images = true;
+            }
+        }
+
+        if chapters.is_empty() && has_images {
+            chapters.push(chapter_from_path(&path));
+        }
+
+        Ok(chapters)
+    }
+
+    fn get_pages(&self, path: &str, _auth: String) -> Result<Vec<String>> {
+        let path = PathBuf::from(path);
+        if !self.is_in_library(&path) {
+            return Err(anyhow!("{:?} is not a chapter in library", path));
+        }
+
+        let mut pages = if path.is_dir() {
+            std::fs::read_dir(&path)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_file() && has_extension(p, IMAGE_EXTENSIONS))
+                .filter_map(|p| p.to_str().map(|p| p.to_string()))
+                .collect::<Vec<String>>()
+        } else if has_extension(&path, ARCHIVE_EXTENSIONS) {
+            let archive = zip::ZipArchive::new(File::open(&path)?)?;
+            archive
+                .file_names()
+                .filter(|name| has_extension(Path::new(name), IMAGE_EXTENSIONS))
+                .filter_map(|name| path.join(name).to_str().map(|p| p.to_string()))
+                .collect::<Vec<String>>()
+        } else {
+            return Err(anyhow!("{:?} is not a directory or archive", path));
+        };
+        pages.sort();
+
+        Ok(pages)
+    }
+
+    /// Returns an image from disk, `url` is either a path to an image file
+    /// or a path to an image inside an archive, e.g `/manga/chapter.cbz/001.jpg`
+    fn get_page(&self, url: &str, _auth: String) -> Result<Vec<u8>> {
+        let path = PathBuf::from(url);
+        if !self.is_in_library(&path) {
+            return Err(anyhow!("{:?} is not a page in library", path));
+        }
+
+        if path.is_file() {
+            return Ok(std::fs::read(&path)?);
+        }
+
+        let archive_path = path
+            .ancestors()
+            .find(|p| p.is_file() && has_extension(p, ARCHIVE_EXTENSIONS))
+            .ok_or_else(|| anyhow!("page {:?} not found", path))?;
+        let entry_name = path
+            .strip_prefix(archive_path)?
+            .to_str()
+            .unwrap_or_default()
+            .replace("\\", "/");
+
+        let mut archive = zip::ZipArchive::new(File::open(archive_path)?)?;
+        let mut entry = archive.by_name(&entry_name)?;
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+}
+
+/// Returns `path` with `..` and symlinks resolved. The end of `path` that doesn't exist on disk,
+/// e.g an image inside an archive, is kept as is and may only contain names.
+fn canonicalize(path: &Path) -> Option<PathBuf> {
+    let mut existing = path;
+    let mut rest = vec![];
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            if rest.iter().any(|c| !matches!(c, Component::Normal(_))) {
+                return None;
+            }
+            return Some(rest.iter().rev().fold(canonical, |path, c| path.join(c)));
+        }
+        rest.push(existing.components().next_back()?);
+        existing = existing.parent()?;
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn modified(path: &Path) -> chrono::NaiveDateTime {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(|t| chrono::DateTime::<chrono::Local>::from(t).naive_local())
+        .unwrap_or_else(|_| chrono::NaiveDateTime::from_timestamp(0, 0))
+}
+
+/// Returns the first image of a manga directory, looking into its first chapter if needed
+fn find_cover(path: &Path) -> Option<String> {
+    let mut entries = std::fs::read_dir(path)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect::<Vec<PathBuf>>();
+    entries.sort();
+
+    if let Some(image) = entries
+        .iter()
+        .find(|p| p.is_file() && has_extension(p, IMAGE_EXTENSIONS))
+    {
+        return image.to_str().map(|p| p.to_string());
+    }
+
+    let chapter = entries.iter().find(|p| p.is_dir())?;
+    find_cover(chapter)
+}
+
+/// Returns the first number found in a chapter name, after the last `ch` or `chapter` word
+/// if there is one, e.g `Vol.2 Chapter 10.5` returns `10.5`
+pub fn chapter_number(name: &str) -> Option<String> {
+    let name = name
+        .to_lowercase()
+        .replace("vol.", "")
+        .replace("volume", "");
+    let is_letter = |c: Option<char>| c.map(char::is_alphabetic).unwrap_or(false);
+    let chapter = name.rmatch_indices("ch").find(|(index, _)| {
+        let after = &name[index + 2..];
+        let after = after.strip_prefix("apter").unwrap_or(after);
+        !is_letter(name[..*index].chars().next_back()) && !is_letter(after.chars().next())
+    });
+    let name = match chapter {
+        Some((index, _)) => &name[index..],
+        None => name.as_str(),
+    };
+
+    let start = name.find(|c: char| c.is_ascii_digit())?;
+    let number = name[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect::<String>();
+
+    Some(number.trim_end_matches('.').to_string())
+}
+
+fn chapter_from_path(path: &Path) -> Chapter {
+    let title = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) if !path.is_dir() => stem.to_string(),
+        _ => file_name(path),
+    };
+
+    Chapter {
+        source: NAME.to_string(),
+        no: chapter_number(&title),
+        title: Some(title),
+        path: path.to_str().unwrap_or_default().to_string(),
+        uploaded: modified(path),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn chapter_numbers() {
+        for (name, number) in &[
+            ("Chapter 10", Some("10")),
+            ("Ch.10.5", Some("10.5")),
+            ("ch 7.", Some("7")),
+            ("Vol.2 Chapter 10.5", Some("10.5")),
+            ("Volume 3 Ch. 21", Some("21")),
+            ("Vol.02", Some("02")),
+            ("Manga 001", Some("001")),
+            ("Chapter 3 - 2nd part", Some("3")),
+            ("My Manga Vol.1 Ch.4 v2", Some("4")),
+            ("Beach 1 Chapter 3", Some("3")),
+            ("Bleach 12", Some("12")),
+            ("Ch10", Some("10")),
+            ("Chapters 1-2 Chapter 5", Some("5")),
+            ("Extra", None),
+            ("Chapter", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                chapter_number(name).as_deref(),
+                *number,
+                "chapter number of {:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn is_in_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        let manga = library.join("Manga A");
+        std::fs::create_dir_all(&manga).unwrap();
+        std::fs::create_dir(dir.path().join("other")).unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(manga.join("Chapter 1.cbz")).unwrap());
+        zip.start_file("001.png", Default::default()).unwrap();
+        zip.write_all(b"page").unwrap();
+        zip.finish().unwrap();
+
+        // the library is given with a `..`, the same directory is still matched
+        let local = Local::new(
+            vec![format!("{}/other/../library", dir.path().display())],
+            "secret",
+        );
+        assert!(local.is_in_library(&manga));
+        assert!(local.is_in_library(&manga.join("Chapter 1.cbz")));
+        assert!(local.is_in_library(&manga.join("Chapter 1.cbz/001.png")));
+        assert!(local.is_in_library(&manga.join("missing.cbz/001.png")));
+        assert!(local.is_in_library(&library.join("../library/Manga A")));
+
+        assert!(!local.is_in_library(dir.path()));
+        assert!(!local.is_in_library(&library.join("../other")));
+        assert!(!local.is_in_library(&manga.join("../../other")));
+        assert!(!local.is_in_library(&manga.join("Chapter 1.cbz/../../../other")));
+        assert!(!local.is_in_library(&manga.join("missing/../../../other")));
+        assert!(!local.is_in_library(Path::new("/etc/passwd")));
+        assert!(!local.is_in_library(Path::new("relative")));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("other"), manga.join("link")).unwrap();
+            assert!(!local.is_in_library(&manga.join("link")));
+            assert!(!local.is_in_library(&manga.join("link/secret.png")));
+        }
+
+        let page = local
+            .get_page(
+                &manga.join("Chapter 1.cbz/001.png").display().to_string(),
+                String::new(),
+            )
+            .unwrap();
+        assert_eq!(page, b"page");
+        assert!(local
+            .get_chapters(
+                &manga.join("../../other").display().to_string(),
+                String::new()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn covers() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        std::fs::create_dir_all(library.join("Manga A/Chapter 1")).unwrap();
+        std::fs::write(library.join("Manga A/Chapter 1/001.png"), b"cover").unwrap();
+        std::fs::create_dir_all(library.join("Empty")).unwrap();
+        std::fs::write(dir.path().join("secret.png"), b"secret").unwrap();
+        let local = Local::new(vec![library.display().to_string()], "secret");
+
+        let manga = local.manga_from_dir(&library.join("Manga A"));
+        assert!(manga
+            .thumbnail_url
+            .starts_with("/api/source/local/cover?library=0&manga=Manga+A&sig="));
+        assert!(!manga
+            .thumbnail_url
+            .contains(&dir.path().display().to_string()));
+        assert_eq!(
+            local.manga_from_dir(&library.join("Empty")).thumbnail_url,
+            ""
+        );
+
+        let url = url::Url::parse(&format!("http://localhost{}", manga.thumbnail_url)).unwrap();
+        let query: CoverQuery = cover_query(&url);
+        assert_eq!(
+            local.cover(&query),
+            Some(("image/png".to_string(), b"cover".to_vec()))
+        );
+
+        // only urls signed with the same secret are served
+        let other = Local::new(vec![library.display().to_string()], "other secret");
+        assert_eq!(other.cover(&query), None);
+        let tampered = CoverQuery {
+            manga: "Empty".to_string(),
+            ..query
+        };
+        assert_eq!(local.cover(&tampered), None);
+
+        // a signed name still can't lead out of the library
+        let outside = CoverQuery {
+            library: 0,
+            manga: "..".to_string(),
+            sig: local.cover_signature(0, ".."),
+        };
+        assert_eq!(local.cover(&outside), None);
+    }
+
+    fn cover_query(url: &url::Url) -> CoverQuery {
+        let pairs: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+        CoverQuery {
+            library: pairs["library"].parse().unwrap(),
+            manga: pairs["manga"].clone(),
+            sig: pairs["sig"].clone(),
+        }
+    }
+}
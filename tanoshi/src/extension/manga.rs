@@ -1,84 +1,119 @@
 use anyhow::{anyhow, Result};
 use serde_json::json;
-use std::io::Read;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
 use warp::Rejection;
 
-use tanoshi_lib::extensions::Extension;
-use tanoshi_lib::manga::{GetParams, Params, SourceIndex, SourceLogin};
+use tanoshi_lib::extensions::{Extension, SESSION_EXPIRED};
+use tanoshi_lib::manga::{Chapter, GetParams, Params, SourceIndex, SourceLogin, SourceLoginResult};
 use tanoshi_lib::rest::{
-    GetChaptersResponse, GetMangaResponse, GetMangasResponse, GetPagesResponse, ReadResponse,
+    GetChaptersResponse, GetFiltersResponse, GetMangaResponse, GetMangasResponse, GetPagesResponse,
+    GetSourceConfigResponse, GlobalSearchResponse, ReadResponse, SourceSearchResult,
 };
 
 use crate::auth::Claims;
-use crate::extension::{repository::Repository, Extensions};
-use crate::handlers::TransactionReject;
+use crate::config;
+use crate::error::Error;
+use crate::extension::guard::{ErrorKind, ExtensionError, Guard, Priority};
+use crate::extension::plugin_config::PluginConfig;
+use crate::extension::session::{Session, Sessions};
+use crate::extension::verify::{self, Manifest, Verifier};
+use crate::extension::watcher::config_name;
+use crate::extension::{index::Index, repository::Repository, ExtensionProxy, Extensions};
+use crate::handlers::reject;
 
 #[derive(Clone)]
 pub struct Manga {
     repo: Repository,
     exts: Arc<RwLock<Extensions>>,
+    index: Index,
+    verifier: Verifier,
+    plugin_config: PluginConfig,
+    guard: Guard,
+    sessions: Sessions,
 }
 
 impl Manga {
-    pub fn new(database_path: String, exts: Arc<RwLock<Extensions>>) -> Self {
+    pub fn new(
+        database_path: String,
+        exts: Arc<RwLock<Extensions>>,
+        repositories: Vec<config::Repository>,
+        verifier: Verifier,
+        plugin_config: PluginConfig,
+        guard: Guard,
+        sessions: Sessions,
+    ) -> Self {
         Self {
             repo: Repository::new(database_path),
             exts,
+            index: Index::new(repositories),
+            verifier,
+            plugin_config,
+            guard,
+            sessions,
         }
     }
 
+    /// Installed sources with a newer compatible version in a repository, reads every repository
+    pub fn outdated_sources(&self) -> Vec<SourceIndex> {
+        let available_sources = self.index.sources();
+        let exts = self.exts.read().unwrap_or_else(PoisonError::into_inner);
+        available_sources
+            .into_iter()
+            .filter_map(|mut s| {
+                let ext = exts.get(&s.name)?;
+                s.installed = true;
+                s.installed_version = ext.info().version;
+                s.update = tanoshi_lib::version::is_update(&s.installed_version, &s.version)
+                    && tanoshi_lib::version::is_compatible(&s.core_version);
+                Some(s).filter(|s| s.update)
+            })
+            .collect()
+    }
+
     pub async fn list_sources(&self) -> Result<impl warp::Reply, Rejection> {
-        let resp = ureq::get(
-            format!(
-                "https://raw.githubusercontent.com/faldez/tanoshi-extensions/repo-{}/index.json",
-                std::env::consts::OS
-            )
-            .as_str(),
-        )
-        .call();
-        let mut available_sources = resp.into_json_deserialize::<Vec<SourceIndex>>().unwrap();
-        let exts = self.exts.read().unwrap();
+        let mut available_sources = self.index.fetch_sources().await;
+        let exts = self.exts.read().unwrap_or_else(PoisonError::into_inner);
 
-        let sources = available_sources
+        let mut sources = available_sources
             .iter_mut()
             .map(|s| {
                 if let Some(ext) = exts.get(&s.name) {
                     s.installed = true;
                     s.installed_version = ext.info().version.clone();
-                    let installed_version = ext
-                        .info()
-                        .version
-                        .split(".")
-                        .map(|v| v.parse::<i32>().unwrap())
-                        .collect::<Vec<i32>>();
-                    let available_version = s
-                        .version
-                        .split(".")
-                        .map(|v| v.parse::<i32>().unwrap())
-                        .collect::<Vec<i32>>();
-                    if installed_version[0] < available_version[0] {
-                        s.update = true;
-                    } else if installed_version[0] == available_version[0]
-                        && installed_version[1] < available_version[1]
-                    {
-                        s.update = true;
-                    } else if installed_version[0] == available_version[0]
-                        && installed_version[1] == available_version[1]
-                        && installed_version[2] < available_version[2]
-                    {
-                        s.update = true;
-                    }
-                    if s.core_version != tanoshi_lib::CORE_VERSION
-                        || s.rustc_version != tanoshi_lib::RUSTC_VERSION
-                    {
-                        s.update = false;
-                    }
+                    s.update = tanoshi_lib::version::is_update(&s.installed_version, &s.version)
+                        && tanoshi_lib::version::is_compatible(&s.core_version);
                 }
                 s.clone()
             })
             .collect::<Vec<SourceIndex>>();
 
+        // built-in and manually copied sources are not listed in the extension repository,
+        // built-in sources have no path
+        for (name, ext) in exts.extensions().iter() {
+            if sources.iter().any(|s| &s.name == name) {
+                continue;
+            }
+            let version = ext.info().version;
+            let path = exts
+                .path(name)
+                .and_then(|path| path.file_name().map(|p| p.to_string_lossy().to_string()))
+                .unwrap_or_default();
+            sources.push(SourceIndex {
+                name: name.clone(),
+                path,
+                rustc_version: tanoshi_lib::RUSTC_VERSION.to_string(),
+                core_version: tanoshi_lib::CORE_VERSION.to_string(),
+                installed_version: version.clone(),
+                version,
+                installed: true,
+                update: false,
+                repository: "".to_string(),
+                hash: "".to_string(),
+                signature: None,
+            });
+        }
+
         Ok(warp::reply::json(&json!(
             {
                 "sources": sources,
@@ -92,117 +127,398 @@ impl Manga {
         source_name: String,
         plugin_path: String,
     ) -> Result<impl warp::Reply, Rejection> {
-        let resp = ureq::get(
-            format!(
-                "https://raw.githubusercontent.com/faldez/tanoshi-extensions/repo-{}/index.json",
-                std::env::consts::OS
-            )
-            .as_str(),
-        )
-        .call();
-        let available_sources = resp.into_json_deserialize::<Vec<SourceIndex>>().unwrap();
-        if let Some(source) = available_sources.iter().find(|s| s.name == source_name) {
-            let ext = if cfg!(target_os = "windows") {
-                "dll"
-            } else if cfg!(target_os = "macos") {
-                "dylib"
-            } else if cfg!(target_os = "linux") {
-                "so"
-            } else {
-                return Err(warp::reject::custom(TransactionReject {
-                    message: "os not supported".to_string(),
-                }));
-            };
+        let source = match self.index.find(&source_name).await {
+            Some(source) => source,
+            None => {
+                return Err(reject(Error::NotFound(format!(
+                    "{} is not in any extension repository",
+                    &source_name
+                ))));
+            }
+        };
 
-            let file_name = format!("{}.{}", &source_name, &ext);
+        match self.install(&source, &plugin_path).await {
+            Ok(_) => Ok(warp::reply()),
+            Err(e) => Err(reject(e)),
+        }
+    }
 
-            let path = std::path::PathBuf::from(&plugin_path).join(&file_name);
+    /// Install every installed source that has a compatible update in the extension repositories
+    pub async fn update_sources(&self, plugin_path: String) -> Result<impl warp::Reply, Rejection> {
+        let available_sources = self.index.fetch_sources().await;
+        let outdated = {
+            let exts = self.exts.read().unwrap_or_else(PoisonError::into_inner);
+            available_sources
+                .into_iter()
+                .filter(|s| match exts.get(&s.name) {
+                    Some(ext) => {
+                        tanoshi_lib::version::is_update(&ext.info().version, &s.version)
+                            && tanoshi_lib::version::is_compatible(&s.core_version)
+                    }
+                    None => false,
+                })
+                .collect::<Vec<SourceIndex>>()
+        };
 
-            {
-                let mut exts = self.exts.write().unwrap();
-                if exts.remove(&source_name).is_ok() {
-                    if std::fs::remove_file(&path).is_ok() {}
+        let mut updated = vec![];
+        let mut failed = vec![];
+        for source in outdated.iter() {
+            match self.install(source, &plugin_path).await {
+                Ok(_) => {
+                    info!("update {} to {}", &source.name, &source.version);
+                    updated.push(json!({
+                        "name": source.name,
+                        "version": source.version,
+                    }));
+                }
+                Err(e) => {
+                    error!("failed to update {}: {}", &source.name, e);
+                    failed.push(json!({
+                        "name": source.name,
+                        "message": e.to_string(),
+                    }));
                 }
             }
+        }
 
-            let resp = ureq::get(
-                format!(
-                    "https://raw.githubusercontent.com/faldez/tanoshi-extensions/repo-{}/{}",
-                    std::env::consts::OS,
-                    &source.path,
-                )
-                .as_str(),
-            )
-            .call();
-
-            let mut reader = resp.into_reader();
-            let mut bytes = vec![];
-            if let Err(e) = reader.read_to_end(&mut bytes) {
-                return Err(warp::reject::custom(TransactionReject {
-                    message: e.to_string(),
-                }));
+        Ok(warp::reply::json(&json!(
+            {
+                "updated": updated,
+                "failed": failed,
+                "status": "success"
             }
+        )))
+    }
 
-            if let Err(e) = std::fs::write(&path, &bytes) {
-                return Err(warp::reject::custom(TransactionReject {
-                    message: e.to_string(),
-                }));
+    async fn install(&self, source: &SourceIndex, plugin_path: &str) -> Result<()> {
+        if !tanoshi_lib::version::is_compatible(&source.core_version) {
+            return Err(Error::BadRequest(format!(
+                "{} {} requires core version {}, but this tanoshi has {}",
+                &source.name,
+                &source.version,
+                &source.core_version,
+                tanoshi_lib::CORE_VERSION
+            ))
+            .into());
+        }
+
+        let ext = if source.path.ends_with(".wasm") {
+            "wasm"
+        } else if source.path.ends_with(".yml") || source.path.ends_with(".yaml") {
+            "yml"
+        } else if cfg!(target_os = "windows") {
+            "dll"
+        } else if cfg!(target_os = "macos") {
+            "dylib"
+        } else if cfg!(target_os = "linux") {
+            "so"
+        } else {
+            return Err(anyhow!("os not supported"));
+        };
+
+        let file_name = format!("{}.{}", &source.name, &ext);
+
+        let path = std::path::PathBuf::from(plugin_path).join(&file_name);
+
+        // downloading, writing and initializing the extension is done before
+        // the lock is taken, so other sources can be used in the meantime
+        let http = self
+            .exts
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .http();
+        let config = self.plugin_config.get(&config_name(&path));
+        let opened = {
+            let manga = self.clone();
+            let source = source.clone();
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || {
+                manga.write_source(&source, &path)?;
+                let plugin = manga.verifier.verify_file(&path)?;
+                unsafe { Extensions::open_file(&http, &plugin, config.as_ref()) }
+            })
+            .await??
+        };
+
+        let old_path = {
+            let mut exts = self.exts.write().unwrap_or_else(PoisonError::into_inner);
+            let old_path = exts.path(&source.name).filter(|p| p != &path);
+            if let Some(old_path) = &old_path {
+                exts.unload(old_path);
             }
+            exts.insert_file(&path, opened);
+            old_path
+        };
+        if let Some(old_path) = old_path {
+            let _ = std::fs::remove_file(&old_path);
+            let _ = std::fs::remove_file(Manifest::path(&old_path));
+        }
+        self.guard.reset(&source.name);
 
-            {
-                let mut ext = self.exts.write().unwrap();
-                if ext.get(&source_name).is_none() {
-                    unsafe {
-                        if let Err(e) = ext.load(path.to_str().unwrap().to_string(), None) {
-                            return Err(warp::reject::custom(TransactionReject {
-                                message: e.to_string(),
-                            }));
-                        }
-                    }
+        Ok(())
+    }
+
+    /// Download a source from its repository and write it to `path` once it is verified
+    fn write_source(&self, source: &SourceIndex, path: &Path) -> Result<()> {
+        let bytes = self.index.download(source)?;
+
+        self.verifier
+            .verify(
+                &source.name,
+                &bytes,
+                &source.hash,
+                source.signature.as_deref(),
+            )
+            .map_err(|e| anyhow!("refuse to install {}: {}", &source.name, e))?;
+
+        // the manifest is written first so the watcher verifies the new file against it
+        let manifest = Manifest {
+            hash: verify::sha256(&bytes),
+            signature: source.signature.clone(),
+        };
+        manifest.write(path)?;
+
+        // write to a temporary file and rename it, so a library that is still
+        // loaded is never overwritten in place and the watcher never sees a partial file
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let part_path = path.with_extension(format!("{}.part", ext));
+        std::fs::write(&part_path, &bytes)?;
+        std::fs::rename(&part_path, path)?;
+
+        Ok(())
+    }
+
+    pub async fn uninstall_source(
+        &self,
+        source_name: String,
+    ) -> Result<impl warp::Reply, Rejection> {
+        let mut exts = self.exts.write().unwrap_or_else(PoisonError::into_inner);
+        if exts.get(&source_name).is_none() {
+            return Err(reject(Error::NotFound(format!(
+                "{} is not installed",
+                &source_name
+            ))));
+        }
+        let path = match exts.path(&source_name) {
+            Some(path) => path,
+            None => {
+                return Err(reject(Error::BadRequest(format!(
+                    "{} is built-in and can not be uninstalled",
+                    &source_name
+                ))));
+            }
+        };
+
+        let names = exts.unload(&path);
+        info!("uninstall {:?} from {:?}", names, path);
+        if let Err(e) = std::fs::remove_file(&path) {
+            return Err(reject(Error::Internal(format!(
+                "failed to remove {:?}: {}",
+                path, e
+            ))));
+        }
+        let _ = std::fs::remove_file(Manifest::path(&path));
+
+        Ok(warp::reply())
+    }
+
+    pub async fn get_source_config(&self, source: String) -> Result<GetSourceConfigResponse> {
+        let (ext, path) = configurable(
+            &self.exts.read().unwrap_or_else(PoisonError::into_inner),
+            &source,
+        )?;
+        let config = self
+            .plugin_config
+            .get(&config_name(&path))
+            .unwrap_or(serde_yaml::Value::Null);
+
+        Ok(GetSourceConfigResponse {
+            preferences: ext.preferences(),
+            config: serde_json::to_value(config)?,
+            status: "success".to_string(),
+        })
+    }
+
+    /// Merge `values` into the config of a source and register its extensions again with it,
+    /// a `null` value removes the key so the extension uses its default
+    pub async fn update_source_config(
+        &self,
+        source: String,
+        values: serde_json::Value,
+    ) -> Result<()> {
+        let values = match values {
+            serde_json::Value::Object(values) => values,
+            _ => return Err(Error::BadRequest("config must be an object".to_string()).into()),
+        };
+
+        let (ext, path) = configurable(
+            &self.exts.read().unwrap_or_else(PoisonError::into_inner),
+            &source,
+        )?;
+        let preferences = ext.preferences();
+        for (key, value) in values.iter().filter(|(_, value)| !value.is_null()) {
+            match preferences.fields.iter().find(|field| field.key() == key) {
+                Some(field) => field
+                    .validate(value)
+                    .map_err(|e| Error::BadRequest(e.to_string()))?,
+                None => {
+                    return Err(
+                        Error::BadRequest(format!("{} has no preference {}", &source, key)).into(),
+                    )
                 }
             }
+        }
 
-            Ok(warp::reply())
-        } else {
-            Err(warp::reject::custom(TransactionReject {
-                message: "extension not found".to_string(),
-            }))
+        let name = config_name(&path);
+        let mut config = match self.plugin_config.get(&name) {
+            Some(serde_yaml::Value::Mapping(config)) => config,
+            _ => serde_yaml::Mapping::new(),
+        };
+        for (key, value) in values {
+            let key = serde_yaml::Value::String(key);
+            if value.is_null() {
+                config.remove(&key);
+            } else {
+                config.insert(key, serde_yaml::to_value(value)?);
+            }
         }
+        let config = serde_yaml::Value::Mapping(config);
+
+        // the source keeps its previous config until the new one is accepted
+        let http = self
+            .exts
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .http();
+        let opened = {
+            let verifier = self.verifier.clone();
+            let path = path.clone();
+            let config = config.clone();
+            tokio::task::spawn_blocking(move || -> Result<_> {
+                let plugin = verifier.verify_file(&path)?;
+                Ok(unsafe { Extensions::open_file(&http, &plugin, Some(&config)) })
+            })
+            .await??
+        };
+        let opened = opened
+            .map_err(|e| Error::BadRequest(format!("{} refused the config: {}", &source, e)))?;
+        self.exts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert_file(&path, opened);
+        info!("reload {} with new config", &source);
+        self.guard.reset(&source);
+
+        self.plugin_config.set(&name, &config)
+    }
+
+    pub async fn get_filters(&self, source: String) -> Result<GetFiltersResponse> {
+        let filters = self.guard.call(&source, |ext| Ok(ext.filters())).await?;
+
+        Ok(GetFiltersResponse {
+            filters,
+            status: "success".to_string(),
+        })
     }
 
     pub async fn list_mangas(
         &self,
         source: String,
         claim: Claims,
-        source_auth: String,
         param: Params,
     ) -> Result<GetMangasResponse> {
-        let exts = self.exts.read().unwrap();
-        let mangas = exts
-            .get(&source)
-            .unwrap()
-            .get_mangas(param, source_auth)
-            .unwrap();
+        let mangas = self
+            .call_auth(
+                &source,
+                Some(&claim.sub),
+                Priority::Interactive,
+                move |ext, auth| ext.get_mangas(param.clone(), auth),
+            )
+            .await?;
         debug!("mangas {:?}", mangas.clone());
 
         let manga_ids = match self.repo.insert_mangas(&source, mangas.clone()) {
             Ok(ids) => ids,
             Err(e) => {
-                return Err(anyhow!("{}", e));
+                return Err(e);
             }
         };
 
         match self.repo.get_mangas(claim.sub, manga_ids) {
             Ok(mangas) => return Ok(mangas),
-            Err(e) => Err(anyhow!("{}", e)),
+            Err(e) => Err(e.into()),
         }
     }
 
+    /// Search every installed source at once, each source has `search_timeout` to answer
+    pub async fn search(&self, claim: Claims, keyword: String) -> Result<GlobalSearchResponse> {
+        let mut sources = self
+            .exts
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extensions()
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>();
+        sources.sort();
+
+        let timeout = self.guard.search_timeout();
+        let searches = sources
+            .into_iter()
+            .map(|source| {
+                let manga = self.clone();
+                let claim = claim.clone();
+                let param = Params {
+                    keyword: Some(keyword.clone()),
+                    ..Default::default()
+                };
+                tokio::spawn(async move {
+                    let (mangas, error) = match tokio::time::timeout(
+                        timeout,
+                        manga.list_mangas(source.clone(), claim, param),
+                    )
+                    .await
+                    {
+                        Ok(Ok(res)) => (res.mangas, None),
+                        Ok(Err(e)) => (vec![], Some(e.to_string())),
+                        Err(_) => (
+                            vec![],
+                            Some(format!("no response after {} seconds", timeout.as_secs())),
+                        ),
+                    };
+                    SourceSearchResult {
+                        source,
+                        mangas,
+                        error,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = vec![];
+        for search in searches {
+            match search.await {
+                Ok(result) => results.push(result),
+                Err(e) => error!("search task failed: {}", e),
+            }
+        }
+
+        Ok(GlobalSearchResponse {
+            sources: results,
+            status: "success".to_string(),
+        })
+    }
+
     pub async fn get_manga_info(&self, manga_id: i32, claim: Claims) -> Result<GetMangaResponse> {
-        let exts = self.exts.read().unwrap();
         let manga = match self.repo.get_manga_detail(manga_id, claim.sub.clone()) {
             Ok(manga) => manga,
-            Err(e) => return Err(anyhow!("{}", e)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                return Err(Error::NotFound(format!("manga {} not found", manga_id)).into())
+            }
+            Err(e) => return Err(e.into()),
         };
 
         if manga.manga.status.is_some()
@@ -212,19 +528,23 @@ impl Manga {
             return Ok(manga);
         }
 
-        let manga = exts
-            .get(&manga.manga.source)
-            .unwrap()
-            .get_manga_info(&manga.manga.path)
-            .unwrap();
+        let path = manga.manga.path;
+        let manga = self
+            .call_auth(
+                &manga.manga.source,
+                Some(&claim.sub),
+                Priority::Interactive,
+                move |ext, auth| ext.get_manga_info(&path, auth),
+            )
+            .await?;
 
         if let Err(e) = self.repo.update_manga_info(manga_id, manga) {
-            return Err(anyhow!("{}", e));
+            return Err(e.into());
         }
 
         match self.repo.get_manga_detail(manga_id, claim.sub) {
             Ok(res) => Ok(res),
-            Err(e) => Err(anyhow!("{}", e)),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -234,117 +554,401 @@ impl Manga {
         claim: Claims,
         param: GetParams,
     ) -> Result<GetChaptersResponse> {
-        let exts = self.exts.read().unwrap();
         let refresh = param.refresh.unwrap_or(false);
+        let cached = self.repo.get_chapters(manga_id, claim.sub.clone());
         if !refresh {
-            if let Ok(chapter) = self.repo.get_chapters(manga_id, claim.sub.clone()) {
+            if let Ok(chapter) = cached {
                 return Ok(chapter);
             }
         }
+        // the chapters already shown are refreshed after pages and searches of other users
+        let priority = match cached {
+            Ok(_) => Priority::Background,
+            Err(_) => Priority::Interactive,
+        };
+
+        self.refresh_chapters(manga_id, &claim.sub, priority)
+            .await?;
+
+        match self.repo.get_chapters(manga_id, claim.sub) {
+            Ok(chapter) => Ok(chapter),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the chapters of a manga from its source for `username`,
+    /// returns the ones that were not saved before
+    pub async fn refresh_chapters(
+        &self,
+        manga_id: i32,
+        username: &str,
+        priority: Priority,
+    ) -> Result<Vec<Chapter>> {
+        let chapters = self
+            .fetch_chapters(manga_id, Some(username), priority)
+            .await?;
+        self.save_chapters(manga_id, username, chapters)
+    }
 
+    /// Returns the chapters of a manga from its source, called with the session of `username`,
+    /// without a session when there is no user
+    pub async fn fetch_chapters(
+        &self,
+        manga_id: i32,
+        username: Option<&str>,
+        priority: Priority,
+    ) -> Result<Vec<Chapter>> {
         let manga = match self.repo.get_manga(manga_id) {
             Ok(manga) => manga,
-            Err(e) => return Err(anyhow!("error get manga: {}", e)),
-        };
-
-        let chapter = match exts.get(&manga.source).unwrap().get_chapters(&manga.path) {
-            Ok(ch) => ch,
-            Err(e) => {
-                return Err(anyhow!("error get manga: {}", e));
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                return Err(Error::NotFound(format!("manga {} not found", manga_id)).into())
             }
+            Err(e) => return Err(e.into()),
         };
 
-        if let Err(e) = self
+        let path = manga.path;
+        let chapters = self
+            .call_auth(&manga.source, username, priority, move |ext, auth| {
+                ext.get_chapters(&path, auth)
+            })
+            .await?;
+        Ok(chapters)
+    }
+
+    /// Saves chapters of a manga for `username`, returns the ones that were not saved before
+    pub fn save_chapters(
+        &self,
+        manga_id: i32,
+        username: &str,
+        chapters: Vec<Chapter>,
+    ) -> Result<Vec<Chapter>> {
+        match self
             .repo
-            .insert_chapters(claim.sub.clone(), manga_id, chapter.clone())
+            .insert_chapters(username.to_string(), manga_id, chapters)
         {
-            return Err(anyhow!("{}", e));
-        }
-
-        match self.repo.get_chapters(manga_id, claim.sub) {
-            Ok(chapter) => Ok(chapter),
-            Err(e) => Err(anyhow!("{}", e)),
+            Ok(chapters) => Ok(chapters),
+            Err(e) => Err(e.into()),
         }
     }
 
     pub async fn get_pages(
         &self,
         chapter_id: i32,
+        username: &str,
         param: GetParams,
     ) -> anyhow::Result<GetPagesResponse> {
-        let exts = self.exts.read().unwrap();
-
-        let refresh = param.refresh.unwrap_or(false);
-        if refresh {
-            if let Err(e) = self.repo.delete_pages(chapter_id) {
-                error!("error delete page: {}", e);
-            }
+        self.check_chapter_user(chapter_id, username)?;
+        if param.refresh.unwrap_or(false) {
+            self.fetch_pages(chapter_id, username, Priority::Interactive)
+                .await?;
         }
 
+        self.save_pages(chapter_id, username, Priority::Interactive)
+            .await
+    }
+
+    /// Returns the pages of a chapter of `username`, they are fetched from its source once
+    pub async fn save_pages(
+        &self,
+        chapter_id: i32,
+        username: &str,
+        priority: Priority,
+    ) -> anyhow::Result<GetPagesResponse> {
+        self.check_chapter_user(chapter_id, username)?;
         if let Ok(pages) = self.repo.get_pages(chapter_id) {
             return Ok(pages);
         };
 
-        if let Ok(chapter) = self.repo.get_chapter(chapter_id) {
-            let pages = exts
-                .get(&chapter.source)
-                .unwrap()
-                .get_pages(&chapter.path)
-                .unwrap();
+        self.fetch_pages(chapter_id, username, priority).await?;
+        match self.repo.get_pages(chapter_id) {
+            Ok(pages) => Ok(pages),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the pages of a chapter from its source, pages that were downloaded keep their files
+    /// and files of pages the chapter no longer has are removed
+    async fn fetch_pages(&self, chapter_id: i32, username: &str, priority: Priority) -> Result<()> {
+        let chapter = match self.repo.get_chapter(chapter_id) {
+            Ok(chapter) => chapter,
+            Err(_) => {
+                return Err(Error::NotFound(format!("chapter {} not found", chapter_id)).into())
+            }
+        };
+        let path = chapter.path.clone();
+        let pages = self
+            .call_auth(
+                &chapter.source,
+                Some(username),
+                priority,
+                move |ext, auth| ext.get_pages(&path, auth),
+            )
+            .await?;
+
+        let removed = self
+            .repo
+            .replace_pages(&chapter.source, chapter_id, &pages)?;
+        for file in removed {
+            if let Err(e) = std::fs::remove_file(&file) {
+                warn!("failed to remove {}: {}", file, e);
+            }
+        }
+        Ok(())
+    }
 
-            match self
-                .repo
-                .insert_pages(chapter.source.clone(), chapter_id, pages.clone())
-            {
-                Ok(_) => {}
-                Err(e) => {
-                    return Err(anyhow::anyhow!("{}", e.to_string()));
-                }
+    /// Chapters belong to the user who opened the manga, sources are called with the session
+    /// of that user, so nobody else may load the pages of a chapter
+    fn check_chapter_user(&self, chapter_id: i32, username: &str) -> Result<()> {
+        match self.repo.get_chapter_user(chapter_id) {
+            Ok(Some(user)) if user == username => Ok(()),
+            Ok(_) | Err(rusqlite::Error::QueryReturnedNoRows) => {
+                Err(Error::NotFound(format!("chapter {} not found", chapter_id)).into())
             }
+            Err(e) => Err(e.into()),
+        }
+    }
 
-            match self.repo.get_pages(chapter_id) {
-                Ok(pages) => return Ok(pages),
-                Err(e) => {
-                    return Err(anyhow::anyhow!("{}", e.to_string()));
-                }
-            };
+    /// Returns source and url of a page of a chapter of `username`
+    fn page_of_user(&self, page_id: i32, username: &str) -> Option<(String, String)> {
+        match self.repo.get_image_from_page_id(page_id) {
+            Ok((source, url, Some(user))) if user == username => Some((source, url)),
+            _ => None,
         }
-        Err(anyhow::anyhow!("pages not found"))
     }
 
-    pub async fn proxy_image(&self, page_id: i32) -> Result<impl warp::Reply, Rejection> {
-        let (source, image_url) = match self.repo.get_image_from_page_id(page_id) {
-            Ok((source, url)) => (source, url),
-            Err(_) => return Err(warp::reject()),
+    /// Returns the content type and bytes of the image of a page, `None` if there is no such page
+    /// or it belongs to a chapter of another user.
+    ///
+    /// A downloaded page is read from its file, the source is only asked when the file is gone.
+    pub async fn get_image(
+        &self,
+        page_id: i32,
+        username: &str,
+    ) -> Result<Option<(String, Vec<u8>)>> {
+        let (source, image_url) = match self.page_of_user(page_id, username) {
+            Some(page) => page,
+            None => return Ok(None),
         };
+        if let Ok(Some(path)) = self.repo.get_local_page(page_id) {
+            match std::fs::read(&path) {
+                Ok(bytes) => {
+                    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+                    return Ok(Some((mime.to_string(), bytes)));
+                }
+                Err(e) => warn!("failed to read downloaded page {}: {}", path, e),
+            }
+        }
+        self.fetch(&source, image_url, username, Priority::Interactive)
+            .await
+            .map(Some)
+    }
+
+    /// Returns the image of a page of `username` from its source, whether it was downloaded or not
+    pub async fn fetch_image(
+        &self,
+        page_id: i32,
+        username: &str,
+        priority: Priority,
+    ) -> Result<Option<(String, Vec<u8>)>> {
+        match self.page_of_user(page_id, username) {
+            Some((source, image_url)) => self
+                .fetch(&source, image_url, username, priority)
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    }
 
-        let exts = self.exts.read().unwrap();
-        let bytes = exts.get(&source).unwrap().get_page(&image_url).unwrap();
+    async fn fetch(
+        &self,
+        source: &str,
+        image_url: String,
+        username: &str,
+        priority: Priority,
+    ) -> Result<(String, Vec<u8>)> {
+        let url = image_url.clone();
+        let bytes = self
+            .call_auth(source, Some(username), priority, move |ext, auth| {
+                ext.get_page(&url, auth)
+            })
+            .await?;
 
         let mime = match url::Url::parse(&image_url) {
-            Ok(url) => mime_guess::from_path(url.path()).first_or_octet_stream(),
+            // pages inside an archive are addressed as `<archive>#<entry>`
+            Ok(url) => url
+                .fragment()
+                .and_then(|entry| mime_guess::from_path(entry).first())
+                .unwrap_or_else(|| mime_guess::from_path(url.path()).first_or_octet_stream()),
             Err(_) => mime_guess::from_path(&image_url).first_or_octet_stream(),
         };
-        let resp = warp::http::Response::builder()
-            .header("Content-Type", mime.as_ref())
+        Ok((mime.to_string(), bytes))
+    }
+
+    pub async fn proxy_image(
+        &self,
+        page_id: i32,
+        username: &str,
+    ) -> Result<impl warp::Reply, Rejection> {
+        let (mime, bytes) = match self.get_image(page_id, username).await {
+            Ok(Some(image)) => image,
+            Ok(None) => {
+                return Err(reject(Error::NotFound(format!(
+                    "page {} not found",
+                    page_id
+                ))))
+            }
+            Err(e) => return Err(reject(e)),
+        };
+
+        warp::http::Response::builder()
+            .header("Content-Type", mime)
             .header("Content-Length", bytes.len())
             .body(bytes)
-            .unwrap();
-
-        Ok(resp)
+            .map_err(|e| reject(Error::Internal(e.to_string())))
     }
 
+    /// Log in to `source` and save the session for the user, the session is never sent
+    /// back to the browser. Credentials are saved too when the user asks to be remembered.
     pub async fn source_login(
         &self,
         source: String,
+        claim: Claims,
         login_info: SourceLogin,
     ) -> Result<impl warp::Reply, Rejection> {
-        let exts = self.exts.read().unwrap();
-        if let Ok(result) = exts.get(&source).unwrap().login(login_info) {
-            return Ok(warp::reply::json(&result));
+        let credentials = match login_info.remember_me {
+            Some(true) => Some(login_info.clone()),
+            _ => None,
+        };
+        let login = self
+            .guard
+            .call(&source, move |ext| ext.login(login_info))
+            .await
+            .map_err(reject)?;
+        let session = Session { login, credentials };
+        if let Err(e) = self.sessions.set(&claim.sub, &source, &session) {
+            return Err(reject(e));
+        }
+
+        Ok(warp::reply::json(&SourceLoginResult {
+            value: "".to_string(),
+            ..session.login
+        }))
+    }
+
+    /// Forget the session and credentials of the user for `source`
+    pub async fn source_logout(
+        &self,
+        source: String,
+        claim: Claims,
+    ) -> Result<impl warp::Reply, Rejection> {
+        match self.sessions.remove(&claim.sub, &source) {
+            Ok(_) => Ok(warp::reply()),
+            Err(e) => Err(reject(e)),
+        }
+    }
+
+    /// Returns the sources the user is logged in to
+    pub async fn source_sessions(&self, claim: Claims) -> Result<impl warp::Reply, Rejection> {
+        match self.sessions.sources(&claim.sub) {
+            Ok(sources) => Ok(warp::reply::json(&json!({ "sources": sources }))),
+            Err(e) => Err(reject(e)),
+        }
+    }
+
+    /// Call `f` with the session of `username` on `source` as `auth`, empty when the user has none.
+    /// With saved credentials the user is logged in again when the session expired,
+    /// or when the extension returns `SESSION_EXPIRED`, then `f` is called again once.
+    async fn call_auth<T, F>(
+        &self,
+        source: &str,
+        username: Option<&str>,
+        priority: Priority,
+        f: F,
+    ) -> Result<T, ExtensionError>
+    where
+        T: Send + 'static,
+        F: Fn(&ExtensionProxy, String) -> anyhow::Result<T> + Send + Sync + 'static,
+    {
+        let mut session = match username {
+            Some(username) => match self.sessions.get(username, source) {
+                Ok(session) => session,
+                Err(e) => {
+                    warn!(
+                        "failed to read session of {} on {}: {}",
+                        username, source, e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        if let (Some(username), Some(expired)) = (username, session.clone()) {
+            if expired.is_expired() {
+                session = self.relogin(username, source, expired).await;
+            }
+        }
+
+        let f = Arc::new(f);
+        let call = |session: &Option<Session>| {
+            let f = f.clone();
+            let auth = session
+                .as_ref()
+                .map(|s| s.login.value.clone())
+                .unwrap_or_default();
+            self.guard
+                .call_with(source, priority, move |ext| f(ext, auth))
+        };
+        match call(&session).await {
+            Err(e) if e.kind == ErrorKind::Failed && e.message.starts_with(SESSION_EXPIRED) => {
+                match (username, session) {
+                    (Some(username), Some(expired)) if expired.credentials.is_some() => {
+                        match self.relogin(username, source, expired).await {
+                            Some(session) => call(&Some(session)).await,
+                            None => Err(e),
+                        }
+                    }
+                    _ => Err(e),
+                }
+            }
+            res => res,
         }
+    }
 
-        Err(warp::reject())
+    /// Log in again with the saved credentials, returns the new session.
+    /// Without credentials or when login fails the user has to log in from web again.
+    async fn relogin(&self, username: &str, source: &str, expired: Session) -> Option<Session> {
+        let credentials = match expired.credentials {
+            Some(credentials) => credentials,
+            None => {
+                if let Err(e) = self.sessions.remove(username, source) {
+                    error!(
+                        "failed to remove session of {} on {}: {}",
+                        username, source, e
+                    );
+                }
+                return None;
+            }
+        };
+
+        let login = credentials.clone();
+        let session = match self.guard.call(source, move |ext| ext.login(login)).await {
+            Ok(login) => Session {
+                login,
+                credentials: Some(credentials),
+            },
+            Err(e) => {
+                warn!("failed to log {} in to {} again: {}", username, source, e);
+                return None;
+            }
+        };
+        info!("logged {} in to {} again", username, source);
+        if let Err(e) = self.sessions.set(username, source, &session) {
+            error!(
+                "failed to save session of {} on {}: {}",
+                username, source, e
+            );
+        }
+        Some(session)
     }
 
     pub async fn read(
@@ -353,18 +957,19 @@ impl Manga {
         claim: Claims,
         param: GetParams,
     ) -> Result<ReadResponse> {
-        let pages = self.get_pages(chapter_id, param.clone()).await.unwrap();
+        let pages = self
+            .get_pages(chapter_id, &claim.sub, param.clone())
+            .await?;
         let chapters = self
             .get_chapters(pages.manga_id, claim.clone(), param)
-            .await
-            .unwrap();
-        let manga = self.get_manga_info(pages.manga_id, claim).await.unwrap();
+            .await?;
+        let manga = self.get_manga_info(pages.manga_id, claim).await?;
 
         let chapter = chapters
             .chapters
             .iter()
             .find(|c| c.id == chapter_id)
-            .unwrap()
+            .ok_or_else(|| Error::NotFound(format!("chapter {} not found", chapter_id)))?
             .to_owned();
 
         Ok(ReadResponse {
@@ -375,3 +980,15 @@ impl Manga {
         })
     }
 }
+
+/// Returns the extension of a source and the file it is loaded from,
+/// built-in sources are not configurable
+fn configurable(exts: &Extensions, source: &String) -> Result<(Arc<ExtensionProxy>, PathBuf)> {
+    let ext = exts
+        .get(source)
+        .ok_or_else(|| Error::NotFound(format!("source {} not found", source)))?;
+    let path = exts.path(source).ok_or_else(|| {
+        Error::BadRequest(format!("{} is built-in and can not be configured", source))
+    })?;
+    Ok((ext, path))
+}
@@ -1,19 +1,70 @@
 use lib::Library;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::{collections::HashMap, sync::Arc};
-use tanoshi_lib::extensions::{Extension, PluginDeclaration};
-use tanoshi_lib::manga::{Chapter, Manga, Params, Source, SourceLogin, SourceLoginResult};
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::ffi::{self, PluginDeclaration};
+use tanoshi_lib::manga::{
+    Chapter, Filters, Manga, Params, Preferences, RateLimit, Source, SourceLogin, SourceLoginResult,
+};
 
 use anyhow::{anyhow, Result};
 
-//pub mod local;
+use verify::Verified;
+
+pub mod guard;
+pub mod http;
+pub mod index;
+pub mod local;
 pub mod manga;
+pub mod opds;
+pub mod plugin_config;
+pub mod remote;
 pub mod repository;
+pub mod scrape;
+pub mod session;
+pub mod verify;
+pub mod wasm;
+pub mod watcher;
 
 pub struct ExtensionProxy {
     extension: Box<dyn Extension>,
+    /// Declared after `extension` so the library is closed only after the extension is dropped
     #[allow(dead_code)]
-    lib: Arc<Library>,
+    lib: Option<Arc<Library>>,
+    rate_limit: Option<RateLimit>,
+}
+
+impl ExtensionProxy {
+    /// `rate_limit` in the plugin config takes precedence over the one declared by the extension
+    fn new(
+        extension: Box<dyn Extension>,
+        lib: Option<Arc<Library>>,
+        config: Option<&serde_yaml::Value>,
+    ) -> Self {
+        let rate_limit = config
+            .and_then(|config| config.get("rate_limit"))
+            .and_then(
+                |rate_limit| match serde_yaml::from_value(rate_limit.clone()) {
+                    Ok(rate_limit) => Some(rate_limit),
+                    Err(e) => {
+                        error!("invalid rate_limit {:?}: {}", rate_limit, e);
+                        None
+                    }
+                },
+            )
+            .or_else(|| extension.info().rate_limit);
+        Self {
+            extension,
+            lib,
+            rate_limit,
+        }
+    }
+
+    pub fn rate_limit(&self) -> Option<&RateLimit> {
+        self.rate_limit.as_ref()
+    }
 }
 
 impl Extension for ExtensionProxy {
@@ -21,24 +72,32 @@ impl Extension for ExtensionProxy {
         self.extension.info()
     }
 
+    fn filters(&self) -> Filters {
+        self.extension.filters()
+    }
+
+    fn preferences(&self) -> Preferences {
+        self.extension.preferences()
+    }
+
     fn get_mangas(&self, param: Params, auth: String) -> Result<Vec<Manga>> {
         self.extension.get_mangas(param, auth)
     }
 
-    fn get_manga_info(&self, path: &String) -> Result<Manga> {
-        self.extension.get_manga_info(path)
+    fn get_manga_info(&self, path: &str, auth: String) -> Result<Manga> {
+        self.extension.get_manga_info(path, auth)
     }
 
-    fn get_chapters(&self, path: &String) -> Result<Vec<Chapter>> {
-        self.extension.get_chapters(path)
+    fn get_chapters(&self, path: &str, auth: String) -> Result<Vec<Chapter>> {
+        self.extension.get_chapters(path, auth)
     }
 
-    fn get_pages(&self, path: &String) -> Result<Vec<String>> {
-        self.extension.get_pages(path)
+    fn get_pages(&self, path: &str, auth: String) -> Result<Vec<String>> {
+        self.extension.get_pages(path, auth)
     }
 
-    fn get_page(&self, url: &String) -> Result<Vec<u8>> {
-        self.extension.get_page(url)
+    fn get_page(&self, url: &str, auth: String) -> Result<Vec<u8>> {
+        self.extension.get_page(url, auth)
     }
 
     fn login(&self, login_info: SourceLogin) -> Result<SourceLoginResult> {
@@ -46,27 +105,145 @@ impl Extension for ExtensionProxy {
     }
 }
 
+/// Extensions loaded from a single file in `plugin_path`
+struct PluginFile {
+    modified: Option<SystemTime>,
+    names: Vec<String>,
+}
+
+/// Returns true if `path` is a file tanoshi can load extensions from
+pub fn is_plugin(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => {
+            ext == "so"
+                || ext == "dll"
+                || ext == "dylib"
+                || ext == "wasm"
+                || ext == "yml"
+                || ext == "yaml"
+        }
+        None => false,
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Extensions are shared as `Arc` so a call in progress keeps its extension,
+/// and the library it came from, alive after it is unloaded or reloaded
 pub struct Extensions {
-    extensions: HashMap<String, ExtensionProxy>,
+    extensions: HashMap<String, Arc<ExtensionProxy>>,
+    files: HashMap<PathBuf, PluginFile>,
+    http: http::HttpClients,
 }
 
 impl Extensions {
-    pub fn new() -> Extensions {
+    pub fn new(http: http::HttpClients) -> Extensions {
         Extensions {
             extensions: HashMap::new(),
+            files: HashMap::new(),
+            http,
         }
     }
 
-    pub fn get(&self, name: &String) -> Option<&ExtensionProxy> {
-        self.extensions.get(name)
+    pub fn get(&self, name: &String) -> Option<Arc<ExtensionProxy>> {
+        self.extensions.get(name).cloned()
+    }
+
+    pub fn extensions(&self) -> &HashMap<String, Arc<ExtensionProxy>> {
+        &self.extensions
     }
 
-    pub unsafe fn load(
-        &mut self,
-        library_path: String,
+    /// Returns the file an extension is loaded from, built-in extensions have none
+    pub fn path(&self, name: &String) -> Option<PathBuf> {
+        self.files
+            .iter()
+            .find(|(_, file)| file.names.contains(name))
+            .map(|(path, _)| path.clone())
+    }
+
+    /// Returns the modified time of a file when its extensions were loaded
+    pub fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.files.get(path).and_then(|file| file.modified)
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// Register an extension that is compiled into tanoshi instead of loaded from a library
+    pub fn insert(&mut self, name: String, extension: Box<dyn Extension>) {
+        self.extensions
+            .insert(name, Arc::new(ExtensionProxy::new(extension, None, None)));
+    }
+
+    /// Clients of the sources, needed to open a file without holding the lock around `Extensions`
+    pub fn http(&self) -> http::HttpClients {
+        self.http.clone()
+    }
+
+    /// Register extensions opened with `Extensions::open_file`, extensions previously
+    /// loaded from the same file are replaced
+    pub fn insert_file(&mut self, path: &Path, extensions: Vec<(String, ExtensionProxy)>) {
+        self.unload(path);
+        let mut names = vec![];
+        for (name, extension) in extensions {
+            for file in self.files.values_mut() {
+                file.names.retain(|n| n != &name);
+            }
+            self.extensions.insert(name.clone(), Arc::new(extension));
+            names.push(name);
+        }
+        let modified = modified(path);
+        self.files
+            .insert(path.to_path_buf(), PluginFile { modified, names });
+    }
+
+    /// Open extensions from a native library, a WebAssembly module or a source definition
+    /// that passed verification. Initializing an extension may do network requests,
+    /// so this doesn't need `Extensions` and the lock around it is only taken to insert the result.
+    /// A panic while loading is returned as an error.
+    pub unsafe fn open_file(
+        http: &http::HttpClients,
+        plugin: &Verified,
         config: Option<&serde_yaml::Value>,
-    ) -> Result<()> {
-        let library_path = PathBuf::from(library_path);
+    ) -> Result<Vec<(String, ExtensionProxy)>> {
+        let path = plugin.path.as_path();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let ext = path.extension().and_then(|ext| ext.to_str());
+            if ext == Some("wasm") {
+                Self::open_wasm(http, path, &plugin.bytes, config)
+            } else if ext == Some("yml") || ext == Some("yaml") {
+                Self::open_scraper(http, path, &plugin.bytes, config)
+            } else {
+                Self::open_library(http, path, &plugin.bytes, config)
+            }
+        }));
+        match res {
+            Ok(res) => res,
+            Err(_) => Err(anyhow!("{:?} panicked while loading", path)),
+        }
+    }
+
+    /// The verified bytes are loaded from a private copy, removed once the library is loaded
+    unsafe fn open_library(
+        http: &http::HttpClients,
+        path: &Path,
+        bytes: &[u8],
+        config: Option<&serde_yaml::Value>,
+    ) -> Result<Vec<(String, ExtensionProxy)>> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let mut copy = tempfile::Builder::new()
+            .prefix("tanoshi-plugin-")
+            .suffix(&format!(".{}", ext))
+            .tempfile()?;
+        copy.write_all(bytes)?;
+        let library_path = copy.into_temp_path();
+
         if cfg!(target_os = "macos") {
             if let Err(e) = std::process::Command::new("install_name_tool")
                 .current_dir(library_path.parent().unwrap())
@@ -81,56 +258,75 @@ impl Extensions {
 
         let library = Arc::new(Library::new(&library_path)?);
 
-        let decl = library
-            .get::<*mut PluginDeclaration>(b"plugin_declaration\0")?
-            .read();
-
-        if decl.rustc_version != tanoshi_lib::RUSTC_VERSION
-            || decl.core_version != tanoshi_lib::CORE_VERSION
-        {
-            return Err(anyhow!("Version mismatch: extension.rustc_version={}, extension.core_version={}, tanoshi_lib.rustc_version={}, tanoshi_lib::core_version={}", 
-                decl.rustc_version , decl.core_version, tanoshi_lib::RUSTC_VERSION, tanoshi_lib::CORE_VERSION)
-            );
-        }
-
-        let mut registrar = PluginRegistrar::new(Arc::clone(&library));
-        (decl.register)(&mut registrar, config);
+        let decl = match library.get::<*const PluginDeclaration>(ffi::DECLARATION_SYMBOL) {
+            Ok(decl) => *decl,
+            Err(_) if library.get::<*const u8>(b"plugin_declaration\0").is_ok() => {
+                return Err(anyhow!(
+                    "{:?} is built for the legacy plugin interface, rebuild it with tanoshi-lib {}",
+                    path,
+                    tanoshi_lib::CORE_VERSION
+                ));
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        self.extensions.extend(registrar.extensions);
+        let extensions = ffi::register_extensions(decl, config, &|source| http.client(source))?
+            .into_iter()
+            .map(|(name, extension)| {
+                (
+                    name,
+                    ExtensionProxy::new(Box::new(extension), Some(Arc::clone(&library)), config),
+                )
+            })
+            .collect();
 
-        Ok(())
+        Ok(extensions)
     }
 
-    pub fn remove(&mut self, name: &String) -> Result<()> {
-        if self.extensions.remove(name).is_some() {
-            Ok(())
-        } else {
-            Err(anyhow!("There is no extension {}", &name))
-        }
+    /// Open a WebAssembly extension, it runs sandboxed so no `unsafe` is needed
+    fn open_wasm(
+        http: &http::HttpClients,
+        path: &Path,
+        bytes: &[u8],
+        config: Option<&serde_yaml::Value>,
+    ) -> Result<Vec<(String, ExtensionProxy)>> {
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let extension = wasm::WasmExtension::new(path, bytes, config, http.client(&name))?;
+        Ok(vec![(
+            extension.info().name,
+            ExtensionProxy::new(Box::new(extension), None, config),
+        )])
     }
-}
-
-pub struct PluginRegistrar {
-    extensions: HashMap<String, ExtensionProxy>,
-    lib: Arc<Library>,
-}
 
-impl PluginRegistrar {
-    fn new(lib: Arc<Library>) -> PluginRegistrar {
-        PluginRegistrar {
-            lib,
-            extensions: HashMap::default(),
-        }
+    /// Open a source defined in YAML, it is run by tanoshi so no `unsafe` is needed
+    fn open_scraper(
+        http: &http::HttpClients,
+        path: &Path,
+        bytes: &[u8],
+        config: Option<&serde_yaml::Value>,
+    ) -> Result<Vec<(String, ExtensionProxy)>> {
+        let name = scrape::ScraperExtension::name(path, bytes)?;
+        let extension = scrape::ScraperExtension::new(path, bytes, http.client(&name))?;
+        Ok(vec![(
+            name,
+            ExtensionProxy::new(Box::new(extension), None, config),
+        )])
     }
-}
 
-impl tanoshi_lib::extensions::PluginRegistrar for PluginRegistrar {
-    fn register_function(&mut self, name: &str, extension: Box<dyn Extension>) {
-        let proxy = ExtensionProxy {
-            extension,
-            lib: Arc::clone(&self.lib),
+    /// Unload every extension loaded from `path`, returns their names.
+    /// The library is closed once calls still using its extensions are finished.
+    pub fn unload(&mut self, path: &Path) -> Vec<String> {
+        let names = match self.files.remove(path) {
+            Some(file) => file.names,
+            None => return vec![],
         };
-
-        self.extensions.insert(name.to_string(), proxy);
+        for name in names.iter() {
+            self.extensions.remove(name);
+        }
+        names
     }
 }
@@ -0,0 +1,906 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::http::{Http, Request};
+use tanoshi_lib::manga::{Chapter, Manga, Params, Source};
+
+use crate::extension::local::{chapter_number, has_extension, IMAGE_EXTENSIONS};
+
+/// Key of the feeds in `plugin_config`
+pub static NAME: &str = "opds";
+
+/// Archives kept in memory, so the pages of a chapter are read from a single download
+static ARCHIVE_CACHE_SIZE: usize = 3;
+/// Pages of a feed followed at most, in case a server links its pages in a loop
+static MAX_FEED_PAGES: usize = 100;
+static ACCEPT: &str =
+    "application/opds+json, application/atom+xml, application/json;q=0.9, application/xml;q=0.9, */*;q=0.8";
+static ARCHIVE_TYPES: &[&str] = &[
+    "application/vnd.comicbook+zip",
+    "application/x-cbz",
+    "application/zip",
+    "application/x-zip-compressed",
+];
+
+/// An OPDS catalog in `plugin_config.opds.feeds`, registered as a source named `name`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Feed {
+    pub name: String,
+    /// Feed listing the series, `http(s)://` or `file://` for a static feed on disk
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    #[serde(default)]
+    feeds: Vec<Feed>,
+}
+
+/// Returns the feeds configured in the `opds` entry of `plugin_config`
+pub fn feeds(config: &serde_yaml::Value) -> Result<Vec<Feed>> {
+    Ok(serde_yaml::from_value::<Config>(config.clone())?.feeds)
+}
+
+/// An entry of a feed, either a navigation entry linking to another feed,
+/// or a publication that can be downloaded as a comic archive
+#[derive(Debug, Default)]
+struct Entry {
+    title: String,
+    authors: Vec<String>,
+    categories: Vec<String>,
+    summary: Option<String>,
+    updated: Option<chrono::NaiveDateTime>,
+    thumbnail: Option<String>,
+    navigation: Option<String>,
+    acquisition: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Page {
+    title: String,
+    entries: Vec<Entry>,
+    next: Option<String>,
+    search: Option<Search>,
+}
+
+#[derive(Debug)]
+enum Search {
+    /// Url with `{searchTerms}` or `{?query}`
+    Template(String),
+    /// Url of an OpenSearch description listing the templates
+    Description(String),
+}
+
+/// What is found at a url, a page of a feed or a comic archive
+enum Document {
+    Feed(Page),
+    Archive(Arc<Vec<u8>>),
+}
+
+/// Built-in source reading comics from an OPDS 1.2 or 2.0 catalog.
+///
+/// Navigation entries of the configured feed are mangas, and the publications
+/// of the feed they link to are chapters. A publication listed directly in the configured feed
+/// is a manga with a single chapter. Only publications available as CBZ are listed,
+/// their pages are read from the downloaded archive.
+pub struct Opds {
+    feed: Feed,
+    http: Http,
+    archives: Mutex<VecDeque<(String, Arc<Vec<u8>>)>>,
+}
+
+impl Opds {
+    pub fn new(feed: Feed, http: Http) -> Self {
+        Self {
+            feed,
+            http,
+            archives: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns the bytes at `url` with the url they were served from
+    fn get(&self, url: &str) -> Result<(Vec<u8>, Option<String>, url::Url)> {
+        let parsed = url::Url::parse(url)?;
+        if parsed.scheme() == "file" {
+            let path = parsed
+                .to_file_path()
+                .map_err(|_| anyhow!("{} is not a valid file", url))?;
+            if !path.starts_with(self.root_dir()?) {
+                return Err(anyhow!("{} is outside of feed {}", url, self.feed.name));
+            }
+            return Ok((std::fs::read(&path)?, None, parsed));
+        }
+
+        let mut req = Request::get(url).header("Accept", ACCEPT);
+        if let Some(username) = &self.feed.username {
+            let credentials = format!(
+                "{}:{}",
+                username,
+                self.feed.password.clone().unwrap_or_default()
+            );
+            req = req.header(
+                "Authorization",
+                &format!("Basic {}", base64::encode(credentials)),
+            );
+        }
+        let resp = self.http.send(req)?.error_for_status()?;
+        let content_type = resp.header("Content-Type").map(str::to_lowercase);
+        let base = url::Url::parse(&resp.url).unwrap_or(parsed);
+        Ok((resp.body, content_type, base))
+    }
+
+    /// Directory a `file://` feed can read from
+    fn root_dir(&self) -> Result<PathBuf> {
+        let root = url::Url::parse(&self.feed.url)?
+            .to_file_path()
+            .map_err(|_| anyhow!("{} is not a valid file", self.feed.url))?;
+        Ok(root.parent().map(Path::to_path_buf).unwrap_or(root))
+    }
+
+    fn fetch(&self, url: &str) -> Result<Document> {
+        if let Some(archive) = self.cached(url) {
+            return Ok(Document::Archive(archive));
+        }
+
+        let (body, content_type, base) = self.get(url)?;
+        let content_type = content_type.unwrap_or_default();
+        let first = body.iter().find(|b| !b.is_ascii_whitespace()).copied();
+        if content_type.contains("json") || (!content_type.contains("xml") && first == Some(b'{')) {
+            Ok(Document::Feed(parse_json(
+                &serde_json::from_slice(&body)?,
+                &base,
+            )))
+        } else if content_type.contains("xml") || first == Some(b'<') {
+            Ok(Document::Feed(parse_xml(
+                &String::from_utf8_lossy(&body),
+                &base,
+            )?))
+        } else {
+            let archive = Arc::new(body);
+            self.cache(url, archive.clone());
+            Ok(Document::Archive(archive))
+        }
+    }
+
+    fn fetch_feed(&self, url: &str) -> Result<Page> {
+        match self.fetch(url)? {
+            Document::Feed(page) => Ok(page),
+            Document::Archive(_) => Err(anyhow!("{} is not an OPDS feed", url)),
+        }
+    }
+
+    fn archive(&self, url: &str) -> Result<Arc<Vec<u8>>> {
+        match self.fetch(url)? {
+            Document::Archive(archive) => Ok(archive),
+            Document::Feed(_) => Err(anyhow!("{} is not a comic archive", url)),
+        }
+    }
+
+    fn cached(&self, url: &str) -> Option<Arc<Vec<u8>>> {
+        self.archives
+            .lock()
+            .ok()?
+            .iter()
+            .find(|(u, _)| u == url)
+            .map(|(_, archive)| archive.clone())
+    }
+
+    fn cache(&self, url: &str, archive: Arc<Vec<u8>>) {
+        if let Ok(mut archives) = self.archives.lock() {
+            archives.retain(|(u, _)| u != url);
+            archives.push_front((url.to_string(), archive));
+            archives.truncate(ARCHIVE_CACHE_SIZE);
+        }
+    }
+
+    /// Returns the url to search `keyword` with, if the feed can be searched
+    fn search_url(&self, search: &Search, keyword: &str) -> Result<Option<String>> {
+        let template = match search {
+            Search::Template(template) => template.clone(),
+            Search::Description(url) => {
+                let (body, _, base) = self.get(url)?;
+                match opensearch_template(&String::from_utf8_lossy(&body), &base)? {
+                    Some(template) => template,
+                    None => return Ok(None),
+                }
+            }
+        };
+        let keyword = url::form_urlencoded::byte_serialize(keyword.as_bytes()).collect::<String>();
+        let url = template
+            .replace("{searchTerms}", &keyword)
+            .replace("{?query}", &format!("?query={}", keyword));
+        Ok(Some(strip_template(&url)))
+    }
+
+    fn manga(&self, entry: Entry) -> Option<Manga> {
+        Some(Manga {
+            source: self.feed.name.clone(),
+            title: entry.title,
+            author: entry.authors,
+            genre: entry.categories,
+            description: entry.summary,
+            path: entry.acquisition.or(entry.navigation)?,
+            thumbnail_url: entry.thumbnail.unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+}
+
+impl Extension for Opds {
+    fn info(&self) -> Source {
+        Source {
+            name: self.feed.name.clone(),
+            url: self.feed.url.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            rate_limit: None,
+        }
+    }
+
+    fn get_mangas(&self, param: Params, _auth: String) -> Result<Vec<Manga>> {
+        let page = param
+            .page
+            .and_then(|page| page.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+        let keyword = param.keyword.unwrap_or_default();
+
+        let mut feed = self.fetch_feed(&self.feed.url)?;
+        let mut filter = None;
+        if !keyword.is_empty() {
+            let url = match &feed.search {
+                Some(search) => self.search_url(search, &keyword)?,
+                None => None,
+            };
+            match url {
+                Some(url) => feed = self.fetch_feed(&url)?,
+                None => filter = Some(keyword.to_lowercase()),
+            }
+        }
+        for _ in 1..page {
+            feed = match feed.next {
+                Some(next) => self.fetch_feed(&next)?,
+                None => return Ok(vec![]),
+            };
+        }
+
+        Ok(feed
+            .entries
+            .into_iter()
+            .filter(|entry| match &filter {
+                Some(keyword) => entry.title.to_lowercase().contains(keyword),
+                None => true,
+            })
+            .filter_map(|entry| self.manga(entry))
+            .collect())
+    }
+
+    fn get_manga_info(&self, path: &str, _auth: String) -> Result<Manga> {
+        let feed = match self.fetch(path)? {
+            Document::Feed(feed) => feed,
+            Document::Archive(_) => {
+                return Ok(Manga {
+                    source: self.feed.name.clone(),
+                    path: path.to_string(),
+                    ..Default::default()
+                })
+            }
+        };
+
+        let mut authors = vec![];
+        for author in feed.entries.iter().flat_map(|entry| entry.authors.iter()) {
+            if !authors.contains(author) {
+                authors.push(author.clone());
+            }
+        }
+        Ok(Manga {
+            source: self.feed.name.clone(),
+            title: feed.title,
+            author: authors,
+            path: path.to_string(),
+            thumbnail_url: feed
+                .entries
+                .iter()
+                .find_map(|entry| entry.thumbnail.clone())
+                .unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+
+    fn get_chapters(&self, path: &str, _auth: String) -> Result<Vec<Chapter>> {
+        let mut feed = match self.fetch(path)? {
+            Document::Feed(feed) => feed,
+            Document::Archive(_) => {
+                let title = Path::new(path.split('?').next().unwrap_or_default())
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(str::to_string);
+                return Ok(vec![Chapter {
+                    source: self.feed.name.clone(),
+                    no: title.as_deref().and_then(chapter_number),
+                    title,
+                    path: path.to_string(),
+                    uploaded: chrono::Utc::now().naive_utc(),
+                    ..Default::default()
+                }]);
+            }
+        };
+
+        let mut chapters = vec![];
+        for _ in 0..MAX_FEED_PAGES {
+            for entry in feed.entries {
+                let path = match entry.acquisition {
+                    Some(path) => path,
+                    None => continue,
+                };
+                chapters.push(Chapter {
+                    source: self.feed.name.clone(),
+                    no: chapter_number(&entry.title),
+                    title: Some(entry.title),
+                    path,
+                    uploaded: entry
+                        .updated
+                        .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
+                    ..Default::default()
+                });
+            }
+            feed = match feed.next {
+                Some(next) => self.fetch_feed(&next)?,
+                None => break,
+            };
+        }
+
+        Ok(chapters)
+    }
+
+    /// Pages are images in the archive, as `<archive url>#<name in archive>`
+    fn get_pages(&self, path: &str, _auth: String) -> Result<Vec<String>> {
+        let archive = self.archive(path)?;
+        let archive = zip::ZipArchive::new(Cursor::new(archive.as_slice()))?;
+        let mut pages = archive
+            .file_names()
+            .filter(|name| has_extension(Path::new(name), IMAGE_EXTENSIONS))
+            .map(|name| format!("{}#{}", path, name))
+            .collect::<Vec<String>>();
+        pages.sort();
+
+        Ok(pages)
+    }
+
+    fn get_page(&self, url: &str, _auth: String) -> Result<Vec<u8>> {
+        let index = url
+            .find('#')
+            .ok_or_else(|| anyhow!("{} is not a page of an archive", url))?;
+        let archive = self.archive(&url[..index])?;
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive.as_slice()))?;
+        let mut entry = archive.by_name(&url[index + 1..])?;
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+}
+
+fn is_archive(mime: &str, href: &str) -> bool {
+    let mime = mime.to_lowercase();
+    ARCHIVE_TYPES.iter().any(|t| mime.starts_with(t))
+        || has_extension(
+            Path::new(href.split('?').next().unwrap_or_default()),
+            &["cbz"],
+        )
+}
+
+/// Entry documents, e.g the `alternate` link of a publication, are not feeds
+fn is_feed(mime: &str) -> bool {
+    let mime = mime.to_lowercase();
+    (mime.contains("profile=opds-catalog") && !mime.contains("type=entry"))
+        || mime.starts_with("application/opds+json")
+}
+
+fn resolve(base: &url::Url, href: &str) -> Option<String> {
+    base.join(href).ok().map(|url| url.to_string())
+}
+
+/// Resolve a search template, its braces are escaped when it is joined to `base`
+fn resolve_template(base: &url::Url, href: &str) -> Option<String> {
+    resolve(base, href).map(|url| url.replace("%7B", "{").replace("%7D", "}"))
+}
+
+/// Remove the template parameters left in a url, e.g `{startPage?}`
+fn strip_template(url: &str) -> String {
+    let mut stripped = String::new();
+    let mut in_param = false;
+    for c in url.chars() {
+        match c {
+            '{' => in_param = true,
+            '}' if in_param => in_param = false,
+            c if !in_param => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+fn parse_date(value: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::DateTime::parse_from_rfc3339(value.trim())
+        .ok()
+        .map(|date| date.naive_utc())
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms(0, 0, 0))
+        })
+}
+
+fn text(node: roxmltree::Node) -> String {
+    node.descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+/// Parse a page of an OPDS 1.2 feed, an Atom feed
+fn parse_xml(body: &str, base: &url::Url) -> Result<Page> {
+    let doc = roxmltree::Document::parse(body)?;
+    let feed = doc.root_element();
+    if feed.tag_name().name() != "feed" {
+        return Err(anyhow!("not an OPDS feed"));
+    }
+
+    let mut page = Page {
+        title: child(feed, "title").map(text).unwrap_or_default(),
+        ..Default::default()
+    };
+    for link in children(feed, "link") {
+        let href = match link
+            .attribute("href")
+            .and_then(|href| resolve_template(base, href))
+        {
+            Some(href) => href,
+            None => continue,
+        };
+        let mime = link.attribute("type").unwrap_or_default();
+        match link.attribute("rel").unwrap_or_default() {
+            "next" => page.next = Some(href),
+            "search" if mime.contains("opensearchdescription") => {
+                page.search = Some(Search::Description(href))
+            }
+            "search" => page.search = Some(Search::Template(href)),
+            _ => {}
+        }
+    }
+
+    for node in children(feed, "entry") {
+        let mut entry = Entry {
+            title: child(node, "title").map(text).unwrap_or_default(),
+            authors: children(node, "author")
+                .filter_map(|author| child(author, "name").map(text))
+                .collect(),
+            categories: children(node, "category")
+                .filter_map(|category| {
+                    category
+                        .attribute("label")
+                        .or_else(|| category.attribute("term"))
+                })
+                .map(str::to_string)
+                .collect(),
+            summary: child(node, "summary")
+                .or_else(|| child(node, "content"))
+                .map(text)
+                .filter(|summary| !summary.is_empty()),
+            updated: child(node, "updated")
+                .or_else(|| child(node, "published"))
+                .and_then(|updated| parse_date(&text(updated))),
+            ..Default::default()
+        };
+        let mut image = None;
+        for link in children(node, "link") {
+            let href = match link.attribute("href").and_then(|href| resolve(base, href)) {
+                Some(href) => href,
+                None => continue,
+            };
+            let mime = link.attribute("type").unwrap_or_default();
+            let rel = link.attribute("rel").unwrap_or_default();
+            if rel.starts_with("http://opds-spec.org/acquisition") {
+                if is_archive(mime, &href) && entry.acquisition.is_none() {
+                    entry.acquisition = Some(href);
+                }
+            } else if rel == "http://opds-spec.org/image/thumbnail" {
+                entry.thumbnail = Some(href);
+            } else if rel == "http://opds-spec.org/image" {
+                image = Some(href);
+            } else if is_feed(mime)
+                && !["related", "self", "up", "start"].contains(&rel)
+                && entry.navigation.is_none()
+            {
+                entry.navigation = Some(href);
+            }
+        }
+        entry.thumbnail = entry.thumbnail.or(image);
+        page.entries.push(entry);
+    }
+
+    Ok(page)
+}
+
+/// Returns the Atom template of an OpenSearch description, or any template if it has none
+fn opensearch_template(body: &str, base: &url::Url) -> Result<Option<String>> {
+    let doc = roxmltree::Document::parse(body)?;
+    let urls = children(doc.root_element(), "Url").collect::<Vec<_>>();
+    Ok(urls
+        .iter()
+        .find(|url| url.attribute("type").unwrap_or_default().contains("atom"))
+        .or_else(|| urls.first())
+        .and_then(|url| url.attribute("template"))
+        .and_then(|template| resolve_template(base, template)))
+}
+
+fn json_strings(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::String(s) => vec![s.clone()],
+        serde_json::Value::Array(values) => values.iter().flat_map(json_strings).collect(),
+        serde_json::Value::Object(object) => {
+            object.get("name").map(json_strings).unwrap_or_default()
+        }
+        _ => vec![],
+    }
+}
+
+fn json_str<'a>(value: &'a serde_json::Value, key: &str) -> &'a str {
+    value.get(key).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+fn json_array<'a>(value: &'a serde_json::Value, key: &str) -> &'a [serde_json::Value] {
+    value
+        .get(key)
+        .and_then(|v| v.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn has_rel(link: &serde_json::Value, rel: &str) -> bool {
+    link.get("rel")
+        .map(json_strings)
+        .unwrap_or_default()
+        .iter()
+        .any(|r| r.starts_with(rel))
+}
+
+/// Parse a page of an OPDS 2.0 feed
+fn parse_json(feed: &serde_json::Value, base: &url::Url) -> Page {
+    let mut page = Page {
+        title: json_str(&feed["metadata"], "title").to_string(),
+        ..Default::default()
+    };
+    for link in json_array(feed, "links") {
+        let href = match resolve_template(base, json_str(link, "href")) {
+            Some(href) => href,
+            None => continue,
+        };
+        if has_rel(link, "next") {
+            page.next = Some(href);
+        } else if has_rel(link, "search") {
+            page.search = Some(Search::Template(href));
+        }
+    }
+
+    let groups = std::iter::once(feed).chain(json_array(feed, "groups").iter());
+    for group in groups {
+        for link in json_array(group, "navigation") {
+            page.entries.push(Entry {
+                title: json_str(link, "title").to_string(),
+                navigation: resolve(base, json_str(link, "href")),
+                ..Default::default()
+            });
+        }
+        for publication in json_array(group, "publications") {
+            let metadata = &publication["metadata"];
+            let links = json_array(publication, "links");
+            page.entries.push(Entry {
+                title: json_str(metadata, "title").to_string(),
+                authors: metadata.get("author").map(json_strings).unwrap_or_default(),
+                categories: metadata
+                    .get("subject")
+                    .map(json_strings)
+                    .unwrap_or_default(),
+                summary: metadata
+                    .get("description")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string),
+                updated: metadata
+                    .get("modified")
+                    .or_else(|| metadata.get("published"))
+                    .and_then(|v| v.as_str())
+                    .and_then(parse_date),
+                thumbnail: json_array(publication, "images")
+                    .first()
+                    .and_then(|image| resolve(base, json_str(image, "href"))),
+                navigation: links
+                    .iter()
+                    .find(|link| is_feed(json_str(link, "type")))
+                    .and_then(|link| resolve(base, json_str(link, "href"))),
+                acquisition: links
+                    .iter()
+                    .find(|link| {
+                        has_rel(link, "http://opds-spec.org/acquisition")
+                            && is_archive(json_str(link, "type"), json_str(link, "href"))
+                    })
+                    .and_then(|link| resolve(base, json_str(link, "href"))),
+            });
+        }
+    }
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    static CATALOG: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Comics</title>
+  <link rel="next" href="catalog-2.xml" type="application/atom+xml;profile=opds-catalog;kind=navigation"/>
+  <link rel="search" href="search.xml" type="application/opensearchdescription+xml"/>
+  <entry>
+    <title>Series A</title>
+    <link rel="subsection" href="series-a.xml" type="application/atom+xml;profile=opds-catalog;kind=acquisition"/>
+    <link rel="http://opds-spec.org/image/thumbnail" href="covers/a.jpg" type="image/jpeg"/>
+  </entry>
+  <entry>
+    <title>One Shot</title>
+    <author><name>Someone</name></author>
+    <category term="action" label="Action"/>
+    <summary>A single   volume</summary>
+    <link rel="http://opds-spec.org/acquisition" href="one-shot.epub" type="application/epub+zip"/>
+    <link rel="http://opds-spec.org/acquisition" href="one-shot.cbz" type="application/vnd.comicbook+zip"/>
+    <link rel="http://opds-spec.org/image" href="covers/one-shot.jpg" type="image/jpeg"/>
+  </entry>
+  <entry>
+    <title>Novel</title>
+    <link rel="http://opds-spec.org/acquisition" href="novel.epub" type="application/epub+zip"/>
+  </entry>
+</feed>"#;
+
+    static SERIES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Series A</title>
+  <entry>
+    <title>Series A Vol. 1</title>
+    <author><name>Author A</name></author>
+    <updated>2020-06-01T10:00:00Z</updated>
+    <link rel="http://opds-spec.org/acquisition/open-access" href="a/vol-1.cbz" type="application/zip"/>
+  </entry>
+  <entry>
+    <title>Series A Vol. 2</title>
+    <updated>2020-07-01</updated>
+    <link rel="http://opds-spec.org/acquisition" href="a/vol-2.cbz"/>
+  </entry>
+</feed>"#;
+
+    static CATALOG_JSON: &str = r#"{
+  "metadata": {"title": "Comics"},
+  "links": [
+    {"rel": "next", "href": "/opds/2"},
+    {"rel": "search", "href": "/opds/search{?query}", "templated": true}
+  ],
+  "navigation": [
+    {"title": "Series A", "href": "/opds/series/a", "type": "application/opds+json"}
+  ],
+  "groups": [{
+    "publications": [{
+      "metadata": {
+        "title": "One Shot",
+        "author": [{"name": "Someone"}, "Someone Else"],
+        "subject": ["Action"],
+        "modified": "2020-06-01T10:00:00+02:00"
+      },
+      "links": [
+        {"rel": "http://opds-spec.org/acquisition", "href": "/files/one-shot.cbz", "type": "application/vnd.comicbook+zip"}
+      ],
+      "images": [{"href": "/covers/one-shot.jpg"}]
+    }]
+  }]
+}"#;
+
+    fn date(value: &str) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn parse_opds_1_feed() {
+        let base = url::Url::parse("https://example.com/opds/catalog.xml").unwrap();
+        let page = parse_xml(CATALOG, &base).unwrap();
+
+        assert_eq!(page.title, "Comics");
+        assert_eq!(
+            page.next.as_deref(),
+            Some("https://example.com/opds/catalog-2.xml")
+        );
+        assert!(
+            matches!(&page.search, Some(Search::Description(url)) if url == "https://example.com/opds/search.xml")
+        );
+        assert_eq!(page.entries.len(), 3);
+
+        let series = &page.entries[0];
+        assert_eq!(series.title, "Series A");
+        assert_eq!(
+            series.navigation.as_deref(),
+            Some("https://example.com/opds/series-a.xml")
+        );
+        assert_eq!(series.acquisition, None);
+        assert_eq!(
+            series.thumbnail.as_deref(),
+            Some("https://example.com/opds/covers/a.jpg")
+        );
+
+        let one_shot = &page.entries[1];
+        assert_eq!(one_shot.authors, vec!["Someone"]);
+        assert_eq!(one_shot.categories, vec!["Action"]);
+        assert_eq!(one_shot.summary.as_deref(), Some("A single volume"));
+        assert_eq!(
+            one_shot.acquisition.as_deref(),
+            Some("https://example.com/opds/one-shot.cbz")
+        );
+        assert_eq!(
+            one_shot.thumbnail.as_deref(),
+            Some("https://example.com/opds/covers/one-shot.jpg")
+        );
+
+        let novel = &page.entries[2];
+        assert_eq!(novel.acquisition, None);
+        assert_eq!(novel.navigation, None);
+    }
+
+    #[test]
+    fn parse_opds_2_feed() {
+        let base = url::Url::parse("https://example.com/opds").unwrap();
+        let feed = serde_json::from_str(CATALOG_JSON).unwrap();
+        let page = parse_json(&feed, &base);
+
+        assert_eq!(page.title, "Comics");
+        assert_eq!(page.next.as_deref(), Some("https://example.com/opds/2"));
+        let template = match &page.search {
+            Some(Search::Template(template)) => template.clone(),
+            _ => panic!("search is not a template: {:?}", page.search),
+        };
+        assert_eq!(template, "https://example.com/opds/search{?query}");
+        assert_eq!(page.entries.len(), 2);
+
+        assert_eq!(page.entries[0].title, "Series A");
+        assert_eq!(
+            page.entries[0].navigation.as_deref(),
+            Some("https://example.com/opds/series/a")
+        );
+
+        let one_shot = &page.entries[1];
+        assert_eq!(one_shot.authors, vec!["Someone", "Someone Else"]);
+        assert_eq!(one_shot.categories, vec!["Action"]);
+        assert_eq!(one_shot.updated, Some(date("2020-06-01T08:00:00")));
+        assert_eq!(
+            one_shot.acquisition.as_deref(),
+            Some("https://example.com/files/one-shot.cbz")
+        );
+        assert_eq!(
+            one_shot.thumbnail.as_deref(),
+            Some("https://example.com/covers/one-shot.jpg")
+        );
+    }
+
+    #[test]
+    fn search_templates() {
+        assert_eq!(
+            strip_template("https://example.com/search?q=one{&startPage?}"),
+            "https://example.com/search?q=one"
+        );
+        let description = r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+  <Url type="text/html" template="/web?q={searchTerms}"/>
+  <Url type="application/atom+xml;profile=opds-catalog" template="/opds/search?q={searchTerms}"/>
+</OpenSearchDescription>"#;
+        let base = url::Url::parse("https://example.com/opds/search.xml").unwrap();
+        assert_eq!(
+            opensearch_template(description, &base).unwrap().as_deref(),
+            Some("https://example.com/opds/search?q={searchTerms}")
+        );
+    }
+
+    fn write_cbz(path: &Path, pages: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, bytes) in pages {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn read_static_feed() {
+        let dir = std::env::temp_dir().join(format!("tanoshi-opds-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("catalog.xml"), CATALOG).unwrap();
+        std::fs::write(dir.join("series-a.xml"), SERIES).unwrap();
+        write_cbz(
+            &dir.join("a/vol-1.cbz"),
+            &[
+                ("002.png", b"page 2"),
+                ("001.png", b"page 1"),
+                ("ComicInfo.xml", b"<ComicInfo/>"),
+            ],
+        );
+
+        let catalog = url::Url::from_file_path(dir.join("catalog.xml")).unwrap();
+        let opds = Opds::new(
+            Feed {
+                name: "comics".to_string(),
+                url: catalog.to_string(),
+                username: None,
+                password: None,
+            },
+            Http::default(),
+        );
+
+        let mangas = opds.get_mangas(Params::default(), String::new()).unwrap();
+        let titles = mangas.iter().map(|m| m.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Series A", "One Shot"]);
+        assert!(mangas.iter().all(|m| m.source == "comics"));
+
+        let series = &mangas[0].path;
+        let manga = opds.get_manga_info(series, String::new()).unwrap();
+        assert_eq!(manga.title, "Series A");
+        assert_eq!(manga.author, vec!["Author A"]);
+
+        let chapters = opds.get_chapters(series, String::new()).unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].no.as_deref(), Some("1"));
+        assert_eq!(chapters[0].uploaded, date("2020-06-01T10:00:00"));
+        assert_eq!(chapters[1].no.as_deref(), Some("2"));
+        assert_eq!(chapters[1].uploaded, date("2020-07-01T00:00:00"));
+
+        let pages = opds.get_pages(&chapters[0].path, String::new()).unwrap();
+        assert_eq!(
+            pages,
+            vec![
+                format!("{}#001.png", chapters[0].path),
+                format!("{}#002.png", chapters[0].path),
+            ]
+        );
+        assert_eq!(opds.get_page(&pages[0], String::new()).unwrap(), b"page 1");
+
+        // a file:// feed only reads under its own directory
+        let outside = url::Url::from_file_path(std::env::temp_dir().join("other.cbz")).unwrap();
+        assert!(opds.get_pages(outside.as_str(), String::new()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
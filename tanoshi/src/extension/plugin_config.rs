@@ -0,0 +1,62 @@
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::BTreeMap;
+
+/// Config passed to plugins when they are registered, keyed by `watcher::config_name`
+///
+/// Config changed from web is stored in the database and takes precedence over
+/// `plugin_config` in the config file.
+#[derive(Clone)]
+pub struct PluginConfig {
+    database_path: String,
+    defaults: BTreeMap<String, serde_yaml::Value>,
+}
+
+impl PluginConfig {
+    pub fn new(database_path: String, defaults: BTreeMap<String, serde_yaml::Value>) -> Self {
+        Self {
+            database_path,
+            defaults,
+        }
+    }
+
+    fn connect_db(&self) -> Result<Connection, rusqlite::Error> {
+        Connection::open(self.database_path.clone())
+    }
+
+    pub fn get(&self, name: &str) -> Option<serde_yaml::Value> {
+        match self.saved(name) {
+            Ok(Some(config)) => Some(config),
+            Ok(None) => self.defaults.get(name).cloned(),
+            Err(e) => {
+                error!("failed to read config of {}: {}", name, e);
+                self.defaults.get(name).cloned()
+            }
+        }
+    }
+
+    fn saved(&self, name: &str) -> Result<Option<serde_yaml::Value>> {
+        let db = self.connect_db()?;
+        let config: Option<String> = db
+            .query_row(
+                "SELECT config FROM source_config WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match config {
+            Some(config) => Ok(Some(serde_yaml::from_str(&config)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, name: &str, config: &serde_yaml::Value) -> Result<()> {
+        let db = self.connect_db()?;
+        db.execute(
+            r#"INSERT INTO source_config(name, config) VALUES (?1, ?2)
+            ON CONFLICT(name) DO UPDATE SET config = excluded.config, updated = CURRENT_TIMESTAMP"#,
+            params![name, serde_yaml::to_string(config)?],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::http::{Http, Request};
+use tanoshi_lib::manga::{Chapter, Manga, Params, Source};
+use tanoshi_lib::rest::{
+    GetChaptersResponse, GetMangaResponse, GetMangasResponse, GetPagesResponse,
+};
+
+/// Key of the servers in `plugin_config`
+pub static NAME: &str = "remote";
+
+/// Another tanoshi in `plugin_config.remote.servers`, registered as a source named `name`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Server {
+    pub name: String,
+    /// Url tanoshi is served at, e.g `https://tanoshi.example.com`
+    pub url: String,
+    /// Token of a user of that server, as returned by its `/api/login`
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    #[serde(default)]
+    servers: Vec<Server>,
+}
+
+/// Returns the servers configured in the `remote` entry of `plugin_config`
+pub fn servers(config: &serde_yaml::Value) -> Result<Vec<Server>> {
+    Ok(serde_yaml::from_value::<Config>(config.clone())?.servers)
+}
+
+/// Built-in source browsing the favorites of a user of another tanoshi through its api.
+///
+/// Paths of mangas and chapters are their ids on the other server,
+/// pages are the urls of its `/api/page/{id}`.
+pub struct Remote {
+    server: Server,
+    base: url::Url,
+    http: Http,
+}
+
+impl Remote {
+    pub fn new(server: Server, http: Http) -> Result<Self> {
+        let mut base = url::Url::parse(&server.url)?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(Self { server, base, http })
+    }
+
+    fn url(&self, path: &str) -> Result<url::Url> {
+        Ok(self.base.join(path.trim_start_matches('/'))?)
+    }
+
+    /// Sends `url` with the token, only to the configured server
+    fn send(&self, url: &url::Url) -> Result<Vec<u8>> {
+        if url.origin() != self.base.origin() {
+            return Err(anyhow!("{} is not on server {}", url, self.server.name));
+        }
+        let req = Request::get(url.as_str()).header("Authorization", &self.server.token);
+        Ok(self.http.send(req)?.error_for_status()?.body)
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let body = self.send(&self.url(path)?)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+impl Extension for Remote {
+    fn info(&self) -> Source {
+        Source {
+            name: self.server.name.clone(),
+            url: self.server.url.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            rate_limit: None,
+        }
+    }
+
+    /// Favorites are listed at once, there is no second page
+    fn get_mangas(&self, param: Params, _auth: String) -> Result<Vec<Manga>> {
+        let page = param
+            .page
+            .as_deref()
+            .and_then(|page| page.parse::<usize>().ok())
+            .unwrap_or(1);
+        if page > 1 {
+            return Ok(vec![]);
+        }
+
+        let mut url = self.url("/api/favorites")?;
+        {
+            let mut query = url.query_pairs_mut();
+            if let serde_json::Value::String(sort_by) = serde_json::to_value(&param.sort_by)? {
+                query.append_pair("sort_by", &sort_by);
+            }
+            if let serde_json::Value::String(sort_order) = serde_json::to_value(&param.sort_order)?
+            {
+                query.append_pair("sort_order", &sort_order);
+            }
+        }
+        let res: GetMangasResponse = serde_json::from_slice(&self.send(&url)?)?;
+
+        let keyword = param.keyword.unwrap_or_default().to_lowercase();
+        Ok(res
+            .mangas
+            .into_iter()
+            .filter(|manga| manga.title.to_lowercase().contains(&keyword))
+            .map(|manga| Manga {
+                id: 0,
+                source: self.server.name.clone(),
+                path: manga.id.to_string(),
+                is_favorite: false,
+                last_read: None,
+                last_page: None,
+                ..manga
+            })
+            .collect())
+    }
+
+    fn get_manga_info(&self, path: &str, _auth: String) -> Result<Manga> {
+        let res: GetMangaResponse = self.get(&format!("/api/manga/{}", path))?;
+        Ok(Manga {
+            id: 0,
+            source: self.server.name.clone(),
+            path: path.to_string(),
+            is_favorite: false,
+            last_read: None,
+            last_page: None,
+            ..res.manga
+        })
+    }
+
+    fn get_chapters(&self, path: &str, _auth: String) -> Result<Vec<Chapter>> {
+        let res: GetChaptersResponse = self.get(&format!("/api/manga/{}/chapter", path))?;
+        Ok(res
+            .chapters
+            .into_iter()
+            .map(|chapter| Chapter {
+                source: self.server.name.clone(),
+                vol: chapter.vol,
+                no: chapter.no,
+                title: chapter.title,
+                path: chapter.id.to_string(),
+                uploaded: chapter.uploaded,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn get_pages(&self, path: &str, _auth: String) -> Result<Vec<String>> {
+        let res: GetPagesResponse = self.get(&format!("/api/chapter/{}", path))?;
+        // pages are saved, the image token they come with expires and the server token is sent instead
+        res.pages
+            .iter()
+            .map(|page| {
+                let mut url = self.url(page)?;
+                url.set_query(None);
+                Ok(url.to_string())
+            })
+            .collect()
+    }
+
+    fn get_page(&self, url: &str, _auth: String) -> Result<Vec<u8>> {
+        self.send(&url::Url::parse(url)?)
+    }
+}
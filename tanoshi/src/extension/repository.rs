@@ -1,6 +1,6 @@
 use rusqlite::types::Value;
 use rusqlite::vtab::array;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 use tanoshi_lib::manga::{Chapter, Manga};
 use tanoshi_lib::rest::{
     GetChaptersResponse, GetMangaResponse, GetMangasResponse, GetPagesResponse,
@@ -20,12 +20,12 @@ impl Repository {
         Self { database_path }
     }
 
-    fn connect_db(&self) -> Connection {
-        Connection::open(self.database_path.clone()).unwrap()
+    fn connect_db(&self) -> Result<Connection, rusqlite::Error> {
+        Connection::open(self.database_path.clone())
     }
 
     pub fn get_manga(&self, manga_id: i32) -> Result<Manga, rusqlite::Error> {
-        let db = self.connect_db();
+        let db = self.connect_db()?;
         let ret = db.query_row(
             r#"SELECT id, source, title, author, status, description, path, thumbnail_url FROM manga WHERE manga.id = ?1"#,
             params![manga_id],
@@ -56,7 +56,7 @@ impl Repository {
     }
 
     pub fn get_chapter(&self, chapter_id: i32) -> Result<Chapter, rusqlite::Error> {
-        let db = self.connect_db();
+        let db = self.connect_db()?;
         let ret = db.query_row(
             "SELECT id, source, manga_id, volume, number, title, path, uploaded FROM chapter WHERE chapter.id = ?1",
             params![chapter_id],
@@ -87,7 +87,7 @@ impl Repository {
                 status: "Empty".to_string(),
             });
         }
-        let db = self.connect_db();
+        let db = self.connect_db()?;
         array::load_module(&db)?;
         let mut order = manga_ids
             .clone()
@@ -164,7 +164,7 @@ impl Repository {
         manga_id: i32,
         username: String,
     ) -> Result<GetMangaResponse, rusqlite::Error> {
-        let db = self.connect_db();
+        let db = self.connect_db()?;
         let manga = db.query_row(
             r#"SELECT
             manga.id,
@@ -225,7 +225,7 @@ impl Repository {
         manga_id: i32,
         username: String,
     ) -> Result<GetChaptersResponse, rusqlite::Error> {
-        let db = self.connect_db();
+        let db = self.connect_db()?;
         let mut stmt = db.prepare(
             r#"SELECT
                 chapter.id,
@@ -282,7 +282,7 @@ impl Repository {
     }
 
     pub fn get_pages(&self, chapter_id: i32) -> Result<GetPagesResponse, rusqlite::Error> {
-        let db = self.connect_db();
+        let db = self.connect_db()?;
         let manga_id = db.query_row(
             r#"SELECT manga.id
             FROM chapter
@@ -315,23 +315,54 @@ impl Repository {
         }
     }
 
+    /// Returns source and url of a page, and the user the chapter of the page belongs to
     pub fn get_image_from_page_id(
         &self,
         page_id: i32,
-    ) -> Result<(String, String), rusqlite::Error> {
-        let db = self.connect_db();
+    ) -> Result<(String, String, Option<String>), rusqlite::Error> {
+        let db = self.connect_db()?;
         let ret = db.query_row(
-            r#"SELECT source, url FROM page WHERE page.id = ?1"#,
+            r#"SELECT page.source, page.url, "user".username FROM page
+            JOIN chapter ON chapter.id = page.chapter_id
+            LEFT JOIN "user" ON "user".id = chapter.user_id
+            WHERE page.id = ?1"#,
             params![page_id],
-            |row| Ok((row.get(0)?, row.get(1)?)),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
         )?;
 
         Ok(ret)
     }
 
+    /// Returns the file a page was downloaded to, `None` if it was not downloaded
+    pub fn get_local_page(&self, page_id: i32) -> Result<Option<String>, rusqlite::Error> {
+        let db = self.connect_db()?;
+        let ret = db
+            .query_row(
+                "SELECT local_path FROM page WHERE id = ?1",
+                params![page_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+
+        Ok(ret)
+    }
+
+    /// Returns the user a chapter belongs to
+    pub fn get_chapter_user(&self, chapter_id: i32) -> Result<Option<String>, rusqlite::Error> {
+        let db = self.connect_db()?;
+        db.query_row(
+            r#"SELECT "user".username FROM chapter
+            LEFT JOIN "user" ON "user".id = chapter.user_id
+            WHERE chapter.id = ?1"#,
+            params![chapter_id],
+            |row| row.get(0),
+        )
+    }
+
     pub fn insert_mangas(&self, source: &String, mangas: Vec<Manga>) -> Result<Vec<i32>> {
         let mut ids = vec![];
-        let mut db = self.connect_db();
+        let mut db = self.connect_db()?;
         let tx = db.transaction()?;
         for m in mangas {
             let last_id = match tx.query_row(
@@ -364,16 +395,18 @@ impl Repository {
         Ok(ids)
     }
 
+    /// Returns the chapters that were not saved yet, with their ids
     pub fn insert_chapters(
         &self,
         username: String,
         manga_id: i32,
         chapters: Vec<Chapter>,
-    ) -> Result<(), rusqlite::Error> {
-        let mut db = self.connect_db();
+    ) -> Result<Vec<Chapter>, rusqlite::Error> {
+        let mut db = self.connect_db()?;
         let tx = db.transaction()?;
+        let mut inserted = vec![];
         for c in chapters {
-            tx.execute(
+            let rows = tx.execute(
                 r#"INSERT INTO chapter(user_id, source, manga_id, volume, number, title, path, uploaded)
                     VALUES(
                     (SELECT id FROM "user" WHERE username = ?1),
@@ -386,36 +419,20 @@ impl Repository {
                     ?8) ON CONFLICT DO NOTHING"#,
                 params![username, c.source, manga_id, c.vol, c.no, c.title, c.path, c.uploaded],
             )?;
+            if rows > 0 {
+                inserted.push(Chapter {
+                    id: tx.last_insert_rowid() as i32,
+                    manga_id,
+                    ..c
+                });
+            }
         }
         tx.commit()?;
-        Ok(())
-    }
-
-    pub fn insert_pages(
-        &self,
-        source: String,
-        chapter_id: i32,
-        pages: Vec<String>,
-    ) -> Result<(), rusqlite::Error> {
-        let mut db = self.connect_db();
-        let tx = db.transaction()?;
-        for i in 0..pages.len() {
-            tx.execute(
-                "INSERT INTO page(source, chapter_id, rank, url)
-                    VALUES(
-                    ?1,
-                    ?2,
-                    ?3,
-                    ?4) ON CONFLICT DO NOTHING",
-                params![source.clone(), chapter_id, (i as i32), pages[i]],
-            )?;
-        }
-        tx.commit()?;
-        Ok(())
+        Ok(inserted)
     }
 
     pub fn update_manga_info(&self, manga_id: i32, manga: Manga) -> Result<(), rusqlite::Error> {
-        let db = self.connect_db();
+        let db = self.connect_db()?;
         let a = if manga.author.is_empty() {
             None
         } else {
@@ -433,9 +450,56 @@ impl Repository {
         Ok(())
     }
 
-    pub fn delete_pages(&self, chapter_id: i32) -> Result<()> {
-        let db = self.connect_db();
-        db.execute("DELETE FROM page WHERE chapter_id = ?", params![chapter_id])?;
-        Ok(())
+    /// Replaces the pages of a chapter by rank, so a downloaded page keeps its file.
+    /// Returns the files of pages the chapter no longer has.
+    /// A finished download is queued again when the chapter got new pages.
+    pub fn replace_pages(
+        &self,
+        source: &str,
+        chapter_id: i32,
+        pages: &[String],
+    ) -> Result<Vec<String>> {
+        let mut db = self.connect_db()?;
+        let tx = db.transaction()?;
+        let mut inserted = 0;
+        for (rank, url) in pages.iter().enumerate() {
+            let updated = tx.execute(
+                "UPDATE page SET source = ?1, url = ?4, updated = CURRENT_TIMESTAMP
+                WHERE chapter_id = ?2 AND rank = ?3",
+                params![source, chapter_id, rank as i32, url],
+            )?;
+            if updated == 0 {
+                inserted += tx.execute(
+                    "INSERT INTO page(source, chapter_id, rank, url) VALUES(?1, ?2, ?3, ?4)",
+                    params![source, chapter_id, rank as i32, url],
+                )?;
+            }
+        }
+
+        let removed = {
+            let mut stmt = tx.prepare(
+                "SELECT local_path FROM page
+                WHERE chapter_id = ?1 AND rank >= ?2 AND local_path IS NOT NULL",
+            )?;
+            let removed = stmt
+                .query_map(params![chapter_id, pages.len() as i32], |row| row.get(0))?
+                .filter_map(|p| p.ok())
+                .collect();
+            removed
+        };
+        tx.execute(
+            "DELETE FROM page WHERE chapter_id = ?1 AND rank >= ?2",
+            params![chapter_id, pages.len() as i32],
+        )?;
+        tx.execute(
+            "UPDATE download SET
+                pages = ?2,
+                status = CASE WHEN ?3 > 0 AND status = 'done' THEN 'queued' ELSE status END,
+                updated = CURRENT_TIMESTAMP
+            WHERE chapter_id = ?1",
+            params![chapter_id, pages.len() as u32, inserted as u32],
+        )?;
+        tx.commit()?;
+        Ok(removed)
     }
 }
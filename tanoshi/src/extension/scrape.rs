@@ -0,0 +1,423 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::Path;
+
+use tanoshi_lib::extensions::Extension;
+use tanoshi_lib::http::{Http, Request};
+use tanoshi_lib::manga::{Chapter, Manga, Params, RateLimit, Source};
+
+/// A source described by a YAML file in `plugin_path` instead of a compiled plugin.
+///
+/// Pages are fetched from `url` templates and read with CSS selectors, see the README
+/// for the format. `{url}` in a template is the url of the source, `{path}` the path
+/// of the manga or chapter, `{page}` the page of the list and `{keyword}` the search keyword.
+/// Paths, thumbnails and images are resolved to absolute urls against the page they are found in.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Definition {
+    pub name: String,
+    pub url: String,
+    pub version: String,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// Sent with every request, e.g `Referer` for sites that refuse hotlinked images
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub mangas: MangasDefinition,
+    pub manga: MangaDefinition,
+    pub chapters: ChaptersDefinition,
+    pub pages: PagesDefinition,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MangasDefinition {
+    pub url: String,
+    /// Used when there is a keyword, a source without it can't be searched
+    #[serde(default)]
+    pub search_url: Option<String>,
+    pub item: Css,
+    pub title: Field,
+    pub path: Field,
+    #[serde(default)]
+    pub thumbnail: Option<Field>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MangaDefinition {
+    #[serde(default = "default_url")]
+    pub url: String,
+    pub title: Field,
+    #[serde(default)]
+    pub author: Option<Field>,
+    #[serde(default)]
+    pub genre: Option<Field>,
+    #[serde(default)]
+    pub status: Option<Field>,
+    #[serde(default)]
+    pub description: Option<Field>,
+    #[serde(default)]
+    pub thumbnail: Option<Field>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaptersDefinition {
+    #[serde(default = "default_url")]
+    pub url: String,
+    pub item: Css,
+    pub path: Field,
+    #[serde(default)]
+    pub title: Option<Field>,
+    #[serde(default)]
+    pub no: Option<Field>,
+    #[serde(default)]
+    pub vol: Option<Field>,
+    #[serde(default)]
+    pub uploaded: Option<Field>,
+    /// chrono format of `uploaded`, e.g `%b %d, %Y`, dates like `3 days ago` are always understood
+    #[serde(default)]
+    pub date_format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PagesDefinition {
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// Every value is an image, in reading order
+    pub image: Field,
+}
+
+fn default_url() -> String {
+    "{path}".to_string()
+}
+
+/// A CSS selector, checked when the definition is loaded
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Css(Selector);
+
+impl TryFrom<String> for Css {
+    type Error = String;
+
+    fn try_from(selector: String) -> Result<Self, Self::Error> {
+        Selector::parse(&selector)
+            .map(Css)
+            .map_err(|e| format!("invalid selector {:?}: {:?}", selector, e))
+    }
+}
+
+/// How to read a value from an element, written either as a selector
+/// or as `{selector, attr, regex}` with every key optional
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FieldDefinition {
+    Selector(String),
+    Field {
+        #[serde(default)]
+        selector: Option<String>,
+        #[serde(default)]
+        attr: Option<String>,
+        #[serde(default)]
+        regex: Option<String>,
+    },
+}
+
+/// Values are read from the elements matching `selector`, or from the element itself without one.
+/// `attr` is an attribute name, `text` (the default) or `html`.
+/// With `regex` only the first group of each match is kept, or the whole match if it has no group.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "FieldDefinition")]
+pub struct Field {
+    selector: Option<Selector>,
+    attr: Option<String>,
+    regex: Option<Regex>,
+}
+
+impl TryFrom<FieldDefinition> for Field {
+    type Error = String;
+
+    fn try_from(definition: FieldDefinition) -> Result<Self, Self::Error> {
+        let (selector, attr, regex) = match definition {
+            FieldDefinition::Selector(selector) => (Some(selector), None, None),
+            FieldDefinition::Field {
+                selector,
+                attr,
+                regex,
+            } => (selector, attr, regex),
+        };
+        Ok(Self {
+            selector: selector
+                .map(|selector| Css::try_from(selector).map(|css| css.0))
+                .transpose()?,
+            attr,
+            regex: regex
+                .map(|regex| {
+                    Regex::new(&regex).map_err(|e| format!("invalid regex {:?}: {}", regex, e))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+impl Field {
+    /// Every value in `element`, in document order
+    fn all(&self, element: ElementRef) -> Vec<String> {
+        let elements = match &self.selector {
+            Some(selector) => element.select(selector).collect(),
+            None => vec![element],
+        };
+        elements
+            .into_iter()
+            .filter_map(|element| self.read(element))
+            .flat_map(|value| match &self.regex {
+                Some(regex) => regex
+                    .captures_iter(&value)
+                    .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+                    .map(|m| m.as_str().to_string())
+                    .collect(),
+                None => vec![value],
+            })
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+
+    fn first(&self, element: ElementRef) -> Option<String> {
+        self.all(element).into_iter().next()
+    }
+
+    fn read(&self, element: ElementRef) -> Option<String> {
+        match self.attr.as_deref() {
+            None | Some("text") => Some(
+                element
+                    .text()
+                    .collect::<String>()
+                    .split_whitespace()
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            ),
+            Some("html") => Some(element.inner_html()),
+            Some(attr) => element.value().attr(attr).map(str::to_string),
+        }
+    }
+}
+
+/// Extension running a `Definition`, requests are sent with the HTTP client of its source
+pub struct ScraperExtension {
+    definition: Definition,
+    http: Http,
+}
+
+impl ScraperExtension {
+    /// `bytes` are the content of the definition at `path`
+    pub fn new(path: &Path, bytes: &[u8], http: Http) -> Result<Self> {
+        let definition = serde_yaml::from_slice::<Definition>(bytes)
+            .map_err(|e| anyhow!("{:?} is not a valid source definition: {}", path, e))?;
+        url::Url::parse(&definition.url)
+            .map_err(|e| anyhow!("url {} of {:?} is invalid: {}", definition.url, path, e))?;
+        Ok(Self { definition, http })
+    }
+
+    /// Returns the name of the source in the definition at `path`, to give it its HTTP client
+    pub fn name(path: &Path, bytes: &[u8]) -> Result<String> {
+        #[derive(Deserialize)]
+        struct Name {
+            name: String,
+        }
+        let name = serde_yaml::from_slice::<Name>(bytes)
+            .map_err(|e| anyhow!("{:?} is not a valid source definition: {}", path, e))?;
+        Ok(name.name)
+    }
+
+    fn render(&self, template: &str, vars: &[(&str, &str)]) -> String {
+        let mut url = template.replace("{url}", self.definition.url.trim_end_matches('/'));
+        for (name, value) in vars {
+            url = url.replace(&format!("{{{}}}", name), value);
+        }
+        url
+    }
+
+    fn request(&self, url: &str) -> Request {
+        self.definition
+            .headers
+            .iter()
+            .fold(Request::get(url), |req, (name, value)| {
+                req.header(name, value)
+            })
+    }
+
+    /// Fetch `url` and returns the document with the url it was served from,
+    /// links in the document are relative to it
+    fn fetch(&self, url: &str) -> Result<(Html, url::Url)> {
+        let resp = self.http.send(self.request(url))?.error_for_status()?;
+        let base = url::Url::parse(&resp.url).or_else(|_| url::Url::parse(url))?;
+        Ok((Html::parse_document(&resp.text()), base))
+    }
+}
+
+fn absolute(base: &url::Url, link: String) -> String {
+    base.join(&link).map(|url| url.to_string()).unwrap_or(link)
+}
+
+/// Parse `value` with `format`, falling back to ISO 8601 dates and `<n> <unit> ago`
+fn parse_date(value: &str, format: Option<&str>, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    if let Some(format) = format {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(date);
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Some(date.and_hms(0, 0, 0));
+        }
+    }
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(date.naive_utc());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.and_hms(0, 0, 0));
+    }
+
+    lazy_static! {
+        static ref AGO: Regex =
+            Regex::new(r"(?i)(\d+|an?)\s+(second|minute|hour|day|week|month|year)s?\s+ago")
+                .unwrap();
+    }
+    let captures = AGO.captures(value)?;
+    let n = captures[1].parse::<i64>().unwrap_or(1);
+    let unit = match &captures[2].to_lowercase()[..] {
+        "second" => Duration::seconds(1),
+        "minute" => Duration::minutes(1),
+        "hour" => Duration::hours(1),
+        "day" => Duration::days(1),
+        "week" => Duration::weeks(1),
+        "month" => Duration::days(30),
+        _ => Duration::days(365),
+    };
+    Some(now - unit * n as i32)
+}
+
+impl Extension for ScraperExtension {
+    fn info(&self) -> Source {
+        Source {
+            name: self.definition.name.clone(),
+            url: self.definition.url.clone(),
+            version: self.definition.version.clone(),
+            rate_limit: self.definition.rate_limit.clone(),
+        }
+    }
+
+    fn get_mangas(&self, param: Params, _auth: String) -> Result<Vec<Manga>> {
+        let def = &self.definition.mangas;
+        let page = param.page.unwrap_or_else(|| "1".to_string());
+        let url = match param.keyword.filter(|keyword| !keyword.is_empty()) {
+            Some(keyword) => {
+                let template = def
+                    .search_url
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("{} does not support search", self.definition.name))?;
+                let keyword =
+                    url::form_urlencoded::byte_serialize(keyword.as_bytes()).collect::<String>();
+                self.render(template, &[("keyword", &keyword), ("page", &page)])
+            }
+            None => self.render(&def.url, &[("page", &page)]),
+        };
+
+        let (document, base) = self.fetch(&url)?;
+        let mangas = document
+            .select(&def.item.0)
+            .filter_map(|item| {
+                Some(Manga {
+                    source: self.definition.name.clone(),
+                    title: def.title.first(item)?,
+                    path: absolute(&base, def.path.first(item)?),
+                    thumbnail_url: def
+                        .thumbnail
+                        .as_ref()
+                        .and_then(|field| field.first(item))
+                        .map(|thumbnail| absolute(&base, thumbnail))
+                        .unwrap_or_default(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Ok(mangas)
+    }
+
+    fn get_manga_info(&self, path: &str, _auth: String) -> Result<Manga> {
+        let def = &self.definition.manga;
+        let (document, base) = self.fetch(&self.render(&def.url, &[("path", path)]))?;
+        let root = document.root_element();
+        let first = |field: &Option<Field>| field.as_ref().and_then(|field| field.first(root));
+        let all = |field: &Option<Field>| {
+            field
+                .as_ref()
+                .map(|field| field.all(root))
+                .unwrap_or_default()
+        };
+
+        Ok(Manga {
+            source: self.definition.name.clone(),
+            title: def
+                .title
+                .first(root)
+                .ok_or_else(|| anyhow!("title of {} not found", path))?,
+            author: all(&def.author),
+            genre: all(&def.genre),
+            status: first(&def.status),
+            description: first(&def.description),
+            path: path.to_string(),
+            thumbnail_url: first(&def.thumbnail)
+                .map(|thumbnail| absolute(&base, thumbnail))
+                .unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+
+    fn get_chapters(&self, path: &str, _auth: String) -> Result<Vec<Chapter>> {
+        let def = &self.definition.chapters;
+        let (document, base) = self.fetch(&self.render(&def.url, &[("path", path)]))?;
+        let now = chrono::Utc::now().naive_utc();
+        let chapters = document
+            .select(&def.item.0)
+            .filter_map(|item| {
+                let first =
+                    |field: &Option<Field>| field.as_ref().and_then(|field| field.first(item));
+                Some(Chapter {
+                    source: self.definition.name.clone(),
+                    vol: first(&def.vol),
+                    no: first(&def.no),
+                    title: first(&def.title),
+                    path: absolute(&base, def.path.first(item)?),
+                    uploaded: first(&def.uploaded)
+                        .and_then(|uploaded| parse_date(&uploaded, def.date_format.as_deref(), now))
+                        .unwrap_or(now),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Ok(chapters)
+    }
+
+    fn get_pages(&self, path: &str, _auth: String) -> Result<Vec<String>> {
+        let def = &self.definition.pages;
+        let (document, base) = self.fetch(&self.render(&def.url, &[("path", path)]))?;
+        Ok(def
+            .image
+            .all(document.root_element())
+            .into_iter()
+            .map(|image| absolute(&base, image))
+            .collect())
+    }
+
+    /// Images are downloaded with `headers` too
+    fn get_page(&self, url: &str, _auth: String) -> Result<Vec<u8>> {
+        Ok(self.http.send(self.request(url))?.error_for_status()?.body)
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+
+use tanoshi_lib::manga::{SourceLogin, SourceLoginResult};
+
+const NONCE_LEN: usize = 12;
+
+/// Logins of a user to a source
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub login: SourceLoginResult,
+    /// Only saved when the user asked to be remembered, used to log in again
+    pub credentials: Option<SourceLogin>,
+}
+
+impl Session {
+    /// `expires` of a login is UTC
+    pub fn is_expired(&self) -> bool {
+        matches!(self.login.expires, Some(expires) if expires <= chrono::Utc::now().naive_utc())
+    }
+}
+
+/// Source sessions of users, encrypted with a key derived from `secret`
+///
+/// A row can only be decrypted for the user and source it is saved for.
+/// Changing `secret` makes saved sessions unreadable, users have to log in to sources again,
+/// as do sessions saved before the user and source were length prefixed in `aad`.
+#[derive(Clone)]
+pub struct Sessions {
+    database_path: String,
+    key: [u8; 32],
+}
+
+impl Sessions {
+    pub fn new(database_path: String, secret: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"tanoshi source session\0");
+        hasher.update(secret.as_bytes());
+        let mut key = [0; 32];
+        key.copy_from_slice(&hasher.finalize());
+        Self { database_path, key }
+    }
+
+    fn connect_db(&self) -> Result<Connection, rusqlite::Error> {
+        Connection::open(self.database_path.clone())
+    }
+
+    pub fn get(&self, username: &str, source: &str) -> Result<Option<Session>> {
+        let db = self.connect_db()?;
+        let row: Option<(Vec<u8>, Option<Vec<u8>>)> = db
+            .query_row(
+                r#"SELECT session, credentials FROM source_session
+                WHERE user_id = (SELECT id FROM "user" WHERE username = ?1) AND source = ?2"#,
+                params![username, source],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let (login, credentials) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let aad = aad(username, source);
+        let login = serde_json::from_slice(&self.open(&aad, &login)?)?;
+        let credentials = match credentials {
+            Some(credentials) => Some(serde_json::from_slice(&self.open(&aad, &credentials)?)?),
+            None => None,
+        };
+        Ok(Some(Session { login, credentials }))
+    }
+
+    pub fn set(&self, username: &str, source: &str, session: &Session) -> Result<()> {
+        let aad = aad(username, source);
+        let login = self.seal(&aad, &serde_json::to_vec(&session.login)?)?;
+        let credentials = match &session.credentials {
+            Some(credentials) => Some(self.seal(&aad, &serde_json::to_vec(credentials)?)?),
+            None => None,
+        };
+
+        let db = self.connect_db()?;
+        let updated = db.execute(
+            r#"INSERT INTO source_session(user_id, source, session, credentials, expires)
+            SELECT id, ?2, ?3, ?4, ?5 FROM "user" WHERE username = ?1
+            ON CONFLICT(user_id, source) DO UPDATE SET
+                session = excluded.session,
+                credentials = excluded.credentials,
+                expires = excluded.expires,
+                updated = CURRENT_TIMESTAMP"#,
+            params![username, source, login, credentials, session.login.expires],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("user {} not found", username));
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, username: &str, source: &str) -> Result<()> {
+        let db = self.connect_db()?;
+        db.execute(
+            r#"DELETE FROM source_session
+            WHERE user_id = (SELECT id FROM "user" WHERE username = ?1) AND source = ?2"#,
+            params![username, source],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the sources `username` is logged in to
+    pub fn sources(&self, username: &str) -> Result<Vec<String>> {
+        let db = self.connect_db()?;
+        let mut stmt = db.prepare(
+            r#"SELECT source FROM source_session
+            WHERE user_id = (SELECT id FROM "user" WHERE username = ?1) ORDER BY source"#,
+        )?;
+        let sources = stmt
+            .query_map(params![username], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(sources)
+    }
+
+    /// Encrypts `plain` as a random nonce followed by the ciphertext,
+    /// `aad` binds it to a user and source so rows can't be swapped
+    fn seal(&self, aad: &[u8], plain: &[u8]) -> Result<Vec<u8>> {
+        let cipher = ChaCha20Poly1305::new(&Key::from(self.key));
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let sealed = cipher
+            .encrypt(&Nonce::from(nonce), Payload { msg: plain, aad })
+            .map_err(|_| anyhow!("failed to encrypt session"))?;
+        Ok([&nonce[..], &sealed].concat())
+    }
+
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("malformed session"));
+        }
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into()?;
+        let cipher = ChaCha20Poly1305::new(&Key::from(self.key));
+        cipher
+            .decrypt(&Nonce::from(nonce), Payload { msg: sealed, aad })
+            .map_err(|_| anyhow!("failed to decrypt session, secret may have changed"))
+    }
+}
+
+/// Associated data of the session of `username` on `source`, each is prefixed with its length
+/// so no other user and source give the same bytes, e.g `a/b` on `c` and `a` on `b/c`
+fn aad(username: &str, source: &str) -> Vec<u8> {
+    let mut aad = vec![];
+    for part in &[username, source] {
+        aad.extend_from_slice(&(part.len() as u64).to_be_bytes());
+        aad.extend_from_slice(part.as_bytes());
+    }
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aad_is_unambiguous() {
+        assert_ne!(aad("a/b", "c"), aad("a", "b/c"));
+        assert_ne!(aad("ab", "c"), aad("a", "bc"));
+        assert_eq!(aad("a", "b"), aad("a", "b"));
+    }
+
+    #[test]
+    fn sealed_session_only_opens_for_its_user_and_source() {
+        let sessions = Sessions::new(String::new(), "secret");
+        let sealed = sessions.seal(&aad("a/b", "c"), b"session").unwrap();
+        assert_eq!(
+            sessions.open(&aad("a/b", "c"), &sealed).unwrap(),
+            b"session"
+        );
+        assert!(sessions.open(&aad("a", "b/c"), &sealed).is_err());
+        assert!(Sessions::new(String::new(), "other")
+            .open(&aad("a/b", "c"), &sealed)
+            .is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{PublicKey, Signature, Verifier as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+/// Hash and signature of an installed plugin, stored next to it as `<plugin>.manifest`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub hash: String,
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+impl Manifest {
+    pub fn path(plugin: &Path) -> PathBuf {
+        let mut path = plugin.as_os_str().to_owned();
+        path.push(".manifest");
+        PathBuf::from(path)
+    }
+
+    pub fn read(plugin: &Path) -> Result<Option<Self>> {
+        match std::fs::read(Self::path(plugin)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn write(&self, plugin: &Path) -> Result<()> {
+        std::fs::write(Self::path(plugin), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// Bytes of a plugin file that passed verification.
+/// Extensions are opened from these bytes instead of reading the file again,
+/// so the file can't be replaced between verifying and loading it.
+pub struct Verified {
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+}
+
+/// Checks plugins against their SHA-256 hash, and if `trusted_keys` is configured,
+/// an ed25519 signature of the plugin file made by one of the keys
+#[derive(Clone)]
+pub struct Verifier {
+    trusted_keys: Vec<PublicKey>,
+    allow_unsigned: bool,
+}
+
+impl Verifier {
+    /// `trusted_keys` are base64 encoded ed25519 public keys,
+    /// `allow_unsigned` allows plugins without manifest when there is no trusted key
+    pub fn new(trusted_keys: &[String], allow_unsigned: bool) -> Result<Self> {
+        let trusted_keys = trusted_keys
+            .iter()
+            .map(|key| {
+                base64::decode(key)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| Ok(PublicKey::from_bytes(&bytes)?))
+                    .map_err(|e| anyhow!("trusted key {} is not a valid ed25519 key: {}", key, e))
+            })
+            .collect::<Result<Vec<PublicKey>>>()?;
+        Ok(Self {
+            trusted_keys,
+            allow_unsigned,
+        })
+    }
+
+    pub fn verify(
+        &self,
+        name: &str,
+        bytes: &[u8],
+        hash: &str,
+        signature: Option<&str>,
+    ) -> Result<()> {
+        if hash.is_empty() {
+            return Err(anyhow!("{} has no sha256 hash to verify", name));
+        }
+        let actual = sha256(bytes);
+        if !actual.eq_ignore_ascii_case(hash) {
+            return Err(anyhow!(
+                "sha256 of {} is {}, expected {}, the file may be corrupted or tampered",
+                name,
+                actual,
+                hash
+            ));
+        }
+
+        if self.trusted_keys.is_empty() {
+            return Ok(());
+        }
+        let signature = match signature {
+            Some(signature) if !signature.is_empty() => signature,
+            _ => {
+                return Err(anyhow!(
+                    "{} is not signed, only signed plugins are allowed when trusted_keys is set",
+                    name
+                ));
+            }
+        };
+        let signature = base64::decode(signature)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(Signature::try_from(&bytes[..])?))
+            .map_err(|e| anyhow!("signature of {} is malformed: {}", name, e))?;
+        if self
+            .trusted_keys
+            .iter()
+            .any(|key| key.verify(bytes, &signature).is_ok())
+        {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "signature of {} does not match any trusted key",
+                name
+            ))
+        }
+    }
+
+    /// Read a plugin file and verify it against its manifest. A plugin without manifest,
+    /// e.g copied manually, is only allowed with `allow_unsigned_plugins` and no `trusted_keys`.
+    pub fn verify_file(&self, path: &Path) -> Result<Verified> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let manifest = Manifest::read(path)?;
+        let bytes = std::fs::read(path)?;
+        match manifest {
+            Some(manifest) => {
+                self.verify(name, &bytes, &manifest.hash, manifest.signature.as_deref())?
+            }
+            None if !self.trusted_keys.is_empty() => {
+                return Err(anyhow!(
+                    "{} has no manifest, only signed plugins are allowed when trusted_keys is set",
+                    name
+                ));
+            }
+            None if !self.allow_unsigned => {
+                return Err(anyhow!(
+                    "{} has no manifest, install it from a repository or set allow_unsigned_plugins",
+                    name
+                ));
+            }
+            None => {}
+        }
+
+        Ok(Verified {
+            path: path.to_path_buf(),
+            bytes,
+        })
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+    fn keypair() -> Keypair {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn plugin(dir: &Path, bytes: &[u8], manifest: Option<Manifest>) -> PathBuf {
+        let path = dir.join("libexample.so");
+        std::fs::write(&path, bytes).unwrap();
+        if let Some(manifest) = manifest {
+            manifest.write(&path).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn verify_file_with_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = Manifest {
+            hash: sha256(b"plugin"),
+            signature: None,
+        };
+        let path = plugin(dir.path(), b"plugin", Some(manifest));
+        let verifier = Verifier::new(&[], false).unwrap();
+
+        let verified = verifier.verify_file(&path).unwrap();
+        assert_eq!(verified.path, path);
+        assert_eq!(verified.bytes, b"plugin");
+
+        std::fs::write(&path, b"tampered").unwrap();
+        let e = verifier.verify_file(&path).err().unwrap();
+        assert!(
+            e.to_string().contains("may be corrupted or tampered"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn verify_file_without_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = plugin(dir.path(), b"plugin", None);
+
+        let e = Verifier::new(&[], false)
+            .unwrap()
+            .verify_file(&path)
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("allow_unsigned_plugins"), "{}", e);
+
+        let verified = Verifier::new(&[], true)
+            .unwrap()
+            .verify_file(&path)
+            .unwrap();
+        assert_eq!(verified.bytes, b"plugin");
+
+        // trusted keys take precedence over allow_unsigned_plugins
+        let key = base64::encode(keypair().public.as_bytes());
+        let e = Verifier::new(&[key], true)
+            .unwrap()
+            .verify_file(&path)
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("trusted_keys"), "{}", e);
+    }
+
+    #[test]
+    fn verify_signature() {
+        let keypair = keypair();
+        let verifier = Verifier::new(&[base64::encode(keypair.public.as_bytes())], false).unwrap();
+        let hash = sha256(b"plugin");
+        let signature = base64::encode(keypair.sign(b"plugin").to_bytes());
+
+        assert!(verifier
+            .verify("example", b"plugin", &hash, Some(&signature))
+            .is_ok());
+        assert!(verifier.verify("example", b"plugin", &hash, None).is_err());
+        assert!(verifier
+            .verify("example", b"plugin", &hash, Some("not base64"))
+            .is_err());
+
+        let other = base64::encode(keypair.sign(b"other").to_bytes());
+        let e = verifier
+            .verify("example", b"plugin", &hash, Some(&other))
+            .unwrap_err();
+        assert!(e.to_string().contains("does not match any trusted key"));
+
+        assert!(Verifier::new(&["not a key".to_string()], false).is_err());
+    }
+}
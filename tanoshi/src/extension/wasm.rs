@@ -0,0 +1,505 @@
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use wasmi::core::Trap;
+use wasmi::{
+    AsContextMut, Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+use tanoshi_lib::extensions::{Extension, Panicked};
+use tanoshi_lib::http::{self, Http};
+use tanoshi_lib::manga::{
+    Chapter, Filters, Manga, Params, Preferences, Source, SourceLogin, SourceLoginResult,
+};
+
+/// Import module of the functions tanoshi provides to WebAssembly extensions
+static HOST_MODULE: &str = "tanoshi";
+/// Instructions a single call into an extension may execute
+static FUEL_PER_CALL: u64 = 1_000_000_000;
+static MAX_MEMORY_SIZE: usize = 64 * 1024 * 1024;
+static MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+static MAX_REDIRECTS: usize = 5;
+
+/// Result of every exported function, either `{"ok": ...}` or `{"err": "message"}`
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Response<T> {
+    Ok(T),
+    Err(String),
+}
+
+impl<T> From<Result<T>> for Response<T> {
+    fn from(result: Result<T>) -> Self {
+        match result {
+            Ok(value) => Response::Ok(value),
+            Err(e) => Response::Err(e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GetMangasArgs {
+    param: Params,
+    auth: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PathArgs<'a> {
+    path: &'a str,
+    auth: String,
+}
+
+/// Request made by an extension through `tanoshi.http_request`
+#[derive(Debug, Deserialize)]
+struct HttpRequest {
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+#[derive(Debug, Serialize)]
+struct HttpResponse {
+    status: u16,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+struct HostState {
+    name: String,
+    network: Network,
+    limits: StoreLimits,
+}
+
+/// Network access of an extension, shared with `get_page` so downloading
+/// an image doesn't need the guest
+#[derive(Clone)]
+struct Network {
+    allowed_hosts: Vec<String>,
+    http: Http,
+}
+
+impl Network {
+    /// Only plain http(s) requests are allowed, and if the plugin config has
+    /// `allowed_hosts` only to those hosts. Hosts that aren't listed explicitly
+    /// must not resolve to an internal address
+    fn check_url(&self, url: &url::Url) -> Result<()> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(anyhow!("scheme {} is not allowed", url.scheme()));
+        }
+        let host = url.host_str().unwrap_or_default();
+        if self.allowed_hosts.iter().any(|h| h == host) {
+            return Ok(());
+        }
+        if !self.allowed_hosts.is_empty() {
+            return Err(anyhow!("host {} is not allowed", host));
+        }
+        super::http::check_public(url)
+    }
+
+    /// Sends a request on behalf of an extension with the HTTP client of its source,
+    /// redirects are followed here so every location is checked
+    fn fetch(&self, request: &HttpRequest) -> Result<(u16, HashMap<String, String>, Vec<u8>)> {
+        let mut url = url::Url::parse(&request.url)?;
+        let mut method = request.method.to_uppercase();
+        let mut body = request.body.clone();
+
+        for _ in 0..=MAX_REDIRECTS {
+            self.check_url(&url)?;
+
+            let mut req = http::Request::new(&method, url.as_str()).redirects(0);
+            for (key, value) in request.headers.iter() {
+                req = req.header(key, value);
+            }
+            if let Some(body) = &body {
+                req = req.body(body.as_bytes());
+            }
+            let resp = self.http.send(req)?;
+
+            if resp.status >= 300 && resp.status < 400 {
+                if let Some(location) = resp.header("location") {
+                    url = url.join(location)?;
+                    if resp.status != 307 && resp.status != 308 {
+                        method = "GET".to_string();
+                        body = None;
+                    }
+                    continue;
+                }
+            }
+
+            if resp.body.len() > MAX_RESPONSE_SIZE {
+                return Err(anyhow!("response of {} is too large", url));
+            }
+
+            return Ok((resp.status, resp.headers.into_iter().collect(), resp.body));
+        }
+
+        Err(anyhow!("too many redirects"))
+    }
+}
+
+struct Guest {
+    store: Store<HostState>,
+    instance: wasmi::Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    dealloc: TypedFunc<(i32, i32), ()>,
+}
+
+impl Guest {
+    fn refuel(&mut self) -> Result<()> {
+        let remaining = self.store.consume_fuel(0).map_err(wasmi::Error::from)?;
+        self.store
+            .add_fuel(FUEL_PER_CALL.saturating_sub(remaining))
+            .map_err(wasmi::Error::from)?;
+        Ok(())
+    }
+
+    /// Calls an exported function, `args` is JSON encoded into guest memory.
+    /// A trap, e.g a panic of the guest or running out of fuel, is returned as [`Panicked`]
+    fn call<A, T>(&mut self, name: &str, args: Option<&A>) -> Result<T>
+    where
+        A: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        self.refuel()?;
+
+        let packed = match args {
+            Some(args) => {
+                let args = serde_json::to_vec(args)?;
+                let (ptr, len) = write_guest(&mut self.store, self.memory, &self.alloc, &args)?;
+                self.instance
+                    .get_typed_func::<(i32, i32), i64>(&self.store, name)
+                    .map_err(|_| anyhow!("{} is not exported", name))?
+                    .call(&mut self.store, (ptr, len))
+                    .map_err(|e| Panicked(e.to_string()))?
+            }
+            None => self
+                .instance
+                .get_typed_func::<(), i64>(&self.store, name)
+                .map_err(|_| anyhow!("{} is not exported", name))?
+                .call(&mut self.store, ())
+                .map_err(|e| Panicked(e.to_string()))?,
+        };
+
+        let (ptr, len) = unpack(packed);
+        let bytes = guest_slice(self.memory.data(&self.store), ptr, len)?;
+        self.dealloc.call(&mut self.store, (ptr, len))?;
+
+        match serde_json::from_slice::<Response<T>>(&bytes)? {
+            Response::Ok(value) => Ok(value),
+            Response::Err(message) => Err(anyhow!("{}", message)),
+        }
+    }
+}
+
+/// An extension compiled to WebAssembly
+///
+/// The module runs inside an interpreter with a memory and instruction limit,
+/// it has no access to the filesystem and can only reach the network through `tanoshi.http_request`.
+pub struct WasmExtension {
+    source: Source,
+    filters: Filters,
+    preferences: Preferences,
+    network: Network,
+    guest: Mutex<Guest>,
+}
+
+impl WasmExtension {
+    /// `bytes` are the content of the module at `path`
+    pub fn new(
+        path: &Path,
+        bytes: &[u8],
+        config: Option<&serde_yaml::Value>,
+        http: Http,
+    ) -> Result<Self> {
+        let mut engine_config = Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+
+        let module = Module::new(&engine, bytes)?;
+
+        let allowed_hosts = config
+            .and_then(|config| config.get("allowed_hosts"))
+            .and_then(|hosts| serde_yaml::from_value::<Vec<String>>(hosts.clone()).ok())
+            .unwrap_or_default();
+        let network = Network {
+            allowed_hosts,
+            http,
+        };
+        let mut store = Store::new(
+            &engine,
+            HostState {
+                name: path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                network: network.clone(),
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(MAX_MEMORY_SIZE)
+                    .build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+
+        let mut linker = <Linker<HostState>>::new(&engine);
+        linker.func_wrap(HOST_MODULE, "http_request", host_http_request)?;
+        linker.func_wrap(HOST_MODULE, "log", host_log)?;
+
+        let instance = linker
+            .instantiate(&mut store, &module)?
+            .ensure_no_start(&mut store)
+            .map_err(|_| anyhow!("{:?} must not have a start function", path))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("{:?} does not export memory", path))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "tanoshi_alloc")?;
+        let dealloc = instance.get_typed_func::<(i32, i32), ()>(&store, "tanoshi_dealloc")?;
+
+        let mut guest = Guest {
+            store,
+            instance,
+            memory,
+            alloc,
+            dealloc,
+        };
+
+        if instance.get_export(&guest.store, "init").is_some() {
+            let config = match config {
+                Some(config) => serde_json::to_value(config)?,
+                None => serde_json::Value::Null,
+            };
+            guest.call::<_, ()>("init", Some(&config))?;
+        }
+
+        let source = guest.call::<(), Source>("info", None)?;
+        if source.name.is_empty() {
+            return Err(anyhow!("{:?} has no name", path));
+        }
+
+        let filters = if instance.get_export(&guest.store, "filters").is_some() {
+            guest.call::<(), Filters>("filters", None)?
+        } else {
+            Filters::default()
+        };
+        let preferences = if instance.get_export(&guest.store, "preferences").is_some() {
+            guest.call::<(), Preferences>("preferences", None)?
+        } else {
+            Preferences::default()
+        };
+
+        Ok(Self {
+            source,
+            filters,
+            preferences,
+            network,
+            guest: Mutex::new(guest),
+        })
+    }
+
+    fn call<A, T>(&self, name: &str, args: &A) -> Result<T>
+    where
+        A: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let mut guest = self
+            .guest
+            .lock()
+            .map_err(|_| anyhow!("{} is unavailable", self.source.name))?;
+        guest.call(name, Some(args))
+    }
+}
+
+impl Extension for WasmExtension {
+    fn info(&self) -> Source {
+        self.source.clone()
+    }
+
+    fn filters(&self) -> Filters {
+        self.filters.clone()
+    }
+
+    fn preferences(&self) -> Preferences {
+        self.preferences.clone()
+    }
+
+    fn get_mangas(&self, param: Params, auth: String) -> Result<Vec<Manga>> {
+        self.call("get_mangas", &GetMangasArgs { param, auth })
+    }
+
+    fn get_manga_info(&self, path: &str, auth: String) -> Result<Manga> {
+        self.call("get_manga_info", &PathArgs { path, auth })
+    }
+
+    fn get_chapters(&self, path: &str, auth: String) -> Result<Vec<Chapter>> {
+        self.call("get_chapters", &PathArgs { path, auth })
+    }
+
+    fn get_pages(&self, path: &str, auth: String) -> Result<Vec<String>> {
+        self.call("get_pages", &PathArgs { path, auth })
+    }
+
+    /// Images are downloaded by tanoshi with the same restrictions as `tanoshi.http_request`
+    fn get_page(&self, url: &str, _auth: String) -> Result<Vec<u8>> {
+        let request = HttpRequest {
+            method: default_method(),
+            url: url.to_string(),
+            headers: HashMap::new(),
+            body: None,
+        };
+        let (status, _, bytes) = self.network.fetch(&request)?;
+        if status >= 400 {
+            return Err(anyhow!("error get page {}: status {}", url, status));
+        }
+        Ok(bytes)
+    }
+
+    fn login(&self, login_info: SourceLogin) -> Result<SourceLoginResult> {
+        self.call("login", &login_info)
+    }
+}
+
+/// Pointer and length of a guest buffer are returned packed as `ptr << 32 | len`
+fn unpack(packed: i64) -> (i32, i32) {
+    ((packed as u64 >> 32) as i32, packed as u32 as i32)
+}
+
+fn pack(ptr: i32, len: i32) -> i64 {
+    ((ptr as u32 as u64) << 32 | len as u32 as u64) as i64
+}
+
+/// Copies bytes into a buffer allocated by the guest, the guest owns the buffer afterward
+fn write_guest(
+    mut ctx: impl AsContextMut<UserState = HostState>,
+    memory: Memory,
+    alloc: &TypedFunc<i32, i32>,
+    bytes: &[u8],
+) -> Result<(i32, i32), wasmi::Error> {
+    let len = bytes.len() as i32;
+    let ptr = alloc.call(&mut ctx, len)?;
+    memory.write(&mut ctx, ptr as usize, bytes)?;
+    Ok((ptr, len))
+}
+
+fn trap(e: impl std::fmt::Display) -> Trap {
+    Trap::new(e.to_string())
+}
+
+fn read_guest(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| trap("memory is not exported"))?;
+    guest_slice(memory.data(caller), ptr, len)
+}
+
+/// Copies a buffer out of guest memory, the range is checked before anything is allocated
+fn guest_slice(data: &[u8], ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    if ptr < 0 || len < 0 {
+        return Err(trap(format!("invalid buffer {} of length {}", ptr, len)));
+    }
+    let start = ptr as usize;
+    data.get(start..start + len as usize)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| trap(format!("buffer {} of length {} is out of bounds", ptr, len)))
+}
+
+/// `tanoshi.http_request(ptr, len) -> i64`, takes a JSON encoded request and
+/// returns `{"ok": {"status", "headers", "body"}}` or `{"err": "message"}`
+fn host_http_request(mut caller: Caller<'_, HostState>, ptr: i32, len: i32) -> Result<i64, Trap> {
+    let request = read_guest(&caller, ptr, len)?;
+    let response: Response<HttpResponse> = serde_json::from_slice::<HttpRequest>(&request)
+        .map_err(anyhow::Error::from)
+        .and_then(|request| caller.data().network.fetch(&request))
+        .map(|(status, headers, body)| HttpResponse {
+            status,
+            headers,
+            body: String::from_utf8_lossy(&body).to_string(),
+        })
+        .into();
+    let response = serde_json::to_vec(&response).map_err(trap)?;
+
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| trap("memory is not exported"))?;
+    let alloc = caller
+        .get_export("tanoshi_alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| trap("tanoshi_alloc is not exported"))?
+        .typed::<i32, i32>(&caller)
+        .map_err(trap)?;
+    let (ptr, len) = write_guest(&mut caller, memory, &alloc, &response).map_err(trap)?;
+
+    Ok(pack(ptr, len))
+}
+
+/// `tanoshi.log(level, ptr, len)`, level is 1 (error) to 5 (trace)
+fn host_log(caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32) -> Result<(), Trap> {
+    let message = read_guest(&caller, ptr, len)?;
+    let message = String::from_utf8_lossy(&message);
+    let name = &caller.data().name;
+    match level {
+        1 => error!("{}: {}", name, message),
+        2 => warn!("{}: {}", name, message),
+        3 => info!("{}: {}", name, message),
+        4 => debug!("{}: {}", name, message),
+        _ => trace!("{}: {}", name, message),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guest_slice_bounds() {
+        let data = [1, 2, 3, 4];
+        assert_eq!(guest_slice(&data, 1, 2).unwrap(), vec![2, 3]);
+        assert_eq!(guest_slice(&data, 4, 0).unwrap(), Vec::<u8>::new());
+        assert!(guest_slice(&data, 0, -1).is_err());
+        assert!(guest_slice(&data, -1, 1).is_err());
+        assert!(guest_slice(&data, 3, 2).is_err());
+        assert!(guest_slice(&data, 0, i32::MAX).is_err());
+    }
+
+    #[test]
+    fn check_url_blocks_internal_hosts() {
+        let url = |s: &str| url::Url::parse(s).unwrap();
+        let open = Network {
+            allowed_hosts: vec![],
+            http: Http::default(),
+        };
+        assert!(open.check_url(&url("file:///etc/passwd")).is_err());
+        assert!(open.check_url(&url("http://127.0.0.1/")).is_err());
+        assert!(open
+            .check_url(&url("http://169.254.169.254/latest"))
+            .is_err());
+        assert!(open.check_url(&url("http://8.8.8.8/")).is_ok());
+
+        let listed = Network {
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+            http: Http::default(),
+        };
+        assert!(listed.check_url(&url("http://127.0.0.1/")).is_ok());
+        assert!(listed.check_url(&url("http://8.8.8.8/")).is_err());
+    }
+
+    #[test]
+    fn pack_roundtrip() {
+        assert_eq!(unpack(pack(16, 32)), (16, 32));
+        assert_eq!(unpack(pack(i32::MAX, i32::MAX)), (i32::MAX, i32::MAX));
+    }
+}
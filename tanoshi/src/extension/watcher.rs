@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::extension::{is_plugin, plugin_config::PluginConfig, verify::Verifier, Extensions};
+
+/// Keeps loaded extensions in sync with the files in `plugin_path`
+///
+/// New files are loaded, changed files are reloaded and extensions of removed files are unloaded.
+/// A file is only loaded once its modified time is the same on two scans in a row,
+/// so a plugin that is still being copied is not loaded halfway.
+pub struct Watcher {
+    extensions: Arc<RwLock<Extensions>>,
+    plugin_path: PathBuf,
+    plugin_config: PluginConfig,
+    verifier: Verifier,
+    seen: HashMap<PathBuf, SystemTime>,
+    failed: HashMap<PathBuf, SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(
+        extensions: Arc<RwLock<Extensions>>,
+        plugin_path: String,
+        plugin_config: PluginConfig,
+        verifier: Verifier,
+    ) -> Self {
+        Self {
+            extensions,
+            plugin_path: PathBuf::from(plugin_path),
+            plugin_config,
+            verifier,
+            seen: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    /// Load every plugin in `plugin_path` without waiting for them to settle
+    pub fn load_all(&mut self) -> anyhow::Result<()> {
+        for (path, modified) in self.plugins()? {
+            self.load(&path, modified);
+            self.seen.insert(path, modified);
+        }
+        Ok(())
+    }
+
+    /// Scan `plugin_path` every `interval` in a background thread
+    pub fn watch(mut self, interval: Duration) {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            if let Err(e) = self.scan() {
+                error!("failed to scan {:?}: {}", self.plugin_path, e);
+            }
+        });
+    }
+
+    fn scan(&mut self) -> anyhow::Result<()> {
+        let plugins = self.plugins()?;
+
+        let removed = self
+            .extensions
+            .read()
+            .unwrap()
+            .paths()
+            .into_iter()
+            .filter(|path| path.starts_with(&self.plugin_path) && !plugins.contains_key(path))
+            .collect::<Vec<PathBuf>>();
+        for path in removed {
+            let names = self
+                .extensions
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .unload(&path);
+            info!("unload plugin {:?} from removed {:?}", names, path);
+        }
+        self.failed.retain(|path, _| plugins.contains_key(path));
+
+        for (path, modified) in plugins.iter() {
+            let settled = self.seen.get(path) == Some(modified);
+            let loaded = self
+                .extensions
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .modified(path)
+                == Some(*modified);
+            let failed = self.failed.get(path) == Some(modified);
+            if settled && !loaded && !failed {
+                self.load(path, *modified);
+            }
+        }
+        self.seen = plugins;
+
+        Ok(())
+    }
+
+    fn plugins(&self) -> anyhow::Result<HashMap<PathBuf, SystemTime>> {
+        Ok(std::fs::read_dir(&self.plugin_path)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_plugin(path))
+            .filter_map(|path| {
+                let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect())
+    }
+
+    fn load(&mut self, path: &Path, modified: SystemTime) {
+        info!("load plugin from {:?}", path);
+        let config = self.plugin_config.get(&config_name(path));
+        let plugin = match self.verifier.verify_file(path) {
+            Ok(plugin) => plugin,
+            Err(e) => {
+                error!("refuse to load {:?}: {}", path, e);
+                let names = self
+                    .extensions
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .unload(path);
+                if !names.is_empty() {
+                    info!("unload plugin {:?} from {:?}", names, path);
+                }
+                self.failed.insert(path.to_path_buf(), modified);
+                return;
+            }
+        };
+        let http = self
+            .extensions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .http();
+        let res = unsafe { Extensions::open_file(&http, &plugin, config.as_ref()) };
+        let mut extensions = self
+            .extensions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        match res {
+            Ok(opened) => {
+                extensions.insert_file(path, opened);
+                self.failed.remove(path);
+            }
+            Err(e) => {
+                error!("not a valid extensions {}", e);
+                extensions.unload(path);
+                self.failed.insert(path.to_path_buf(), modified);
+            }
+        }
+    }
+}
+
+/// Plugin config is keyed by file name without `lib` prefix and extension
+pub fn config_name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or_default()
+        .to_string()
+        .replace("lib", "")
+}
@@ -3,6 +3,8 @@ use rusqlite::{params, Connection};
 use tanoshi_lib::manga::{Manga, Params, SortByParam, SortOrderParam};
 use tanoshi_lib::rest::{AddFavoritesResponse, GetMangasResponse};
 
+use crate::error::Error;
+
 #[derive(Clone)]
 pub struct Favorites {
     database_path: String,
@@ -13,12 +15,16 @@ impl Favorites {
         Favorites { database_path }
     }
 
-    fn connect_db(&self) -> Connection {
-        Connection::open(self.database_path.clone()).unwrap()
+    fn connect_db(&self) -> Result<Connection, Error> {
+        Ok(Connection::open(self.database_path.clone())?)
     }
 
-    pub async fn get_favorites(&self, params: Params, username: String) -> GetMangasResponse {
-        let db = self.connect_db();
+    pub async fn get_favorites(
+        &self,
+        params: Params,
+        username: String,
+    ) -> Result<GetMangasResponse, Error> {
+        let db = self.connect_db()?;
 
         let sort_by = match params.sort_by.unwrap_or_default() {
             SortByParam::LastUpdated=> "last_updated",
@@ -57,7 +63,7 @@ impl Favorites {
          FROM manga
          INNER JOIN favorite f on manga.id = f.manga_id AND f.user_id = (SELECT id FROM "user" WHERE username = ?1)
          JOIN (SELECT manga_id, MAX(uploaded) as last_updated FROM chapter GROUP BY manga_id) c on manga.id = c.manga_id
-         ORDER BY {} {}"#, sort_by, sort_order).as_str()).unwrap();
+         ORDER BY {} {}"#, sort_by, sort_order).as_str())?;
         let mangas = stmt
             .query_map(params![username], |row| {
                 let author = row
@@ -79,50 +85,49 @@ impl Favorites {
                     is_favorite: row.get(8)?,
                     genre: vec![],
                 })
-            })
-            .unwrap()
+            })?
             .filter_map(|f| f.ok())
             .collect();
 
-        GetMangasResponse {
+        Ok(GetMangasResponse {
             mangas,
             status: "success".to_string(),
-        }
+        })
     }
 
-    pub async fn add_favorite(&self, username: String, manga_id: i32) -> AddFavoritesResponse {
-        let db = self.connect_db();
-        match db.execute(
+    pub async fn add_favorite(
+        &self,
+        username: String,
+        manga_id: i32,
+    ) -> Result<AddFavoritesResponse, Error> {
+        let db = self.connect_db()?;
+        db.execute(
             r#"INSERT INTO favorite(user_id, manga_id)
         VALUES(
             (SELECT id FROM "user" WHERE username = ?1),
             ?2
         ) ON CONFLICT DO NOTHING"#,
             params![username, manga_id],
-        ) {
-            Ok(_) => AddFavoritesResponse {
-                status: "success".to_string(),
-            },
-            Err(e) => AddFavoritesResponse {
-                status: format!("failed, reason: {}", e.to_string()),
-            },
-        }
+        )?;
+        Ok(AddFavoritesResponse {
+            status: "success".to_string(),
+        })
     }
 
-    pub async fn remove_favorites(&self, username: String, manga_id: i32) -> AddFavoritesResponse {
-        let db = self.connect_db();
-        match db.execute(
+    pub async fn remove_favorites(
+        &self,
+        username: String,
+        manga_id: i32,
+    ) -> Result<AddFavoritesResponse, Error> {
+        let db = self.connect_db()?;
+        db.execute(
             r#"DELETE FROM favorite 
         WHERE user_id = (SELECT id FROM "user" WHERE username = ?1)
         AND manga_id = ?2"#,
             params![username, manga_id],
-        ) {
-            Ok(_) => AddFavoritesResponse {
-                status: "success".to_string(),
-            },
-            Err(e) => AddFavoritesResponse {
-                status: format!("failed, reason: {}", e.to_string()),
-            },
-        }
+        )?;
+        Ok(AddFavoritesResponse {
+            status: "success".to_string(),
+        })
     }
 }
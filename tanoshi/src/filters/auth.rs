@@ -1,6 +1,7 @@
 use super::{with_admin_role, with_authorization};
 use crate::auth::{auth::Auth, User};
-use crate::handlers::auth as auth_handler;
+use crate::error::Error;
+use crate::handlers::{auth as auth_handler, reject};
 use warp::Filter;
 
 pub fn authentication(
@@ -12,7 +13,11 @@ pub fn authentication(
         .or(user_list(secret.clone(), auth.clone()))
         .or(modify_user_role(secret.clone(), auth.clone()))
         .or(change_password(secret.clone(), auth.clone()))
-        .or(validate(secret))
+        .or(feed_token(secret.clone(), auth.clone()))
+        .or(reset_feed_token(secret.clone(), auth.clone()))
+        .or(telegram_link_token(secret.clone(), auth.clone()))
+        .or(unlink_telegram_chat(secret.clone(), auth.clone()))
+        .or(validate(secret, auth))
 }
 
 pub fn login(
@@ -74,13 +79,59 @@ pub fn change_password(
         .and_then(auth_handler::change_password)
 }
 
+pub fn feed_token(
+    secret: String,
+    auth: Auth,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "user" / "feed-token")
+        .and(warp::get())
+        .and(with_authorization(secret))
+        .and(with_auth(auth))
+        .and_then(auth_handler::feed_token)
+}
+
+pub fn reset_feed_token(
+    secret: String,
+    auth: Auth,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "user" / "feed-token")
+        .and(warp::post())
+        .and(with_authorization(secret))
+        .and(with_auth(auth))
+        .and_then(auth_handler::reset_feed_token)
+}
+
+pub fn telegram_link_token(
+    secret: String,
+    auth: Auth,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "user" / "telegram")
+        .and(warp::post())
+        .and(with_authorization(secret))
+        .and(with_auth(auth))
+        .and_then(auth_handler::telegram_link_token)
+}
+
+pub fn unlink_telegram_chat(
+    secret: String,
+    auth: Auth,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "user" / "telegram")
+        .and(warp::delete())
+        .and(with_authorization(secret))
+        .and(with_auth(auth))
+        .and_then(auth_handler::unlink_telegram_chat)
+}
+
 pub fn validate(
     secret: String,
+    auth: Auth,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "validate")
         .and(warp::get())
         .and(with_authorization(secret))
-        .map(|claim| Ok(warp::reply::json(&claim)))
+        .and(with_auth(auth))
+        .and_then(auth_handler::get_claims)
 }
 
 fn with_secret(
@@ -96,8 +147,9 @@ fn json_body() -> impl Filter<Extract = (User,), Error = warp::Rejection> + Clon
 fn text_body() -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
     warp::body::content_length_limit(1024 * 16)
         .and(warp::body::bytes())
-        .map(|bytes: bytes::Bytes| {
-            String::from_utf8(bytes.to_vec()).expect("failed to parse password")
+        .and_then(|bytes: bytes::Bytes| async move {
+            String::from_utf8(bytes.to_vec())
+                .map_err(|_| reject(Error::BadRequest("password is not utf-8".to_string())))
         })
 }
 
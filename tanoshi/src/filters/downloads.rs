@@ -0,0 +1,66 @@
+use crate::download::{DownloadParam, Downloads};
+use crate::filters::with_authorization;
+use crate::handlers::downloads as downloads_handler;
+
+use warp::Filter;
+
+pub fn downloads(
+    secret: String,
+    downloads: Downloads,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    get_downloads(secret.clone(), downloads.clone())
+        .or(download_chapter(secret.clone(), downloads.clone()))
+        .or(download_manga(secret.clone(), downloads.clone()))
+        .or(delete_download(secret, downloads))
+}
+
+fn get_downloads(
+    secret: String,
+    downloads: Downloads,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "downloads")
+        .and(warp::get())
+        .and(with_authorization(secret))
+        .and(with_downloads(downloads))
+        .and_then(downloads_handler::get_downloads)
+}
+
+fn download_chapter(
+    secret: String,
+    downloads: Downloads,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "downloads" / "chapter" / i32)
+        .and(warp::post())
+        .and(with_authorization(secret))
+        .and(with_downloads(downloads))
+        .and_then(downloads_handler::download_chapter)
+}
+
+fn download_manga(
+    secret: String,
+    downloads: Downloads,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "downloads" / "manga" / i32)
+        .and(warp::post())
+        .and(with_authorization(secret))
+        .and(warp::query::<DownloadParam>())
+        .and(with_downloads(downloads))
+        .and_then(downloads_handler::download_manga)
+}
+
+fn delete_download(
+    secret: String,
+    downloads: Downloads,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "downloads" / "chapter" / i32)
+        .and(warp::delete())
+        .and(with_authorization(secret))
+        .and(with_downloads(downloads))
+        .and_then(downloads_handler::delete_download)
+}
+
+fn with_downloads(
+    downloads: Downloads,
+) -> impl Filter<Extract = (Downloads,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || downloads.clone())
+}
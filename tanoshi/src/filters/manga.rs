@@ -2,24 +2,43 @@
 // use crate::auth::Claims;
 // use crate::filters::settings::settings::auth_handler;
 // use crate::filters::with_db;
-use crate::filters::with_authorization;
+use crate::filters::{with_admin_role, with_authorization, with_image_authorization};
 use crate::handlers::manga;
 
+use crate::extension::local::{CoverQuery, Local};
 use crate::extension::manga::Manga;
 
-use tanoshi_lib::manga::{GetParams, Params, SourceLogin};
+use tanoshi_lib::manga::{GetParams, Params, SearchParams, SourceLogin};
 use warp::{filters::BoxedFilter, Filter, Reply};
 
-pub fn manga(secret: String, plugin_path: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+pub fn manga(
+    secret: String,
+    plugin_path: String,
+    manga: Manga,
+    local: Local,
+) -> BoxedFilter<(impl Reply,)> {
     list_sources(manga.clone())
+        .or(local_cover(local))
+        .or(get_filters(secret.clone(), manga.clone()))
+        .or(get_source_config(secret.clone(), manga.clone()))
+        .or(update_source_config(secret.clone(), manga.clone()))
+        .or(search(secret.clone(), manga.clone()))
         .or(list_mangas(secret.clone(), manga.clone()))
         .or(get_manga_info(secret.clone(), manga.clone()))
         .or(get_chapters(secret.clone(), manga.clone()))
-        .or(get_pages(manga.clone()))
+        .or(get_pages(secret.clone(), manga.clone()))
         .or(read(secret.clone(), manga.clone()))
-        .or(proxy_image(manga.clone()))
-        .or(source_login(manga.clone()))
-        .or(install_source(plugin_path, manga.clone()))
+        .or(proxy_image(secret.clone(), manga.clone()))
+        .or(source_sessions(secret.clone(), manga.clone()))
+        .or(source_login(secret.clone(), manga.clone()))
+        .or(source_logout(secret.clone(), manga.clone()))
+        .or(install_source(
+            secret.clone(),
+            plugin_path.clone(),
+            manga.clone(),
+        ))
+        .or(update_sources(secret.clone(), plugin_path, manga.clone()))
+        .or(uninstall_source(secret.clone(), manga.clone()))
         .boxed()
 }
 
@@ -31,26 +50,102 @@ pub fn list_sources(manga: Manga) -> BoxedFilter<(impl Reply,)> {
         .boxed()
 }
 
-pub fn install_source(plugin_path: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+/// Covers of the local source are loaded by `<img>` too, their urls are signed instead
+pub fn local_cover(local: Local) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "source" / "local" / "cover")
+        .and(warp::get())
+        .and(warp::query::<CoverQuery>())
+        .and(warp::any().map(move || local.clone()))
+        .and_then(manga::local_cover)
+        .boxed()
+}
+
+pub fn install_source(
+    secret: String,
+    plugin_path: String,
+    manga: Manga,
+) -> BoxedFilter<(impl Reply,)> {
     warp::path!("api" / "source" / "install" / String)
         .and(warp::post())
+        .and(with_admin_role(secret))
         .and(with_plugin_path(plugin_path))
         .and(with_manga(manga))
         .and_then(manga::install_source)
         .boxed()
 }
 
+pub fn update_sources(
+    secret: String,
+    plugin_path: String,
+    manga: Manga,
+) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "source" / "update")
+        .and(warp::post())
+        .and(with_admin_role(secret))
+        .and(with_plugin_path(plugin_path))
+        .and(with_manga(manga))
+        .and_then(manga::update_sources)
+        .boxed()
+}
+
+pub fn uninstall_source(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "source" / String)
+        .and(warp::delete())
+        .and(with_admin_role(secret))
+        .and(with_manga(manga))
+        .and_then(manga::uninstall_source)
+        .boxed()
+}
+
+pub fn get_source_config(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "source" / String / "config")
+        .and(warp::get())
+        .and(with_admin_role(secret))
+        .and(with_manga(manga))
+        .and_then(manga::get_source_config)
+        .boxed()
+}
+
+pub fn update_source_config(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "source" / String / "config")
+        .and(warp::put())
+        .and(with_admin_role(secret))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::json())
+        .and(with_manga(manga))
+        .and_then(manga::update_source_config)
+        .boxed()
+}
+
+pub fn get_filters(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "source" / String / "filters")
+        .and(warp::get())
+        .and(with_authorization(secret))
+        .and(with_manga(manga))
+        .and_then(manga::get_filters)
+        .boxed()
+}
+
 pub fn list_mangas(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
     warp::path!("api" / "source" / String)
         .and(warp::get())
         .and(with_authorization(secret))
-        .and(with_source_authorization())
         .and(warp::query::<Params>())
         .and(with_manga(manga))
         .and_then(manga::list_mangas)
         .boxed()
 }
 
+pub fn search(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "search")
+        .and(warp::get())
+        .and(with_authorization(secret))
+        .and(warp::query::<SearchParams>())
+        .and(with_manga(manga))
+        .and_then(manga::search)
+        .boxed()
+}
+
 pub fn get_manga_info(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
     warp::path!("api" / "manga" / i32)
         .and(warp::get())
@@ -70,37 +165,61 @@ pub fn get_chapters(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)>
         .boxed()
 }
 
-pub fn get_pages(manga: Manga) -> BoxedFilter<(impl Reply,)> {
+pub fn get_pages(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
     warp::path!("api" / "chapter" / i32)
         .and(warp::get())
+        .and(with_authorization(secret.clone()))
         .and(warp::query::<GetParams>())
+        .and(with_secret(secret))
         .and(with_manga(manga))
         .and_then(manga::get_pages)
         .boxed()
 }
 
-pub fn proxy_image(manga: Manga) -> BoxedFilter<(impl Reply,)> {
+/// Pages are loaded by `<img>` of the web app, their urls come with an image token as `?token=`
+pub fn proxy_image(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
     warp::path!("api" / "page" / i32)
         .and(warp::get())
+        .and(with_image_authorization(secret))
         .and(with_manga(manga))
         .and_then(manga::proxy_image)
         .boxed()
 }
 
-pub fn source_login(manga: Manga) -> BoxedFilter<(impl Reply,)> {
+pub fn source_sessions(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "login")
+        .and(warp::get())
+        .and(with_authorization(secret))
+        .and(with_manga(manga))
+        .and_then(manga::source_sessions)
+        .boxed()
+}
+
+pub fn source_login(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
     warp::path!("api" / "login" / String)
         .and(warp::post())
+        .and(with_authorization(secret))
         .and(json_body())
         .and(with_manga(manga))
         .and_then(manga::source_login)
         .boxed()
 }
 
+pub fn source_logout(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("api" / "login" / String)
+        .and(warp::delete())
+        .and(with_authorization(secret))
+        .and(with_manga(manga))
+        .and_then(manga::source_logout)
+        .boxed()
+}
+
 pub fn read(secret: String, manga: Manga) -> BoxedFilter<(impl Reply,)> {
     warp::path!("api" / "read" / i32)
         .and(warp::get())
-        .and(with_authorization(secret))
+        .and(with_authorization(secret.clone()))
         .and(warp::query::<GetParams>())
+        .and(with_secret(secret))
         .and(with_manga(manga))
         .and_then(manga::read)
         .boxed()
@@ -110,6 +229,12 @@ fn json_body() -> impl Filter<Extract = (SourceLogin,), Error = warp::Rejection>
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
 
+fn with_secret(
+    secret: String,
+) -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || secret.clone())
+}
+
 fn with_plugin_path(
     plugin_path: String,
 ) -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
@@ -121,8 +246,3 @@ fn with_manga(
 ) -> impl Filter<Extract = (Manga,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || manga.clone())
 }
-
-pub fn with_source_authorization(
-) -> impl Filter<Extract = (String,), Error = warp::reject::Rejection> + Clone {
-    warp::header::header("sourceauthorization").map(move |token: String| token.clone())
-}
@@ -1,15 +1,20 @@
-use serde_json::json;
-
 use warp::Filter;
 
+use tanoshi_lib::rest::{ErrorCode, ErrorResponse};
+
 use crate::auth::Claims;
+use crate::error::{self, Error};
+use crate::extension::guard::ExtensionError;
 use crate::handlers::auth as auth_handler;
 
 pub mod auth;
+pub mod downloads;
 pub mod favorites;
 pub mod history;
 pub mod manga;
+pub mod opds;
 pub mod updates;
+pub mod webhooks;
 
 #[derive(Debug)]
 pub struct ExpiredOrInvalidToken;
@@ -29,6 +34,36 @@ pub fn with_authorization(
         })
 }
 
+#[derive(Debug, serde::Deserialize)]
+pub struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Username of a request for an image, from the token in the authorization header
+/// or an image token sent as `?token=`, for urls the browser loads by itself.
+/// A login token is never accepted in the query, urls end up in logs and history.
+pub fn with_image_authorization(
+    secret: String,
+) -> impl Filter<Extract = (String,), Error = warp::reject::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<TokenQuery>())
+        .map(
+            move |header: Option<String>, query: TokenQuery| match (header, query.token) {
+                (Some(header), _) => {
+                    auth_handler::validate(secret.clone(), header).map(|claim| claim.sub)
+                }
+                (None, Some(token)) => auth_handler::validate_image_token(&secret, &token),
+                (None, None) => None,
+            },
+        )
+        .and_then(|username: Option<String>| async move {
+            match username {
+                Some(username) => Ok(username),
+                None => Err(warp::reject::custom(ExpiredOrInvalidToken)),
+            }
+        })
+}
+
 pub fn with_admin_role(
     secret: String,
 ) -> impl Filter<Extract = (Claims,), Error = warp::reject::Rejection> + Clone {
@@ -38,36 +73,129 @@ pub fn with_admin_role(
             match claim {
                 Some(claim) => match claim.role.as_str() {
                     "ADMIN" => Ok(claim),
-                    _ => Err(warp::reject::custom(ExpiredOrInvalidToken)),
+                    _ => Err(warp::reject::custom(Error::Forbidden(
+                        "admin role required".to_string(),
+                    ))),
                 },
                 None => Err(warp::reject::custom(ExpiredOrInvalidToken)),
             }
         })
 }
 
+/// Answers every rejection of the api as an `ErrorResponse`,
+/// a path that matches nothing is left to the web app
 pub async fn handle_rejection(
     err: warp::reject::Rejection,
 ) -> Result<impl warp::Reply, warp::reject::Rejection> {
-    let code;
-    let message;
-
     if err.is_not_found() {
         return Err(warp::reject());
+    }
+
+    let res = if let Some(e) = err.find::<Error>() {
+        e.response()
+    } else if let Some(e) = err.find::<ExtensionError>() {
+        Error::Extension(e.clone()).response()
     } else if let Some(ExpiredOrInvalidToken) = err.find() {
-        code = warp::http::StatusCode::UNAUTHORIZED;
-        message = "Unauthorized".to_string();
+        error_response(ErrorCode::Unauthorized, "expired or invalid token")
     } else if let Some(e) = err.find::<warp::reject::MissingHeader>() {
-        code = warp::http::StatusCode::UNAUTHORIZED;
-        message = format!("Mission {} header", e.clone());
+        match e.name() {
+            "authorization" => error_response(ErrorCode::Unauthorized, &e.to_string()),
+            _ => error_response(ErrorCode::BadRequest, &e.to_string()),
+        }
+    } else if let Some(e) = err.find::<warp::reject::InvalidHeader>() {
+        error_response(ErrorCode::BadRequest, &e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        error_response(ErrorCode::BadRequest, &e.to_string())
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        error_response(ErrorCode::BadRequest, &e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::LengthRequired>() {
+        error_response(ErrorCode::BadRequest, &e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::PayloadTooLarge>() {
+        error_response(ErrorCode::BadRequest, &e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::UnsupportedMediaType>() {
+        error_response(ErrorCode::BadRequest, &e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::MethodNotAllowed>() {
+        error_response(ErrorCode::MethodNotAllowed, &e.to_string())
+    } else {
+        error_response(
+            ErrorCode::Internal,
+            &format!("unhandled rejection: {:?}", err),
+        )
+    };
+
+    let code = error::status(res.code);
+    if code.is_server_error() {
+        error!("code: {}, message: {}", code, &res.message);
     } else {
-        code = warp::http::StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Unhandled".to_string();
+        debug!("code: {}, message: {}", code, &res.message);
     }
 
-    error!("code: {}, message: {:?}", code.clone(), err);
+    Ok(warp::reply::with_status(warp::reply::json(&res), code))
+}
 
-    Ok(warp::reply::with_status(
-        warp::reply::json(&json!({ "message": message })),
+fn error_response(code: ErrorCode, message: &str) -> ErrorResponse {
+    ErrorResponse {
         code,
-    ))
+        message: message.to_string(),
+        source: None,
+        status: "error".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::auth::Auth;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    fn login_token(secret: &str) -> String {
+        let claims = Claims {
+            sub: "user".to_string(),
+            role: "READER".to_string(),
+            exp: 10000000000,
+            telegram_chat_id: None,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    async fn username(req: warp::test::RequestBuilder) -> Option<String> {
+        req.filter(&with_image_authorization("secret".to_string()))
+            .await
+            .ok()
+    }
+
+    #[tokio::test]
+    async fn image_authorization() {
+        let login = login_token("secret");
+        let image = Auth::image_token("secret", "user").unwrap();
+
+        let header = warp::test::request()
+            .path("/api/page/1")
+            .header("authorization", &login);
+        assert_eq!(username(header).await.as_deref(), Some("user"));
+        let query = warp::test::request().path(&format!("/api/page/1?token={}", image));
+        assert_eq!(username(query).await.as_deref(), Some("user"));
+
+        // login tokens stay out of urls, image tokens can't be used to log in
+        let query = warp::test::request().path(&format!("/api/page/1?token={}", login));
+        assert_eq!(username(query).await, None);
+        let header = warp::test::request()
+            .path("/api/page/1")
+            .header("authorization", &image);
+        assert_eq!(username(header).await, None);
+        assert!(Auth::validate("secret".to_string(), image).is_none());
+
+        let other = Auth::image_token("other secret", "user").unwrap();
+        let query = warp::test::request().path(&format!("/api/page/1?token={}", other));
+        assert_eq!(username(query).await, None);
+        assert_eq!(
+            username(warp::test::request().path("/api/page/1")).await,
+            None
+        );
+    }
 }
@@ -0,0 +1,219 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use warp::http::StatusCode;
+use warp::Filter;
+
+use crate::auth::{auth::Auth, Claims};
+use crate::handlers::opds;
+use crate::opds::{FeedAuth, FeedQuery, Opds};
+
+/// OPDS readers can't send the bearer token of the web app,
+/// so feeds also accept basic auth or the feed token of the user as `?token=`
+#[derive(Debug)]
+pub struct FeedUnauthorized;
+
+impl warp::reject::Reject for FeedUnauthorized {}
+
+/// How long accepted basic auth credentials are remembered
+const CREDENTIALS_TTL: Duration = Duration::from_secs(60);
+
+/// Checks basic auth of readers. Readers send it with every page, so accepted credentials
+/// are remembered for a while instead of hashing the password on every request,
+/// keyed by a hash of the header so the password isn't kept in memory.
+#[derive(Clone)]
+pub struct FeedAuthenticator {
+    auth: Auth,
+    accepted: Arc<Mutex<HashMap<String, (Claims, Instant)>>>,
+}
+
+impl FeedAuthenticator {
+    pub fn new(auth: Auth) -> Self {
+        Self {
+            auth,
+            accepted: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The password is checked on the blocking thread pool, hashing it takes a while
+    async fn basic_auth(&self, header: &str) -> Option<Claims> {
+        let encoded = header.strip_prefix("Basic ")?;
+        let key = format!("{:x}", Sha256::digest(header.as_bytes()));
+        if let Some((claim, at)) = self
+            .accepted
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+        {
+            if at.elapsed() < CREDENTIALS_TTL {
+                return Some(claim.clone());
+            }
+        }
+
+        let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+        let mut credentials = decoded.splitn(2, ':');
+        let username = credentials.next()?.to_string();
+        let password = credentials.next()?.to_string();
+        let auth = self.auth.clone();
+        let claim = tokio::task::spawn_blocking(move || auth.check(&username, &password))
+            .await
+            .ok()??;
+
+        let mut accepted = self.accepted.lock().unwrap_or_else(PoisonError::into_inner);
+        accepted.retain(|_, (_, at)| at.elapsed() < CREDENTIALS_TTL);
+        accepted.insert(key, (claim.clone(), Instant::now()));
+        Some(claim)
+    }
+}
+
+pub fn opds(
+    secret: String,
+    auth: Auth,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let auth = FeedAuthenticator::new(auth);
+    root(secret.clone(), auth.clone(), opds.clone())
+        .or(favorites(secret.clone(), auth.clone(), opds.clone()))
+        .or(manga(secret.clone(), auth.clone(), opds.clone()))
+        .or(updates(secret.clone(), auth.clone(), opds.clone()))
+        .or(history(secret.clone(), auth.clone(), opds.clone()))
+        .or(download(secret.clone(), auth.clone(), opds.clone()))
+        .or(page(secret, auth, opds))
+        .recover(unauthorized)
+}
+
+pub fn root(
+    secret: String,
+    auth: FeedAuthenticator,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("opds")
+        .and(warp::get())
+        .and(with_feed_auth(secret, auth))
+        .and(with_opds(opds))
+        .and_then(opds::root)
+}
+
+pub fn favorites(
+    secret: String,
+    auth: FeedAuthenticator,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("opds" / "favorites")
+        .and(warp::get())
+        .and(with_feed_auth(secret, auth))
+        .and(with_opds(opds))
+        .and_then(opds::favorites)
+}
+
+pub fn manga(
+    secret: String,
+    auth: FeedAuthenticator,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("opds" / "manga" / i32)
+        .and(warp::get())
+        .and(with_feed_auth(secret, auth))
+        .and(with_opds(opds))
+        .and_then(opds::manga)
+}
+
+pub fn updates(
+    secret: String,
+    auth: FeedAuthenticator,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("opds" / "updates")
+        .and(warp::get())
+        .and(with_feed_auth(secret, auth))
+        .and(warp::query::<FeedQuery>())
+        .and(with_opds(opds))
+        .and_then(opds::updates)
+}
+
+pub fn history(
+    secret: String,
+    auth: FeedAuthenticator,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("opds" / "history")
+        .and(warp::get())
+        .and(with_feed_auth(secret, auth))
+        .and(warp::query::<FeedQuery>())
+        .and(with_opds(opds))
+        .and_then(opds::history)
+}
+
+pub fn download(
+    secret: String,
+    auth: FeedAuthenticator,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("opds" / "chapter" / i32 / "download")
+        .and(warp::get())
+        .and(with_feed_auth(secret, auth))
+        .and(with_opds(opds))
+        .and_then(opds::download)
+}
+
+pub fn page(
+    secret: String,
+    auth: FeedAuthenticator,
+    opds: Opds,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("opds" / "chapter" / i32 / "page" / i64)
+        .and(warp::get())
+        .and(with_feed_auth(secret, auth))
+        .and(with_opds(opds))
+        .and_then(opds::page)
+}
+
+/// Accepts basic auth, the bearer token of the web app, or `?token=`
+fn with_feed_auth(
+    secret: String,
+    auth: FeedAuthenticator,
+) -> impl Filter<Extract = (FeedAuth,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<FeedQuery>())
+        .and_then(move |header: Option<String>, query: FeedQuery| {
+            let secret = secret.clone();
+            let auth = auth.clone();
+            async move {
+                let claim = match (header, query.token) {
+                    (Some(header), _) => match auth.basic_auth(&header).await {
+                        Some(claim) => Some(claim),
+                        None => Auth::validate(secret, header),
+                    }
+                    .map(|claim| (claim, None)),
+                    (None, Some(token)) => auth
+                        .auth
+                        .validate_feed_token(&token)
+                        .map(|claim| (claim, Some(token))),
+                    (None, None) => None,
+                };
+                match claim {
+                    Some((claim, token)) => Ok(FeedAuth { claim, token }),
+                    None => Err(warp::reject::custom(FeedUnauthorized)),
+                }
+            }
+        })
+}
+
+/// Asks the reader for credentials instead of answering with the json error of the api
+async fn unauthorized(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    if err.find::<FeedUnauthorized>().is_some() {
+        return Ok(warp::reply::with_header(
+            warp::reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED),
+            "WWW-Authenticate",
+            r#"Basic realm="tanoshi""#,
+        ));
+    }
+    Err(err)
+}
+
+fn with_opds(
+    opds: Opds,
+) -> impl Filter<Extract = (Opds,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || opds.clone())
+}
@@ -1,5 +1,6 @@
-use crate::filters::with_authorization;
+use crate::filters::{with_admin_role, with_authorization};
 use crate::handlers::updates;
+use crate::update::scheduler::Scheduler;
 use crate::update::{Update, UpdateParam};
 
 use warp::Filter;
@@ -7,8 +8,11 @@ use warp::Filter;
 pub fn updates(
     secret: String,
     update: Update,
+    scheduler: Scheduler,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     get_updates(secret.clone(), update.clone())
+        .or(get_library_update(secret.clone(), scheduler.clone()))
+        .or(run_library_update(secret, scheduler))
 }
 
 pub fn get_updates(
@@ -23,8 +27,36 @@ pub fn get_updates(
         .and_then(updates::get_updates)
 }
 
+pub fn get_library_update(
+    secret: String,
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "updates" / "library")
+        .and(warp::get())
+        .and(with_admin_role(secret))
+        .and(with_scheduler(scheduler))
+        .and_then(updates::get_library_update)
+}
+
+pub fn run_library_update(
+    secret: String,
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "updates" / "library")
+        .and(warp::post())
+        .and(with_admin_role(secret))
+        .and(with_scheduler(scheduler))
+        .and_then(updates::run_library_update)
+}
+
 fn with_update(
     update: Update,
 ) -> impl Filter<Extract = (Update,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || update.clone())
 }
+
+fn with_scheduler(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = (Scheduler,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || scheduler.clone())
+}
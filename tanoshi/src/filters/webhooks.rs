@@ -0,0 +1,96 @@
+use crate::filters::with_authorization;
+use crate::handlers::webhooks as webhooks_handler;
+use crate::webhook::Webhooks;
+
+use tanoshi_lib::rest::WebhookRequest;
+use warp::Filter;
+
+pub fn webhooks(
+    secret: String,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    get_webhooks(secret.clone(), webhooks.clone())
+        .or(add_webhook(secret.clone(), webhooks.clone()))
+        .or(update_webhook(secret.clone(), webhooks.clone()))
+        .or(delete_webhook(secret.clone(), webhooks.clone()))
+        .or(test_webhook(secret.clone(), webhooks.clone()))
+        .or(get_deliveries(secret, webhooks))
+}
+
+fn get_webhooks(
+    secret: String,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webhooks")
+        .and(warp::get())
+        .and(with_authorization(secret))
+        .and(with_webhooks(webhooks))
+        .and_then(webhooks_handler::get_webhooks)
+}
+
+fn add_webhook(
+    secret: String,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webhooks")
+        .and(warp::post())
+        .and(with_authorization(secret))
+        .and(json_body())
+        .and(with_webhooks(webhooks))
+        .and_then(webhooks_handler::add_webhook)
+}
+
+fn update_webhook(
+    secret: String,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webhooks" / i32)
+        .and(warp::put())
+        .and(with_authorization(secret))
+        .and(json_body())
+        .and(with_webhooks(webhooks))
+        .and_then(webhooks_handler::update_webhook)
+}
+
+fn delete_webhook(
+    secret: String,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webhooks" / i32)
+        .and(warp::delete())
+        .and(with_authorization(secret))
+        .and(with_webhooks(webhooks))
+        .and_then(webhooks_handler::delete_webhook)
+}
+
+fn test_webhook(
+    secret: String,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webhooks" / i32 / "test")
+        .and(warp::post())
+        .and(with_authorization(secret))
+        .and(with_webhooks(webhooks))
+        .and_then(webhooks_handler::test_webhook)
+}
+
+fn get_deliveries(
+    secret: String,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "webhooks" / i32 / "deliveries")
+        .and(warp::get())
+        .and(with_authorization(secret))
+        .and(with_webhooks(webhooks))
+        .and_then(webhooks_handler::get_deliveries)
+}
+
+fn json_body() -> impl Filter<Extract = (WebhookRequest,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+fn with_webhooks(
+    webhooks: Webhooks,
+) -> impl Filter<Extract = (Webhooks,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || webhooks.clone())
+}
@@ -1,36 +1,28 @@
 use crate::auth::{auth::Auth, Claims, User};
+use crate::handlers::reject;
 use serde_json::json;
 
-use std::convert::Infallible;
+use warp::Rejection;
 
 pub async fn register(
     user: User,
     _claims: Claims,
     auth: Auth,
-) -> Result<impl warp::Reply, Infallible> {
-    let res = auth.register(user).await;
+) -> Result<impl warp::Reply, Rejection> {
+    let res = auth.register(user).await.map_err(reject)?;
     Ok(warp::reply::with_status(
         warp::reply::json(&res),
         warp::http::StatusCode::CREATED,
     ))
 }
 
-pub async fn login(user: User, token: String, auth: Auth) -> Result<impl warp::Reply, Infallible> {
-    let res = auth.login(token, user).await;
-    if res.status == "failed" {
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&res),
-            warp::http::StatusCode::UNAUTHORIZED,
-        ));
-    }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&res),
-        warp::http::StatusCode::OK,
-    ))
+pub async fn login(user: User, token: String, auth: Auth) -> Result<impl warp::Reply, Rejection> {
+    let res = auth.login(token, user).await.map_err(reject)?;
+    Ok(warp::reply::json(&res))
 }
 
-pub async fn user_list(_claims: Claims, auth: Auth) -> Result<impl warp::Reply, Infallible> {
-    let res = auth.user_list().await;
+pub async fn user_list(_claims: Claims, auth: Auth) -> Result<impl warp::Reply, Rejection> {
+    let res = auth.user_list().await.map_err(reject)?;
     Ok(warp::reply::json(&json!({ "users": res })))
 }
 
@@ -38,36 +30,67 @@ pub async fn modify_user_role(
     user: User,
     _claims: Claims,
     auth: Auth,
-) -> Result<impl warp::Reply, Infallible> {
-    match auth.modify_user_role(user).await {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply(),
-            warp::http::status::StatusCode::OK,
-        )),
-        Err(_) => Ok(warp::reply::with_status(
-            warp::reply(),
-            warp::http::status::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
-    }
+) -> Result<impl warp::Reply, Rejection> {
+    auth.modify_user_role(user).await.map_err(reject)?;
+    Ok(warp::reply())
 }
 
 pub async fn change_password(
     password: String,
     claims: Claims,
     auth: Auth,
-) -> Result<impl warp::Reply, Infallible> {
-    match auth.change_password(claims.sub, password).await {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply(),
-            warp::http::status::StatusCode::OK,
-        )),
-        Err(_) => Ok(warp::reply::with_status(
-            warp::reply(),
-            warp::http::status::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
-    }
+) -> Result<impl warp::Reply, Rejection> {
+    auth.change_password(claims.sub, password)
+        .await
+        .map_err(reject)?;
+    Ok(warp::reply())
+}
+
+pub async fn feed_token(claims: Claims, auth: Auth) -> Result<impl warp::Reply, Rejection> {
+    let token = auth.feed_token(claims.sub).await.map_err(reject)?;
+    Ok(warp::reply::json(
+        &json!({ "token": token, "status": "success" }),
+    ))
+}
+
+pub async fn reset_feed_token(claims: Claims, auth: Auth) -> Result<impl warp::Reply, Rejection> {
+    let token = auth.reset_feed_token(claims.sub).await.map_err(reject)?;
+    Ok(warp::reply::json(
+        &json!({ "token": token, "status": "success" }),
+    ))
+}
+
+pub async fn telegram_link_token(
+    claims: Claims,
+    auth: Auth,
+) -> Result<impl warp::Reply, Rejection> {
+    let token = auth.telegram_link_token(claims.sub).await.map_err(reject)?;
+    Ok(warp::reply::json(&json!({
+        "token": token,
+        "command": format!("/start {}", token),
+        "status": "success"
+    })))
+}
+
+pub async fn unlink_telegram_chat(
+    claims: Claims,
+    auth: Auth,
+) -> Result<impl warp::Reply, Rejection> {
+    auth.unlink_telegram_chat(claims.sub)
+        .await
+        .map_err(reject)?;
+    Ok(warp::reply())
+}
+
+pub async fn get_claims(claims: Claims, auth: Auth) -> Result<impl warp::Reply, Rejection> {
+    let claims = auth.refresh_claims(claims).await.map_err(reject)?;
+    Ok(warp::reply::json(&claims))
 }
 
 pub fn validate(secret: String, token: String) -> Option<Claims> {
     Auth::validate(secret, token)
 }
+
+pub fn validate_image_token(secret: &str, token: &str) -> Option<String> {
+    Auth::validate_image_token(secret, token)
+}
@@ -0,0 +1,35 @@
+use crate::auth::Claims;
+use crate::download::{DownloadParam, Downloads};
+use warp::Rejection;
+
+pub async fn get_downloads(
+    claim: Claims,
+    downloads: Downloads,
+) -> Result<impl warp::Reply, Rejection> {
+    downloads.get_downloads(claim).await
+}
+
+pub async fn download_chapter(
+    chapter_id: i32,
+    claim: Claims,
+    downloads: Downloads,
+) -> Result<impl warp::Reply, Rejection> {
+    downloads.download_chapter(claim, chapter_id).await
+}
+
+pub async fn download_manga(
+    manga_id: i32,
+    claim: Claims,
+    param: DownloadParam,
+    downloads: Downloads,
+) -> Result<impl warp::Reply, Rejection> {
+    downloads.download_manga(claim, manga_id, param).await
+}
+
+pub async fn delete_download(
+    chapter_id: i32,
+    claim: Claims,
+    downloads: Downloads,
+) -> Result<impl warp::Reply, Rejection> {
+    downloads.delete_download(claim, chapter_id).await
+}
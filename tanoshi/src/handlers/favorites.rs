@@ -1,11 +1,12 @@
 use crate::auth::Claims;
 use crate::favorites::favorites::Favorites;
+use crate::handlers::reject;
 
-use std::convert::Infallible;
 use tanoshi_lib::manga::Params;
+use warp::Rejection;
 
-pub async fn get_favorites(claim: Claims, params: Params,fav: Favorites) -> Result<impl warp::Reply, Infallible> {
-    let res = fav.get_favorites(params, claim.sub).await;
+pub async fn get_favorites(claim: Claims, params: Params,fav: Favorites) -> Result<impl warp::Reply, Rejection> {
+    let res = fav.get_favorites(params, claim.sub).await.map_err(reject)?;
     Ok(warp::reply::json(&res))
 }
 
@@ -13,8 +14,8 @@ pub async fn add_favorites(
     manga_id: i32,
     claim: Claims,
     fav: Favorites,
-) -> Result<impl warp::Reply, Infallible> {
-    let res = fav.add_favorite(claim.sub, manga_id).await;
+) -> Result<impl warp::Reply, Rejection> {
+    let res = fav.add_favorite(claim.sub, manga_id).await.map_err(reject)?;
     Ok(warp::reply::json(&res))
 }
 
@@ -22,7 +23,7 @@ pub async fn remove_favorites(
     manga_id: i32,
     claim: Claims,
     fav: Favorites,
-) -> Result<impl warp::Reply, Infallible> {
-    let res = fav.remove_favorites(claim.sub, manga_id).await;
+) -> Result<impl warp::Reply, Rejection> {
+    let res = fav.remove_favorites(claim.sub, manga_id).await.map_err(reject)?;
     Ok(warp::reply::json(&res))
 }
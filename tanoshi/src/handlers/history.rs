@@ -1,13 +1,13 @@
 use crate::auth::Claims;
 use crate::history::{History, HistoryParam};
-use std::convert::Infallible;
 use tanoshi_lib::rest::HistoryRequest;
+use warp::Rejection;
 
 pub async fn get_history(
     claim: Claims,
     param: HistoryParam,
     history: History,
-) -> Result<impl warp::Reply, Infallible> {
+) -> Result<impl warp::Reply, Rejection> {
     history.get_history(claim, param).await
 }
 
@@ -15,6 +15,6 @@ pub async fn add_history(
     claim: Claims,
     request: HistoryRequest,
     history: History,
-) -> Result<impl warp::Reply, Infallible> {
+) -> Result<impl warp::Reply, Rejection> {
     history.add_history(claim, request).await
 }
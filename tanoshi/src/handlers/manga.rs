@@ -1,10 +1,12 @@
-use tanoshi_lib::manga::{GetParams, Params, SourceLogin};
+use tanoshi_lib::manga::{GetParams, Params, SearchParams, SourceLogin};
 use warp::Rejection;
 
-use crate::auth::Claims;
+use crate::auth::{auth::Auth, Claims};
+use crate::error::Error;
+use crate::extension::local::{CoverQuery, Local};
 use crate::extension::manga::Manga;
 
-use crate::handlers::TransactionReject;
+use crate::handlers::reject;
 
 pub async fn list_sources(manga: Manga) -> Result<impl warp::Reply, Rejection> {
     manga.list_sources().await
@@ -12,24 +14,83 @@ pub async fn list_sources(manga: Manga) -> Result<impl warp::Reply, Rejection> {
 
 pub async fn install_source(
     name: String,
+    _claims: Claims,
     plugin_path: String,
     manga: Manga,
 ) -> Result<impl warp::Reply, Rejection> {
     manga.install_source(name, plugin_path).await
 }
 
+pub async fn update_sources(
+    _claims: Claims,
+    plugin_path: String,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    manga.update_sources(plugin_path).await
+}
+
+pub async fn uninstall_source(
+    name: String,
+    _claims: Claims,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    manga.uninstall_source(name).await
+}
+
+pub async fn get_source_config(
+    source: String,
+    _claims: Claims,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    match manga.get_source_config(source).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+pub async fn update_source_config(
+    source: String,
+    _claims: Claims,
+    values: serde_json::Value,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    match manga.update_source_config(source, values).await {
+        Ok(_) => Ok(warp::reply()),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+pub async fn get_filters(
+    source: String,
+    _claim: Claims,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    match manga.get_filters(source).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(reject(e)),
+    }
+}
+
 pub async fn list_mangas(
     source: String,
     claim: Claims,
-    source_auth: String,
     param: Params,
     manga: Manga,
 ) -> Result<impl warp::Reply, Rejection> {
-    match manga.list_mangas(source, claim, source_auth, param).await {
+    match manga.list_mangas(source, claim, param).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+pub async fn search(
+    claim: Claims,
+    param: SearchParams,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    match manga.search(claim, param.keyword).await {
         Ok(res) => Ok(warp::reply::json(&res)),
-        Err(e) => Err(warp::reject::custom(TransactionReject {
-            message: e.to_string(),
-        })),
+        Err(e) => Err(reject(e)),
     }
 }
 
@@ -40,9 +101,7 @@ pub async fn get_manga_info(
 ) -> Result<impl warp::Reply, Rejection> {
     match manga.get_manga_info(manga_id, claim).await {
         Ok(res) => Ok(warp::reply::json(&res)),
-        Err(e) => Err(warp::reject::custom(TransactionReject {
-            message: e.to_string(),
-        })),
+        Err(e) => Err(reject(e)),
     }
 }
 
@@ -54,47 +113,90 @@ pub async fn get_chapters(
 ) -> Result<impl warp::Reply, Rejection> {
     match manga.get_chapters(manga_id, claim, param).await {
         Ok(res) => Ok(warp::reply::json(&res)),
-        Err(e) => Err(warp::reject::custom(TransactionReject {
-            message: e.to_string(),
-        })),
+        Err(e) => Err(reject(e)),
     }
 }
 
 pub async fn get_pages(
     chapter_id: i32,
+    claim: Claims,
     param: GetParams,
+    secret: String,
     manga: Manga,
 ) -> Result<impl warp::Reply, Rejection> {
-    match manga.get_pages(chapter_id, param).await {
-        Ok(pages) => Ok(warp::reply::json(&pages)),
-        Err(e) => Err(warp::reject::custom(TransactionReject {
-            message: e.to_string(),
-        })),
-    }
+    let mut res = manga
+        .get_pages(chapter_id, &claim.sub, param)
+        .await
+        .map_err(reject)?;
+    res.pages = with_image_token(res.pages, &secret, &claim.sub)?;
+    Ok(warp::reply::json(&res))
+}
+
+pub async fn proxy_image(
+    page_id: i32,
+    username: String,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    manga.proxy_image(page_id, &username).await
 }
 
-pub async fn proxy_image(page_id: i32, manga: Manga) -> Result<impl warp::Reply, Rejection> {
-    manga.proxy_image(page_id).await
+pub async fn local_cover(query: CoverQuery, local: Local) -> Result<impl warp::Reply, Rejection> {
+    let cover = tokio::task::spawn_blocking(move || local.cover(&query))
+        .await
+        .map_err(|e| reject(Error::Internal(e.to_string())))?;
+    let (mime, bytes) =
+        cover.ok_or_else(|| reject(Error::NotFound("cover not found".to_string())))?;
+
+    warp::http::Response::builder()
+        .header("Content-Type", mime)
+        .header("Content-Length", bytes.len())
+        .body(bytes)
+        .map_err(|e| reject(Error::Internal(e.to_string())))
 }
 
 pub async fn source_login(
     source: String,
+    claim: Claims,
     login_info: SourceLogin,
     manga: Manga,
 ) -> Result<impl warp::Reply, Rejection> {
-    manga.source_login(source, login_info).await
+    manga.source_login(source, claim, login_info).await
+}
+
+pub async fn source_logout(
+    source: String,
+    claim: Claims,
+    manga: Manga,
+) -> Result<impl warp::Reply, Rejection> {
+    manga.source_logout(source, claim).await
+}
+
+pub async fn source_sessions(claim: Claims, manga: Manga) -> Result<impl warp::Reply, Rejection> {
+    manga.source_sessions(claim).await
 }
 
 pub async fn read(
     chapter_id: i32,
     claim: Claims,
     param: GetParams,
+    secret: String,
     manga: Manga,
 ) -> Result<impl warp::Reply, Rejection> {
-    match manga.read(chapter_id, claim, param).await {
-        Ok(res) => Ok(warp::reply::json(&res)),
-        Err(e) => Err(warp::reject::custom(TransactionReject {
-            message: e.to_string(),
-        })),
-    }
+    let username = claim.sub.clone();
+    let mut res = manga.read(chapter_id, claim, param).await.map_err(reject)?;
+    res.pages = with_image_token(res.pages, &secret, &username)?;
+    Ok(warp::reply::json(&res))
+}
+
+/// Adds an image token of the user to page urls, `<img>` can't send the authorization header
+fn with_image_token(
+    pages: Vec<String>,
+    secret: &str,
+    username: &str,
+) -> Result<Vec<String>, Rejection> {
+    let token = Auth::image_token(secret, username).map_err(reject)?;
+    Ok(pages
+        .into_iter()
+        .map(|page| format!("{}?token={}", page, token))
+        .collect())
 }
@@ -1,12 +1,16 @@
+use crate::error::Error;
+
 pub mod auth;
+pub mod downloads;
 pub mod favorites;
 pub mod history;
 pub mod manga;
+pub mod opds;
 pub mod updates;
+pub mod webhooks;
 
-#[derive(Debug)]
-pub struct TransactionReject {
-    pub message: String,
-}
-
-impl warp::reject::Reject for TransactionReject{}
\ No newline at end of file
+/// Reject with the typed error, so the client knows whether a source failed,
+/// something was not found or the request was wrong
+pub fn reject(e: impl Into<Error>) -> warp::Rejection {
+    warp::reject::custom(e.into())
+}
\ No newline at end of file
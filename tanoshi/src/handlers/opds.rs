@@ -0,0 +1,128 @@
+use warp::http::{Response, StatusCode};
+use warp::hyper::Body;
+use warp::{Rejection, Reply};
+
+use crate::handlers::reject;
+use crate::opds::feed::{self, Feed};
+use crate::opds::{FeedAuth, FeedQuery, Opds};
+
+pub async fn root(auth: FeedAuth, opds: Opds) -> Result<impl warp::Reply, Rejection> {
+    Ok(feed_reply(opds.root(&auth), feed::NAVIGATION))
+}
+
+pub async fn favorites(auth: FeedAuth, opds: Opds) -> Result<impl warp::Reply, Rejection> {
+    match opds.favorites(&auth).await {
+        Ok(feed) => Ok(feed_reply(feed, feed::NAVIGATION)),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+pub async fn manga(
+    manga_id: i32,
+    auth: FeedAuth,
+    opds: Opds,
+) -> Result<impl warp::Reply, Rejection> {
+    match opds.manga(manga_id, &auth).await {
+        Ok(feed) => Ok(feed_reply(feed, feed::ACQUISITION)),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+pub async fn updates(
+    auth: FeedAuth,
+    query: FeedQuery,
+    opds: Opds,
+) -> Result<impl warp::Reply, Rejection> {
+    match opds.updates(&auth, query.page.unwrap_or(1).max(1)) {
+        Ok(feed) => Ok(feed_reply(feed, feed::ACQUISITION)),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+pub async fn history(
+    auth: FeedAuth,
+    query: FeedQuery,
+    opds: Opds,
+) -> Result<impl warp::Reply, Rejection> {
+    match opds.history(&auth, query.page.unwrap_or(1).max(1)) {
+        Ok(feed) => Ok(feed_reply(feed, feed::ACQUISITION)),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+pub async fn download(
+    chapter_id: i32,
+    auth: FeedAuth,
+    opds: Opds,
+) -> Result<impl warp::Reply, Rejection> {
+    let (name, cbz) = match opds.download(chapter_id, &auth.claim).await {
+        Ok(Some(cbz)) => cbz,
+        Ok(None) => return Ok(not_found("chapter not found")),
+        Err(e) => return Err(reject(e)),
+    };
+
+    Ok(Response::builder()
+        .header("Content-Type", feed::CBZ)
+        .header("Content-Disposition", content_disposition(&name))
+        .body(Body::wrap_stream(cbz))
+        .into_response())
+}
+
+pub async fn page(
+    chapter_id: i32,
+    page: i64,
+    auth: FeedAuth,
+    opds: Opds,
+) -> Result<impl warp::Reply, Rejection> {
+    let (mime, bytes) = match opds.page(chapter_id, page, &auth.claim).await {
+        Ok(Some(image)) => image,
+        Ok(None) => return Ok(not_found("page not found")),
+        Err(e) => return Err(reject(e)),
+    };
+
+    Ok(Response::builder()
+        .header("Content-Type", mime)
+        .header("Cache-Control", "max-age=315360000")
+        .body(bytes)
+        .into_response())
+}
+
+fn feed_reply(feed: Feed, kind: &str) -> warp::reply::Response {
+    Response::builder()
+        .header("Content-Type", kind)
+        .body(feed.to_xml())
+        .into_response()
+}
+
+/// Unlike the json api, a missing chapter is answered here instead of falling through to the web app
+fn not_found(message: &'static str) -> warp::reply::Response {
+    warp::reply::with_status(message, StatusCode::NOT_FOUND).into_response()
+}
+
+/// Header values are ascii only, the full name is sent percent encoded as `filename*`
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded: String = name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+    format!(
+        r#"attachment; filename="{}"; filename*=UTF-8''{}"#,
+        fallback, encoded
+    )
+}
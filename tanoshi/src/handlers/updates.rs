@@ -1,4 +1,5 @@
 use crate::auth::Claims;
+use crate::update::scheduler::Scheduler;
 use crate::update::{Update, UpdateParam};
 use warp::Rejection;
 
@@ -9,3 +10,17 @@ pub async fn get_updates(
 ) -> Result<impl warp::Reply, Rejection> {
     update.get_updates(claim, param).await
 }
+
+pub async fn get_library_update(
+    _claim: Claims,
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, Rejection> {
+    scheduler.get_status().await
+}
+
+pub async fn run_library_update(
+    _claim: Claims,
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, Rejection> {
+    scheduler.run_update().await
+}
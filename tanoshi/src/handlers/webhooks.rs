@@ -0,0 +1,52 @@
+use crate::auth::Claims;
+use crate::webhook::Webhooks;
+use tanoshi_lib::rest::WebhookRequest;
+use warp::Rejection;
+
+pub async fn get_webhooks(
+    claim: Claims,
+    webhooks: Webhooks,
+) -> Result<impl warp::Reply, Rejection> {
+    webhooks.get_webhooks(claim).await
+}
+
+pub async fn add_webhook(
+    claim: Claims,
+    request: WebhookRequest,
+    webhooks: Webhooks,
+) -> Result<impl warp::Reply, Rejection> {
+    webhooks.add_webhook(claim, request).await
+}
+
+pub async fn update_webhook(
+    id: i32,
+    claim: Claims,
+    request: WebhookRequest,
+    webhooks: Webhooks,
+) -> Result<impl warp::Reply, Rejection> {
+    webhooks.update_webhook(claim, id, request).await
+}
+
+pub async fn delete_webhook(
+    id: i32,
+    claim: Claims,
+    webhooks: Webhooks,
+) -> Result<impl warp::Reply, Rejection> {
+    webhooks.delete_webhook(claim, id).await
+}
+
+pub async fn test_webhook(
+    id: i32,
+    claim: Claims,
+    webhooks: Webhooks,
+) -> Result<impl warp::Reply, Rejection> {
+    webhooks.test_webhook(claim, id).await
+}
+
+pub async fn get_deliveries(
+    id: i32,
+    claim: Claims,
+    webhooks: Webhooks,
+) -> Result<impl warp::Reply, Rejection> {
+    webhooks.get_deliveries(claim, id).await
+}
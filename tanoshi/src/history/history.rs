@@ -1,9 +1,11 @@
 use crate::auth::Claims;
+use crate::error::Error;
+use crate::handlers::reject;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
-use std::convert::Infallible;
 use tanoshi_lib::manga::History as HistoryModel;
 use tanoshi_lib::rest::{HistoryRequest, HistoryResponse};
+use warp::Rejection;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct HistoryParam {
@@ -20,21 +22,32 @@ impl History {
         Self { database_path }
     }
 
-    fn connect_db(&self) -> Connection {
-        Connection::open(self.database_path.clone()).unwrap()
+    fn connect_db(&self) -> Result<Connection, Error> {
+        Ok(Connection::open(self.database_path.clone())?)
     }
 
     pub async fn get_history(
         &self,
         claim: Claims,
         param: HistoryParam,
-    ) -> Result<impl warp::Reply, Infallible> {
-        let db = self.connect_db();
+    ) -> Result<impl warp::Reply, Rejection> {
+        let history = self.history(&claim.sub, param.page).map_err(reject)?;
+
+        let res = HistoryResponse {
+            history,
+            status: "success".to_string(),
+        };
+
+        Ok(warp::reply::json(&res))
+    }
+
+    /// Chapters a user read, last read first, 10 for each page
+    pub fn history(&self, username: &str, page: i32) -> Result<Vec<HistoryModel>, Error> {
+        let db = self.connect_db()?;
         let limit: i64 = 10;
-        let offset: i64 = (param.page as i64 * limit) - limit;
-        let mut stmt = db
-            .prepare(
-                r#"SELECT 
+        let offset: i64 = (page as i64 * limit) - limit;
+        let mut stmt = db.prepare(
+            r#"SELECT 
         manga.title AS title, 
         manga.thumbnail_url as thumbnail_url,
         COALESCE(chapter.number, chapter.volume) AS chapter, 
@@ -48,10 +61,9 @@ impl History {
         JOIN "user" ON "user".id = history.user_id
         WHERE "user".username = ?1 ORDER BY at DESC
         LIMIT ?2 OFFSET ?3"#,
-            )
-            .unwrap();
+        )?;
         let history = stmt
-            .query_map(params![claim.sub, limit, offset], |row| {
+            .query_map(params![username, limit, offset], |row| {
                 Ok(HistoryModel {
                     title: row.get(0)?,
                     thumbnail_url: row.get(1)?,
@@ -63,35 +75,19 @@ impl History {
                     days: None,
                     show_sep: None,
                 })
-            })
-            .unwrap()
+            })?
             .filter_map(|h| h.ok())
             .collect();
-
-        let res = HistoryResponse {
-            history,
-            status: "success".to_string(),
-        };
-
-        if res.status != "success" {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&res),
-                warp::http::status::StatusCode::BAD_REQUEST,
-            ));
-        }
-        Ok(warp::reply::with_status(
-            warp::reply::json(&res),
-            warp::http::status::StatusCode::OK,
-        ))
+        Ok(history)
     }
 
     pub async fn add_history(
         &self,
         claim: Claims,
         request: HistoryRequest,
-    ) -> Result<impl warp::Reply, Infallible> {
-        let db = self.connect_db();
-        let reply = match db.execute(
+    ) -> Result<impl warp::Reply, Rejection> {
+        let db = self.connect_db().map_err(reject)?;
+        db.execute(
             r#"INSERT INTO history(user_id, chapter_id, last_page, at)
             VALUES(
             (SELECT id FROM "user" WHERE username = ?1),
@@ -103,23 +99,12 @@ impl History {
             at = excluded.at,
             updated = CURRENT_TIMESTAMP"#,
             params![claim.sub, request.chapter_id, request.read, request.at],
-        ) {
-            Ok(_) => warp::reply::with_status(
-                warp::reply::json(&HistoryResponse {
-                    history: vec![],
-                    status: "success".to_string(),
-                }),
-                warp::http::status::StatusCode::OK,
-            ),
-            Err(e) => warp::reply::with_status(
-                warp::reply::json(&HistoryResponse {
-                    history: vec![],
-                    status: format!("failed, reason: {}", e.to_string()),
-                }),
-                warp::http::status::StatusCode::BAD_REQUEST,
-            ),
-        };
+        )
+        .map_err(reject)?;
 
-        Ok(reply)
+        Ok(warp::reply::json(&HistoryResponse {
+            history: vec![],
+            status: "success".to_string(),
+        }))
     }
 }
@@ -8,12 +8,17 @@ extern crate lazy_static;
 
 mod auth;
 mod config;
+mod download;
+mod error;
 mod extension;
 mod favorites;
 mod filters;
 mod handlers;
 mod history;
+mod opds;
+mod telegram;
 mod update;
+mod webhook;
 
 use anyhow::{anyhow, Result};
 use clap::Clap;
@@ -25,7 +30,16 @@ use warp::{http::header::HeaderValue, path::Tail, reply::Response, Filter, Rejec
 use config::Config;
 
 lazy_static! {
-    static ref QUERIES: Vec<&'static str> = vec![include_str!("../migration/1.sql"),];
+    static ref QUERIES: Vec<&'static str> = vec![
+        include_str!("../migration/1.sql"),
+        include_str!("../migration/2.sql"),
+        include_str!("../migration/3.sql"),
+        include_str!("../migration/4.sql"),
+        include_str!("../migration/5.sql"),
+        include_str!("../migration/6.sql"),
+        include_str!("../migration/7.sql"),
+        include_str!("../migration/8.sql"),
+    ];
 }
 
 #[derive(RustEmbed)]
@@ -114,8 +128,9 @@ async fn main() -> Result<()> {
                     username: "admin".to_string(),
                     password: Some("admin".to_string()),
                     role: "ADMIN".to_string(),
+                    telegram_chat_id: None,
                 })
-                .await;
+                .await?;
             }
 
             if let Err(e) = conn.pragma_update(
@@ -129,45 +144,73 @@ async fn main() -> Result<()> {
     }
 
     let secret = config.secret;
-    let plugin_config = config.plugin_config;
+    let plugin_config = extension::plugin_config::PluginConfig::new(
+        config.database_path.clone(),
+        config.plugin_config,
+    );
     let plugin_path = config.plugin_path.clone();
 
     info!("Plugins directory: {}", &plugin_path);
 
-    let extensions = Arc::new(RwLock::new(extension::Extensions::new()));
-
-    for entry in std::fs::read_dir(&plugin_path)?
-        .into_iter()
-        .filter(move |path| {
-            if let Ok(p) = path {
-                let ext = p
-                    .clone()
-                    .path()
-                    .extension()
-                    .unwrap_or("".as_ref())
-                    .to_owned();
-                if ext == "so" || ext == "dll" || ext == "dylib" {
-                    return true;
+    let http = extension::http::HttpClients::new(config.http.clone())?;
+    let extensions = Arc::new(RwLock::new(extension::Extensions::new(http.clone())));
+    let verifier =
+        extension::verify::Verifier::new(&config.trusted_keys, config.allow_unsigned_plugins)?;
+
+    let mut watcher = extension::watcher::Watcher::new(
+        extensions.clone(),
+        plugin_path.clone(),
+        plugin_config.clone(),
+        verifier.clone(),
+    );
+    if let Err(e) = watcher.load_all() {
+        error!("failed to load plugins from {}: {}", &plugin_path, e);
+    }
+    if config.plugin_watch_interval > 0 {
+        watcher.watch(std::time::Duration::from_secs(config.plugin_watch_interval));
+    }
+
+    let local = extension::local::Local::new(config.local_path.clone(), &secret);
+    if !config.local_path.is_empty() {
+        info!("Local library: {:?}", &config.local_path);
+        extensions
+            .write()
+            .unwrap()
+            .insert(extension::local::NAME.to_string(), Box::new(local.clone()));
+    }
+
+    if let Some(opds) = plugin_config.get(extension::opds::NAME) {
+        match extension::opds::feeds(&opds) {
+            Ok(feeds) => {
+                for feed in feeds {
+                    info!("OPDS feed {}: {}", &feed.name, &feed.url);
+                    let client = http.client(&feed.name);
+                    extensions.write().unwrap().insert(
+                        feed.name.clone(),
+                        Box::new(extension::opds::Opds::new(feed, client)),
+                    );
                 }
             }
-            return false;
-        })
-    {
-        let path = entry?.path();
-        let name = path
-            .file_stem()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or_default()
-            .to_string()
-            .replace("lib", "");
-        info!("load plugin from {:?}", path.clone());
-        let mut exts = extensions.write().unwrap();
-        unsafe {
-            match exts.load(path.to_str().unwrap().to_string(), plugin_config.get(&name)) {
-                Ok(_) => {}
-                Err(e) => error!("not a valid extensions {}", e),
+            Err(e) => error!("invalid OPDS feeds in plugin_config: {}", e),
+        }
+    }
+
+    if let Some(remote) = plugin_config.get(extension::remote::NAME) {
+        match extension::remote::servers(&remote) {
+            Ok(servers) => {
+                for server in servers {
+                    info!("Remote tanoshi {}: {}", &server.name, &server.url);
+                    let name = server.name.clone();
+                    let client = http.client(&name);
+                    match extension::remote::Remote::new(server, client) {
+                        Ok(remote) => {
+                            extensions.write().unwrap().insert(name, Box::new(remote));
+                        }
+                        Err(e) => error!("invalid url of remote tanoshi {}: {}", name, e),
+                    }
+                }
             }
+            Err(e) => error!("invalid remote servers in plugin_config: {}", e),
         }
     }
 
@@ -179,17 +222,77 @@ async fn main() -> Result<()> {
     let auth = auth::auth::Auth::new(config.database_path.clone());
     let auth_api = filters::auth::authentication(secret.clone(), auth.clone());
 
-    let manga = extension::manga::Manga::new(config.database_path.clone(), extensions.clone());
-    let manga_api = filters::manga::manga(secret.clone(), plugin_path.clone(), manga);
+    let guard = extension::guard::Guard::new(extensions.clone(), config.extension_limits.clone());
+    let sessions = extension::session::Sessions::new(config.database_path.clone(), &secret);
+
+    let manga = extension::manga::Manga::new(
+        config.database_path.clone(),
+        extensions.clone(),
+        config.repositories.clone(),
+        verifier,
+        plugin_config,
+        guard,
+        sessions,
+    );
+    let manga_api =
+        filters::manga::manga(secret.clone(), plugin_path.clone(), manga.clone(), local);
 
     let fav = favorites::Favorites::new(config.database_path.clone());
-    let fav_api = filters::favorites::favorites(secret.clone(), fav);
+    let fav_api = filters::favorites::favorites(secret.clone(), fav.clone());
 
     let history = history::History::new(config.database_path.clone());
     let history_api = filters::history::history(secret.clone(), history.clone());
 
     let update = update::Update::new(config.database_path.clone());
-    let updates_api = filters::updates::updates(secret.clone(), update.clone());
+
+    let telegram = match &config.telegram_token {
+        Some(token) => {
+            info!("Telegram bot: {}", &config.telegram_api);
+            let telegram = telegram::Telegram::new(
+                &config.telegram_api,
+                token,
+                config.base_url.clone(),
+                auth.clone(),
+                update.clone(),
+                history.clone(),
+            );
+            telegram.clone().listen();
+            Some(telegram)
+        }
+        None => None,
+    };
+
+    let webhooks = webhook::Webhooks::new(
+        config.database_path.clone(),
+        config.base_url.clone(),
+        config.webhook.clone(),
+    );
+    let webhooks_api = filters::webhooks::webhooks(secret.clone(), webhooks.clone());
+
+    let scheduler = update::scheduler::Scheduler::new(
+        config.database_path.clone(),
+        manga.clone(),
+        telegram,
+        webhooks,
+    );
+    if config.update_interval > 0 {
+        scheduler.schedule(std::time::Duration::from_secs(
+            config.update_interval * 60 * 60,
+        ));
+    }
+
+    info!("Downloads directory: {}", &config.download_path);
+    let downloads = download::Downloads::new(
+        config.database_path.clone(),
+        config.download_path.clone(),
+        manga.clone(),
+    );
+    downloads.start();
+    let downloads_api = filters::downloads::downloads(secret.clone(), downloads);
+
+    let opds = opds::Opds::new(config.database_path.clone(), manga, fav);
+    let opds_api = filters::opds::opds(secret.clone(), auth.clone(), opds);
+    let updates_api = filters::updates::updates(secret.clone(), update.clone(), scheduler);
 
     let version_check = warp::path!("version")
         .and(warp::get())
@@ -200,6 +303,9 @@ async fn main() -> Result<()> {
         .or(fav_api)
         .or(history_api)
         .or(updates_api)
+        .or(opds_api)
+        .or(webhooks_api)
+        .or(downloads_api)
         .or(version_check)
         .recover(filters::handle_rejection);
 
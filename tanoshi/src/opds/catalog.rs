@@ -0,0 +1,527 @@
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use tokio::sync::mpsc;
+
+use tanoshi_lib::manga::{GetParams, Params};
+
+use crate::auth::Claims;
+use crate::extension::manga::Manga;
+use crate::favorites::Favorites;
+use crate::opds::cbz::CbzWriter;
+use crate::opds::feed::{self, Entry, Feed, Link};
+
+const PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FeedQuery {
+    pub token: Option<String>,
+    pub page: Option<i64>,
+}
+
+/// User a feed is requested for, links of the feed carry the feed token when the client used it
+#[derive(Debug, Clone, Default)]
+pub struct FeedAuth {
+    pub claim: Claims,
+    pub token: Option<String>,
+}
+
+impl FeedAuth {
+    fn href(&self, path: &str) -> String {
+        match &self.token {
+            Some(token) if path.contains('?') => format!("{}&token={}", path, token),
+            Some(token) => format!("{}?token={}", path, token),
+            None => path.to_string(),
+        }
+    }
+}
+
+/// A chapter with the manga it belongs to, as listed in updates and history feeds
+struct ChapterRow {
+    id: i32,
+    manga_id: i32,
+    manga_title: String,
+    thumbnail_url: String,
+    vol: Option<String>,
+    no: Option<String>,
+    title: Option<String>,
+    at: Option<chrono::NaiveDateTime>,
+}
+
+/// Publishes favorites, updates and history of a user as OPDS catalogs
+#[derive(Clone)]
+pub struct Opds {
+    database_path: String,
+    manga: Manga,
+    favorites: Favorites,
+}
+
+impl Opds {
+    pub fn new(database_path: String, manga: Manga, favorites: Favorites) -> Self {
+        Self {
+            database_path,
+            manga,
+            favorites,
+        }
+    }
+
+    fn connect_db(&self) -> Result<Connection> {
+        Ok(Connection::open(self.database_path.clone())?)
+    }
+
+    pub fn root(&self, auth: &FeedAuth) -> Feed {
+        let entries = vec![
+            (
+                "favorites",
+                "Favorites",
+                "Mangas in your library",
+                feed::NAVIGATION,
+            ),
+            (
+                "updates",
+                "Updates",
+                "New chapters of your favorites",
+                feed::ACQUISITION,
+            ),
+            (
+                "history",
+                "History",
+                "Chapters you have read",
+                feed::ACQUISITION,
+            ),
+        ]
+        .into_iter()
+        .map(|(path, title, content, kind)| Entry {
+            id: format!("tanoshi:{}", path),
+            title: title.to_string(),
+            content: Some(content.to_string()),
+            links: vec![Link::new(
+                "subsection",
+                auth.href(&format!("/opds/{}", path)),
+                kind,
+            )],
+            ..Default::default()
+        })
+        .collect();
+
+        Feed {
+            id: "tanoshi:root".to_string(),
+            title: "Tanoshi".to_string(),
+            links: self.feed_links(auth, "/opds", feed::NAVIGATION),
+            entries,
+            ..Default::default()
+        }
+    }
+
+    pub async fn favorites(&self, auth: &FeedAuth) -> Result<Feed> {
+        let mangas = self
+            .favorites
+            .get_favorites(Params::default(), auth.claim.sub.clone())
+            .await?
+            .mangas;
+
+        let entries = mangas
+            .into_iter()
+            .map(|manga| {
+                let mut links = vec![Link::new(
+                    "subsection",
+                    auth.href(&format!("/opds/manga/{}", manga.id)),
+                    feed::ACQUISITION,
+                )];
+                links.extend(thumbnail_links(&manga.thumbnail_url));
+                Entry {
+                    id: format!("tanoshi:manga:{}", manga.id),
+                    title: manga.title,
+                    authors: manga.author,
+                    content: manga.description,
+                    links,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        Ok(Feed {
+            id: "tanoshi:favorites".to_string(),
+            title: "Favorites".to_string(),
+            links: self.feed_links(auth, "/opds/favorites", feed::NAVIGATION),
+            entries,
+            ..Default::default()
+        })
+    }
+
+    pub async fn manga(&self, manga_id: i32, auth: &FeedAuth) -> Result<Feed> {
+        let manga = self
+            .manga
+            .get_manga_info(manga_id, auth.claim.clone())
+            .await?
+            .manga;
+        let chapters = self
+            .manga
+            .get_chapters(manga_id, auth.claim.clone(), GetParams { refresh: None })
+            .await?
+            .chapters;
+        let counts = self.page_counts(manga_id, &auth.claim.sub)?;
+
+        let entries = chapters
+            .into_iter()
+            .map(|chapter| {
+                let row = ChapterRow {
+                    id: chapter.id,
+                    manga_id,
+                    manga_title: manga.title.clone(),
+                    thumbnail_url: manga.thumbnail_url.clone(),
+                    vol: chapter.vol,
+                    no: chapter.no,
+                    title: chapter.title,
+                    at: Some(chapter.uploaded),
+                };
+                self.chapter_entry(auth, row, counts.get(&chapter.id).copied(), false)
+            })
+            .collect();
+
+        let path = format!("/opds/manga/{}", manga_id);
+        let mut links = self.feed_links(auth, &path, feed::ACQUISITION);
+        links.extend(thumbnail_links(&manga.thumbnail_url));
+        Ok(Feed {
+            id: format!("tanoshi:manga:{}", manga_id),
+            title: manga.title,
+            links,
+            entries,
+            ..Default::default()
+        })
+    }
+
+    pub fn updates(&self, auth: &FeedAuth, page: i64) -> Result<Feed> {
+        let rows = self.chapters(
+            r#"SELECT chapter.id, manga.id, manga.title, manga.thumbnail_url,
+                chapter.volume, chapter.number, chapter.title, chapter.uploaded,
+                (SELECT COUNT(page.id) FROM page WHERE page.chapter_id = chapter.id)
+            FROM chapter
+            JOIN manga ON manga.id = chapter.manga_id
+            JOIN favorite ON favorite.manga_id = manga.id
+            JOIN "user" ON "user".id = favorite.user_id AND "user".id = chapter.user_id
+            WHERE "user".username = ?1 ORDER BY chapter.uploaded DESC
+            LIMIT ?2 OFFSET ?3"#,
+            &auth.claim.sub,
+            page,
+        )?;
+        Ok(self.chapters_feed(auth, "updates", "Updates", page, rows))
+    }
+
+    pub fn history(&self, auth: &FeedAuth, page: i64) -> Result<Feed> {
+        let rows = self.chapters(
+            r#"SELECT chapter.id, manga.id, manga.title, manga.thumbnail_url,
+                chapter.volume, chapter.number, chapter.title, history.at,
+                (SELECT COUNT(page.id) FROM page WHERE page.chapter_id = chapter.id)
+            FROM chapter
+            JOIN manga ON manga.id = chapter.manga_id
+            JOIN history ON history.chapter_id = chapter.id
+            JOIN "user" ON "user".id = history.user_id
+            WHERE "user".username = ?1 ORDER BY history.at DESC
+            LIMIT ?2 OFFSET ?3"#,
+            &auth.claim.sub,
+            page,
+        )?;
+        Ok(self.chapters_feed(auth, "history", "History", page, rows))
+    }
+
+    /// Returns the file name of a chapter packed as CBZ and its content,
+    /// sent one page at a time as the pages are read. `None` if the chapter doesn't belong to the user
+    pub async fn download(
+        &self,
+        chapter_id: i32,
+        claim: &Claims,
+    ) -> Result<Option<(String, mpsc::Receiver<Result<Vec<u8>>>)>> {
+        let name = match self.chapter_name(chapter_id, &claim.sub)? {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let page_ids = self.page_ids(chapter_id, &claim.sub).await?;
+
+        let (mut tx, rx) = mpsc::channel(1);
+        let opds = self.clone();
+        let username = claim.sub.clone();
+        tokio::spawn(async move {
+            let mut cbz = CbzWriter::new();
+            for (i, page_id) in page_ids.into_iter().enumerate() {
+                let chunk = match opds.cbz_page(&mut cbz, i, page_id, &username).await {
+                    Ok(Some(chunk)) => Ok(chunk),
+                    Ok(None) => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                // the reader stopped the download
+                if tx.send(chunk).await.is_err() || failed {
+                    return;
+                }
+            }
+            let _ = tx.send(Ok(cbz.finish())).await;
+        });
+
+        Ok(Some((format!("{}.cbz", name), rx)))
+    }
+
+    async fn cbz_page(
+        &self,
+        cbz: &mut CbzWriter,
+        index: usize,
+        page_id: i32,
+        username: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let (mime, bytes) = match self.manga.get_image(page_id, username).await? {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        let ext = match mime.as_str() {
+            "image/jpeg" => "jpg",
+            mime => mime_guess::get_mime_extensions_str(mime)
+                .and_then(|exts| exts.first())
+                .unwrap_or(&"jpg"),
+        };
+        Ok(Some(
+            cbz.file(&format!("{:03}.{}", index + 1, ext), &bytes)?,
+        ))
+    }
+
+    /// Returns the image of a page of a chapter, `page` is zero based as in OPDS-PSE,
+    /// `None` if the chapter doesn't belong to the user or has no such page
+    pub async fn page(
+        &self,
+        chapter_id: i32,
+        page: i64,
+        claim: &Claims,
+    ) -> Result<Option<(String, Vec<u8>)>> {
+        if self.chapter_name(chapter_id, &claim.sub)?.is_none() {
+            return Ok(None);
+        }
+        let page_ids = self.page_ids(chapter_id, &claim.sub).await?;
+        match usize::try_from(page)
+            .ok()
+            .and_then(|page| page_ids.get(page))
+        {
+            Some(page_id) => self.manga.get_image(*page_id, &claim.sub).await,
+            None => Ok(None),
+        }
+    }
+
+    fn feed_links(&self, auth: &FeedAuth, path: &str, kind: &str) -> Vec<Link> {
+        vec![
+            Link::new("self", auth.href(path), kind),
+            Link::new("start", auth.href("/opds"), feed::NAVIGATION),
+        ]
+    }
+
+    fn chapters(&self, query: &str, username: &str, page: i64) -> Result<Vec<(ChapterRow, i64)>> {
+        let db = self.connect_db()?;
+        let mut stmt = db.prepare(query)?;
+        let rows = stmt
+            .query_map(
+                params![username, PAGE_SIZE, (page - 1) * PAGE_SIZE],
+                |row| {
+                    Ok((
+                        ChapterRow {
+                            id: row.get(0)?,
+                            manga_id: row.get(1)?,
+                            manga_title: row.get(2)?,
+                            thumbnail_url: row.get(3)?,
+                            vol: row.get(4)?,
+                            no: row.get(5)?,
+                            title: row.get(6)?,
+                            at: row.get(7)?,
+                        },
+                        row.get(8)?,
+                    ))
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    fn chapters_feed(
+        &self,
+        auth: &FeedAuth,
+        path: &str,
+        title: &str,
+        page: i64,
+        rows: Vec<(ChapterRow, i64)>,
+    ) -> Feed {
+        let mut links = self.feed_links(
+            auth,
+            &format!("/opds/{}?page={}", path, page),
+            feed::ACQUISITION,
+        );
+        if page > 1 {
+            links.push(Link::new(
+                "previous",
+                auth.href(&format!("/opds/{}?page={}", path, page - 1)),
+                feed::ACQUISITION,
+            ));
+        }
+        if rows.len() as i64 == PAGE_SIZE {
+            links.push(Link::new(
+                "next",
+                auth.href(&format!("/opds/{}?page={}", path, page + 1)),
+                feed::ACQUISITION,
+            ));
+        }
+
+        let entries = rows
+            .into_iter()
+            .map(|(row, count)| self.chapter_entry(auth, row, Some(count), true))
+            .collect();
+
+        Feed {
+            id: format!("tanoshi:{}", path),
+            title: title.to_string(),
+            links,
+            entries,
+            ..Default::default()
+        }
+    }
+
+    /// Entry of a chapter with its CBZ acquisition link,
+    /// the page streaming link is only known once the pages of the chapter are fetched
+    fn chapter_entry(
+        &self,
+        auth: &FeedAuth,
+        row: ChapterRow,
+        pages: Option<i64>,
+        with_manga: bool,
+    ) -> Entry {
+        let chapter = chapter_title(&row.vol, &row.no, &row.title);
+        let title = if with_manga {
+            format!("{} - {}", row.manga_title, chapter)
+        } else {
+            chapter
+        };
+
+        let mut links = vec![Link::new(
+            feed::REL_ACQUISITION,
+            auth.href(&format!("/opds/chapter/{}/download", row.id)),
+            feed::CBZ,
+        )];
+        if let Some(count) = pages.filter(|count| *count > 0) {
+            links.push(Link {
+                count: Some(count),
+                ..Link::new(
+                    feed::REL_STREAM,
+                    auth.href(&format!("/opds/chapter/{}/page/{{pageNumber}}", row.id)),
+                    "image/jpeg",
+                )
+            });
+        }
+        if with_manga {
+            links.push(Link {
+                title: Some(row.manga_title.clone()),
+                ..Link::new(
+                    "related",
+                    auth.href(&format!("/opds/manga/{}", row.manga_id)),
+                    feed::ACQUISITION,
+                )
+            });
+        }
+        links.extend(thumbnail_links(&row.thumbnail_url));
+
+        Entry {
+            id: format!("tanoshi:chapter:{}", row.id),
+            title,
+            updated: row.at,
+            links,
+            ..Default::default()
+        }
+    }
+
+    /// Number of fetched pages of each chapter of a manga
+    fn page_counts(&self, manga_id: i32, username: &str) -> Result<HashMap<i32, i64>> {
+        let db = self.connect_db()?;
+        let mut stmt = db.prepare(
+            r#"SELECT page.chapter_id, COUNT(page.id) FROM page
+            JOIN chapter ON chapter.id = page.chapter_id
+            WHERE chapter.manga_id = ?1
+                AND chapter.user_id = (SELECT id FROM "user" WHERE username = ?2)
+            GROUP BY page.chapter_id"#,
+        )?;
+        let counts = stmt
+            .query_map(params![manga_id, username], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(counts)
+    }
+
+    /// Returns "<manga title> - <chapter title>" if the chapter belongs to the user
+    fn chapter_name(&self, chapter_id: i32, username: &str) -> Result<Option<String>> {
+        let db = self.connect_db()?;
+        let name = db
+            .query_row(
+                r#"SELECT manga.title, chapter.volume, chapter.number, chapter.title
+                FROM chapter
+                JOIN manga ON manga.id = chapter.manga_id
+                JOIN "user" ON "user".id = chapter.user_id
+                WHERE chapter.id = ?1 AND "user".username = ?2"#,
+                params![chapter_id, username],
+                |row| {
+                    let manga: String = row.get(0)?;
+                    let chapter = chapter_title(&row.get(1)?, &row.get(2)?, &row.get(3)?);
+                    Ok(format!("{} - {}", manga, chapter))
+                },
+            )
+            .optional()?;
+        Ok(name)
+    }
+
+    /// Ids of the pages of a chapter in reading order, pages are fetched from the source first if needed
+    async fn page_ids(&self, chapter_id: i32, username: &str) -> Result<Vec<i32>> {
+        let query = |db: &Connection| -> Result<Vec<i32>> {
+            let mut stmt =
+                db.prepare(r#"SELECT id FROM page WHERE chapter_id = ?1 ORDER BY rank"#)?;
+            let ids = stmt
+                .query_map(params![chapter_id], |row| row.get(0))?
+                .collect::<Result<Vec<i32>, _>>()?;
+            Ok(ids)
+        };
+
+        let ids = query(&self.connect_db()?)?;
+        if !ids.is_empty() {
+            return Ok(ids);
+        }
+        self.manga
+            .get_pages(chapter_id, username, GetParams { refresh: None })
+            .await?;
+        query(&self.connect_db()?)
+    }
+}
+
+fn thumbnail_links(thumbnail_url: &str) -> Vec<Link> {
+    if thumbnail_url.is_empty() {
+        return vec![];
+    }
+    let kind = mime_guess::from_path(thumbnail_url.split('?').next().unwrap_or_default())
+        .first_or(mime_guess::mime::IMAGE_JPEG);
+    vec![
+        Link::new(feed::REL_IMAGE, thumbnail_url.to_string(), kind.as_ref()),
+        Link::new(
+            feed::REL_THUMBNAIL,
+            thumbnail_url.to_string(),
+            kind.as_ref(),
+        ),
+    ]
+}
+
+fn chapter_title(vol: &Option<String>, no: &Option<String>, title: &Option<String>) -> String {
+    let mut parts = vec![];
+    if let Some(vol) = vol.as_ref().filter(|v| !v.is_empty()) {
+        parts.push(format!("Vol. {}", vol));
+    }
+    if let Some(no) = no.as_ref().filter(|n| !n.is_empty()) {
+        parts.push(format!("Ch. {}", no));
+    }
+    let label = parts.join(" ");
+    match title.as_ref().filter(|t| !t.is_empty()) {
+        Some(title) if label.is_empty() => title.clone(),
+        Some(title) => format!("{} - {}", label, title),
+        None => label,
+    }
+}
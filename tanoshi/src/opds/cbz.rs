@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Result};
+use std::convert::TryFrom;
+
+/// Modification date of every file, 1980-01-01 in MS-DOS format
+static DOS_DATE: u16 = 0x21;
+
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes a CBZ one page at a time, so a chapter is streamed to the reader instead of
+/// being packed in memory. Pages are stored without compression, images are compressed already,
+/// so the size and checksum of a page are known before its header is written.
+#[derive(Default)]
+pub struct CbzWriter {
+    entries: Vec<Entry>,
+    offset: u32,
+}
+
+impl CbzWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the bytes of a file in the archive, its header followed by its content
+    pub fn file(&mut self, name: &str, bytes: &[u8]) -> Result<Vec<u8>> {
+        if self.entries.len() >= u16::MAX as usize {
+            return Err(anyhow!("archive has too many files"));
+        }
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(bytes);
+        let entry = Entry {
+            name: name.to_string(),
+            crc: crc.finalize(),
+            size: u32::try_from(bytes.len()).map_err(|_| anyhow!("{} is too large", name))?,
+            offset: self.offset,
+        };
+
+        // local file header: version 2.0, no flags, stored, time and date
+        let mut chunk = Vec::with_capacity(30 + name.len() + bytes.len());
+        chunk.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        chunk.extend_from_slice(&20u16.to_le_bytes());
+        chunk.extend_from_slice(&0u16.to_le_bytes());
+        chunk.extend_from_slice(&0u16.to_le_bytes());
+        chunk.extend_from_slice(&0u16.to_le_bytes());
+        chunk.extend_from_slice(&DOS_DATE.to_le_bytes());
+        chunk.extend_from_slice(&entry.crc.to_le_bytes());
+        chunk.extend_from_slice(&entry.size.to_le_bytes());
+        chunk.extend_from_slice(&entry.size.to_le_bytes());
+        chunk.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        chunk.extend_from_slice(&0u16.to_le_bytes());
+        chunk.extend_from_slice(name.as_bytes());
+        chunk.extend_from_slice(bytes);
+
+        self.offset = u32::try_from(chunk.len())
+            .ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .ok_or_else(|| anyhow!("archive is too large"))?;
+        self.entries.push(entry);
+        Ok(chunk)
+    }
+
+    /// Returns the central directory that ends the archive
+    pub fn finish(self) -> Vec<u8> {
+        let mut chunk = vec![];
+        for entry in self.entries.iter() {
+            // central directory header: made by and needs version 2.0, no flags, stored, time and date
+            chunk.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            chunk.extend_from_slice(&20u16.to_le_bytes());
+            chunk.extend_from_slice(&20u16.to_le_bytes());
+            chunk.extend_from_slice(&0u16.to_le_bytes());
+            chunk.extend_from_slice(&0u16.to_le_bytes());
+            chunk.extend_from_slice(&0u16.to_le_bytes());
+            chunk.extend_from_slice(&DOS_DATE.to_le_bytes());
+            chunk.extend_from_slice(&entry.crc.to_le_bytes());
+            chunk.extend_from_slice(&entry.size.to_le_bytes());
+            chunk.extend_from_slice(&entry.size.to_le_bytes());
+            chunk.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            // no extra field, comment, disk number and attributes
+            chunk.extend_from_slice(&[0; 12]);
+            chunk.extend_from_slice(&entry.offset.to_le_bytes());
+            chunk.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let count = self.entries.len() as u16;
+        let size = chunk.len() as u32;
+        // end of central directory, on a single disk
+        chunk.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        chunk.extend_from_slice(&[0; 4]);
+        chunk.extend_from_slice(&count.to_le_bytes());
+        chunk.extend_from_slice(&count.to_le_bytes());
+        chunk.extend_from_slice(&size.to_le_bytes());
+        chunk.extend_from_slice(&self.offset.to_le_bytes());
+        chunk.extend_from_slice(&0u16.to_le_bytes());
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn read_written_archive() {
+        let mut writer = CbzWriter::new();
+        let mut cbz = vec![];
+        cbz.extend(writer.file("001.jpg", b"first page").unwrap());
+        cbz.extend(writer.file("002.png", b"").unwrap());
+        cbz.extend(writer.file("003.png", &[7; 1000]).unwrap());
+        cbz.extend(writer.finish());
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(cbz)).unwrap();
+        assert_eq!(archive.len(), 3);
+        for (name, content) in &[
+            ("001.jpg", b"first page".to_vec()),
+            ("002.png", vec![]),
+            ("003.png", vec![7; 1000]),
+        ] {
+            let mut file = archive.by_name(name).unwrap();
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes).unwrap();
+            assert_eq!(&bytes, content);
+        }
+    }
+
+    #[test]
+    fn empty_archive() {
+        let archive = zip::ZipArchive::new(Cursor::new(CbzWriter::new().finish())).unwrap();
+        assert_eq!(archive.len(), 0);
+    }
+}
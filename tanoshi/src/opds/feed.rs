@@ -0,0 +1,136 @@
+use chrono::NaiveDateTime;
+
+pub static NAVIGATION: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+pub static ACQUISITION: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+pub static CBZ: &str = "application/vnd.comicbook+zip";
+
+pub static REL_ACQUISITION: &str = "http://opds-spec.org/acquisition";
+pub static REL_IMAGE: &str = "http://opds-spec.org/image";
+pub static REL_THUMBNAIL: &str = "http://opds-spec.org/image/thumbnail";
+pub static REL_STREAM: &str = "http://vaemendis.net/opds-pse/stream";
+
+#[derive(Debug, Clone, Default)]
+pub struct Link {
+    pub rel: String,
+    pub href: String,
+    pub kind: String,
+    pub title: Option<String>,
+    /// `pse:count` of a page streaming link
+    pub count: Option<i64>,
+}
+
+impl Link {
+    pub fn new(rel: &str, href: String, kind: &str) -> Self {
+        Self {
+            rel: rel.to_string(),
+            href,
+            kind: kind.to_string(),
+            title: None,
+            count: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    pub id: String,
+    pub title: String,
+    pub updated: Option<NaiveDateTime>,
+    pub authors: Vec<String>,
+    pub content: Option<String>,
+    pub links: Vec<Link>,
+}
+
+/// Atom feed of an OPDS 1.2 catalog
+#[derive(Debug, Clone, Default)]
+pub struct Feed {
+    pub id: String,
+    pub title: String,
+    pub updated: Option<NaiveDateTime>,
+    pub links: Vec<Link>,
+    pub entries: Vec<Entry>,
+}
+
+impl Feed {
+    pub fn to_xml(&self) -> String {
+        let now = chrono::Utc::now().naive_utc();
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        xml.push_str(
+            r#"<feed xmlns="http://www.w3.org/2005/Atom" xmlns:opds="http://opds-spec.org/2010/catalog" xmlns:pse="http://vaemendis.net/opds-pse/ns">"#,
+        );
+        element(&mut xml, "id", &self.id);
+        element(&mut xml, "title", &self.title);
+        element(&mut xml, "updated", &timestamp(self.updated.unwrap_or(now)));
+        xml.push_str("<author><name>tanoshi</name></author>");
+        for link in self.links.iter() {
+            write_link(&mut xml, link);
+        }
+        for entry in self.entries.iter() {
+            xml.push_str("<entry>");
+            element(&mut xml, "id", &entry.id);
+            element(&mut xml, "title", &entry.title);
+            element(
+                &mut xml,
+                "updated",
+                &timestamp(entry.updated.unwrap_or(now)),
+            );
+            for author in entry.authors.iter().filter(|a| !a.trim().is_empty()) {
+                xml.push_str("<author>");
+                element(&mut xml, "name", author.trim());
+                xml.push_str("</author>");
+            }
+            if let Some(content) = entry.content.as_ref().filter(|c| !c.is_empty()) {
+                xml.push_str(r#"<content type="text">"#);
+                xml.push_str(&escape(content));
+                xml.push_str("</content>");
+            }
+            for link in entry.links.iter() {
+                write_link(&mut xml, link);
+            }
+            xml.push_str("</entry>");
+        }
+        xml.push_str("</feed>");
+        xml
+    }
+}
+
+fn timestamp(at: NaiveDateTime) -> String {
+    at.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn element(xml: &mut String, name: &str, text: &str) {
+    xml.push_str(&format!("<{}>{}</{}>", name, escape(text), name));
+}
+
+fn write_link(xml: &mut String, link: &Link) {
+    xml.push_str(&format!(
+        r#"<link rel="{}" href="{}" type="{}""#,
+        escape(&link.rel),
+        escape(&link.href),
+        escape(&link.kind)
+    ));
+    if let Some(title) = &link.title {
+        xml.push_str(&format!(r#" title="{}""#, escape(title)));
+    }
+    if let Some(count) = link.count {
+        xml.push_str(&format!(r#" pse:count="{}""#, count));
+    }
+    xml.push_str("/>");
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // characters not allowed in XML 1.0
+            c if (c as u32) < 0x20 && c != '\t' && c != '\n' && c != '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
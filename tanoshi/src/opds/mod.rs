@@ -0,0 +1,4 @@
+pub mod catalog;
+pub mod cbz;
+pub mod feed;
+pub use catalog::*;
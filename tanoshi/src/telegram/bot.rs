@@ -0,0 +1,451 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+
+use tanoshi_lib::manga::Chapter;
+
+use crate::auth::auth::Auth;
+use crate::error::Error;
+use crate::history::History;
+use crate::update::Update;
+
+/// Seconds a `getUpdates` call waits for a message
+const POLL_TIMEOUT: u64 = 30;
+/// Milliseconds to wait for a connection to the Bot API
+const CONNECT_TIMEOUT: u64 = 10_000;
+/// Chapters listed in one notification, Telegram refuses messages over 4096 characters
+const MAX_CHAPTERS: usize = 20;
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BotUpdate {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+/// Telegram bot sending new chapters to the chats users linked in settings,
+/// and answering commands in those chats.
+///
+/// Messages are formatted as HTML, links point to the web app at `base_url`.
+#[derive(Clone)]
+pub struct Telegram {
+    /// Bot API url with the token, e.g `https://api.telegram.org/bot<token>`
+    api: String,
+    base_url: Option<String>,
+    agent: ureq::Agent,
+    auth: Auth,
+    update: Update,
+    history: History,
+}
+
+impl Telegram {
+    pub fn new(
+        api: &str,
+        token: &str,
+        base_url: Option<String>,
+        auth: Auth,
+        update: Update,
+        history: History,
+    ) -> Self {
+        Self {
+            api: format!("{}/bot{}", api.trim_end_matches('/'), token),
+            base_url: base_url.map(|url| url.trim_end_matches('/').to_string()),
+            agent: ureq::Agent::new().build(),
+            auth,
+            update,
+            history,
+        }
+    }
+
+    fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+        timeout: u64,
+    ) -> Result<T> {
+        let resp = self
+            .agent
+            .post(&format!("{}/{}", self.api, method))
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(timeout * 1000)
+            .send_json(body);
+        // the url has the token, it is left out of errors
+        if let Some(e) = resp.synthetic_error() {
+            return Err(anyhow!("telegram {}: {}", method, e));
+        }
+        let res: ApiResponse<T> = resp
+            .into_json_deserialize()
+            .map_err(|e| anyhow!("telegram {}: {}", method, e))?;
+        match res.result {
+            Some(result) if res.ok => Ok(result),
+            _ => Err(anyhow!(
+                "telegram {}: {}",
+                method,
+                res.description.unwrap_or_else(|| "no result".to_string())
+            )),
+        }
+    }
+
+    pub fn send_message(&self, chat_id: i64, text: &str) -> Result<()> {
+        self.call::<serde_json::Value>(
+            "sendMessage",
+            json!({
+                "chat_id": chat_id,
+                "text": text,
+                "parse_mode": "HTML",
+                "disable_web_page_preview": true,
+            }),
+            POLL_TIMEOUT,
+        )?;
+        Ok(())
+    }
+
+    /// Sends new chapters of a favorite to the chat `username` linked, if any
+    pub async fn notify(&self, username: &str, title: &str, chapters: &[Chapter]) -> Result<()> {
+        let chat_id = match self.auth.telegram_chat_id(username)? {
+            Some(chat_id) => chat_id,
+            None => return Ok(()),
+        };
+
+        let mut text = format!("New chapters of <b>{}</b>", escape(title));
+        for chapter in chapters.iter().take(MAX_CHAPTERS) {
+            let name = chapter_name(chapter);
+            text.push('\n');
+            text.push_str(&self.link(&name, &format!("/chapter/{}/page/1", chapter.id)));
+        }
+        if chapters.len() > MAX_CHAPTERS {
+            text.push_str(&format!("\nand {} more", chapters.len() - MAX_CHAPTERS));
+        }
+
+        let telegram = self.clone();
+        tokio::task::spawn_blocking(move || telegram.send_message(chat_id, &text)).await?
+    }
+
+    /// Answers commands sent to the bot until the process exits
+    pub fn listen(self) {
+        std::thread::spawn(move || {
+            let mut offset = 0;
+            loop {
+                match self.poll(offset, POLL_TIMEOUT) {
+                    Ok(next) => offset = next,
+                    Err(e) => {
+                        error!("failed to get telegram messages: {}", e);
+                        std::thread::sleep(Duration::from_secs(10));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Answers messages sent after `offset`, returns the offset of the next ones
+    fn poll(&self, offset: i64, timeout: u64) -> Result<i64> {
+        let updates = self.call::<Vec<BotUpdate>>(
+            "getUpdates",
+            json!({
+                "offset": offset,
+                "timeout": timeout,
+                "allowed_updates": ["message"],
+            }),
+            timeout + 10,
+        )?;
+        let mut offset = offset;
+        for update in updates {
+            offset = update.update_id + 1;
+            if let Some(message) = update.message {
+                self.handle(message);
+            }
+        }
+        Ok(offset)
+    }
+
+    fn handle(&self, message: Message) {
+        let chat_id = message.chat.id;
+        let mut words = match message.text.as_deref().map(str::trim) {
+            Some(text) if text.starts_with('/') => text.split_whitespace(),
+            _ => return,
+        };
+        // commands in groups are sent as `/command@bot`
+        let command = words
+            .next()
+            .unwrap_or_default()
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let argument = words.next();
+
+        let answer = match (command.as_str(), argument) {
+            ("/start", Some(token)) => self.link_chat(chat_id, token),
+            _ => self.answer(chat_id, &command),
+        };
+        let text = match answer {
+            Ok(text) => text,
+            Err(e) => {
+                error!(
+                    "failed to answer {} in telegram chat {}: {}",
+                    command, chat_id, e
+                );
+                "Something went wrong, try again later".to_string()
+            }
+        };
+        if let Err(e) = self.send_message(chat_id, &text) {
+            error!("failed to answer telegram chat {}: {}", chat_id, e);
+        }
+    }
+
+    /// Links the chat with a token from the account settings, sent as `/start <token>`
+    fn link_chat(&self, chat_id: i64, token: &str) -> Result<String> {
+        match self.auth.link_telegram_chat(token, chat_id) {
+            Ok(Some(username)) => Ok(format!(
+                "Hi {}, new chapters of your favorites are sent here now",
+                escape(&username)
+            )),
+            Ok(None) => Ok(
+                "This link is expired, get a new one in the account settings of tanoshi"
+                    .to_string(),
+            ),
+            Err(Error::Conflict(_)) => {
+                Ok("This chat is linked to another user already".to_string())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn answer(&self, chat_id: i64, command: &str) -> Result<String> {
+        let username = match self.auth.telegram_user(chat_id)? {
+            Some(username) => username,
+            None => {
+                return Ok("Link this chat in the account settings of tanoshi to get new chapters of your favorites here".to_string())
+            }
+        };
+
+        match command {
+            "/updates" => {
+                let updates = self.update.updates(&username, 1)?;
+                if updates.is_empty() {
+                    return Ok("No new chapters yet".to_string());
+                }
+                Ok(updates
+                    .iter()
+                    .map(|u| {
+                        format!(
+                            "<b>{}</b> {}",
+                            escape(&u.title),
+                            self.link(
+                                &format!("Chapter {}", u.number),
+                                &format!("/chapter/{}/page/1", u.chapter_id)
+                            )
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n"))
+            }
+            "/continue" => {
+                let history = self.history.history(&username, 1)?;
+                if history.is_empty() {
+                    return Ok("Nothing read yet".to_string());
+                }
+                Ok(history
+                    .iter()
+                    .map(|h| {
+                        format!(
+                            "<b>{}</b> {}",
+                            escape(&h.title),
+                            self.link(
+                                &format!("Chapter {}, page {}", h.chapter, h.read + 1),
+                                &format!("/chapter/{}/page/{}", h.chapter_id, h.read + 1)
+                            )
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n"))
+            }
+            _ => Ok(format!(
+                "Hi {}, new chapters of your favorites are sent here\n\n/updates - newest chapters of your favorites\n/continue - chapters you were reading",
+                escape(&username)
+            )),
+        }
+    }
+
+    /// Link to a page of the web app, only the text without `base_url`
+    fn link(&self, text: &str, path: &str) -> String {
+        match &self.base_url {
+            Some(base_url) => format!(r#"<a href="{}{}">{}</a>"#, base_url, path, escape(text)),
+            None => escape(text),
+        }
+    }
+}
+
+fn chapter_name(chapter: &Chapter) -> String {
+    let number = chapter
+        .no
+        .clone()
+        .or_else(|| chapter.vol.clone())
+        .unwrap_or_default();
+    match chapter.title.as_ref().filter(|title| !title.is_empty()) {
+        Some(title) => format!("Chapter {} - {}", number, title),
+        None => format!("Chapter {}", number),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Bot API answering `getUpdates` with `updates` once, bodies of `sendMessage` are sent to the receiver
+    fn mock_bot_api(updates: serde_json::Value) -> (String, mpsc::Receiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut updates = Some(updates);
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let result = if request_line.contains("/botTOKEN/getUpdates ") {
+                    updates.take().unwrap_or_else(|| json!([]))
+                } else if request_line.contains("/botTOKEN/sendMessage ") {
+                    tx.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                    json!({ "message_id": 1 })
+                } else {
+                    json!(null)
+                };
+                let response = json!({ "ok": !result.is_null(), "result": result }).to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        (api, rx)
+    }
+
+    fn database(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("tanoshi-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = rusqlite::Connection::open(&path).unwrap();
+        for query in crate::QUERIES.iter() {
+            db.execute_batch(query).unwrap();
+        }
+        db.execute(
+            r#"INSERT INTO "user"(username, password, role) VALUES ('alice', '', 'READER'), ('bob', '', 'READER')"#,
+            rusqlite::params![],
+        )
+        .unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn message(update_id: i64, chat_id: i64, text: &str) -> serde_json::Value {
+        json!({
+            "update_id": update_id,
+            "message": { "chat": { "id": chat_id }, "text": text }
+        })
+    }
+
+    #[tokio::test]
+    async fn chat_is_linked_with_start_token() {
+        let database_path = database("telegram-link");
+        let auth = Auth::new(database_path.clone());
+        let token = auth.telegram_link_token("alice".to_string()).await.unwrap();
+
+        let (api, sent) = mock_bot_api(json!([
+            // a chat id alone doesn't link a chat
+            message(1, 42, "/updates"),
+            message(2, 42, "/start wrong"),
+            message(3, 42, &format!("/start {}", token)),
+            // the token is gone once it is used
+            message(4, 7, &format!("/start@tanoshi_bot {}", token)),
+        ]));
+        let telegram = Telegram::new(
+            &api,
+            "TOKEN",
+            None,
+            auth.clone(),
+            Update::new(database_path.clone()),
+            History::new(database_path.clone()),
+        );
+
+        let offset = tokio::task::spawn_blocking(move || telegram.poll(0, 0))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(offset, 5);
+
+        let answers: Vec<(i64, String)> = sent
+            .try_iter()
+            .map(|m| {
+                (
+                    m["chat_id"].as_i64().unwrap(),
+                    m["text"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(answers.len(), 4);
+        assert_eq!(answers[0].0, 42);
+        assert!(answers[0].1.starts_with("Link this chat"));
+        assert!(answers[1].1.contains("expired"));
+        assert!(answers[2].1.starts_with("Hi alice"));
+        assert_eq!(answers[3].0, 7);
+        assert!(answers[3].1.contains("expired"));
+
+        assert_eq!(auth.telegram_user(42).unwrap(), Some("alice".to_string()));
+        assert_eq!(auth.telegram_user(7).unwrap(), None);
+
+        // a linked chat can't be claimed by another user
+        let token = auth.telegram_link_token("bob".to_string()).await.unwrap();
+        assert!(matches!(
+            auth.link_telegram_chat(&token, 42),
+            Err(Error::Conflict(_))
+        ));
+        assert_eq!(auth.telegram_chat_id("bob").unwrap(), None);
+
+        let _ = std::fs::remove_file(&database_path);
+    }
+}
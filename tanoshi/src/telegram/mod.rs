@@ -0,0 +1,2 @@
+pub mod bot;
+pub use bot::*;
@@ -0,0 +1,403 @@
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tanoshi_lib::manga::Chapter;
+use tanoshi_lib::rest::{LibraryUpdate, LibraryUpdateError, LibraryUpdateResponse, WebhookEvent};
+use warp::Rejection;
+
+use crate::error::Error;
+use crate::extension::guard::{ErrorKind, Priority};
+use crate::extension::manga::Manga;
+use crate::handlers::reject;
+use crate::telegram::Telegram;
+use crate::webhook::Webhooks;
+
+/// A manga favorited by a user
+#[derive(Debug, Clone)]
+struct Favorite {
+    username: String,
+    manga_id: i32,
+    title: String,
+    source: String,
+    /// Whether the user is logged in to the source
+    session: bool,
+}
+
+/// Refreshes the chapters of the favorites of every user, every `update_interval` and on demand.
+///
+/// Chapters are fetched with `Priority::Background` and one manga at a time for each source,
+/// so an update never holds off users and stays within the rate limit of every source.
+/// A manga is fetched once for each user logged in to its source, with their session,
+/// and once for all other users favoriting it, without a session.
+/// New chapters are sent to the telegram chat and webhooks of the user, except for favorites
+/// that had no chapters saved yet. Failed favorites and outdated sources are sent to
+/// webhooks of admins after every update.
+#[derive(Clone)]
+pub struct Scheduler {
+    database_path: String,
+    manga: Manga,
+    telegram: Option<Telegram>,
+    webhooks: Webhooks,
+    status: Arc<Mutex<LibraryUpdate>>,
+}
+
+impl Scheduler {
+    pub fn new(
+        database_path: String,
+        manga: Manga,
+        telegram: Option<Telegram>,
+        webhooks: Webhooks,
+    ) -> Self {
+        Self {
+            database_path,
+            manga,
+            telegram,
+            webhooks,
+            status: Arc::new(Mutex::new(LibraryUpdate::default())),
+        }
+    }
+
+    fn connect_db(&self) -> Result<Connection, Error> {
+        Ok(Connection::open(self.database_path.clone())?)
+    }
+
+    fn update_status<F: FnOnce(&mut LibraryUpdate)>(&self, f: F) {
+        f(&mut self.status.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    pub fn status(&self) -> LibraryUpdate {
+        self.status
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub async fn get_status(&self) -> Result<impl warp::Reply, Rejection> {
+        Ok(warp::reply::json(&LibraryUpdateResponse {
+            update: self.status(),
+            status: "success".to_string(),
+        }))
+    }
+
+    pub async fn run_update(&self) -> Result<impl warp::Reply, Rejection> {
+        if !self.start() {
+            return Err(reject(Error::Conflict(
+                "library update is already running".to_string(),
+            )));
+        }
+        Ok(warp::reply::with_status(
+            warp::reply::json(&LibraryUpdateResponse {
+                update: self.status(),
+                status: "success".to_string(),
+            }),
+            warp::http::StatusCode::ACCEPTED,
+        ))
+    }
+
+    /// Runs an update every `interval`, counted from the end of the previous one
+    pub fn schedule(&self, interval: Duration) {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let next = chrono::Utc::now().naive_utc()
+                    + chrono::Duration::from_std(interval)
+                        .unwrap_or_else(|_| chrono::Duration::zero());
+                scheduler.update_status(|status| status.next_at = Some(next));
+                tokio::time::delay_for(interval).await;
+                if scheduler.begin() {
+                    scheduler.run().await;
+                } else {
+                    info!("library update is still running, skip scheduled update");
+                }
+            }
+        });
+    }
+
+    /// Starts an update in the background, returns false when one is already running
+    pub fn start(&self) -> bool {
+        if !self.begin() {
+            return false;
+        }
+        let scheduler = self.clone();
+        tokio::spawn(async move { scheduler.run().await });
+        true
+    }
+
+    /// Marks an update as running and resets the status of the previous one
+    fn begin(&self) -> bool {
+        let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+        if status.running {
+            return false;
+        }
+        *status = LibraryUpdate {
+            running: true,
+            started_at: Some(chrono::Utc::now().naive_utc()),
+            next_at: status.next_at,
+            ..Default::default()
+        };
+        true
+    }
+
+    async fn run(&self) {
+        info!("library update started");
+        let favorites = match self.favorites() {
+            Ok(favorites) => favorites,
+            Err(e) => {
+                error!("failed to read favorites for library update: {}", e);
+                vec![]
+            }
+        };
+
+        let updates = fetches(favorites)
+            .into_values()
+            .map(|favorites| {
+                let scheduler = self.clone();
+                tokio::spawn(async move { scheduler.update_source(favorites).await })
+            })
+            .collect::<Vec<_>>();
+        for update in updates {
+            if let Err(e) = update.await {
+                error!("library update task failed: {}", e);
+            }
+        }
+
+        self.update_status(|status| {
+            status.running = false;
+            status.finished_at = Some(chrono::Utc::now().naive_utc());
+            info!(
+                "library update found {} new chapters in {} mangas, {} failed",
+                status.new_chapters,
+                status.mangas,
+                status.errors.len()
+            );
+        });
+
+        self.webhooks.library_update_failed(&self.status());
+        if self.webhooks.subscribed(WebhookEvent::ExtensionUpdate) {
+            let manga = self.manga.clone();
+            match tokio::task::spawn_blocking(move || manga.outdated_sources()).await {
+                Ok(sources) => self.webhooks.extension_updates(&sources),
+                Err(e) => error!("failed to check sources for updates: {}", e),
+            }
+        }
+    }
+
+    /// Refreshes mangas of one source in turn, the rest are skipped once the source
+    /// is missing or disabled. `mangas` holds the favorites sharing a fetch of a manga,
+    /// either the favorite of a user with a session or the favorites of users without one.
+    async fn update_source(&self, mangas: Vec<Vec<Favorite>>) {
+        let mut skip: Option<String> = None;
+        for favorites in mangas {
+            if let Some(message) = skip.as_ref() {
+                for favorite in favorites.iter() {
+                    self.failed(favorite, message.clone());
+                }
+                continue;
+            }
+
+            let first = &favorites[0];
+            let username = Some(first.username.as_str()).filter(|_| first.session);
+            match self
+                .manga
+                .fetch_chapters(first.manga_id, username, Priority::Background)
+                .await
+            {
+                Ok(chapters) => {
+                    for favorite in favorites.iter() {
+                        self.save(favorite, chapters.clone()).await;
+                    }
+                }
+                Err(e) => {
+                    let e = Error::from(e);
+                    if let Error::Extension(ext) = &e {
+                        if matches!(ext.kind, ErrorKind::NotFound | ErrorKind::Disabled) {
+                            skip = Some(e.to_string());
+                        }
+                    }
+                    for favorite in favorites.iter() {
+                        self.failed(favorite, e.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Saves chapters fetched from the source for the user of a favorite
+    async fn save(&self, favorite: &Favorite, chapters: Vec<Chapter>) {
+        let notify = self.has_chapters(favorite);
+        match self
+            .manga
+            .save_chapters(favorite.manga_id, &favorite.username, chapters)
+        {
+            Ok(chapters) => {
+                self.update_status(|status| {
+                    status.mangas += 1;
+                    status.new_chapters += chapters.len();
+                });
+                if notify && !chapters.is_empty() {
+                    self.notify(favorite, &chapters).await;
+                }
+            }
+            Err(e) => self.failed(favorite, e.to_string()),
+        }
+    }
+
+    /// Sends new chapters of a favorite to the telegram chat and webhooks of the user
+    async fn notify(&self, favorite: &Favorite, chapters: &[Chapter]) {
+        self.webhooks.new_chapters(
+            &favorite.username,
+            favorite.manga_id,
+            &favorite.title,
+            chapters,
+        );
+        if let Some(telegram) = &self.telegram {
+            if let Err(e) = telegram
+                .notify(&favorite.username, &favorite.title, chapters)
+                .await
+            {
+                warn!(
+                    "failed to send new chapters of {} to {}: {}",
+                    favorite.title, favorite.username, e
+                );
+            }
+        }
+    }
+
+    fn failed(&self, favorite: &Favorite, message: String) {
+        warn!(
+            "failed to update chapters of {} for {}: {}",
+            favorite.title, favorite.username, message
+        );
+        self.update_status(|status| {
+            status.errors.push(LibraryUpdateError {
+                manga_id: favorite.manga_id,
+                title: favorite.title.clone(),
+                source: favorite.source.clone(),
+                message,
+            })
+        });
+    }
+
+    /// Whether the user had any chapter of the favorite before
+    fn has_chapters(&self, favorite: &Favorite) -> bool {
+        let has_chapters = self.connect_db().and_then(|db| {
+            Ok(db.query_row(
+                r#"SELECT EXISTS(
+                    SELECT 1 FROM chapter
+                    JOIN "user" ON "user".id = chapter.user_id
+                    WHERE chapter.manga_id = ?1 AND "user".username = ?2)"#,
+                params![favorite.manga_id, favorite.username],
+                |row| row.get(0),
+            )?)
+        });
+        match has_chapters {
+            Ok(has_chapters) => has_chapters,
+            Err(e) => {
+                error!("failed to read chapters of {}: {}", favorite.title, e);
+                false
+            }
+        }
+    }
+
+    fn favorites(&self) -> Result<Vec<Favorite>, Error> {
+        let db = self.connect_db()?;
+        let mut stmt = db.prepare(
+            r#"SELECT "user".username, manga.id, manga.title, manga.source,
+                EXISTS(SELECT 1 FROM source_session
+                    WHERE source_session.user_id = "user".id
+                    AND source_session.source = manga.source)
+            FROM favorite
+            JOIN "user" ON "user".id = favorite.user_id
+            JOIN manga ON manga.id = favorite.manga_id
+            ORDER BY manga.source, manga.title, manga.id"#,
+        )?;
+        let favorites = stmt
+            .query_map(params![], |row| {
+                Ok(Favorite {
+                    username: row.get(0)?,
+                    manga_id: row.get(1)?,
+                    title: row.get(2)?,
+                    source: row.get(3)?,
+                    session: row.get(4)?,
+                })
+            })?
+            .filter_map(|f| f.ok())
+            .collect();
+        Ok(favorites)
+    }
+}
+
+/// Groups favorites by source and by fetch of a manga. Favorites are ordered by source and manga,
+/// so favorites of a manga are next to each other. Chapters fetched with the session of a user
+/// are only saved for that user, users without a session share a fetch.
+fn fetches(favorites: Vec<Favorite>) -> BTreeMap<String, Vec<Vec<Favorite>>> {
+    let mut sources: BTreeMap<String, Vec<Vec<Favorite>>> = BTreeMap::new();
+    for favorite in favorites {
+        let mangas = sources.entry(favorite.source.clone()).or_default();
+        let shared = mangas
+            .iter_mut()
+            .rev()
+            .take_while(|users| users[0].manga_id == favorite.manga_id)
+            .find(|users| !users[0].session && !favorite.session);
+        match shared {
+            Some(users) => users.push(favorite),
+            None => mangas.push(vec![favorite]),
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn favorite(username: &str, manga_id: i32, source: &str, session: bool) -> Favorite {
+        Favorite {
+            username: username.to_string(),
+            manga_id,
+            title: format!("manga {}", manga_id),
+            source: source.to_string(),
+            session,
+        }
+    }
+
+    #[test]
+    fn users_with_a_session_fetch_alone() {
+        let sources = fetches(vec![
+            favorite("alice", 1, "mangadex", false),
+            favorite("bob", 1, "mangadex", true),
+            favorite("carol", 1, "mangadex", false),
+            favorite("dave", 1, "mangadex", true),
+            favorite("alice", 2, "mangadex", false),
+            favorite("alice", 3, "local", false),
+            favorite("bob", 3, "local", false),
+        ]);
+
+        let users = |source: &str| -> Vec<Vec<(i32, String)>> {
+            sources[source]
+                .iter()
+                .map(|favorites| {
+                    favorites
+                        .iter()
+                        .map(|f| (f.manga_id, f.username.clone()))
+                        .collect()
+                })
+                .collect()
+        };
+        assert_eq!(
+            users("mangadex"),
+            vec![
+                vec![(1, "alice".to_string()), (1, "carol".to_string())],
+                vec![(1, "bob".to_string())],
+                vec![(1, "dave".to_string())],
+                vec![(2, "alice".to_string())],
+            ]
+        );
+        assert_eq!(
+            users("local"),
+            vec![vec![(3, "alice".to_string()), (3, "bob".to_string())]]
+        );
+    }
+}
@@ -1,4 +1,6 @@
 use crate::auth::Claims;
+use crate::error::Error;
+use crate::handlers::reject;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 use tanoshi_lib::manga::Update as UpdateModel;
@@ -20,8 +22,8 @@ impl Update {
         Self { database_path }
     }
 
-    fn connect_db(&self) -> Connection {
-        Connection::open(self.database_path.clone()).unwrap()
+    fn connect_db(&self) -> Result<Connection, Error> {
+        Ok(Connection::open(self.database_path.clone())?)
     }
 
     pub async fn get_updates(
@@ -29,12 +31,23 @@ impl Update {
         claim: Claims,
         param: UpdateParam,
     ) -> Result<impl warp::Reply, Rejection> {
-        let db = self.connect_db();
+        let updates = self.updates(&claim.sub, param.page).map_err(reject)?;
+
+        let res = UpdatesResponse {
+            updates,
+            status: "success".to_string(),
+        };
+
+        Ok(warp::reply::json(&res))
+    }
+
+    /// Newest chapters of the favorites of a user, 10 for each page
+    pub fn updates(&self, username: &str, page: i32) -> Result<Vec<UpdateModel>, Error> {
+        let db = self.connect_db()?;
         let limit = 10;
-        let offset = (param.page * limit) - limit;
-        let mut stmt = db
-            .prepare(
-                r#"SELECT 
+        let offset = (page * limit) - limit;
+        let mut stmt = db.prepare(
+            r#"SELECT 
                 manga.title AS title, 
                 manga.thumbnail_url AS thumbnail_url, 
                 chapter.uploaded AS uploaded,
@@ -47,10 +60,9 @@ impl Update {
                 JOIN "user" ON "user".id = favorite.user_id AND "user".id = chapter.user_id
                 WHERE "user".username = ?1 ORDER BY uploaded DESC
                 LIMIT ?2 OFFSET ?3"#,
-            )
-            .unwrap();
+        )?;
         let updates = stmt
-            .query_map(params![claim.sub, limit as i64, offset as i64], |row| {
+            .query_map(params![username, limit as i64, offset as i64], |row| {
                 Ok(UpdateModel {
                     title: row.get(0)?,
                     thumbnail_url: row.get(1)?,
@@ -61,16 +73,9 @@ impl Update {
                     days: None,
                     show_sep: None,
                 })
-            })
-            .unwrap()
+            })?
             .filter_map(|u| u.ok())
             .collect();
-
-        let res = UpdatesResponse {
-            updates,
-            status: "success".to_string(),
-        };
-
-        Ok(warp::reply::json(&res))
+        Ok(updates)
     }
 }
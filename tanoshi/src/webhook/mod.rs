@@ -0,0 +1,3 @@
+pub mod template;
+pub mod webhooks;
+pub use webhooks::*;
@@ -0,0 +1,39 @@
+use regex::{Captures, Regex};
+use serde_json::{Map, Value};
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{\{\s*([A-Za-z_]+)\s*\}\}").unwrap();
+}
+
+/// Replaces `{{name}}` in every string of `template` with the variable `name`.
+///
+/// A string that is only a placeholder becomes the value of the variable, e.g an array
+/// of chapters, in other strings values are written as text. Unknown variables are empty.
+pub fn render(template: &Value, vars: &Map<String, Value>) -> Value {
+    match template {
+        Value::String(text) => {
+            if let Some(captures) = PLACEHOLDER.captures(text) {
+                if captures[0].len() == text.len() {
+                    return vars.get(&captures[1]).cloned().unwrap_or(Value::Null);
+                }
+            }
+            Value::String(
+                PLACEHOLDER
+                    .replace_all(text, |captures: &Captures| match vars.get(&captures[1]) {
+                        Some(Value::String(value)) => value.clone(),
+                        Some(Value::Null) | None => "".to_string(),
+                        Some(value) => value.to_string(),
+                    })
+                    .to_string(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.iter().map(|v| render(v, vars)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), render(v, vars)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}